@@ -1,3 +1,11 @@
 pub fn main() {
     println!("cargo:rerun-if-changed=.cargo/layout.ld");
+
+    // Network boot drives the USB ethernet with the kernel's copy of USPi.
+    if std::env::var_os("CARGO_FEATURE_NETBOOT").is_some() {
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        println!("cargo:rustc-link-search=native={}/../kern/.cargo", dir);
+        println!("cargo:rustc-link-lib=static=uspi");
+        println!("cargo:rustc-link-lib=static=uspienv");
+    }
 }
@@ -1,6 +1,13 @@
 #!/bin/sh
 
 TOP=$(git rev-parse --show-toplevel)
+
+# The console (MiniUart) is the second serial port, exposed as a pty; with
+# `console=ttyAMA0` on the kernel command line it is the first one (the PL011)
+# instead. To debug the kernel with gdb, stop at a `brk`, type `gdb` at the
+# `(dbg)` prompt, close the terminal and run:
+#
+#   aarch64-none-elf-gdb -ex 'target remote /dev/pts/<n>' <kernel.elf>
 $TOP/bin/qemu-system-aarch64 \
     -nographic \
     -M raspi3 \
@@ -6,6 +6,8 @@
 
 #[cfg(not(test))]
 mod init;
+#[cfg(any(feature = "netboot", test))]
+mod netboot;
 
 use xmodem::Xmodem;
 use core::time::Duration;
@@ -31,14 +33,24 @@ unsafe fn jump_to(addr: *mut u8) -> ! {
 
 fn kmain() -> ! {
     // FIXME: Implement the bootloader.
+    let mut memory;
+    unsafe {
+        memory = core::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE);
+    }
+
+    // With `netboot=` on the command line, try the network first.
+    #[cfg(all(feature = "netboot", not(test)))]
+    {
+        if netboot::fetch(&mut memory).is_ok() {
+            unsafe {
+                jump_to(BINARY_START);
+            }
+        }
+    }
+
     let mut mini = pi::uart::MiniUart::new();
     mini.set_read_timeout(Duration::from_millis(750));
     loop {
-        let mut memory;
-        unsafe {
-            memory = core::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE);
-
-        }
         let xxx = Xmodem::receive(&mut mini, &mut memory);
         if xxx.is_ok() {
             unsafe {
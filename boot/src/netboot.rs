@@ -0,0 +1,172 @@
+//! Network boot: fetches the kernel over the USB ethernet with TFTP or HTTP,
+//! as configured by the `netboot=` option of the command line (see
+//! `config`). Only what a single download needs is implemented: ARP, IPv4
+//! without fragmentation, UDP, a DHCP client and a receive-only TCP client.
+
+#![cfg_attr(test, allow(dead_code))]
+
+mod config;
+mod dhcp;
+mod eth;
+mod http;
+mod tcp;
+mod tftp;
+
+#[cfg(not(test))]
+mod irq;
+#[cfg(not(test))]
+mod usb;
+
+#[cfg(not(test))]
+use core::time::Duration;
+
+#[cfg(not(test))]
+use pi::atags::Atags;
+use pi::timer::current_time;
+
+#[cfg(not(test))]
+use self::config::{parse_cmdline, Protocol, DEFAULT_PATH};
+#[cfg(not(test))]
+use self::eth::Interface;
+#[cfg(not(test))]
+use self::usb::Usb;
+
+/// How long to wait for the ethernet link to come up.
+#[cfg(not(test))]
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// No `netboot=` option on the command line.
+    Disabled,
+    NoDevice,
+    NoLink,
+    /// The ethernet device failed to send a frame.
+    Device,
+    NoAddress,
+    NoServer,
+    Unreachable,
+    TimedOut,
+    Refused,
+    /// A TFTP error code, or an HTTP status code.
+    Server(u16),
+    TooLarge,
+    BadResponse,
+}
+
+/// Fetches the kernel into `memory` if the command line asks for it.
+/// Returns its size.
+///
+/// The USB controller and interrupts are shut down again before returning,
+/// whether the kernel was fetched or not.
+#[cfg(not(test))]
+pub fn fetch(memory: &mut [u8]) -> Result<usize, Error> {
+    let cmdline = Atags::get().find_map(|atag| atag.cmd()).ok_or(Error::Disabled)?;
+    let config = parse_cmdline(cmdline).ok_or(Error::Disabled)?;
+    if !irq::enable() {
+        return Err(Error::NoDevice);
+    }
+
+    let result = Usb::initialize().ok_or(Error::NoDevice).and_then(|usb| {
+        let result = download(usb, config, memory);
+        usb::reset();
+        result
+    });
+    irq::disable();
+    result
+}
+
+/// Configures the interface of `usb` and downloads the kernel as `config`
+/// says.
+#[cfg(not(test))]
+fn download(usb: Usb, config: config::Config, memory: &mut [u8]) -> Result<usize, Error> {
+    if !usb.is_eth_available() {
+        return Err(Error::NoDevice);
+    }
+    if !usb.wait_for_link(LINK_TIMEOUT) {
+        return Err(Error::NoLink);
+    }
+    let mac = usb.mac_address();
+    let mut iface = Interface::new(usb, mac);
+
+    let lease = match config.dhcp {
+        true => dhcp::acquire(&mut iface).ok(),
+        false => None,
+    };
+    match (&lease, config.fallback) {
+        (Some(lease), _) => {
+            iface.address = lease.address;
+            iface.netmask = lease.netmask;
+            iface.gateway = lease.gateway;
+        }
+        (None, Some(fallback)) => {
+            iface.address = fallback.client;
+            iface.netmask = fallback.netmask;
+            iface.gateway = fallback.gateway;
+        }
+        (None, None) => return Err(Error::NoAddress),
+    }
+
+    let source = config.source;
+    let server = source
+        .server
+        .or_else(|| lease.as_ref().and_then(|lease| lease.boot_server))
+        .or_else(|| config.fallback.and_then(|fallback| fallback.server))
+        .ok_or(Error::NoServer)?;
+    let path = source
+        .path
+        .or_else(|| lease.as_ref().and_then(|lease| lease.boot_file()))
+        .unwrap_or(DEFAULT_PATH);
+
+    match source.protocol {
+        Protocol::Tftp => tftp::fetch(&mut iface, server, source.port, path, memory),
+        Protocol::Http => http::fetch(&mut iface, server, source.port, path, memory),
+    }
+}
+
+/// Returns a local port for a new transfer, in the dynamic range.
+pub fn ephemeral_port() -> u16 {
+    49152 + (current_time().as_micros() % 16384) as u16
+}
+
+/// Writes into a fixed buffer, remembering whether anything did not fit.
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    overflow: bool,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer { buf, len: 0, overflow: false }
+    }
+
+    pub fn put(&mut self, bytes: &[u8]) {
+        match self.buf.get_mut(self.len..self.len + bytes.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(bytes);
+                self.len += bytes.len();
+            }
+            None => self.overflow = true,
+        }
+    }
+
+    /// Returns the length written, or `Error::TooLarge` if something did
+    /// not fit.
+    pub fn finish(self) -> Result<usize, Error> {
+        match self.overflow {
+            true => Err(Error::TooLarge),
+            false => Ok(self.len),
+        }
+    }
+}
+
+impl<'a> core::fmt::Write for Writer<'a> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.put(s.as_bytes());
+        match self.overflow {
+            true => Err(core::fmt::Error),
+            false => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! The network boot options of the ATAG command line. The firmware reads the
+//! command line from `cmdline.txt`, or from the file named by `cmdline=` in
+//! `config.txt`.
+//!
+//! - `netboot=tftp` or `netboot=http`: fetch `kernel.bin`, or the boot file
+//!   named by the DHCP server, from the boot server.
+//! - `netboot=tftp://<server>[:<port>]/<path>` or
+//!   `netboot=http://<server>[:<port>]/<path>`: fetch `<path>` from
+//!   `<server>`. Either part may be left out, as in `tftp:///kernel.bin`.
+//! - `ip=...`: the IPv4 configuration, in the format the kernel takes. The
+//!   address is acquired with DHCP unless a static one is given; with an
+//!   `autoconf` field of `dhcp`, the static one is the fallback. Its `server`
+//!   field is the boot server when the `netboot=` option names none.
+//!
+//! The boot server defaults to the one named by the DHCP server. The last
+//! valid option of each kind wins.
+
+use super::eth::Ipv4;
+
+/// The file fetched when neither the command line nor DHCP names one.
+pub const DEFAULT_PATH: &str = "kernel.bin";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Tftp,
+    Http,
+}
+
+impl Protocol {
+    pub fn default_port(self) -> u16 {
+        match self {
+            Protocol::Tftp => 69,
+            Protocol::Http => 80,
+        }
+    }
+}
+
+/// Where the kernel is fetched from, as given by `netboot=`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Source<'a> {
+    pub protocol: Protocol,
+    pub server: Option<Ipv4>,
+    pub port: u16,
+    pub path: Option<&'a str>,
+}
+
+/// A static IPv4 configuration, as given by `ip=`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Static {
+    pub client: Ipv4,
+    pub server: Option<Ipv4>,
+    pub gateway: Option<Ipv4>,
+    pub netmask: Ipv4,
+}
+
+/// The network boot configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config<'a> {
+    pub source: Source<'a>,
+    /// Whether to acquire the address with DHCP.
+    pub dhcp: bool,
+    /// The address used without DHCP, or when no lease is acquired.
+    pub fallback: Option<Static>,
+}
+
+/// Parses a dotted-decimal IPv4 address.
+pub fn parse_ipv4(s: &str) -> Option<Ipv4> {
+    let mut address = [0; 4];
+    let mut octets = s.split('.');
+    for octet in address.iter_mut() {
+        *octet = octets.next()?.parse().ok()?;
+    }
+    match octets.next() {
+        None => Some(address),
+        Some(_) => None,
+    }
+}
+
+/// Parses the value of a `netboot=` option.
+fn parse_source(option: &str) -> Option<Source> {
+    let (protocol, rest) = if option.starts_with("tftp") {
+        (Protocol::Tftp, &option["tftp".len()..])
+    } else if option.starts_with("http") {
+        (Protocol::Http, &option["http".len()..])
+    } else {
+        return None;
+    };
+    let mut source = Source { protocol, server: None, port: protocol.default_port(), path: None };
+    if rest.is_empty() {
+        return Some(source);
+    }
+    if !rest.starts_with("://") {
+        return None;
+    }
+
+    let rest = &rest["://".len()..];
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => (rest, ""),
+    };
+    let mut parts = authority.splitn(2, ':');
+    match parts.next().unwrap() {
+        "" => {}
+        server => source.server = Some(parse_ipv4(server)?),
+    }
+    if let Some(port) = parts.next() {
+        source.port = port.parse().ok().filter(|&port| port != 0)?;
+    }
+    if !path.is_empty() {
+        source.path = Some(path);
+    }
+    Some(source)
+}
+
+/// Parses the value of an `ip=` option: whether to use DHCP and the static
+/// configuration, if any.
+fn parse_ip(option: &str) -> Option<(bool, Option<Static>)> {
+    match option {
+        "dhcp" | "on" | "any" => return Some((true, None)),
+        "off" | "none" => return None,
+        _ => {}
+    }
+
+    let mut fields = option.split(':');
+    let client = parse_ipv4(fields.next()?)?;
+    let mut optional = || match fields.next() {
+        None | Some("") => Some(None),
+        Some(field) => parse_ipv4(field).map(Some),
+    };
+    let server = optional()?;
+    let gateway = optional()?;
+    let netmask = optional()?.unwrap_or([255, 255, 255, 0]);
+    let _hostname = fields.next();
+    let _device = fields.next();
+    let dhcp = match fields.next() {
+        Some("dhcp") | Some("on") | Some("any") => true,
+        _ => false,
+    };
+    Some((dhcp, Some(Static { client, server, gateway, netmask })))
+}
+
+/// Returns the network boot configuration of `cmdline`, or `None` if it has
+/// no valid `netboot=` option.
+pub fn parse_cmdline(cmdline: &str) -> Option<Config> {
+    let mut source = None;
+    let mut address = (true, None);
+    for arg in cmdline.split_whitespace() {
+        if arg.starts_with("netboot=") {
+            if let Some(option) = parse_source(&arg["netboot=".len()..]) {
+                source = Some(option);
+            }
+        } else if arg.starts_with("ip=") {
+            if let Some(option) = parse_ip(&arg["ip=".len()..]) {
+                address = option;
+            }
+        }
+    }
+    let (dhcp, fallback) = address;
+    Some(Config { source: source?, dhcp: dhcp || fallback.is_none(), fallback })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(cmdline: &str) -> Option<Source> {
+        parse_cmdline(cmdline).map(|config| config.source)
+    }
+
+    #[test]
+    fn netboot_option() {
+        assert_eq!(source("console=ttyS0"), None);
+        assert_eq!(source("netboot=nfs"), None);
+        assert_eq!(
+            source("netboot=tftp"),
+            Some(Source { protocol: Protocol::Tftp, server: None, port: 69, path: None })
+        );
+        assert_eq!(
+            source("netboot=http://10.0.0.1:8080/boot/kernel.bin"),
+            Some(Source {
+                protocol: Protocol::Http,
+                server: Some([10, 0, 0, 1]),
+                port: 8080,
+                path: Some("boot/kernel.bin"),
+            })
+        );
+        assert_eq!(
+            source("netboot=tftp:///pi3.bin"),
+            Some(Source { protocol: Protocol::Tftp, server: None, port: 69, path: Some("pi3.bin") })
+        );
+        assert_eq!(
+            source("netboot=http://10.0.0.1"),
+            Some(Source {
+                protocol: Protocol::Http,
+                server: Some([10, 0, 0, 1]),
+                port: 80,
+                path: None,
+            })
+        );
+        assert_eq!(source("netboot=tftp netboot=http:10.0.0.1").unwrap().protocol, Protocol::Tftp);
+        assert_eq!(source("netboot=tftp://10.0.0.1:0/"), None);
+        assert_eq!(source("netboot=tftp://10.0.0/"), None);
+    }
+
+    #[test]
+    fn ip_option() {
+        let config = parse_cmdline("netboot=tftp").unwrap();
+        assert!(config.dhcp);
+        assert_eq!(config.fallback, None);
+
+        let config = parse_cmdline("netboot=tftp ip=10.0.0.2:10.0.0.1:10.0.0.254").unwrap();
+        assert!(!config.dhcp);
+        assert_eq!(
+            config.fallback,
+            Some(Static {
+                client: [10, 0, 0, 2],
+                server: Some([10, 0, 0, 1]),
+                gateway: Some([10, 0, 0, 254]),
+                netmask: [255, 255, 255, 0],
+            })
+        );
+
+        let config = parse_cmdline("ip=192.168.1.9::::pi::dhcp netboot=http").unwrap();
+        assert!(config.dhcp);
+        assert_eq!(config.fallback.unwrap().server, None);
+
+        let config = parse_cmdline("netboot=tftp ip=10.0.0.2 ip=garbage ip=off").unwrap();
+        assert!(!config.dhcp);
+        assert_eq!(config.fallback.unwrap().client, [10, 0, 0, 2]);
+    }
+}
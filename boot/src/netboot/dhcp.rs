@@ -0,0 +1,278 @@
+//! A DHCP client that acquires an address once and reads the boot server and
+//! boot file of the lease. The lease is not renewed: the kernel acquires its
+//! own.
+
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+use super::config::parse_ipv4;
+use super::eth::{self, Device, Interface, Ipv4, Mac, BROADCAST, MTU, UNSPECIFIED};
+use super::Error;
+
+const CLIENT_PORT: u16 = 68;
+const SERVER_PORT: u16 = 67;
+const MAGIC: [u8; 4] = [99, 130, 83, 99];
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_REQUESTED_ADDRESS: u8 = 50;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETERS: u8 = 55;
+const OPT_TFTP_SERVER: u8 = 66;
+const OPT_BOOT_FILE: u8 = 67;
+const OPT_END: u8 = 255;
+
+/// Offset of the options, after the fixed BOOTP fields and the magic cookie.
+const OPTIONS: usize = 240;
+/// Size of the messages sent; some servers ignore shorter BOOTP messages.
+const MESSAGE_LEN: usize = 300;
+const BOOT_FILE_MAX: usize = 128;
+
+/// How long to wait for the server before starting over, and how many times.
+const RETRANSMIT: Duration = Duration::from_secs(2);
+const ATTEMPTS: usize = 4;
+
+/// A lease acquired from a DHCP server.
+#[derive(Clone, Copy)]
+pub struct Lease {
+    pub address: Ipv4,
+    pub netmask: Ipv4,
+    pub gateway: Option<Ipv4>,
+    server_id: Ipv4,
+    /// The TFTP server, or the next server of the BOOTP fields.
+    pub boot_server: Option<Ipv4>,
+    boot_file: [u8; BOOT_FILE_MAX],
+    boot_file_len: usize,
+}
+
+impl Lease {
+    /// The file the server tells to boot, if any.
+    pub fn boot_file(&self) -> Option<&str> {
+        match self.boot_file_len {
+            0 => None,
+            len => core::str::from_utf8(&self.boot_file[..len]).ok(),
+        }
+    }
+}
+
+/// Writes a message of type `kind` to `buf`, requesting the address of
+/// `offer` if there is one. Returns its length.
+fn write_message(buf: &mut [u8; MESSAGE_LEN], xid: u32, mac: Mac, kind: u8, offer: Option<&Lease>) -> usize {
+    *buf = [0; MESSAGE_LEN];
+    buf[0..4].copy_from_slice(&[1, 1, 6, 0]);
+    buf[4..8].copy_from_slice(&xid.to_be_bytes());
+    // Ask for broadcast replies: there is no address to unicast to yet.
+    buf[10] = 0x80;
+    buf[28..34].copy_from_slice(&mac);
+    buf[236..240].copy_from_slice(&MAGIC);
+
+    let mut options = OPTIONS;
+    let mut put = |option: &[u8]| {
+        buf[options..options + option.len()].copy_from_slice(option);
+        options += option.len();
+    };
+    put(&[OPT_MESSAGE_TYPE, 1, kind]);
+    if let Some(offer) = offer {
+        put(&[OPT_REQUESTED_ADDRESS, 4]);
+        put(&offer.address);
+        put(&[OPT_SERVER_ID, 4]);
+        put(&offer.server_id);
+    }
+    put(&[OPT_PARAMETERS, 4, OPT_SUBNET_MASK, OPT_ROUTER, OPT_TFTP_SERVER, OPT_BOOT_FILE]);
+    put(&[OPT_END]);
+    MESSAGE_LEN
+}
+
+/// Returns the NUL-terminated string at the start of `bytes`.
+fn c_string(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
+/// Parses the reply `message` to the message `xid` sent by `mac`. Returns its
+/// type and the lease it offers.
+fn parse_message(message: &[u8], xid: u32, mac: Mac) -> Option<(u8, Lease)> {
+    if message.len() < OPTIONS || message[0] != 2 || message[236..240] != MAGIC {
+        return None;
+    }
+    if message[4..8] != xid.to_be_bytes() || message[28..34] != mac {
+        return None;
+    }
+
+    let ipv4 = |bytes: &[u8]| {
+        let mut address = UNSPECIFIED;
+        address.copy_from_slice(&bytes[..4]);
+        address
+    };
+    let next_server = ipv4(&message[20..24]);
+    let mut lease = Lease {
+        address: ipv4(&message[16..20]),
+        netmask: [255, 255, 255, 0],
+        gateway: None,
+        server_id: UNSPECIFIED,
+        boot_server: Some(next_server).filter(|&server| server != UNSPECIFIED),
+        boot_file: [0; BOOT_FILE_MAX],
+        boot_file_len: 0,
+    };
+    let mut boot_file = c_string(&message[108..236]);
+    let mut kind = None;
+
+    let mut options = &message[OPTIONS..];
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPT_PAD => {
+                options = rest;
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        let len = *rest.first()? as usize;
+        let value = rest.get(1..1 + len)?;
+        options = &rest[1 + len..];
+        match (code, len) {
+            (OPT_MESSAGE_TYPE, 1) => kind = Some(value[0]),
+            (OPT_SUBNET_MASK, 4) => lease.netmask = ipv4(value),
+            (OPT_ROUTER, _) if len >= 4 => lease.gateway = Some(ipv4(value)),
+            (OPT_SERVER_ID, 4) => lease.server_id = ipv4(value),
+            (OPT_TFTP_SERVER, _) => {
+                let name = core::str::from_utf8(c_string(value)).ok();
+                if let Some(server) = name.and_then(parse_ipv4) {
+                    lease.boot_server = Some(server);
+                }
+            }
+            (OPT_BOOT_FILE, _) => boot_file = c_string(value),
+            _ => {}
+        }
+    }
+
+    let len = core::cmp::min(boot_file.len(), BOOT_FILE_MAX);
+    lease.boot_file[..len].copy_from_slice(&boot_file[..len]);
+    lease.boot_file_len = len;
+    Some((kind?, lease))
+}
+
+/// Broadcasts a message of type `kind` and waits for a reply of type
+/// `expected`. Fails with `Error::Refused` on a NAK.
+fn exchange<D: Device>(
+    iface: &mut Interface<D>,
+    xid: u32,
+    kind: u8,
+    offer: Option<&Lease>,
+    expected: u8,
+) -> Result<Lease, Error> {
+    let mut message = [0; MESSAGE_LEN];
+    let len = write_message(&mut message, xid, iface.mac, kind, offer);
+    iface.send_udp(BROADCAST, CLIENT_PORT, SERVER_PORT, &message[..len])?;
+
+    let mut buf = [0; MTU];
+    let deadline = current_time() + RETRANSMIT;
+    while current_time() < deadline {
+        let packet = match iface.recv(&mut buf) {
+            Some(packet) => packet,
+            None => continue,
+        };
+        let reply = match eth::parse_udp(&packet, &buf[..packet.len]) {
+            Some((SERVER_PORT, CLIENT_PORT, payload)) => parse_message(payload, xid, iface.mac),
+            _ => None,
+        };
+        match reply {
+            Some((kind, lease)) if kind == expected => return Ok(lease),
+            Some((NAK, _)) => return Err(Error::Refused),
+            _ => {}
+        }
+    }
+    Err(Error::TimedOut)
+}
+
+/// Acquires a lease for the interface.
+pub fn acquire<D: Device>(iface: &mut Interface<D>) -> Result<Lease, Error> {
+    let xid = current_time().as_micros() as u32;
+    for _ in 0..ATTEMPTS {
+        let offer = match exchange(iface, xid, DISCOVER, None, OFFER) {
+            Err(Error::TimedOut) => continue,
+            result => result?,
+        };
+        match exchange(iface, xid, REQUEST, Some(&offer), ACK) {
+            Err(Error::TimedOut) | Err(Error::Refused) => continue,
+            result => return result,
+        }
+    }
+    Err(Error::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: Mac = [0xb8, 0x27, 0xeb, 1, 2, 3];
+
+    /// Returns the server's reply to the request `request`, of type `kind`.
+    fn reply(request: &[u8], kind: u8, options: &[u8]) -> [u8; 400] {
+        let mut reply = [0; 400];
+        reply[..OPTIONS].copy_from_slice(&request[..OPTIONS]);
+        reply[0] = 2;
+        reply[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        reply[20..24].copy_from_slice(&[10, 0, 0, 1]);
+        reply[108..115].copy_from_slice(b"pxe.bin");
+        reply[OPTIONS..OPTIONS + 3].copy_from_slice(&[OPT_MESSAGE_TYPE, 1, kind]);
+        let end = OPTIONS + 3 + options.len();
+        reply[OPTIONS + 3..end].copy_from_slice(options);
+        reply[end] = OPT_END;
+        reply
+    }
+
+    #[test]
+    fn offer_and_request() {
+        let mut discover = [0; MESSAGE_LEN];
+        write_message(&mut discover, 42, MAC, DISCOVER, None);
+        assert_eq!(&discover[OPTIONS..OPTIONS + 3], &[OPT_MESSAGE_TYPE, 1, DISCOVER]);
+
+        let options = [
+            OPT_SERVER_ID, 4, 10, 0, 0, 254, OPT_PAD, OPT_SUBNET_MASK, 4, 255, 255, 0, 0,
+            OPT_ROUTER, 8, 10, 0, 0, 254, 10, 0, 0, 253,
+        ];
+        let offer = reply(&discover, OFFER, &options);
+        let (kind, lease) = parse_message(&offer, 42, MAC).unwrap();
+        assert_eq!(kind, OFFER);
+        assert_eq!(lease.address, [10, 0, 0, 2]);
+        assert_eq!(lease.netmask, [255, 255, 0, 0]);
+        assert_eq!(lease.gateway, Some([10, 0, 0, 254]));
+        assert_eq!(lease.server_id, [10, 0, 0, 254]);
+        assert_eq!(lease.boot_server, Some([10, 0, 0, 1]));
+        assert_eq!(lease.boot_file(), Some("pxe.bin"));
+
+        let mut request = [0; MESSAGE_LEN];
+        write_message(&mut request, 42, MAC, REQUEST, Some(&lease));
+        let options = &request[OPTIONS..OPTIONS + 15];
+        assert_eq!(options, &[53, 1, REQUEST, 50, 4, 10, 0, 0, 2, 54, 4, 10, 0, 0, 254][..]);
+
+        // A reply to someone else, and a truncated option.
+        assert!(parse_message(&offer, 43, MAC).is_none());
+        assert!(parse_message(&reply(&discover, OFFER, &[OPT_ROUTER, 200, 10]), 42, MAC).is_none());
+    }
+
+    #[test]
+    fn boot_options() {
+        let mut discover = [0; MESSAGE_LEN];
+        write_message(&mut discover, 7, MAC, DISCOVER, None);
+        let mut options = [0; 32];
+        options[..12].copy_from_slice(b"\x42\x0a10.0.0.99\0");
+        options[12..24].copy_from_slice(b"\x43\x0akernel.bin");
+        let ack = reply(&discover, ACK, &options[..24]);
+        let (kind, lease) = parse_message(&ack, 7, MAC).unwrap();
+        assert_eq!(kind, ACK);
+        assert_eq!(lease.boot_server, Some([10, 0, 0, 99]));
+        assert_eq!(lease.boot_file(), Some("kernel.bin"));
+        assert_eq!(lease.gateway, None);
+    }
+}
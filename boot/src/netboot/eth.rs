@@ -0,0 +1,385 @@
+//! Just enough of ethernet, ARP and IPv4 to talk to one boot server: frames
+//! are sent and received one at a time, ARP requests for the local address
+//! are answered and IPv4 packets addressed to it are handed to the caller.
+//! Fragmented packets are dropped.
+
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+use super::Error;
+
+pub type Mac = [u8; 6];
+pub type Ipv4 = [u8; 4];
+
+pub const BROADCAST: Ipv4 = [255; 4];
+pub const UNSPECIFIED: Ipv4 = [0; 4];
+
+/// Size of the frame buffers; matches `USPI_FRAME_BUFFER_SIZE`.
+pub const FRAME_SIZE: usize = 1600;
+/// Largest IPv4 packet sent or received.
+pub const MTU: usize = 1500;
+
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERNET_HEADER: usize = 14;
+const IPV4_HEADER: usize = 20;
+const UDP_HEADER: usize = 8;
+
+/// How long to wait for the answer of an ARP request, and how many are sent.
+const ARP_TIMEOUT: Duration = Duration::from_millis(500);
+const ARP_ATTEMPTS: usize = 4;
+const ARP_ENTRIES: usize = 4;
+
+/// Sends and receives ethernet frames.
+pub trait Device {
+    /// Sends `frame`. Returns `false` if it could not be queued.
+    fn send(&mut self, frame: &[u8]) -> bool;
+
+    /// Receives a frame into `buf` without waiting. Returns its length.
+    fn recv(&mut self, buf: &mut [u8; FRAME_SIZE]) -> Option<usize>;
+}
+
+/// Returns the internet checksum of `data`, starting from the partial sum
+/// `sum`.
+pub fn checksum(mut sum: u32, data: &[u8]) -> u16 {
+    for chunk in data.chunks(2) {
+        let word = match *chunk {
+            [hi, lo] => u16::from_be_bytes([hi, lo]),
+            [hi] => u16::from_be_bytes([hi, 0]),
+            _ => unreachable!(),
+        };
+        sum += word as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Returns the partial sum of the pseudo header of a TCP or UDP segment.
+pub fn pseudo_header_sum(src: Ipv4, dst: Ipv4, protocol: u8, len: usize) -> u32 {
+    let word = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]) as u32;
+    word(&src[..2]) + word(&src[2..]) + word(&dst[..2]) + word(&dst[2..])
+        + protocol as u32
+        + len as u32
+}
+
+/// An IPv4 packet addressed to the interface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Packet {
+    pub src: Ipv4,
+    pub dst: Ipv4,
+    pub protocol: u8,
+    /// Length of the payload, which is copied to the caller's buffer.
+    pub len: usize,
+}
+
+/// Parses the IPv4 packet `packet`. Returns its header and its payload.
+pub fn parse_ipv4(packet: &[u8]) -> Option<(Packet, &[u8])> {
+    if packet.len() < IPV4_HEADER || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = (packet[0] & 0xf) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < IPV4_HEADER || total_len < header_len || total_len > packet.len() {
+        return None;
+    }
+    if checksum(0, &packet[..header_len]) != 0 {
+        return None;
+    }
+    // More fragments, or an offset.
+    if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+        return None;
+    }
+
+    let mut src = UNSPECIFIED;
+    let mut dst = UNSPECIFIED;
+    src.copy_from_slice(&packet[12..16]);
+    dst.copy_from_slice(&packet[16..20]);
+    let payload = &packet[header_len..total_len];
+    Some((Packet { src, dst, protocol: packet[9], len: payload.len() }, payload))
+}
+
+/// Parses the UDP datagram `segment` of the packet `packet`. Returns its
+/// source port, destination port and payload.
+pub fn parse_udp<'a>(packet: &Packet, segment: &'a [u8]) -> Option<(u16, u16, &'a [u8])> {
+    if packet.protocol != PROTOCOL_UDP || segment.len() < UDP_HEADER {
+        return None;
+    }
+    let len = u16::from_be_bytes([segment[4], segment[5]]) as usize;
+    if len < UDP_HEADER || len > segment.len() {
+        return None;
+    }
+    let sum = u16::from_be_bytes([segment[6], segment[7]]);
+    let pseudo = pseudo_header_sum(packet.src, packet.dst, PROTOCOL_UDP, len);
+    if sum != 0 && checksum(pseudo, &segment[..len]) != 0 {
+        return None;
+    }
+    let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+    let dst_port = u16::from_be_bytes([segment[2], segment[3]]);
+    Some((src_port, dst_port, &segment[UDP_HEADER..len]))
+}
+
+/// An ethernet interface with one IPv4 address.
+pub struct Interface<D: Device> {
+    device: D,
+    pub mac: Mac,
+    /// `UNSPECIFIED` until the interface is configured.
+    pub address: Ipv4,
+    pub netmask: Ipv4,
+    pub gateway: Option<Ipv4>,
+    arp: [(Ipv4, Mac); ARP_ENTRIES],
+    arp_next: usize,
+    ident: u16,
+    rx: [u8; FRAME_SIZE],
+    tx: [u8; FRAME_SIZE],
+}
+
+impl<D: Device> Interface<D> {
+    pub fn new(device: D, mac: Mac) -> Interface<D> {
+        Interface {
+            device,
+            mac,
+            address: UNSPECIFIED,
+            netmask: UNSPECIFIED,
+            gateway: None,
+            arp: [(UNSPECIFIED, [0; 6]); ARP_ENTRIES],
+            arp_next: 0,
+            ident: 0,
+            rx: [0; FRAME_SIZE],
+            tx: [0; FRAME_SIZE],
+        }
+    }
+
+    /// Sends the `len` bytes of payload in `tx` to `dst_mac`.
+    fn send_frame(&mut self, dst_mac: Mac, ethertype: u16, len: usize) -> Result<(), Error> {
+        self.tx[0..6].copy_from_slice(&dst_mac);
+        self.tx[6..12].copy_from_slice(&self.mac);
+        self.tx[12..14].copy_from_slice(&ethertype.to_be_bytes());
+        // Pad to the minimum frame size.
+        let len = core::cmp::max(ETHERNET_HEADER + len, 60);
+        match self.device.send(&self.tx[..len]) {
+            true => Ok(()),
+            false => Err(Error::Device),
+        }
+    }
+
+    fn send_arp(&mut self, operation: u16, target_mac: Mac, target: Ipv4) -> Result<(), Error> {
+        let arp = &mut self.tx[ETHERNET_HEADER..ETHERNET_HEADER + 28];
+        arp[0..6].copy_from_slice(&[0, 1, 8, 0, 6, 4]);
+        arp[6..8].copy_from_slice(&operation.to_be_bytes());
+        arp[8..14].copy_from_slice(&self.mac);
+        arp[14..18].copy_from_slice(&self.address);
+        arp[18..24].copy_from_slice(&target_mac);
+        arp[24..28].copy_from_slice(&target);
+        let dst_mac = if operation == 1 { [0xff; 6] } else { target_mac };
+        self.send_frame(dst_mac, ETHERTYPE_ARP, 28)
+    }
+
+    /// Answers an ARP request for the interface's address and learns the
+    /// sender of the ARP packet in `rx`.
+    fn handle_arp(&mut self, len: usize) {
+        let arp = &self.rx[ETHERNET_HEADER..len];
+        if arp.len() < 28 || arp[0..6] != [0, 1, 8, 0, 6, 4] || self.address == UNSPECIFIED {
+            return;
+        }
+        let operation = u16::from_be_bytes([arp[6], arp[7]]);
+        let mut sender_mac = [0; 6];
+        let mut sender = UNSPECIFIED;
+        let mut target = UNSPECIFIED;
+        sender_mac.copy_from_slice(&arp[8..14]);
+        sender.copy_from_slice(&arp[14..18]);
+        target.copy_from_slice(&arp[24..28]);
+        if target != self.address {
+            return;
+        }
+        self.learn(sender, sender_mac);
+        if operation == 1 {
+            self.send_arp(2, sender_mac, sender).ok();
+        }
+    }
+
+    fn learn(&mut self, address: Ipv4, mac: Mac) {
+        if let Some(entry) = self.arp.iter_mut().find(|entry| entry.0 == address) {
+            entry.1 = mac;
+            return;
+        }
+        self.arp[self.arp_next] = (address, mac);
+        self.arp_next = (self.arp_next + 1) % ARP_ENTRIES;
+    }
+
+    /// Returns the hardware address `dst` is reached through, asking for it
+    /// with ARP if needed.
+    fn resolve(&mut self, dst: Ipv4) -> Result<Mac, Error> {
+        if dst == BROADCAST {
+            return Ok([0xff; 6]);
+        }
+        let same_subnet = (0..4).all(|i| dst[i] & self.netmask[i] == self.address[i] & self.netmask[i]);
+        let hop = match (same_subnet, self.gateway) {
+            (true, _) => dst,
+            (false, Some(gateway)) => gateway,
+            (false, None) => return Err(Error::Unreachable),
+        };
+
+        for _ in 0..ARP_ATTEMPTS {
+            if let Some(&(_, mac)) = self.arp.iter().find(|entry| entry.0 == hop) {
+                return Ok(mac);
+            }
+            self.send_arp(1, [0; 6], hop)?;
+            let deadline = current_time() + ARP_TIMEOUT;
+            while current_time() < deadline && !self.arp.iter().any(|entry| entry.0 == hop) {
+                self.poll_frame();
+            }
+        }
+        self.arp.iter().find(|entry| entry.0 == hop).map(|&(_, mac)| mac).ok_or(Error::Unreachable)
+    }
+
+    /// Receives a frame into `rx` and handles it if it is an ARP packet.
+    /// Returns the length of an IPv4 packet addressed to the interface.
+    fn poll_frame(&mut self) -> Option<usize> {
+        let len = self.device.recv(&mut self.rx)?;
+        if len < ETHERNET_HEADER {
+            return None;
+        }
+        match u16::from_be_bytes([self.rx[12], self.rx[13]]) {
+            ETHERTYPE_ARP => {
+                self.handle_arp(len);
+                None
+            }
+            ETHERTYPE_IPV4 => Some(len),
+            _ => None,
+        }
+    }
+
+    /// Receives an IPv4 packet addressed to the interface, or broadcast,
+    /// without waiting. Its payload is copied to `buf`.
+    pub fn recv(&mut self, buf: &mut [u8]) -> Option<Packet> {
+        let len = self.poll_frame()?;
+        let (packet, payload) = parse_ipv4(&self.rx[ETHERNET_HEADER..len])?;
+        let for_us = self.address == UNSPECIFIED || packet.dst == self.address || packet.dst == BROADCAST;
+        if !for_us || payload.len() > buf.len() {
+            return None;
+        }
+        buf[..payload.len()].copy_from_slice(payload);
+        Some(packet)
+    }
+
+    /// Sends `payload` to `dst` in an IPv4 packet of protocol `protocol`.
+    pub fn send(&mut self, dst: Ipv4, protocol: u8, payload: &[u8]) -> Result<(), Error> {
+        if IPV4_HEADER + payload.len() > MTU {
+            return Err(Error::TooLarge);
+        }
+        let dst_mac = self.resolve(dst)?;
+        self.ident = self.ident.wrapping_add(1);
+
+        let len = IPV4_HEADER + payload.len();
+        let header = &mut self.tx[ETHERNET_HEADER..ETHERNET_HEADER + IPV4_HEADER];
+        header[0] = 0x45;
+        header[1] = 0;
+        header[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        header[4..6].copy_from_slice(&self.ident.to_be_bytes());
+        // Don't fragment.
+        header[6..8].copy_from_slice(&[0x40, 0]);
+        header[8] = 64;
+        header[9] = protocol;
+        header[10..12].copy_from_slice(&[0, 0]);
+        header[12..16].copy_from_slice(&self.address);
+        header[16..20].copy_from_slice(&dst);
+        let sum = checksum(0, header);
+        header[10..12].copy_from_slice(&sum.to_be_bytes());
+
+        let start = ETHERNET_HEADER + IPV4_HEADER;
+        self.tx[start..start + payload.len()].copy_from_slice(payload);
+        self.send_frame(dst_mac, ETHERTYPE_IPV4, len)
+    }
+
+    /// Sends `data` in a UDP datagram from `src_port` to `dst`:`dst_port`.
+    pub fn send_udp(&mut self, dst: Ipv4, src_port: u16, dst_port: u16, data: &[u8]) -> Result<(), Error> {
+        let mut segment = [0; MTU - IPV4_HEADER];
+        let len = UDP_HEADER + data.len();
+        if len > segment.len() {
+            return Err(Error::TooLarge);
+        }
+        segment[0..2].copy_from_slice(&src_port.to_be_bytes());
+        segment[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        segment[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+        segment[UDP_HEADER..len].copy_from_slice(data);
+        let pseudo = pseudo_header_sum(self.address, dst, PROTOCOL_UDP, len);
+        let sum = match checksum(pseudo, &segment[..len]) {
+            0 => 0xffff,
+            sum => sum,
+        };
+        segment[6..8].copy_from_slice(&sum.to_be_bytes());
+        self.send(dst, PROTOCOL_UDP, &segment[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        // The header of RFC 1071's example, and a header with its checksum.
+        assert_eq!(checksum(0, &[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]), !0xddf2);
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(checksum(0, &header), 0);
+        assert_eq!(checksum(0, &[0xff]), 0x00ff);
+    }
+
+    fn udp_packet(payload: &[u8]) -> [u8; 64] {
+        let mut packet = [0; 64];
+        let len = IPV4_HEADER + UDP_HEADER + payload.len();
+        packet[0] = 0x45;
+        packet[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        packet[8] = 64;
+        packet[9] = PROTOCOL_UDP;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        packet[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        let sum = checksum(0, &packet[..IPV4_HEADER]);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+
+        let segment = &mut packet[IPV4_HEADER..len];
+        segment[0..2].copy_from_slice(&69u16.to_be_bytes());
+        segment[2..4].copy_from_slice(&2000u16.to_be_bytes());
+        segment[4..6].copy_from_slice(&((UDP_HEADER + payload.len()) as u16).to_be_bytes());
+        segment[8..].copy_from_slice(payload);
+        let pseudo = pseudo_header_sum([10, 0, 0, 1], [10, 0, 0, 2], PROTOCOL_UDP, segment.len());
+        let sum = checksum(pseudo, segment);
+        segment[6..8].copy_from_slice(&sum.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn parse_udp_datagram() {
+        let packet = udp_packet(b"hello");
+        let (header, segment) = parse_ipv4(&packet).unwrap();
+        assert_eq!(header.src, [10, 0, 0, 1]);
+        assert_eq!(header.dst, [10, 0, 0, 2]);
+        assert_eq!(header.len, UDP_HEADER + 5);
+        assert_eq!(parse_udp(&header, segment), Some((69, 2000, &b"hello"[..])));
+
+        // A corrupted payload, then a corrupted header.
+        let mut corrupted = packet;
+        corrupted[IPV4_HEADER + UDP_HEADER] ^= 1;
+        let (header, segment) = parse_ipv4(&corrupted).unwrap();
+        assert_eq!(parse_udp(&header, segment), None);
+        corrupted[8] = 1;
+        assert!(parse_ipv4(&corrupted).is_none());
+
+        // A fragment.
+        let mut fragment = packet;
+        fragment[6] |= 0x20;
+        fragment[10..12].copy_from_slice(&[0, 0]);
+        let sum = checksum(0, &fragment[..IPV4_HEADER]);
+        fragment[10..12].copy_from_slice(&sum.to_be_bytes());
+        assert!(parse_ipv4(&fragment).is_none());
+    }
+}
@@ -0,0 +1,158 @@
+//! Fetches a file with an HTTP/1.0 `GET` request.
+
+use super::eth::{Device, Interface, Ipv4, MTU};
+use super::tcp::Connection;
+use super::{Error, Writer};
+
+/// The longest response head accepted.
+const HEAD_MAX: usize = 2048;
+
+/// Writes the request for `path` on `server`:`port` to `buf`. Returns its
+/// length.
+fn request(buf: &mut [u8], server: Ipv4, port: u16, path: &str) -> Result<usize, Error> {
+    use core::fmt::Write;
+
+    let [a, b, c, d] = server;
+    let mut writer = Writer::new(buf);
+    write!(writer, "GET /{} HTTP/1.0\r\nHost: {}.{}.{}.{}", path.trim_start_matches('/'), a, b, c, d).ok();
+    if port != 80 {
+        write!(writer, ":{}", port).ok();
+    }
+    writer.put(b"\r\nConnection: close\r\n\r\n");
+    writer.finish()
+}
+
+/// Returns the length of the response head at the start of `buf`, once it
+/// has all been received.
+fn head_len(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|window| window == b"\r\n\r\n").map(|i| i + 4)
+}
+
+/// Parses the response head `head`. Returns the length of the body if the
+/// server gave it, or fails with `Error::Server` and the status code unless
+/// the request succeeded.
+fn parse_head(head: &[u8]) -> Result<Option<usize>, Error> {
+    let head = core::str::from_utf8(head).map_err(|_| Error::BadResponse)?;
+    let mut lines = head.split("\r\n");
+    let mut status = lines.next().ok_or(Error::BadResponse)?.split(' ');
+    let version = status.next().ok_or(Error::BadResponse)?;
+    let code: u16 = status.next().and_then(|code| code.parse().ok()).ok_or(Error::BadResponse)?;
+    if !version.starts_with("HTTP/1.") {
+        return Err(Error::BadResponse);
+    }
+    if code != 200 {
+        return Err(Error::Server(code));
+    }
+
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap();
+        if let (true, Some(value)) = (name.eq_ignore_ascii_case("content-length"), parts.next()) {
+            return value.trim().parse().map(Some).map_err(|_| Error::BadResponse);
+        }
+    }
+    Ok(None)
+}
+
+/// Fetches `path` from the HTTP server at `server`:`port` into `memory`.
+/// Returns its size.
+pub fn fetch<D: Device>(
+    iface: &mut Interface<D>,
+    server: Ipv4,
+    port: u16,
+    path: &str,
+    memory: &mut [u8],
+) -> Result<usize, Error> {
+    let mut buf = [0; HEAD_MAX];
+    let request_len = request(&mut buf, server, port, path)?;
+    let mut connection = Connection::connect(iface, server, port)?;
+    let result = receive(&mut connection, &buf[..request_len], memory);
+    connection.close();
+    result
+}
+
+/// Sends `request` on `connection` and receives the body of the response
+/// into `memory`.
+fn receive<D: Device>(connection: &mut Connection<D>, request: &[u8], memory: &mut [u8]) -> Result<usize, Error> {
+    connection.send(request)?;
+
+    let mut head = [0; HEAD_MAX];
+    let mut received = 0;
+    let head_end = loop {
+        if let Some(end) = head_len(&head[..received]) {
+            break end;
+        }
+        if received == head.len() {
+            return Err(Error::BadResponse);
+        }
+        match connection.recv(&mut head[received..])? {
+            0 => return Err(Error::BadResponse),
+            len => received += len,
+        }
+    };
+    let content_len = parse_head(&head[..head_end])?;
+    if content_len.map_or(false, |len| len > memory.len()) {
+        return Err(Error::TooLarge);
+    }
+
+    let body = &head[head_end..received];
+    if body.len() > memory.len() {
+        return Err(Error::TooLarge);
+    }
+    memory[..body.len()].copy_from_slice(body);
+    let mut size = body.len();
+    loop {
+        let len = match memory.get_mut(size..).filter(|rest| !rest.is_empty()) {
+            Some(rest) => connection.recv(rest)?,
+            // Full: anything more does not fit.
+            None => match connection.recv(&mut [0; MTU])? {
+                0 => 0,
+                _ => return Err(Error::TooLarge),
+            },
+        };
+        if len == 0 {
+            break;
+        }
+        size += len;
+    }
+
+    match content_len {
+        Some(len) if len != size => Err(Error::BadResponse),
+        _ => Ok(size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests() {
+        let mut buf = [0; 256];
+        let len = request(&mut buf, [10, 0, 0, 1], 80, "kernel.bin").unwrap();
+        assert_eq!(
+            &buf[..len],
+            &b"GET /kernel.bin HTTP/1.0\r\nHost: 10.0.0.1\r\nConnection: close\r\n\r\n"[..]
+        );
+        let len = request(&mut buf, [10, 0, 0, 1], 8080, "/pi/kernel.bin").unwrap();
+        assert!(buf[..len].starts_with(b"GET /pi/kernel.bin HTTP/1.0\r\nHost: 10.0.0.1:8080\r\n"));
+        assert_eq!(request(&mut [0; 32], [10, 0, 0, 1], 80, "kernel.bin"), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn heads() {
+        let response = b"HTTP/1.1 200 OK\r\nServer: x\r\ncontent-length:  1234\r\n\r\nbody";
+        let end = head_len(response).unwrap();
+        assert_eq!(&response[end..], b"body");
+        assert_eq!(parse_head(&response[..end]), Ok(Some(1234)));
+        assert_eq!(head_len(b"HTTP/1.0 200 OK\r\n"), None);
+
+        assert_eq!(parse_head(b"HTTP/1.0 200 OK\r\n\r\n"), Ok(None));
+        assert_eq!(parse_head(b"HTTP/1.0 404 Not Found\r\n\r\n"), Err(Error::Server(404)));
+        assert_eq!(parse_head(b"SSH-2.0-OpenSSH\r\n\r\n"), Err(Error::BadResponse));
+        assert_eq!(
+            parse_head(b"HTTP/1.1 200 OK\r\nContent-Length: lots\r\n\r\n"),
+            Err(Error::BadResponse)
+        );
+    }
+}
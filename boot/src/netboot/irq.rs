@@ -0,0 +1,97 @@
+//! Interrupts for USPi. The bootloader has no exception vectors of its own;
+//! while the network is up, IRQs and FIQs are taken at EL2 by
+//! `netboot_vectors` and handed to the handlers USPi connected.
+
+use core::ffi::c_void;
+
+use aarch64::{current_el, HCR_EL2, VBAR_EL2};
+use pi::interrupt::{Controller, Interrupt};
+
+global_asm!(include_str!("vectors.s"));
+
+/// An interrupt handler of USPi and its parameter.
+type Handler = (unsafe extern "C" fn(*mut c_void), usize);
+
+/// Most IRQ handlers connected at once: USPi's timer, and a spare.
+const IRQ_HANDLERS: usize = 2;
+
+/// The handlers are only changed while their interrupt is disabled.
+static mut IRQS: [Option<(Interrupt, Handler)>; IRQ_HANDLERS] = [None; IRQ_HANDLERS];
+static mut FIQ: Option<Handler> = None;
+
+/// Called by `netboot_vectors` for an IRQ (0) or an FIQ (1).
+#[no_mangle]
+unsafe extern "C" fn handle_interrupt(kind: u64) {
+    if kind == 1 {
+        if let Some((handler, param)) = FIQ {
+            handler(param as *mut c_void);
+        }
+        return;
+    }
+
+    let controller = Controller::new();
+    for &(int, (handler, param)) in IRQS.iter().flatten() {
+        if controller.is_pending(int) {
+            handler(param as *mut c_void);
+        }
+    }
+}
+
+/// Routes `int` to `handler`: the USB interrupt as the FIQ, the others as
+/// IRQs.
+pub unsafe fn connect(int: Interrupt, handler: unsafe extern "C" fn(*mut c_void), param: *mut c_void) {
+    let mut controller = Controller::new();
+    if int == Interrupt::Usb {
+        FIQ = Some((handler, param as usize));
+        controller.enable_fiq(int);
+        return;
+    }
+
+    let slot = IRQS.iter_mut().find(|slot| slot.map_or(true, |(connected, _)| connected == int));
+    match slot {
+        Some(slot) => *slot = Some((int, (handler, param as usize))),
+        None => panic!("too many interrupt handlers"),
+    }
+    controller.enable(int);
+}
+
+/// Takes IRQs and FIQs at EL2 with `netboot_vectors`. Returns `false` if
+/// the bootloader does not run at EL2.
+pub fn enable() -> bool {
+    extern "C" {
+        static netboot_vectors: u64;
+    }
+
+    if current_el() != 2 {
+        return false;
+    }
+    unsafe {
+        VBAR_EL2.set(&netboot_vectors as *const u64 as u64);
+        HCR_EL2.set(HCR_EL2.get() | HCR_EL2::IMO | HCR_EL2::FMO);
+    }
+    aarch64::enable_irq_interrupt();
+    aarch64::enable_fiq_interrupt();
+    true
+}
+
+/// Masks and disables the interrupts connected by USPi, and stops taking
+/// interrupts at EL2, before the kernel is started.
+pub fn disable() {
+    aarch64::disable_irq_interrupt();
+    aarch64::disable_fiq_interrupt();
+
+    let mut controller = Controller::new();
+    unsafe {
+        for slot in IRQS.iter_mut() {
+            if let Some((int, _)) = slot.take() {
+                controller.disable(int);
+            }
+        }
+        if FIQ.take().is_some() {
+            controller.disable_fiq();
+        }
+        if current_el() == 2 {
+            HCR_EL2.set(HCR_EL2.get() & !(HCR_EL2::IMO | HCR_EL2::FMO));
+        }
+    }
+}
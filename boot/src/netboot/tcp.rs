@@ -0,0 +1,295 @@
+//! A TCP client for one connection that sends a short request and then
+//! receives in bulk. Segments that arrive in order are acknowledged right
+//! away; the others are dropped and acknowledged again so the server
+//! retransmits what is missing.
+
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+use super::eth::{self, Device, Interface, Ipv4, Packet, MTU, PROTOCOL_TCP};
+use super::{ephemeral_port, Error};
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+const HEADER: usize = 20;
+/// The largest segment the server may send: the one that fits in an
+/// unfragmented packet.
+const MSS: usize = MTU - 20 - HEADER;
+/// The receive window: a few segments, so that the USB ethernet is not
+/// flooded.
+const WINDOW: u16 = (8 * MSS) as u16;
+/// The longest request `send` takes.
+const REQUEST_MAX: usize = 512;
+
+/// How long to wait for the server before sending the last segment again,
+/// and how many times it is sent.
+const RETRANSMIT: Duration = Duration::from_secs(1);
+const ATTEMPTS: usize = 5;
+
+/// Returns whether sequence number `a` comes before `b`.
+fn before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// A TCP segment.
+#[derive(Debug, PartialEq)]
+struct Segment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    data: &'a [u8],
+}
+
+/// Parses the TCP segment `segment` of the packet `packet`.
+fn parse_segment<'a>(packet: &Packet, segment: &'a [u8]) -> Option<Segment<'a>> {
+    if packet.protocol != PROTOCOL_TCP || segment.len() < HEADER {
+        return None;
+    }
+    let pseudo = eth::pseudo_header_sum(packet.src, packet.dst, PROTOCOL_TCP, segment.len());
+    if eth::checksum(pseudo, segment) != 0 {
+        return None;
+    }
+    let offset = (segment[12] >> 4) as usize * 4;
+    if offset < HEADER || offset > segment.len() {
+        return None;
+    }
+    let word = |i: usize| u32::from_be_bytes([segment[i], segment[i + 1], segment[i + 2], segment[i + 3]]);
+    Some(Segment {
+        src_port: u16::from_be_bytes([segment[0], segment[1]]),
+        dst_port: u16::from_be_bytes([segment[2], segment[3]]),
+        seq: word(4),
+        ack: word(8),
+        flags: segment[13],
+        data: &segment[offset..],
+    })
+}
+
+/// Writes `segment` from `src` to `dst` to `buf`, with the maximum segment
+/// size option on a SYN. Returns its length.
+fn write_segment(buf: &mut [u8], src: Ipv4, dst: Ipv4, segment: &Segment) -> usize {
+    let options: &[u8] = if segment.flags & SYN != 0 {
+        &[2, 4, (MSS >> 8) as u8, MSS as u8]
+    } else {
+        &[]
+    };
+    let offset = HEADER + options.len();
+    let len = offset + segment.data.len();
+    buf[0..2].copy_from_slice(&segment.src_port.to_be_bytes());
+    buf[2..4].copy_from_slice(&segment.dst_port.to_be_bytes());
+    buf[4..8].copy_from_slice(&segment.seq.to_be_bytes());
+    buf[8..12].copy_from_slice(&segment.ack.to_be_bytes());
+    buf[12] = ((offset / 4) as u8) << 4;
+    buf[13] = segment.flags;
+    buf[14..16].copy_from_slice(&WINDOW.to_be_bytes());
+    buf[16..20].copy_from_slice(&[0, 0, 0, 0]);
+    buf[HEADER..offset].copy_from_slice(options);
+    buf[offset..len].copy_from_slice(segment.data);
+    let sum = eth::checksum(eth::pseudo_header_sum(src, dst, PROTOCOL_TCP, len), &buf[..len]);
+    buf[16..18].copy_from_slice(&sum.to_be_bytes());
+    len
+}
+
+/// A connection to a server.
+pub struct Connection<'a, D: Device> {
+    iface: &'a mut Interface<D>,
+    remote: Ipv4,
+    local_port: u16,
+    remote_port: u16,
+    /// The first byte sent and not acknowledged, and the next one to send.
+    snd_una: u32,
+    snd_nxt: u32,
+    /// The next byte expected from the server.
+    rcv_nxt: u32,
+    /// The request, kept until it is acknowledged.
+    request: [u8; REQUEST_MAX],
+    request_len: usize,
+    /// Whether the server has closed its side.
+    finished: bool,
+}
+
+impl<'a, D: Device> Connection<'a, D> {
+    /// Connects to `remote`:`port`.
+    pub fn connect(iface: &'a mut Interface<D>, remote: Ipv4, port: u16) -> Result<Self, Error> {
+        let iss = current_time().as_micros() as u32;
+        let mut connection = Connection {
+            iface,
+            remote,
+            local_port: ephemeral_port(),
+            remote_port: port,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            rcv_nxt: 0,
+            request: [0; REQUEST_MAX],
+            request_len: 0,
+            finished: false,
+        };
+
+        let mut buf = [0; MTU];
+        for _ in 0..ATTEMPTS {
+            connection.send_segment(iss, SYN, &[])?;
+            let deadline = current_time() + RETRANSMIT;
+            while current_time() < deadline {
+                let segment = match connection.recv_segment(&mut buf) {
+                    Some(segment) => segment,
+                    None => continue,
+                };
+                if segment.flags & RST != 0 {
+                    return Err(Error::Refused);
+                }
+                if segment.flags & (SYN | ACK) == SYN | ACK && segment.ack == iss.wrapping_add(1) {
+                    connection.snd_una = segment.ack;
+                    connection.rcv_nxt = segment.seq.wrapping_add(1);
+                    connection.send_ack()?;
+                    return Ok(connection);
+                }
+            }
+        }
+        Err(Error::TimedOut)
+    }
+
+    fn send_segment(&mut self, seq: u32, flags: u8, data: &[u8]) -> Result<(), Error> {
+        let ack = if flags & SYN != 0 { 0 } else { self.rcv_nxt };
+        let flags = if flags & SYN != 0 { flags } else { flags | ACK };
+        let segment = Segment { src_port: self.local_port, dst_port: self.remote_port, seq, ack, flags, data };
+        let mut buf = [0; MTU - 20];
+        let len = write_segment(&mut buf, self.iface.address, self.remote, &segment);
+        self.iface.send(self.remote, PROTOCOL_TCP, &buf[..len])
+    }
+
+    fn send_ack(&mut self) -> Result<(), Error> {
+        self.send_segment(self.snd_nxt, ACK, &[])
+    }
+
+    /// Receives a segment of the connection into `buf` without waiting.
+    fn recv_segment<'b>(&mut self, buf: &'b mut [u8; MTU]) -> Option<Segment<'b>> {
+        let packet = self.iface.recv(buf)?;
+        let buf: &'b [u8; MTU] = buf;
+        let segment = parse_segment(&packet, &buf[..packet.len])?;
+        let ours = packet.src == self.remote
+            && segment.src_port == self.remote_port
+            && segment.dst_port == self.local_port;
+        if ours {
+            Some(segment)
+        } else {
+            None
+        }
+    }
+
+    /// Sends `data`, which is retransmitted by `recv` until the server
+    /// acknowledges it.
+    pub fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.len() > REQUEST_MAX || self.request_len != 0 {
+            return Err(Error::TooLarge);
+        }
+        self.request[..data.len()].copy_from_slice(data);
+        self.request_len = data.len();
+        let seq = self.snd_nxt;
+        self.snd_nxt = self.snd_nxt.wrapping_add(data.len() as u32);
+        self.send_segment(seq, PSH, data)
+    }
+
+    /// Receives data into `buf`, which must not be empty. Returns how much,
+    /// or 0 once the server has closed the connection.
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut segment_buf = [0; MTU];
+        let mut attempts = 0;
+        let mut deadline = current_time() + RETRANSMIT;
+        while !self.finished {
+            if current_time() > deadline {
+                attempts += 1;
+                if attempts == ATTEMPTS {
+                    return Err(Error::TimedOut);
+                }
+                if self.snd_una != self.snd_nxt {
+                    let request = self.request;
+                    self.send_segment(self.snd_una, PSH, &request[..self.request_len])?;
+                } else {
+                    self.send_ack()?;
+                }
+                deadline = current_time() + RETRANSMIT;
+            }
+
+            let segment = match self.recv_segment(&mut segment_buf) {
+                Some(segment) => segment,
+                None => continue,
+            };
+            if segment.flags & RST != 0 {
+                return Err(Error::Refused);
+            }
+            let acked = segment.flags & ACK != 0
+                && before(self.snd_una, segment.ack)
+                && !before(self.snd_nxt, segment.ack);
+            if acked {
+                self.snd_una = segment.ack;
+            }
+            if segment.data.is_empty() && segment.flags & FIN == 0 {
+                continue;
+            }
+            if segment.seq != self.rcv_nxt {
+                self.send_ack()?;
+                continue;
+            }
+
+            let len = core::cmp::min(segment.data.len(), buf.len());
+            buf[..len].copy_from_slice(&segment.data[..len]);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+            if segment.flags & FIN != 0 && len == segment.data.len() {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                self.finished = true;
+            }
+            self.send_ack()?;
+            if len > 0 {
+                return Ok(len);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Closes the connection: with a FIN once the server has closed its
+    /// side, with a reset otherwise. Nothing more is waited for.
+    pub fn close(mut self) {
+        let flags = if self.finished { FIN } else { RST };
+        self.send_segment(self.snd_nxt, flags, &[]).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_numbers() {
+        assert!(before(1, 2));
+        assert!(!before(2, 2));
+        assert!(before(0xffff_fff0, 0x10));
+        assert!(!before(0x10, 0xffff_fff0));
+    }
+
+    #[test]
+    fn segments() {
+        let (src, dst) = ([10, 0, 0, 2], [10, 0, 0, 1]);
+        let syn = Segment { src_port: 50000, dst_port: 80, seq: 7, ack: 0, flags: SYN, data: &[] };
+        let mut buf = [0; MTU];
+        let len = write_segment(&mut buf, src, dst, &syn);
+        assert_eq!(len, HEADER + 4);
+        assert_eq!(&buf[20..24], &[2, 4, 0x05, 0xb4]);
+
+        let packet = Packet { src, dst, protocol: PROTOCOL_TCP, len };
+        assert_eq!(parse_segment(&packet, &buf[..len]), Some(syn));
+
+        let data = Segment { src_port: 80, dst_port: 50000, seq: 1, ack: 8, flags: ACK | PSH, data: b"HTTP" };
+        let len = write_segment(&mut buf, dst, src, &data);
+        let packet = Packet { src: dst, dst: src, protocol: PROTOCOL_TCP, len };
+        assert_eq!(parse_segment(&packet, &buf[..len]), Some(data));
+
+        // Checked against the wrong addresses.
+        assert_eq!(parse_segment(&Packet { src, dst, ..packet }, &buf[..len]), None);
+    }
+}
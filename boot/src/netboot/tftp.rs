@@ -0,0 +1,239 @@
+//! A TFTP (RFC 1350) client that reads one file, asking for large blocks
+//! (RFC 2348) and for the size of the file (RFC 2349).
+
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+use super::eth::{self, Device, Interface, Ipv4, MTU};
+use super::{ephemeral_port, Error, Writer};
+
+const OP_RRQ: u16 = 1;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+/// TFTP error code for a file that does not fit.
+const DISK_FULL: u16 = 3;
+
+/// The block size asked for: the largest that fits in an unfragmented
+/// packet.
+const BLOCK_SIZE: usize = MTU - 20 - 8 - 4;
+/// The block size of servers that ignore the option.
+const DEFAULT_BLOCK_SIZE: usize = 512;
+
+/// How long to wait for the server before sending the last packet again, and
+/// how many times it is sent.
+const RETRANSMIT: Duration = Duration::from_secs(1);
+const ATTEMPTS: usize = 5;
+
+/// A packet sent by the server.
+#[derive(Debug, PartialEq)]
+enum Reply<'a> {
+    Data { block: u16, data: &'a [u8] },
+    Error { code: u16 },
+    Oack { block_size: Option<usize>, size: Option<usize> },
+}
+
+/// Parses the packet `packet` sent by the server.
+fn parse_reply(packet: &[u8]) -> Option<Reply> {
+    if packet.len() < 4 {
+        return None;
+    }
+    let number = u16::from_be_bytes([packet[2], packet[3]]);
+    match u16::from_be_bytes([packet[0], packet[1]]) {
+        OP_DATA => Some(Reply::Data { block: number, data: &packet[4..] }),
+        OP_ERROR => Some(Reply::Error { code: number }),
+        OP_OACK => {
+            let mut block_size = None;
+            let mut size = None;
+            let mut fields = packet[2..].split(|&byte| byte == 0);
+            while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+                let value = core::str::from_utf8(value).ok()?.parse().ok()?;
+                if name.eq_ignore_ascii_case(b"blksize") {
+                    block_size = Some(value);
+                } else if name.eq_ignore_ascii_case(b"tsize") {
+                    size = Some(value);
+                }
+            }
+            Some(Reply::Oack { block_size, size })
+        }
+        _ => None,
+    }
+}
+
+/// Writes a read request for `path` in octet mode to `buf`. Returns its
+/// length.
+fn read_request(buf: &mut [u8], path: &str) -> Result<usize, Error> {
+    use core::fmt::Write;
+
+    let mut writer = Writer::new(buf);
+    writer.put(&OP_RRQ.to_be_bytes());
+    write!(writer, "{}\0octet\0blksize\0{}\0tsize\00\0", path, BLOCK_SIZE).map_err(|_| Error::TooLarge)?;
+    writer.finish()
+}
+
+/// The state of a transfer.
+struct Transfer<'a, D: Device> {
+    iface: &'a mut Interface<D>,
+    server: Ipv4,
+    local_port: u16,
+    /// The port the server sends from, once it has answered.
+    remote_port: Option<u16>,
+}
+
+impl<'a, D: Device> Transfer<'a, D> {
+    fn send(&mut self, packet: &[u8], port: u16) -> Result<(), Error> {
+        self.iface.send_udp(self.server, self.local_port, port, packet)
+    }
+
+    fn ack(&mut self, block: u16) -> Result<(), Error> {
+        let mut ack = [0; 4];
+        ack[..2].copy_from_slice(&OP_ACK.to_be_bytes());
+        ack[2..].copy_from_slice(&block.to_be_bytes());
+        let port = self.remote_port.ok_or(Error::BadResponse)?;
+        self.send(&ack, port)
+    }
+
+    /// Tells the server the file does not fit and gives up.
+    fn abort(&mut self) -> Error {
+        let mut error = [0; 5];
+        error[..2].copy_from_slice(&OP_ERROR.to_be_bytes());
+        error[2..4].copy_from_slice(&DISK_FULL.to_be_bytes());
+        if let Some(port) = self.remote_port {
+            self.send(&error, port).ok();
+        }
+        Error::TooLarge
+    }
+}
+
+/// Reads the file `path` from the TFTP server at `server`:`port` into
+/// `memory`. Returns its size.
+pub fn fetch<D: Device>(
+    iface: &mut Interface<D>,
+    server: Ipv4,
+    port: u16,
+    path: &str,
+    memory: &mut [u8],
+) -> Result<usize, Error> {
+    let mut request = [0; 512];
+    let request_len = read_request(&mut request, path)?;
+    let mut transfer = Transfer { iface, server, local_port: ephemeral_port(), remote_port: None };
+
+    let mut buf = [0; MTU];
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut expected: u16 = 1;
+    let mut received = 0;
+    let mut attempts = 1;
+    transfer.send(&request[..request_len], port)?;
+    let mut deadline = current_time() + RETRANSMIT;
+    loop {
+        if current_time() > deadline {
+            if attempts == ATTEMPTS {
+                return Err(Error::TimedOut);
+            }
+            attempts += 1;
+            match transfer.remote_port {
+                None => transfer.send(&request[..request_len], port)?,
+                Some(_) => transfer.ack(expected.wrapping_sub(1))?,
+            }
+            deadline = current_time() + RETRANSMIT;
+        }
+
+        let packet = match transfer.iface.recv(&mut buf) {
+            Some(packet) => packet,
+            None => continue,
+        };
+        let (src_port, dst_port, payload) = match eth::parse_udp(&packet, &buf[..packet.len]) {
+            Some(datagram) => datagram,
+            None => continue,
+        };
+        let from_server = transfer.remote_port.map_or(true, |remote| remote == src_port);
+        if packet.src != server || dst_port != transfer.local_port || !from_server {
+            continue;
+        }
+
+        match parse_reply(payload) {
+            Some(Reply::Oack { block_size: size, size: file_size }) if transfer.remote_port.is_none() => {
+                transfer.remote_port = Some(src_port);
+                block_size = size.unwrap_or(DEFAULT_BLOCK_SIZE);
+                if file_size.map_or(false, |file_size| file_size > memory.len()) {
+                    return Err(transfer.abort());
+                }
+                transfer.ack(0)?;
+            }
+            Some(Reply::Data { block, data }) => {
+                transfer.remote_port = Some(src_port);
+                if block != expected {
+                    // A duplicate of the last block: our ack was lost.
+                    if block == expected.wrapping_sub(1) {
+                        transfer.ack(block)?;
+                    }
+                    continue;
+                }
+                if data.len() > block_size {
+                    return Err(Error::BadResponse);
+                }
+                let end = received + data.len();
+                if end > memory.len() {
+                    return Err(transfer.abort());
+                }
+                memory[received..end].copy_from_slice(data);
+                received = end;
+                transfer.ack(block)?;
+                if data.len() < block_size {
+                    return Ok(received);
+                }
+                // Servers wrap the block number around to 0 for large files.
+                expected = expected.wrapping_add(1);
+            }
+            Some(Reply::Error { code }) => return Err(Error::Server(code)),
+            _ => continue,
+        }
+        attempts = 1;
+        deadline = current_time() + RETRANSMIT;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request() {
+        let mut buf = [0; 512];
+        let len = read_request(&mut buf, "kernel.bin").unwrap();
+        assert_eq!(&buf[..len], &b"\x00\x01kernel.bin\0octet\0blksize\x001468\0tsize\x000\0"[..]);
+
+        let mut small = [0; 16];
+        assert_eq!(read_request(&mut small, "kernel.bin"), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn replies() {
+        assert_eq!(
+            parse_reply(b"\x00\x03\x00\x07abc"),
+            Some(Reply::Data { block: 7, data: b"abc" })
+        );
+        assert_eq!(
+            parse_reply(b"\x00\x03\xff\xff"),
+            Some(Reply::Data { block: 65535, data: b"" })
+        );
+        assert_eq!(
+            parse_reply(b"\x00\x05\x00\x01File not found\0"),
+            Some(Reply::Error { code: 1 })
+        );
+        assert_eq!(
+            parse_reply(b"\x00\x06BLKSIZE\x001468\0tsize\x004194304\0"),
+            Some(Reply::Oack { block_size: Some(1468), size: Some(4194304) })
+        );
+        assert_eq!(
+            parse_reply(b"\x00\x06blksize\x00512\0"),
+            Some(Reply::Oack { block_size: Some(512), size: None })
+        );
+        assert_eq!(parse_reply(b"\x00\x06tsize\0big\0"), None);
+        assert_eq!(parse_reply(b"\x00\x04\x00\x01"), None);
+        assert_eq!(parse_reply(b"\x00\x03"), None);
+    }
+}
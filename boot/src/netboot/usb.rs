@@ -0,0 +1,213 @@
+//! The USB ethernet, driven by USPi, and the environment USPi expects from
+//! its host: memory, delays, interrupts and logging. This mirrors the
+//! kernel's `net::uspi` without its allocator and scheduler.
+
+#![allow(non_snake_case)]
+
+use core::ffi::c_void;
+use core::ptr;
+use core::time::Duration;
+
+use pi::common::IO_BASE;
+use pi::interrupt::Interrupt;
+use pi::timer::{current_time, spin_sleep};
+
+use super::eth::{Device, Mac, FRAME_SIZE};
+use super::irq;
+
+type TInterruptHandler = Option<unsafe extern "C" fn(pParam: *mut c_void)>;
+
+extern "C" {
+    /// Returns 0 on failure
+    fn USPiInitialize() -> i32;
+    /// Returns != 0 if available
+    fn USPiEthernetAvailable() -> i32;
+    fn USPiGetMACAddress(Buffer: &mut [u8; 6]);
+    /// Returns != 0 if link is up
+    fn USPiEthernetIsLinkUp() -> i32;
+    /// Returns 0 on failure
+    fn USPiSendFrame(pBuffer: *const u8, nLength: u32) -> i32;
+    /// pBuffer must have size USPI_FRAME_BUFFER_SIZE
+    /// Returns 0 if no frame is available or on failure
+    fn USPiReceiveFrame(pBuffer: *mut u8, pResultLength: *mut u32) -> i32;
+}
+
+/// The core reset register of the USB host controller (DWHCI `GRSTCTL`).
+const USB_CORE_RESET: *mut u32 = (IO_BASE + 0x98_0010) as *mut u32;
+const CORE_SOFT_RESET: u32 = 1 << 0;
+
+/// The USB ethernet adapter.
+pub struct Usb(());
+
+impl Usb {
+    /// Initializes USPi. Returns `None` if it fails, e.g. when no USB device
+    /// answers. Interrupts must be enabled with `irq::enable()`.
+    pub fn initialize() -> Option<Usb> {
+        match unsafe { USPiInitialize() } {
+            0 => None,
+            _ => Some(Usb(())),
+        }
+    }
+
+    pub fn is_eth_available(&self) -> bool {
+        unsafe { USPiEthernetAvailable() != 0 }
+    }
+
+    pub fn mac_address(&self) -> Mac {
+        let mut mac = [0; 6];
+        unsafe { USPiGetMACAddress(&mut mac) };
+        mac
+    }
+
+    /// Waits up to `timeout` for the link to come up.
+    pub fn wait_for_link(&self, timeout: Duration) -> bool {
+        let deadline = current_time() + timeout;
+        while current_time() < deadline {
+            if unsafe { USPiEthernetIsLinkUp() != 0 } {
+                return true;
+            }
+            spin_sleep(Duration::from_millis(100));
+        }
+        false
+    }
+}
+
+impl Device for Usb {
+    fn send(&mut self, frame: &[u8]) -> bool {
+        unsafe { USPiSendFrame(frame.as_ptr(), frame.len() as u32) != 0 }
+    }
+
+    fn recv(&mut self, buf: &mut [u8; FRAME_SIZE]) -> Option<usize> {
+        let mut len = 0;
+        match unsafe { USPiReceiveFrame(buf.as_mut_ptr(), &mut len) } {
+            0 => None,
+            _ => Some(len as usize),
+        }
+    }
+}
+
+/// Resets the USB host controller so that it stops its DMA transfers into
+/// the bootloader's memory before the kernel is started. The kernel's USPi
+/// initializes it again.
+pub fn reset() {
+    unsafe {
+        ptr::write_volatile(USB_CORE_RESET, CORE_SOFT_RESET);
+        let deadline = current_time() + Duration::from_millis(10);
+        while ptr::read_volatile(USB_CORE_RESET) & CORE_SOFT_RESET != 0 && current_time() < deadline {}
+    }
+}
+
+/// Memory for USPi's `malloc`, in blocks of power-of-two sizes that are
+/// recycled through one free list per size. Each block starts with a header
+/// holding its size class and, while it is free, the next free block.
+mod heap {
+    const HEAP_SIZE: usize = 4 << 20;
+    const HEADER: usize = 16;
+    const MIN_CLASS: usize = 5;
+    const CLASSES: usize = 23;
+
+    #[repr(align(16))]
+    struct Heap([u8; HEAP_SIZE]);
+
+    static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+    static mut NEXT: usize = 0;
+    static mut FREE: [usize; CLASSES] = [0; CLASSES];
+
+    /// Runs `f` with interrupts masked: USPi allocates from its handlers too.
+    fn critical<R, F: FnOnce() -> R>(f: F) -> R {
+        let mask = aarch64::get_interrupt_mask();
+        aarch64::disable_irq_interrupt();
+        aarch64::disable_fiq_interrupt();
+        let result = f();
+        aarch64::set_interrupt_mask(mask);
+        result
+    }
+
+    pub fn alloc(size: usize) -> *mut u8 {
+        let class = core::cmp::max(MIN_CLASS, (size + HEADER).next_power_of_two().trailing_zeros() as usize);
+        if class >= CLASSES {
+            return core::ptr::null_mut();
+        }
+        critical(|| unsafe {
+            let block = match FREE[class] {
+                0 => {
+                    if NEXT + (1 << class) > HEAP_SIZE {
+                        return core::ptr::null_mut();
+                    }
+                    let block = HEAP.0.as_mut_ptr().add(NEXT) as *mut usize;
+                    NEXT += 1 << class;
+                    block
+                }
+                free => {
+                    let block = free as *mut usize;
+                    FREE[class] = *block.add(1);
+                    block
+                }
+            };
+            *block = class;
+            (block as *mut u8).add(HEADER)
+        })
+    }
+
+    pub fn free(ptr: *mut u8) {
+        critical(|| unsafe {
+            let block = ptr.sub(HEADER) as *mut usize;
+            let class = *block;
+            *block.add(1) = FREE[class];
+            FREE[class] = block as usize;
+        })
+    }
+}
+
+#[no_mangle]
+fn malloc(size: u32) -> *mut c_void {
+    heap::alloc(size as usize) as *mut c_void
+}
+
+#[no_mangle]
+fn free(ptr: *mut c_void) {
+    if !ptr.is_null() {
+        heap::free(ptr as *mut u8);
+    }
+}
+
+#[no_mangle]
+pub fn TimerSimpleMsDelay(nMilliSeconds: u32) {
+    spin_sleep(Duration::from_millis(nMilliSeconds as u64));
+}
+
+#[no_mangle]
+pub fn TimerSimpleusDelay(nMicroSeconds: u32) {
+    spin_sleep(Duration::from_micros(nMicroSeconds as u64));
+}
+
+#[no_mangle]
+pub fn MsDelay(nMilliSeconds: u32) {
+    spin_sleep(Duration::from_millis(nMilliSeconds as u64));
+}
+
+#[no_mangle]
+pub fn usDelay(nMicroSeconds: u32) {
+    spin_sleep(Duration::from_micros(nMicroSeconds as u64));
+}
+
+/// Connects `pHandler` to `nIRQ`: the USB interrupt as the FIQ, the others
+/// as IRQs.
+#[no_mangle]
+pub unsafe fn ConnectInterrupt(nIRQ: u32, pHandler: TInterruptHandler, pParam: *mut c_void) {
+    let handler = pHandler.expect("USPi registered a null interrupt handler");
+    irq::connect(Interrupt::from(nIRQ as usize), handler, pParam);
+}
+
+/// The bootloader has nowhere to log to: the serial line is kept quiet for
+/// XMODEM.
+#[no_mangle]
+pub unsafe fn DoLogWrite(_pSource: *const u8, _Severity: u32, _pMessage: *const u8) {}
+
+#[no_mangle]
+pub fn DebugHexdump(_pBuffer: *const c_void, _nBufLen: u32, _pSource: *const u8) {}
+
+#[no_mangle]
+pub unsafe fn uspi_assertion_failed(_pExpr: *const u8, _pFile: *const u8, _nLine: u32) {
+    panic!("USPi assertion failed");
+}
@@ -0,0 +1,97 @@
+// Saves the registers a Rust function may clobber, calls
+// `handle_interrupt(kind)` and returns from the exception.
+.global interrupt
+interrupt:
+    stp     x0, x1, [SP, #-16]!
+    stp     x2, x3, [SP, #-16]!
+    stp     x4, x5, [SP, #-16]!
+    stp     x6, x7, [SP, #-16]!
+    stp     x8, x9, [SP, #-16]!
+    stp     x10, x11, [SP, #-16]!
+    stp     x12, x13, [SP, #-16]!
+    stp     x14, x15, [SP, #-16]!
+    stp     x16, x17, [SP, #-16]!
+    stp     x18, xzr, [SP, #-16]!
+
+    stp     q0, q1, [SP, #-32]!
+    stp     q2, q3, [SP, #-32]!
+    stp     q4, q5, [SP, #-32]!
+    stp     q6, q7, [SP, #-32]!
+    stp     q16, q17, [SP, #-32]!
+    stp     q18, q19, [SP, #-32]!
+    stp     q20, q21, [SP, #-32]!
+    stp     q22, q23, [SP, #-32]!
+    stp     q24, q25, [SP, #-32]!
+    stp     q26, q27, [SP, #-32]!
+    stp     q28, q29, [SP, #-32]!
+    stp     q30, q31, [SP, #-32]!
+
+    mov     x0, x29
+    bl      handle_interrupt
+
+    ldp     q30, q31, [SP], #32
+    ldp     q28, q29, [SP], #32
+    ldp     q26, q27, [SP], #32
+    ldp     q24, q25, [SP], #32
+    ldp     q22, q23, [SP], #32
+    ldp     q20, q21, [SP], #32
+    ldp     q18, q19, [SP], #32
+    ldp     q16, q17, [SP], #32
+    ldp     q6, q7, [SP], #32
+    ldp     q4, q5, [SP], #32
+    ldp     q2, q3, [SP], #32
+    ldp     q0, q1, [SP], #32
+
+    ldp     x18, xzr, [SP], #16
+    ldp     x16, x17, [SP], #16
+    ldp     x14, x15, [SP], #16
+    ldp     x12, x13, [SP], #16
+    ldp     x10, x11, [SP], #16
+    ldp     x8, x9, [SP], #16
+    ldp     x6, x7, [SP], #16
+    ldp     x4, x5, [SP], #16
+    ldp     x2, x3, [SP], #16
+    ldp     x0, x1, [SP], #16
+
+    ldp     x29, lr, [SP], #16
+    eret
+
+// An IRQ (kind 0) or FIQ (kind 1) taken at the current EL.
+.macro HANDLER kind
+    .align 7
+    stp     x29, lr, [SP, #-16]!
+    mov     x29, \kind
+    b       interrupt
+.endm
+
+// An exception the bootloader does not expect: halt.
+.macro HALT
+    .align 7
+1:
+    wfe
+    b       1b
+.endm
+
+.align 11
+.global netboot_vectors
+netboot_vectors:
+    // current EL with SP0
+    HALT
+    HALT
+    HALT
+    HALT
+    // current EL with SPx: synchronous, IRQ, FIQ, SError
+    HALT
+    HANDLER 0
+    HANDLER 1
+    HALT
+    // lower EL, AArch64
+    HALT
+    HALT
+    HALT
+    HALT
+    // lower EL, AArch32
+    HALT
+    HALT
+    HALT
+    HALT
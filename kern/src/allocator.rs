@@ -2,9 +2,22 @@ mod linked_list;
 mod util;
 
 mod bin;
+mod buddy;
 mod bump;
+mod debug;
+mod slab;
 
+pub use self::debug::{AllocStats, Corruption, LiveAlloc};
+pub use self::slab::{caches as slab_caches, Slab, SlabBox, SlabCache, SlabStats};
+
+#[cfg(not(feature = "buddy-allocator"))]
 type AllocatorImpl = bin::Allocator;
+#[cfg(feature = "buddy-allocator")]
+type AllocatorImpl = buddy::Allocator;
+
+/// The heap: the configured allocator, instrumented according to the
+/// `alloc-stats` and `alloc-debug` features.
+type HeapImpl = debug::Tracked<AllocatorImpl>;
 
 #[cfg(test)]
 mod tests;
@@ -24,7 +37,7 @@ pub trait LocalAlloc {
 }
 
 /// Thread-safe (locking) wrapper around a particular memory allocator.
-pub struct Allocator(Mutex<Option<AllocatorImpl>>);
+pub struct Allocator(Mutex<Option<HeapImpl>>);
 
 impl Allocator {
     /// Returns an uninitialized `Allocator`.
@@ -45,7 +58,55 @@ impl Allocator {
     pub unsafe fn initialize(&self) {
         let (start, end) = memory_map().expect("failed to find memory map");
         info!("heap beg: {:x}, end: {:x}", start, end);
-        *self.0.lock() = Some(AllocatorImpl::new(start, end));
+        *self.0.lock() = Some(HeapImpl::new(
+            AllocatorImpl::new(start, end),
+            cfg!(feature = "alloc-stats"),
+            cfg!(feature = "alloc-debug"),
+        ));
+    }
+
+    /// Returns a snapshot of the allocation statistics, or `None` if the
+    /// kernel was built without the `alloc-stats` feature.
+    pub fn stats(&self) -> Option<AllocStats> {
+        self.0.lock().as_ref().and_then(|heap| heap.stats().cloned())
+    }
+
+    /// Returns `true` if allocations are surrounded by red zones and tracked
+    /// for leaks (the `alloc-debug` feature).
+    pub fn is_debug(&self) -> bool {
+        self.0.lock().as_ref().map(|heap| heap.is_debug()).unwrap_or(false)
+    }
+
+    /// Starts a new leak-tracking window and returns its first sequence number.
+    pub fn set_mark(&self) -> usize {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("allocator uninitialized")
+            .set_mark()
+    }
+
+    /// Copies the allocations made since the last `set_mark()` that are still
+    /// live into `buf`, and returns how many there are in total.
+    pub fn live(&self, buf: &mut [LiveAlloc]) -> usize {
+        self.0
+            .lock()
+            .as_ref()
+            .expect("allocator uninitialized")
+            .live(buf)
+    }
+
+    /// Verifies the red zones of every live allocation.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first corruption found.
+    pub fn check(&self) -> Result<usize, Corruption> {
+        self.0
+            .lock()
+            .as_ref()
+            .expect("allocator uninitialized")
+            .check()
     }
 }
 
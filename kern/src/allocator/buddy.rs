@@ -0,0 +1,206 @@
+use core::alloc::Layout;
+use core::cmp::max;
+use core::fmt;
+
+use crate::allocator::linked_list::LinkedList;
+use crate::allocator::util::*;
+use crate::allocator::LocalAlloc;
+
+/// log2 of the smallest block size. A free block must be able to hold the
+/// `usize` link of the intrusive free list.
+const MIN_ORDER: usize = 3;
+/// Number of block sizes: 2^3 (8 bytes) up to 2^32 (4GiB).
+const NUM_ORDERS: usize = 30;
+
+/// Returns the free-list index for a block of `size` bytes, where `size` is
+/// a power of two not smaller than `1 << MIN_ORDER`.
+fn order_of(size: usize) -> usize {
+    size.trailing_zeros() as usize - MIN_ORDER
+}
+
+/// Returns the size in bytes of blocks in free list `order`.
+fn block_size(order: usize) -> usize {
+    1 << (order + MIN_ORDER)
+}
+
+/// A binary buddy allocator.
+///
+/// Every block is a power of two in size and naturally aligned, i.e. a block
+/// of `2^k` bytes starts at a multiple of `2^k`. The buddy of the block at
+/// `addr` is therefore always at `addr ^ 2^k`. Allocation pops the smallest
+/// large-enough free block and splits it in halves until it fits, pushing the
+/// unused halves onto their free lists. Deallocation merges a block with its
+/// buddy for as long as the buddy is free, so freed memory coalesces back into
+/// large blocks instead of staying fragmented in small size classes.
+///
+///   order 0 (2^3 bytes)  : free blocks of 8 bytes
+///   order 1 (2^4 bytes)  : free blocks of 16 bytes
+///   ...
+///   order 29 (2^32 bytes): free blocks of 4GiB
+pub struct Allocator {
+    free: [LinkedList; NUM_ORDERS],
+    start: usize,
+    end: usize,
+    allocated: usize,
+}
+
+impl Allocator {
+    /// Creates a new buddy allocator that will allocate memory from the region
+    /// starting at address `start` and ending at address `end`.
+    ///
+    /// The region is carved into the largest naturally aligned blocks that
+    /// fit, so no memory is wasted when `start` or `end` are unaligned.
+    pub fn new(start: usize, end: usize) -> Allocator {
+        let mut allocator = Allocator {
+            free: [LinkedList::new(); NUM_ORDERS],
+            start,
+            end,
+            allocated: 0,
+        };
+
+        let mut addr = align_up(start, block_size(0));
+        while addr < end && end - addr >= block_size(0) {
+            let mut order = NUM_ORDERS - 1;
+            while addr % block_size(order) != 0 || end - addr < block_size(order) {
+                order -= 1;
+            }
+            unsafe { allocator.free[order].push(addr as *mut usize) };
+            addr += block_size(order);
+        }
+
+        allocator
+    }
+
+    /// Returns the free-list index serving `layout`, or `None` if `layout` is
+    /// too large for any block.
+    fn order_for(layout: &Layout) -> Option<usize> {
+        let size = max(max(layout.size(), layout.align()), block_size(0));
+        let size = size.checked_next_power_of_two()?;
+        let order = order_of(size);
+        if order < NUM_ORDERS {
+            Some(order)
+        } else {
+            None
+        }
+    }
+
+    /// Removes the block at `addr` from free list `order`. Returns `true` if
+    /// the block was free.
+    fn take_free(&mut self, order: usize, addr: usize) -> bool {
+        for node in self.free[order].iter_mut() {
+            if node.value() as usize == addr {
+                node.pop();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns the number of bytes currently handed out by this allocator.
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Returns the number of free blocks in each free list.
+    pub fn free_counts(&self) -> [usize; NUM_ORDERS] {
+        let mut counts = [0; NUM_ORDERS];
+        for (order, list) in self.free.iter().enumerate() {
+            counts[order] = list.iter().count();
+        }
+        counts
+    }
+}
+
+impl LocalAlloc for Allocator {
+    /// Allocates memory. Returns a pointer meeting the size and alignment
+    /// properties of `layout.size()` and `layout.align()`.
+    ///
+    /// If this method returns an `Ok(addr)`, `addr` will be non-null address
+    /// pointing to a block of storage suitable for holding an instance of
+    /// `layout`. In particular, the block will be at least `layout.size()`
+    /// bytes large and will be aligned to `layout.align()`. The returned block
+    /// of storage may or may not have its contents initialized or zeroed.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure that `layout.size() > 0` and that
+    /// `layout.align()` is a power of two. Parameters not meeting these
+    /// conditions may result in undefined behavior.
+    ///
+    /// # Errors
+    ///
+    /// Returning null pointer (`core::ptr::null_mut`)
+    /// indicates that either memory is exhausted
+    /// or `layout` does not meet this allocator's
+    /// size or alignment constraints.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 || !layout.align().is_power_of_two() {
+            return core::ptr::null_mut();
+        }
+        let order = match Allocator::order_for(&layout) {
+            Some(order) => order,
+            None => return core::ptr::null_mut(),
+        };
+
+        let mut current = match (order..NUM_ORDERS).find(|&i| !self.free[i].is_empty()) {
+            Some(i) => i,
+            None => return core::ptr::null_mut(),
+        };
+        let block = self.free[current].pop().unwrap() as usize;
+
+        // split the block, giving back the upper half each time
+        while current > order {
+            current -= 1;
+            self.free[current].push((block + block_size(current)) as *mut usize);
+        }
+
+        self.allocated += block_size(order);
+        block as *mut u8
+    }
+
+    /// Deallocates the memory referenced by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure the following:
+    ///
+    ///   * `ptr` must denote a block of memory currently allocated via this
+    ///     allocator
+    ///   * `layout` must properly represent the original layout used in the
+    ///     allocation call that returned `ptr`
+    ///
+    /// Parameters not meeting these conditions may result in undefined
+    /// behavior.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let mut order = match Allocator::order_for(&layout) {
+            Some(order) => order,
+            None => return,
+        };
+        self.allocated -= block_size(order);
+
+        // merge with the buddy while it is free
+        let mut addr = ptr as usize;
+        while order < NUM_ORDERS - 1 {
+            let buddy = addr ^ block_size(order);
+            if !self.take_free(order, buddy) {
+                break;
+            }
+            addr = addr & buddy;
+            order += 1;
+        }
+        self.free[order].push(addr as *mut usize);
+    }
+}
+
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Buddy Allocator: start: {:x}, end: {:x}, allocated: {}, free blocks per order: {:?}",
+            self.start,
+            self.end,
+            self.allocated,
+            &self.free_counts()[..]
+        )
+    }
+}
@@ -0,0 +1,364 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::mem::size_of;
+use core::ptr;
+
+use crate::allocator::LocalAlloc;
+use crate::param::PAGE_SIZE;
+
+/// Number of size classes tracked by `AllocStats`, matching the bin allocator.
+pub const NUM_BINS: usize = 30;
+
+/// Bytes of canary placed after every allocation in debug mode. At least as
+/// many canary bytes are placed in front of the allocation header.
+const REDZONE: usize = 16;
+/// Minimum distance between the start of a block and the pointer handed out.
+const MIN_FRONT: usize = 64;
+
+const CANARY: u8 = 0xFD;
+const POISON: u8 = 0x6B;
+
+const MAGIC_LIVE: usize = 0xA110_CA7E_D0D0_0001;
+const MAGIC_FREED: usize = 0xF7EE_D0D0_F7EE_0002;
+
+/// Returns the size class of an allocation of `size` bytes:
+/// class 0 holds (0, 2^3], class 1 holds (2^3, 2^4], and so on.
+pub fn bin_of(size: usize) -> usize {
+    let size = core::cmp::max(size, 8);
+    match size.checked_next_power_of_two() {
+        Some(pow) => core::cmp::min(pow.trailing_zeros() as usize - 3, NUM_BINS - 1),
+        None => NUM_BINS - 1,
+    }
+}
+
+/// Allocation counters of one size class.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BinStats {
+    /// Successful allocations.
+    pub allocs: usize,
+    /// Allocations not freed yet.
+    pub live: usize,
+}
+
+/// Counters kept by a `Tracked` allocator.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AllocStats {
+    pub allocs: usize,
+    pub frees: usize,
+    /// Allocations the underlying allocator could not satisfy.
+    pub failed: usize,
+    /// Bytes requested by live allocations.
+    pub in_use: usize,
+    /// High-water mark of `in_use`.
+    pub peak: usize,
+    /// `(size, align)` of the most recent failed allocation.
+    pub last_failed: Option<(usize, usize)>,
+    pub bins: [BinStats; NUM_BINS],
+}
+
+impl fmt::Display for AllocStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "in use:  {} bytes in {} allocations", self.in_use, self.allocs - self.frees)?;
+        writeln!(f, "peak:    {} bytes", self.peak)?;
+        writeln!(f, "allocs:  {}, frees: {}, failed: {}", self.allocs, self.frees, self.failed)?;
+        if let Some((size, align)) = self.last_failed {
+            writeln!(f, "last failure: {} bytes aligned to {}", size, align)?;
+        }
+        writeln!(f, "{:>12} {:>10} {:>10}", "size", "allocs", "live")?;
+        for (i, bin) in self.bins.iter().enumerate().filter(|(_, b)| b.allocs > 0) {
+            writeln!(f, "{:>12} {:>10} {:>10}", 1usize << (i + 3), bin.allocs, bin.live)?;
+        }
+        Ok(())
+    }
+}
+
+/// A live allocation reported by `Tracked::live()`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LiveAlloc {
+    pub addr: usize,
+    pub size: usize,
+    /// Allocation sequence number; see `Tracked::seq()`.
+    pub seq: usize,
+}
+
+/// A detected heap corruption.
+#[derive(Debug, Clone, Copy)]
+pub struct Corruption {
+    pub addr: usize,
+    pub what: &'static str,
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {:#x}", self.what, self.addr)
+    }
+}
+
+/// Bookkeeping placed immediately before every allocation in debug mode.
+#[repr(C)]
+struct Header {
+    prev: *mut Header,
+    next: *mut Header,
+    size: usize,
+    seq: usize,
+    magic: usize,
+}
+
+/// A wrapper around another `LocalAlloc` that counts allocations and,
+/// in debug mode, surrounds every allocation with red zones.
+///
+/// In debug mode a block handed to the inner allocator looks like:
+///
+///   | canary | Header | data (layout.size()) | canary |
+///                     ^ returned pointer
+///
+/// `dealloc` verifies the header and both canaries before releasing the block
+/// and panics on double frees, overruns and underruns. Freed data is poisoned
+/// with `0x6B` so stale reads are easy to recognize. Live allocations are kept
+/// on a list, which makes it possible to look for leaks.
+///
+/// Allocations aligned to a page or more are passed to the inner allocator
+/// as they are: the front red zone of such a block would take a whole page,
+/// and the inner allocator rounds the block up to the next size class, so a
+/// page would cost four. They are counted but not checked.
+pub struct Tracked<A> {
+    inner: A,
+    stats: AllocStats,
+    enabled: bool,
+    debug: bool,
+    live: *mut Header,
+    seq: usize,
+    mark: usize,
+}
+
+unsafe impl<A: Send> Send for Tracked<A> {}
+
+impl<A: LocalAlloc> Tracked<A> {
+    /// Wraps `inner`. Statistics are kept if `enabled` is `true`; red zones,
+    /// poisoning and the live list are used if `debug` is `true`, which
+    /// implies `enabled`.
+    pub fn new(inner: A, enabled: bool, debug: bool) -> Tracked<A> {
+        Tracked {
+            inner,
+            stats: AllocStats::default(),
+            enabled: enabled || debug,
+            debug,
+            live: ptr::null_mut(),
+            seq: 0,
+            mark: 0,
+        }
+    }
+
+    /// Returns the statistics, or `None` if they are not kept.
+    pub fn stats(&self) -> Option<&AllocStats> {
+        if self.enabled {
+            Some(&self.stats)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if red zones and the live list are in use.
+    pub fn is_debug(&self) -> bool {
+        self.debug
+    }
+
+    /// Returns the sequence number the next allocation will get.
+    pub fn seq(&self) -> usize {
+        self.seq
+    }
+
+    /// Remembers the current sequence number. `live()` reports allocations
+    /// made after the most recent mark.
+    pub fn set_mark(&mut self) -> usize {
+        self.mark = self.seq;
+        self.mark
+    }
+
+    /// Copies the live allocations made since the last mark into `buf`, most
+    /// recent first. Returns the total number of such allocations, which may
+    /// exceed `buf.len()`. Always returns 0 outside debug mode.
+    pub fn live(&self, buf: &mut [LiveAlloc]) -> usize {
+        let mut count = 0;
+        let mut hdr = self.live;
+        while !hdr.is_null() {
+            let header = unsafe { &*hdr };
+            if header.seq < self.mark {
+                break;
+            }
+            if count < buf.len() {
+                buf[count] = LiveAlloc {
+                    addr: hdr as usize + size_of::<Header>(),
+                    size: header.size,
+                    seq: header.seq,
+                };
+            }
+            count += 1;
+            hdr = header.next;
+        }
+        count
+    }
+
+    /// Verifies the red zones of every live allocation. Returns the number of
+    /// allocations checked.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first corruption found.
+    pub fn check(&self) -> Result<usize, Corruption> {
+        let mut count = 0;
+        let mut hdr = self.live;
+        while !hdr.is_null() {
+            unsafe {
+                Self::verify(hdr)?;
+                hdr = (*hdr).next;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns `true` if an allocation of `layout` gets red zones in debug
+    /// mode.
+    fn is_guarded(layout: &Layout) -> bool {
+        layout.align() < PAGE_SIZE
+    }
+
+    /// Returns the distance from block start to data and the layout of the
+    /// block backing a debug allocation of `layout`.
+    fn block_layout(layout: &Layout) -> Option<(usize, Layout)> {
+        let front = core::cmp::max(layout.align(), MIN_FRONT);
+        let size = front.checked_add(layout.size())?.checked_add(REDZONE)?;
+        let align = core::cmp::max(layout.align(), core::mem::align_of::<Header>());
+        Layout::from_size_align(size, align).ok().map(|l| (front, l))
+    }
+
+    unsafe fn verify(hdr: *mut Header) -> Result<(), Corruption> {
+        let data = hdr as usize + size_of::<Header>();
+        let corruption = |what| Err(Corruption { addr: data, what });
+        match (*hdr).magic {
+            MAGIC_LIVE => {}
+            MAGIC_FREED => return corruption("double free"),
+            _ => return corruption("corrupted allocation header (underrun?)"),
+        }
+
+        let front = core::slice::from_raw_parts((hdr as *const u8).sub(REDZONE), REDZONE);
+        if front.iter().any(|&b| b != CANARY) {
+            return corruption("front red zone overwritten (underrun)");
+        }
+        let back = core::slice::from_raw_parts((data + (*hdr).size) as *const u8, REDZONE);
+        if back.iter().any(|&b| b != CANARY) {
+            return corruption("back red zone overwritten (overrun)");
+        }
+        Ok(())
+    }
+
+    unsafe fn debug_alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (front, block) = match Self::block_layout(&layout) {
+            Some(block) => block,
+            None => return ptr::null_mut(),
+        };
+        let base = self.inner.alloc(block);
+        if base.is_null() {
+            return base;
+        }
+
+        let data = base.add(front);
+        let hdr = data.sub(size_of::<Header>()) as *mut Header;
+        ptr::write_bytes(base, CANARY, front - size_of::<Header>());
+        ptr::write_bytes(data.add(layout.size()), CANARY, REDZONE);
+        hdr.write(Header {
+            prev: ptr::null_mut(),
+            next: self.live,
+            size: layout.size(),
+            seq: self.seq,
+            magic: MAGIC_LIVE,
+        });
+        if !self.live.is_null() {
+            (*self.live).prev = hdr;
+        }
+        self.live = hdr;
+        data
+    }
+
+    unsafe fn debug_dealloc(&mut self, data: *mut u8, layout: Layout) {
+        let (front, block) = Self::block_layout(&layout).expect("invalid layout");
+        let hdr = data.sub(size_of::<Header>()) as *mut Header;
+        if let Err(corruption) = Self::verify(hdr) {
+            panic!("heap corruption: {}", corruption);
+        }
+        if (*hdr).size != layout.size() {
+            panic!(
+                "heap corruption: freeing {} bytes at {:#x} allocated with {} bytes",
+                layout.size(),
+                data as usize,
+                (*hdr).size
+            );
+        }
+
+        let header = &mut *hdr;
+        if header.prev.is_null() {
+            self.live = header.next;
+        } else {
+            (*header.prev).next = header.next;
+        }
+        if !header.next.is_null() {
+            (*header.next).prev = header.prev;
+        }
+        header.magic = MAGIC_FREED;
+        ptr::write_bytes(data, POISON, layout.size());
+
+        self.inner.dealloc(data.sub(front), block);
+    }
+}
+
+impl<A: LocalAlloc> LocalAlloc for Tracked<A> {
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = if self.debug && Self::is_guarded(&layout) {
+            self.debug_alloc(layout)
+        } else {
+            self.inner.alloc(layout)
+        };
+
+        if self.enabled {
+            let stats = &mut self.stats;
+            if ptr.is_null() {
+                stats.failed += 1;
+                stats.last_failed = Some((layout.size(), layout.align()));
+            } else {
+                stats.allocs += 1;
+                stats.in_use += layout.size();
+                stats.peak = core::cmp::max(stats.peak, stats.in_use);
+                let bin = &mut stats.bins[bin_of(layout.size())];
+                bin.allocs += 1;
+                bin.live += 1;
+            }
+        }
+        if !ptr.is_null() {
+            self.seq += 1;
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        if self.debug && Self::is_guarded(&layout) {
+            self.debug_dealloc(ptr, layout);
+        } else {
+            self.inner.dealloc(ptr, layout);
+        }
+
+        if self.enabled {
+            let stats = &mut self.stats;
+            stats.frees += 1;
+            stats.in_use = stats.in_use.saturating_sub(layout.size());
+            let bin = &mut stats.bins[bin_of(layout.size())];
+            bin.live = bin.live.saturating_sub(1);
+        }
+    }
+}
+
+impl<A: fmt::Debug> fmt::Debug for Tracked<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.inner)
+    }
+}
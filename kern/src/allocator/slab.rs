@@ -0,0 +1,369 @@
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::cmp::max;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kernel_api::{OsError, OsResult};
+
+use crate::allocator::linked_list::LinkedList;
+use crate::allocator::util::align_up;
+use crate::mutex::Mutex;
+use crate::param::{NCORES, PAGE_SIZE};
+
+/// Size of the chunks a slab requests from the global allocator.
+const CHUNK_SIZE: usize = PAGE_SIZE;
+
+/// Number of objects a per-core magazine holds.
+const MAGAZINE_SIZE: usize = 16;
+
+/// Every named cache, registered on its first allocation.
+static CACHES: Mutex<Vec<&'static dyn SlabInfo>> = Mutex::new(Vec::new());
+
+/// A snapshot of a cache's usage, as reported by the `slabinfo` command.
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    pub name: &'static str,
+    /// Bytes per object slot, including alignment padding.
+    pub obj_size: usize,
+    /// Objects carved from each chunk.
+    pub per_chunk: usize,
+    /// Chunks taken from the global allocator.
+    pub chunks: usize,
+    /// Objects handed out to callers.
+    pub in_use: usize,
+    /// Free objects parked in per-core magazines.
+    pub cached: usize,
+    /// Free objects on the shared free list.
+    pub free: usize,
+}
+
+/// Returns the statistics of every cache that has been used so far.
+pub fn caches() -> Vec<SlabStats> {
+    CACHES.lock().iter().map(|cache| cache.stats()).collect()
+}
+
+trait SlabInfo: Sync {
+    fn stats(&self) -> SlabStats;
+}
+
+/// An untyped pool of equally sized object slots.
+///
+/// Memory is taken from the global allocator in `CHUNK_SIZE` chunks which are
+/// cut into slots and threaded onto an intrusive free list. Chunks are kept for
+/// the lifetime of the slab, so a steady stream of allocations and frees of
+/// one object type never touches the general-purpose allocator.
+pub struct Slab {
+    size: usize,
+    align: usize,
+    free: LinkedList,
+    chunks: Vec<usize>,
+    in_use: usize,
+    nr_free: usize,
+}
+
+unsafe impl Send for Slab {}
+
+impl Slab {
+    /// Returns an empty slab for objects of `size` bytes aligned to `align`.
+    pub const fn new(size: usize, align: usize) -> Slab {
+        Slab {
+            size,
+            align,
+            free: LinkedList::new(),
+            chunks: Vec::new(),
+            in_use: 0,
+            nr_free: 0,
+        }
+    }
+
+    /// Size of one slot. A free slot must be able to hold a free-list link.
+    fn slot_size(&self) -> usize {
+        align_up(max(self.size, size_of::<usize>()), self.slot_align())
+    }
+
+    fn slot_align(&self) -> usize {
+        max(self.align, align_of::<usize>())
+    }
+
+    fn chunk_layout(&self) -> Layout {
+        let size = max(CHUNK_SIZE, self.slot_size());
+        Layout::from_size_align(size, self.slot_align()).unwrap()
+    }
+
+    fn per_chunk(&self) -> usize {
+        self.chunk_layout().size() / self.slot_size()
+    }
+
+    /// Takes a new chunk from the global allocator and adds its slots to the
+    /// free list. Returns `false` if the allocator is out of memory.
+    fn grow(&mut self) -> bool {
+        let layout = self.chunk_layout();
+        let chunk = unsafe { alloc::alloc::alloc(layout) } as usize;
+        if chunk == 0 {
+            return false;
+        }
+        self.chunks.push(chunk);
+
+        let slot = self.slot_size();
+        for i in (0..self.per_chunk()).rev() {
+            unsafe { self.free.push((chunk + i * slot) as *mut usize) };
+        }
+        self.nr_free += self.per_chunk();
+        true
+    }
+
+    /// Returns an uninitialized object slot, or `None` if memory is exhausted.
+    pub fn alloc(&mut self) -> Option<*mut u8> {
+        if self.free.is_empty() && !self.grow() {
+            return None;
+        }
+        let ptr = self.free.pop()?;
+        self.nr_free -= 1;
+        self.in_use += 1;
+        Some(ptr as *mut u8)
+    }
+
+    /// Returns `ptr` to the free list.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc()` on this slab and must not be
+    /// used afterwards.
+    pub unsafe fn free(&mut self, ptr: *mut u8) {
+        self.free.push(ptr as *mut usize);
+        self.in_use -= 1;
+        self.nr_free += 1;
+    }
+
+    /// Returns the number of slots handed out.
+    pub fn in_use(&self) -> usize {
+        self.in_use
+    }
+
+    /// Returns the number of slots on the free list.
+    pub fn nr_free(&self) -> usize {
+        self.nr_free
+    }
+
+    /// Returns the number of chunks taken from the global allocator.
+    pub fn nr_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+impl Drop for Slab {
+    fn drop(&mut self) {
+        let layout = self.chunk_layout();
+        for &chunk in &self.chunks {
+            unsafe { alloc::alloc::dealloc(chunk as *mut u8, layout) };
+        }
+    }
+}
+
+impl fmt::Debug for Slab {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Slab")
+            .field("slot_size", &self.slot_size())
+            .field("chunks", &self.chunks.len())
+            .field("in_use", &self.in_use)
+            .field("free", &self.nr_free)
+            .finish()
+    }
+}
+
+/// A per-core stack of free objects that is refilled from and drained to the
+/// shared slab in batches, so most allocations only take an uncontended lock.
+struct Magazine {
+    objs: [usize; MAGAZINE_SIZE],
+    len: usize,
+}
+
+impl Magazine {
+    const fn new() -> Magazine {
+        Magazine { objs: [0; MAGAZINE_SIZE], len: 0 }
+    }
+
+    fn push(&mut self, ptr: *mut u8) {
+        self.objs[self.len] = ptr as usize;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<*mut u8> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.objs[self.len] as *mut u8)
+    }
+}
+
+const fn magazines() -> [Mutex<Magazine>; NCORES] {
+    [
+        Mutex::new(Magazine::new()),
+        Mutex::new(Magazine::new()),
+        Mutex::new(Magazine::new()),
+        Mutex::new(Magazine::new()),
+    ]
+}
+
+/// A named cache of `T` objects.
+///
+/// Caches are meant to be declared as statics next to the type they hold:
+///
+/// ```rust,ignore
+/// static TRAP_FRAMES: SlabCache<TrapFrame> = SlabCache::new("trap_frame");
+///
+/// let tf: SlabBox<TrapFrame> = TRAP_FRAMES.alloc(TrapFrame::default())?;
+/// ```
+pub struct SlabCache<T> {
+    name: &'static str,
+    slab: Mutex<Slab>,
+    magazines: Option<[Mutex<Magazine>; NCORES]>,
+    registered: AtomicBool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> SlabCache<T> {
+    /// Returns a cache named `name` whose objects all come from one shared
+    /// free list.
+    pub const fn new(name: &'static str) -> SlabCache<T> {
+        SlabCache {
+            name,
+            slab: Mutex::new(Slab::new(size_of::<T>(), align_of::<T>())),
+            magazines: None,
+            registered: AtomicBool::new(false),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a cache named `name` that keeps a magazine of free objects per
+    /// core in front of the shared free list. Use this for objects that are
+    /// allocated on hot paths from several cores.
+    pub const fn with_magazines(name: &'static str) -> SlabCache<T> {
+        SlabCache {
+            name,
+            slab: Mutex::new(Slab::new(size_of::<T>(), align_of::<T>())),
+            magazines: Some(magazines()),
+            registered: AtomicBool::new(false),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Moves `val` into a newly allocated object of this cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::NoMemory` if no object could be allocated.
+    pub fn alloc(&'static self, val: T) -> OsResult<SlabBox<T>>
+    where
+        T: Send + 'static,
+    {
+        if !self.registered.swap(true, Ordering::AcqRel) {
+            CACHES.lock().push(self);
+        }
+
+        let ptr = self.alloc_raw().ok_or(OsError::NoMemory)? as *mut T;
+        unsafe {
+            ptr.write(val);
+            Ok(SlabBox { ptr: NonNull::new_unchecked(ptr), cache: self })
+        }
+    }
+
+    fn alloc_raw(&self) -> Option<*mut u8> {
+        let magazines = match &self.magazines {
+            Some(magazines) => magazines,
+            None => return self.slab.lock().alloc(),
+        };
+
+        let mut magazine = magazines[aarch64::affinity()].lock();
+        if magazine.len == 0 {
+            let mut slab = self.slab.lock();
+            while magazine.len < MAGAZINE_SIZE / 2 {
+                match slab.alloc() {
+                    Some(ptr) => magazine.push(ptr),
+                    None => break,
+                }
+            }
+        }
+        magazine.pop()
+    }
+
+    unsafe fn free_raw(&self, ptr: *mut u8) {
+        let magazines = match &self.magazines {
+            Some(magazines) => magazines,
+            None => return self.slab.lock().free(ptr),
+        };
+
+        let mut magazine = magazines[aarch64::affinity()].lock();
+        if magazine.len == MAGAZINE_SIZE {
+            let mut slab = self.slab.lock();
+            while magazine.len > MAGAZINE_SIZE / 2 {
+                slab.free(magazine.pop().unwrap());
+            }
+        }
+        magazine.push(ptr);
+    }
+}
+
+impl<T: Send> SlabInfo for SlabCache<T> {
+    fn stats(&self) -> SlabStats {
+        // magazines are always locked before the slab
+        let cached = match &self.magazines {
+            Some(magazines) => magazines.iter().map(|m| m.lock().len).sum(),
+            None => 0,
+        };
+        let slab = self.slab.lock();
+        SlabStats {
+            name: self.name,
+            obj_size: slab.slot_size(),
+            per_chunk: slab.per_chunk(),
+            chunks: slab.nr_chunks(),
+            in_use: slab.in_use().saturating_sub(cached),
+            cached,
+            free: slab.nr_free(),
+        }
+    }
+}
+
+/// An owned `T` allocated from a `SlabCache`, returned to the cache on drop.
+pub struct SlabBox<T: 'static> {
+    ptr: NonNull<T>,
+    cache: &'static SlabCache<T>,
+}
+
+unsafe impl<T: Send> Send for SlabBox<T> {}
+unsafe impl<T: Sync> Sync for SlabBox<T> {}
+
+impl<T> Deref for SlabBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            core::ptr::drop_in_place(self.ptr.as_ptr());
+            self.cache.free_raw(self.ptr.as_ptr() as *mut u8);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SlabBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
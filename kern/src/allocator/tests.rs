@@ -85,7 +85,7 @@ mod allocator {
 
     use core::alloc::Layout;
 
-    use crate::allocator::{bin, bump, LocalAlloc};
+    use crate::allocator::{bin, buddy, bump, LocalAlloc};
 
     macro_rules! test_allocators {
         (@$kind:ident, $name:ident, $mem:expr, |$info:pat| $block:expr) => {
@@ -105,9 +105,15 @@ mod allocator {
             }
         };
 
-        ($bin:ident, $bump:ident, $mem:expr, |$info:pat| $block:expr) => (
+        ($bin:ident, $bump:ident, $buddy:ident, $mem:expr, |$info:pat| $block:expr) => (
             test_allocators!(@bin, $bin, $mem, |$info| $block);
             test_allocators!(@bump, $bump, $mem, |$info| $block);
+            test_allocators!(@buddy, $buddy, $mem, |$info| $block);
+        );
+
+        (@reuse $bin:ident, $buddy:ident, $mem:expr, |$info:pat| $block:expr) => (
+            test_allocators!(@bin, $bin, $mem, |$info| $block);
+            test_allocators!(@buddy, $buddy, $mem, |$info| $block);
         );
     }
 
@@ -166,12 +172,12 @@ mod allocator {
         }
     }
 
-    test_allocators!(bin_exhausted, bump_exhausted, 128, |(_, _, mut a)| {
+    test_allocators!(bin_exhausted, bump_exhausted, buddy_exhausted, 128, |(_, _, mut a)| {
         let result = a.alloc(layout!(1024, 128));
         assert!(result.is_null());
     });
 
-    test_allocators!(bin_alloc, bump_alloc, 8 * (1 << 20), |(start, end, a)| {
+    test_allocators!(bin_alloc, bump_alloc, buddy_alloc, 8 * (1 << 20), |(start, end, a)| {
         let layouts = [
             layout!(16, 16),
             layout!(16, 128),
@@ -197,7 +203,7 @@ mod allocator {
         test_layouts!(layouts, start, end, a);
     });
 
-    test_allocators!(bin_alloc_2, bump_alloc_2, 16 * (1 << 20), |(
+    test_allocators!(bin_alloc_2, bump_alloc_2, buddy_alloc_2, 16 * (1 << 20), |(
         start,
         end,
         a,
@@ -217,7 +223,7 @@ mod allocator {
         }
     }
 
-    test_allocators!(bin_dealloc_s, bump_dealloc_s, 4096, |(_, _, mut a)| {
+    test_allocators!(bin_dealloc_s, bump_dealloc_s, buddy_dealloc_s, 4096, |(_, _, mut a)| {
         let layouts = [layout!(16, 16), layout!(16, 128), layout!(16, 256)];
 
         let mut pointers: Vec<(usize, Layout)> = vec![];
@@ -235,7 +241,7 @@ mod allocator {
         }
     });
 
-    test_allocators!(@bin, bin_dealloc_1, 65536, |(_, _, mut a)| {
+    test_allocators!(@reuse bin_dealloc_1, buddy_dealloc_1, 65536, |(_, _, mut a)| {
         let layouts = [
             layout!(16, 16),
             layout!(16, 256),
@@ -275,7 +281,7 @@ mod allocator {
         }
     });
 
-    test_allocators!(@bin, bin_dealloc_2, 8192, |(_, _, mut a)| {
+    test_allocators!(@reuse bin_dealloc_2, buddy_dealloc_2, 8192, |(_, _, mut a)| {
         let layouts = [
             layout!(3072, 16),
             layout!(512, 32),
@@ -298,6 +304,287 @@ mod allocator {
             }
         }
     });
+
+    test_allocators!(@buddy, buddy_coalesce, 1 << 20, |(_, _, mut a)| {
+        // fragment the whole region into small blocks, then free every other
+        // one before the rest so that merging happens out of order
+        let small = layout!(64, 8);
+        let mut ptrs = vec![];
+        loop {
+            let ptr = a.alloc(small.clone());
+            if ptr.is_null() {
+                break;
+            }
+            scribble(ptr, small.size());
+            ptrs.push(ptr);
+        }
+        assert!(ptrs.len() >= (1 << 20) / 64 - 64);
+
+        let big = layout!(1 << 18, 1 << 18);
+        assert!(a.alloc(big.clone()).is_null());
+
+        for ptr in ptrs.iter().step_by(2) {
+            a.dealloc(*ptr, small.clone());
+        }
+        assert!(a.alloc(big.clone()).is_null());
+        for ptr in ptrs.iter().skip(1).step_by(2) {
+            a.dealloc(*ptr, small.clone());
+        }
+        assert_eq!(a.allocated(), 0);
+
+        // everything has coalesced back, so large aligned blocks fit again
+        let ptr = a.alloc(big.clone());
+        assert!(!ptr.is_null());
+        assert!(ptr as usize % big.align() == 0);
+        scribble(ptr, big.size());
+        a.dealloc(ptr, big);
+    });
+
+    test_allocators!(@buddy, buddy_fragmentation_stress, 4 << 20, |(start, end, mut a)| {
+        let initial = a.free_counts();
+
+        // deterministic pseudo-random mix of allocations and frees
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+
+        let mut live: Vec<(usize, Layout)> = vec![];
+        for _ in 0..20000 {
+            if live.is_empty() || next() % 3 != 0 {
+                let size = 1 + next() % 4096;
+                let align = 1 << (next() % 10);
+                let layout = layout!(size, align);
+                let ptr = a.alloc(layout.clone());
+                if ptr.is_null() {
+                    continue;
+                }
+                assert!(ptr as usize % align == 0);
+                assert!(ptr as usize >= start && ptr as usize + size <= end);
+                scribble(ptr, size);
+                live.push((ptr as usize, layout));
+            } else {
+                let (ptr, layout) = live.swap_remove(next() % live.len());
+                a.dealloc(ptr as *mut u8, layout);
+            }
+        }
+
+        // live blocks never overlap
+        live.sort_by_key(|&(ptr, _)| ptr);
+        for window in live.windows(2) {
+            assert!(window[1].0 - window[0].0 >= window[0].1.size());
+        }
+
+        for (ptr, layout) in live {
+            a.dealloc(ptr as *mut u8, layout);
+        }
+
+        // once everything is freed the free lists look exactly as they did
+        // before the first allocation
+        assert_eq!(a.allocated(), 0);
+        assert_eq!(&a.free_counts()[..], &initial[..]);
+    });
+}
+
+mod tracked {
+    extern crate alloc;
+    use alloc::raw_vec::RawVec;
+
+    use core::alloc::Layout;
+
+    use crate::allocator::debug::{bin_of, Tracked};
+    use crate::allocator::{bin, LiveAlloc, LocalAlloc};
+    use crate::param::PAGE_SIZE;
+
+    fn with_heap<F: FnOnce(Tracked<bin::Allocator>)>(enabled: bool, debug: bool, f: F) {
+        let mem: RawVec<u8> = RawVec::with_capacity(1 << 16);
+        let start = mem.ptr() as usize;
+        f(Tracked::new(bin::Allocator::new(start, start + (1 << 16)), enabled, debug));
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn counts_allocations() {
+        with_heap(true, false, |mut a| unsafe {
+            let p1 = a.alloc(layout(100, 8));
+            let p2 = a.alloc(layout(16, 16));
+            assert!(!p1.is_null() && !p2.is_null());
+            a.dealloc(p1, layout(100, 8));
+            assert!(a.alloc(layout(1 << 20, 8)).is_null());
+
+            let stats = a.stats().unwrap();
+            assert_eq!((stats.allocs, stats.frees, stats.failed), (2, 1, 1));
+            assert_eq!(stats.in_use, 16);
+            assert_eq!(stats.peak, 116);
+            assert_eq!(stats.last_failed, Some((1 << 20, 8)));
+            assert_eq!(stats.bins[bin_of(100)].allocs, 1);
+            assert_eq!(stats.bins[bin_of(100)].live, 0);
+            assert_eq!(stats.bins[bin_of(16)].live, 1);
+        });
+    }
+
+    #[test]
+    fn stats_are_optional() {
+        with_heap(false, false, |mut a| unsafe {
+            assert!(!a.alloc(layout(8, 8)).is_null());
+            assert!(a.stats().is_none());
+        });
+    }
+
+    #[test]
+    fn debug_keeps_alignment_and_poisons_freed_memory() {
+        with_heap(false, true, |mut a| unsafe {
+            let ptr = a.alloc(layout(200, 256));
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 256, 0);
+            core::ptr::write_bytes(ptr, 0xAF, 200);
+            assert_eq!(a.check().unwrap(), 1);
+
+            a.dealloc(ptr, layout(200, 256));
+            let freed = core::slice::from_raw_parts(ptr, 200);
+            assert!(freed.iter().all(|&b| b == 0x6B));
+            assert_eq!(a.check().unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn debug_leaves_page_aligned_allocations_unpadded() {
+        // too small for a page with a page of red zone in front of it
+        let mem: RawVec<u8> = RawVec::with_capacity(3 * PAGE_SIZE);
+        let start = mem.ptr() as usize;
+        let mut a = Tracked::new(bin::Allocator::new(start, start + 3 * PAGE_SIZE), false, true);
+        unsafe {
+            let ptr = a.alloc(layout(PAGE_SIZE, PAGE_SIZE));
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % PAGE_SIZE, 0);
+            assert_eq!(a.check().unwrap(), 0);
+            a.dealloc(ptr, layout(PAGE_SIZE, PAGE_SIZE));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "overrun")]
+    fn debug_detects_overrun() {
+        with_heap(false, true, |mut a| unsafe {
+            let ptr = a.alloc(layout(10, 8));
+            *ptr.add(10) = 0;
+            a.dealloc(ptr, layout(10, 8));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn debug_detects_double_free() {
+        with_heap(false, true, |mut a| unsafe {
+            let ptr = a.alloc(layout(32, 8));
+            a.dealloc(ptr, layout(32, 8));
+            a.dealloc(ptr, layout(32, 8));
+        });
+    }
+
+    #[test]
+    fn debug_check_finds_underrun() {
+        with_heap(false, true, |mut a| unsafe {
+            let ptr = a.alloc(layout(32, 8));
+            *ptr.sub(48) = 0;
+            let corruption = a.check().unwrap_err();
+            assert_eq!(corruption.addr, ptr as usize);
+        });
+    }
+
+    #[test]
+    fn debug_reports_leaks_since_mark() {
+        with_heap(false, true, |mut a| unsafe {
+            let old = a.alloc(layout(8, 8));
+            assert_eq!(a.set_mark(), 1);
+
+            let leaked = a.alloc(layout(24, 8));
+            let freed = a.alloc(layout(40, 8));
+            a.dealloc(freed, layout(40, 8));
+
+            let mut live = [LiveAlloc::default(); 4];
+            assert_eq!(a.live(&mut live), 1);
+            assert_eq!(live[0].addr, leaked as usize);
+            assert_eq!(live[0].size, 24);
+            assert_eq!(live[0].seq, 1);
+
+            a.dealloc(old, layout(8, 8));
+            a.dealloc(leaked, layout(24, 8));
+            assert_eq!(a.live(&mut live), 0);
+        });
+    }
+}
+
+mod slab {
+    use core::mem::{align_of, size_of};
+
+    use crate::allocator::Slab;
+
+    #[repr(align(64))]
+    struct Object([u8; 100]);
+
+    #[test]
+    fn slots_are_aligned_and_distinct() {
+        let mut slab = Slab::new(size_of::<Object>(), align_of::<Object>());
+        let mut ptrs = vec![];
+        for _ in 0..100 {
+            let ptr = slab.alloc().unwrap() as usize;
+            assert_eq!(ptr % 64, 0);
+            unsafe { core::ptr::write_bytes(ptr as *mut u8, 0xAF, size_of::<Object>()) };
+            ptrs.push(ptr);
+        }
+
+        ptrs.sort();
+        for window in ptrs.windows(2) {
+            assert!(window[1] - window[0] >= size_of::<Object>());
+        }
+        assert_eq!(slab.in_use(), 100);
+    }
+
+    #[test]
+    fn grows_by_chunks_and_reuses_freed_slots() {
+        let mut slab = Slab::new(8, 8);
+        let first = slab.alloc().unwrap();
+        assert_eq!(slab.nr_chunks(), 1);
+        let per_chunk = slab.nr_free() + 1;
+
+        let mut ptrs = vec![first];
+        for _ in 1..per_chunk {
+            ptrs.push(slab.alloc().unwrap());
+        }
+        assert_eq!(slab.nr_chunks(), 1);
+        assert_eq!(slab.nr_free(), 0);
+
+        ptrs.push(slab.alloc().unwrap());
+        assert_eq!(slab.nr_chunks(), 2);
+
+        for ptr in ptrs.drain(..) {
+            unsafe { slab.free(ptr) };
+        }
+        assert_eq!(slab.in_use(), 0);
+
+        // freed slots are handed out again before the slab grows
+        let ptr = slab.alloc().unwrap();
+        assert_eq!(slab.nr_chunks(), 2);
+        unsafe { slab.free(ptr) };
+    }
+
+    #[test]
+    fn objects_larger_than_a_chunk() {
+        let mut slab = Slab::new(crate::param::PAGE_SIZE + 1, 16);
+        let a = slab.alloc().unwrap();
+        let b = slab.alloc().unwrap();
+        assert_eq!(slab.nr_chunks(), 2);
+        assert_ne!(a, b);
+        unsafe {
+            slab.free(a);
+            slab.free(b);
+        }
+    }
 }
 
 mod linked_list {
@@ -1,79 +1,429 @@
+mod ring;
+mod serial;
+
+use alloc::boxed::Box;
+use aarch64::CNTKCTL_EL1;
+use alloc::vec::Vec;
 use core::fmt;
-use pi::uart::MiniUart;
+use pi::interrupt::Controller;
 use shim::io;
 
 use crate::mutex::Mutex;
+use crate::param::{CONSOLE_EVENT_STREAM_BIT, NCORES};
+use crate::traps::irq::IrqHandlerRegistry;
+use crate::GLOABAL_IRQ;
+use self::ring::Ring;
+pub use self::serial::Serial;
+
+/// Number of virtual terminals.
+pub const NUM_VTS: usize = 3;
+
+/// The virtual terminals multiplexed on the serial line. Only the terminal in
+/// the foreground is shown; each one keeps its recent output, which is
+/// replayed when switching to it with Alt-F1..Alt-F3 (or Alt-1..Alt-3).
+/// The shell brings `Programs` to the foreground while a program runs in the
+/// foreground of the console's session.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Vt {
+    /// The shell and `kprint!` output.
+    Shell = 0,
+    /// Output of user processes.
+    Programs = 1,
+    /// The kernel log, as shown by `dmesg`.
+    Log = 2,
+}
+
+/// Longest line kept by the line buffer of a core. Longer lines are written
+/// in pieces.
+const LINE_MAX: usize = 160;
+
+const ESC: u8 = 0x1b;
+
+/// Ctrl-C, which interrupts the foreground program instead of being input.
+const INTERRUPT: u8 = 0x03;
+
+/// Input sequences switching terminals: xterm's Alt-F1..F3 and Meta-1..3.
+const HOTKEYS: &[(&[u8], usize)] = &[
+    (b"\x1b[1;3P", 0),
+    (b"\x1b[1;3Q", 1),
+    (b"\x1b[1;3R", 2),
+    (b"\x1b1", 0),
+    (b"\x1b2", 1),
+    (b"\x1b3", 2),
+];
+
+struct Terminal {
+    /// Recent output, replayed when switching to this terminal.
+    history: Ring,
+    /// Input typed while this terminal was in the foreground.
+    input: Ring,
+    /// Core that left an unfinished line on this terminal.
+    partial: Option<usize>,
+}
+
+impl Terminal {
+    const fn new() -> Terminal {
+        Terminal { history: Ring::new(), input: Ring::new(), partial: None }
+    }
+}
+
+/// A line being assembled by one core.
+#[derive(Copy, Clone)]
+struct Line {
+    vt: usize,
+    len: usize,
+    buf: [u8; LINE_MAX],
+}
+
+impl Line {
+    const fn new() -> Line {
+        Line { vt: 0, len: 0, buf: [0; LINE_MAX] }
+    }
+}
+
+/// Picks the terminal hotkeys out of the input stream.
+struct Hotkeys {
+    buf: [u8; 8],
+    len: usize,
+}
+
+impl Hotkeys {
+    const fn new() -> Hotkeys {
+        Hotkeys { buf: [0; 8], len: 0 }
+    }
+
+    /// Feeds the input byte `byte`. Bytes that are not part of a hotkey are
+    /// passed to `deliver`, possibly delayed until the sequence they start
+    /// turns out not to be one. Returns the terminal to switch to when a
+    /// hotkey is complete.
+    fn feed<F: FnMut(u8)>(&mut self, byte: u8, mut deliver: F) -> Option<usize> {
+        if self.len == 0 && byte != ESC {
+            deliver(byte);
+            return None;
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+        let seq = &self.buf[..self.len];
+        if let Some(&(_, vt)) = HOTKEYS.iter().find(|&&(keys, _)| keys == seq) {
+            self.len = 0;
+            return Some(vt);
+        }
+        if HOTKEYS.iter().any(|&(keys, _)| keys.starts_with(seq)) {
+            return None;
+        }
+
+        for &b in seq {
+            deliver(b);
+        }
+        self.len = 0;
+        None
+    }
+}
 
 /// A global singleton allowing read/write access to the console.
 pub struct Console {
-    inner: Option<MiniUart>,
+    inner: Option<Serial>,
+    vts: [Terminal; NUM_VTS],
+    foreground: usize,
+    /// Partial lines of buffered output, per core.
+    lines: [Line; NCORES],
+    hotkeys: Hotkeys,
+    /// Whether Ctrl-C was typed on the `Programs` terminal.
+    interrupted: bool,
 }
 
 impl Console {
     /// Creates a new instance of `Console`.
     const fn new() -> Console {
-        Console { inner: None }
+        Console {
+            inner: None,
+            vts: [Terminal::new(), Terminal::new(), Terminal::new()],
+            foreground: 0,
+            lines: [Line::new(); NCORES],
+            hotkeys: Hotkeys::new(),
+            interrupted: false,
+        }
     }
 
     /// Initializes the console if it's not already initialized.
     #[inline]
     fn initialize(&mut self) {
         if self.inner.is_none() {
-            self.inner = Some(MiniUart::new());
-        } 
+            self.inner = Some(Serial::from_cmdline());
+        }
     }
 
-    /// Returns a mutable borrow to the inner `MiniUart`, initializing it as
+    /// Returns a mutable borrow to the inner `Serial`, initializing it as
     /// needed.
-    fn inner(&mut self) -> &mut MiniUart {
+    fn inner(&mut self) -> &mut Serial {
         self.initialize();
         self.inner.as_mut().unwrap()
     }
 
-    /// Reads a byte from the UART device, blocking until a byte is available.
-    pub fn read_byte(&mut self) -> u8 {
-        self.inner().read_byte()
+    /// Returns the UART itself, bypassing the terminals. Used to hand the
+    /// serial line over to another protocol.
+    pub fn uart(&mut self) -> &mut Serial {
+        self.inner()
     }
 
-    /// Writes the byte `byte` to the UART device.
-    pub fn write_byte(&mut self, byte: u8) {
-        self.inner().write_byte(byte);
+    /// Writes raw bytes to the UART, translating `\n` into `\r\n`.
+    fn send(&mut self, bytes: &[u8]) {
+        let uart = self.inner();
+        for &byte in bytes {
+            if byte == b'\n' {
+                uart.write_byte(b'\r');
+            }
+            uart.write_byte(byte);
+        }
     }
-}
 
-impl io::Read for Console {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner().read(buf)
+    /// Writes `bytes` to terminal `vt` from the current core. If another core
+    /// left an unfinished line there, that line is ended first so that lines
+    /// of different cores never mix.
+    fn emit(&mut self, vt: usize, bytes: &[u8]) {
+        let last = match bytes.last() {
+            Some(&last) => last,
+            None => return,
+        };
+        let core = aarch64::affinity();
+        if self.vts[vt].partial.map_or(false, |owner| owner != core) {
+            self.emit_raw(vt, b"\n");
+        }
+        self.emit_raw(vt, bytes);
+        self.vts[vt].partial = if last == b'\n' { None } else { Some(core) };
+    }
+
+    fn emit_raw(&mut self, vt: usize, bytes: &[u8]) {
+        for &byte in bytes {
+            self.vts[vt].history.push(byte);
+        }
+        if vt == self.foreground {
+            self.send(bytes);
+        }
+    }
+
+    /// Writes `bytes` to terminal `vt` right away. The whole write is atomic
+    /// with respect to the other cores.
+    pub fn write_to(&mut self, vt: Vt, bytes: &[u8]) {
+        self.emit(vt as usize, bytes);
+    }
+
+    /// Writes `bytes` to terminal `vt` a line at a time: bytes are collected
+    /// in the current core's line buffer until a newline, until the buffer is
+    /// full or until `flush_line` is called.
+    pub fn write_buffered(&mut self, vt: Vt, bytes: &[u8]) {
+        let core = aarch64::affinity();
+        for &byte in bytes {
+            if self.lines[core].len > 0 && self.lines[core].vt != vt as usize {
+                self.flush_line();
+            }
+            let line = &mut self.lines[core];
+            line.vt = vt as usize;
+            line.buf[line.len] = byte;
+            line.len += 1;
+            if byte == b'\n' || line.len == LINE_MAX {
+                self.flush_line();
+            }
+        }
+    }
+
+    /// Writes out the partial line buffered by the current core.
+    pub fn flush_line(&mut self) {
+        let core = aarch64::affinity();
+        let line = self.lines[core];
+        self.lines[core].len = 0;
+        self.emit(line.vt, &line.buf[..line.len]);
+    }
+
+    /// Writes `args` to terminal `vt` atomically.
+    pub fn print(&mut self, vt: Vt, args: fmt::Arguments) {
+        struct Writer<'a>(&'a mut Console, Vt);
+
+        impl<'a> fmt::Write for Writer<'a> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.write_to(self.1, s.as_bytes());
+                Ok(())
+            }
+        }
+
+        fmt::Write::write_fmt(&mut Writer(self, vt), args).unwrap();
+    }
+
+    /// Brings terminal `vt` to the foreground, redrawing the screen with its
+    /// recent output.
+    pub fn switch_to(&mut self, vt: Vt) {
+        self.switch_to_index(vt as usize);
+    }
+
+    fn switch_to_index(&mut self, vt: usize) {
+        if vt == self.foreground {
+            return;
+        }
+        self.foreground = vt;
+        self.send(b"\x1b[2J\x1b[H");
+        let history = self.history_of(vt);
+        self.send(&history);
+    }
+
+    /// Returns the recent output of terminal `vt`.
+    pub fn history(&self, vt: Vt) -> Vec<u8> {
+        self.history_of(vt as usize)
+    }
+
+    fn history_of(&self, vt: usize) -> Vec<u8> {
+        let (a, b) = self.vts[vt].history.as_slices();
+        let mut history = Vec::with_capacity(a.len() + b.len());
+        history.extend_from_slice(a);
+        history.extend_from_slice(b);
+        history
+    }
+
+    /// Forgets the recent output of terminal `vt`.
+    pub fn clear_history(&mut self, vt: Vt) {
+        self.vts[vt as usize].history.clear();
+    }
+
+    /// Moves the bytes received by the UART to the input of the foreground
+    /// terminal, switching terminals on hotkeys and recording Ctrl-C typed on
+    /// the `Programs` terminal for `take_interrupt()`, and wakes up the cores
+    /// waiting in `read_byte`. When the UART is driven by interrupts, it is
+    /// serviced here as well so that the console keeps working on cores
+    /// running with interrupts masked.
+    fn poll(&mut self) {
+        if self.inner().interrupts_enabled() {
+            self.inner().handle_interrupt();
+        }
+        let mut received = false;
+        while self.inner().has_byte() {
+            let byte = self.inner().read_byte();
+            let programs = self.foreground == Vt::Programs as usize;
+            let input = &mut self.vts[self.foreground].input;
+            let interrupted = &mut self.interrupted;
+            let deliver = |b: u8| match b {
+                INTERRUPT if programs => *interrupted = true,
+                _ => input.push(b),
+            };
+            if let Some(vt) = self.hotkeys.feed(byte, deliver) {
+                self.switch_to_index(vt);
+            }
+            received = true;
+        }
+        if received {
+            aarch64::sev();
+        }
+    }
+
+    /// Returns a byte typed on terminal `vt`, if there is one. This method
+    /// does not block.
+    pub fn try_read_byte(&mut self, vt: Vt) -> Option<u8> {
+        self.poll();
+        self.vts[vt as usize].input.pop()
+    }
+
+    /// Reads the bytes typed on terminal `vt` into `buf`, without blocking.
+    /// Returns the number of bytes read.
+    pub fn read(&mut self, vt: Vt, buf: &mut [u8]) -> usize {
+        self.poll();
+        let input = &mut self.vts[vt as usize].input;
+        let mut len = 0;
+        while len < buf.len() {
+            match input.pop() {
+                Some(byte) => buf[len] = byte,
+                None => break,
+            }
+            len += 1;
+        }
+        len
+    }
+
+    /// Returns whether Ctrl-C was typed on the `Programs` terminal since the
+    /// last call.
+    pub fn take_interrupt(&mut self) -> bool {
+        self.poll();
+        core::mem::replace(&mut self.interrupted, false)
+    }
+
+    /// Returns `true` if a byte typed on the shell's terminal can be read
+    /// without blocking.
+    pub fn has_byte(&mut self) -> bool {
+        self.poll();
+        !self.vts[Vt::Shell as usize].input.is_empty()
+    }
+
+    /// Writes the byte `byte` to the shell's terminal.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.write_to(Vt::Shell, &[byte]);
     }
 }
 
 impl io::Write for Console {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner().write(buf)
+        self.write_to(Vt::Shell, buf);
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush();
         Ok(())
     }
 }
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.inner().write_str(s)
+        self.write_to(Vt::Shell, s.as_bytes());
+        Ok(())
     }
 }
 
 /// Global `Console` singleton.
 pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
 
+/// Switches the console to interrupt-driven operation. Must be called on
+/// core 0, which handles the global interrupts.
+pub fn enable_interrupts() {
+    let interrupt = CONSOLE.lock().inner().interrupt();
+    GLOABAL_IRQ.register(interrupt, Box::new(|_| CONSOLE.lock().poll()));
+    CONSOLE.lock().inner().enable_interrupts();
+    Controller::new().enable(interrupt);
+
+    // Core 0 takes the interrupt, so nothing signals it while it waits for
+    // input itself; let the counter event stream wake it up instead.
+    unsafe {
+        let evnti = (CONSOLE_EVENT_STREAM_BIT << 4) & CNTKCTL_EL1::EVNTI;
+        CNTKCTL_EL1.set((CNTKCTL_EL1.get() & !CNTKCTL_EL1::EVNTI) | evnti | CNTKCTL_EL1::EVNTEN);
+    }
+}
+
+/// Reads a byte typed on terminal `vt`, blocking until there is one. The
+/// console is not kept locked while waiting, so the other cores can still
+/// write to it.
+///
+/// Once interrupts are enabled, the waiting core sleeps between polls. Cores
+/// other than core 0 are woken up when new input is signaled; core 0 takes
+/// the interrupt itself, which cannot happen while it waits here, so it is
+/// woken up by the counter event stream and polls the UART.
+pub fn read_byte(vt: Vt) -> u8 {
+    loop {
+        let sleep = {
+            let mut console = CONSOLE.lock();
+            if let Some(byte) = console.try_read_byte(vt) {
+                return byte;
+            }
+            console.inner().interrupts_enabled()
+        };
+        if sleep {
+            aarch64::wfe();
+        }
+    }
+}
+
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     #[cfg(not(test))]
     {
-        use core::fmt::Write;
-        let mut console = CONSOLE.lock();
-        console.write_fmt(args).unwrap();
+        CONSOLE.lock().print(Vt::Shell, args);
     }
 
     #[cfg(test)]
@@ -93,3 +443,35 @@ pub macro kprintln {
 pub macro kprint($($arg:tt)*) {
     _print(format_args!($($arg)*))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(hotkeys: &mut Hotkeys, input: &[u8]) -> (Vec<u8>, Vec<usize>) {
+        let (mut delivered, mut switches) = (Vec::new(), Vec::new());
+        for &byte in input {
+            if let Some(vt) = hotkeys.feed(byte, |b| delivered.push(b)) {
+                switches.push(vt);
+            }
+        }
+        (delivered, switches)
+    }
+
+    #[test]
+    fn hotkeys() {
+        let mut hotkeys = Hotkeys::new();
+        assert_eq!(feed(&mut hotkeys, b"ls\r"), (b"ls\r".to_vec(), vec![]));
+        assert_eq!(feed(&mut hotkeys, b"a\x1b[1;3Qb"), (b"ab".to_vec(), vec![1]));
+        assert_eq!(feed(&mut hotkeys, b"\x1b3\x1b1"), (vec![], vec![2, 0]));
+    }
+
+    #[test]
+    fn other_escapes_pass_through() {
+        let mut hotkeys = Hotkeys::new();
+        assert_eq!(feed(&mut hotkeys, b"\x1b[A"), (b"\x1b[A".to_vec(), vec![]));
+        assert_eq!(feed(&mut hotkeys, b"\x1b[1;5C"), (b"\x1b[1;5C".to_vec(), vec![]));
+        assert_eq!(feed(&mut hotkeys, b"\x1b"), (vec![], vec![]));
+        assert_eq!(feed(&mut hotkeys, b"x"), (b"\x1bx".to_vec(), vec![]));
+    }
+}
@@ -0,0 +1,98 @@
+/// Capacity of a `Ring` in bytes.
+pub const RING_SIZE: usize = 8192;
+
+/// A fixed-size byte queue. When it is full, pushing a byte drops the oldest
+/// one, so the ring always holds the most recent `RING_SIZE` bytes.
+pub struct Ring {
+    buf: [u8; RING_SIZE],
+    /// Index of the oldest byte.
+    head: usize,
+    len: usize,
+}
+
+impl Ring {
+    pub const fn new() -> Ring {
+        Ring { buf: [0; RING_SIZE], head: 0, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends `byte`, dropping the oldest byte if the ring is full.
+    pub fn push(&mut self, byte: u8) {
+        self.buf[(self.head + self.len) % RING_SIZE] = byte;
+        if self.len == RING_SIZE {
+            self.head = (self.head + 1) % RING_SIZE;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    /// Removes and returns the oldest byte.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % RING_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    /// Returns the contents, oldest first, as two slices.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let end = self.head + self.len;
+        if end <= RING_SIZE {
+            (&self.buf[self.head..end], &[])
+        } else {
+            (&self.buf[self.head..], &self.buf[..end - RING_SIZE])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(ring: &Ring) -> Vec<u8> {
+        let (a, b) = ring.as_slices();
+        a.iter().chain(b).cloned().collect()
+    }
+
+    #[test]
+    fn push_pop() {
+        let mut ring = Ring::new();
+        assert_eq!(ring.pop(), None);
+        for &b in b"abc" {
+            ring.push(b);
+        }
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.pop(), Some(b'a'));
+        assert_eq!(contents(&ring), b"bc");
+        ring.clear();
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn overwrites_oldest() {
+        let mut ring = Ring::new();
+        for i in 0..RING_SIZE + 10 {
+            ring.push(i as u8);
+        }
+        assert_eq!(ring.len(), RING_SIZE);
+        let bytes = contents(&ring);
+        assert_eq!(bytes[0], 10);
+        assert_eq!(bytes[RING_SIZE - 1], (RING_SIZE + 9) as u8);
+        assert_eq!(ring.pop(), Some(10));
+    }
+}
@@ -0,0 +1,168 @@
+use pi::atags::Atags;
+use pi::interrupt::Interrupt;
+use pi::pl011::{self, Pl011};
+use pi::uart::MiniUart;
+use shim::io::{self, Read, Write};
+
+/// The serial port the console runs on.
+///
+/// The port is chosen at boot by a `console=` option on the ATAG command
+/// line, named as on Linux: `console=ttyS0` selects the mini UART and
+/// `console=ttyAMA0[,<options>]` the PL011, with `options` as parsed by
+/// `pl011::Config::parse` (e.g. `console=ttyAMA0,115200n8`). The last
+/// recognized option wins; without one, the mini UART is used.
+pub enum Serial {
+    Mini(MiniUart),
+    Pl011(Pl011),
+}
+
+/// A serial port named on the command line.
+#[derive(Debug, PartialEq)]
+enum Choice {
+    Mini,
+    Pl011(pl011::Config),
+}
+
+/// Returns the serial port selected by the last recognized `console=` option
+/// of `cmdline`, if any. Options naming other devices, such as the ones added
+/// by the firmware, are ignored.
+fn parse_cmdline(cmdline: &str) -> Option<Choice> {
+    let mut choice = None;
+    for arg in cmdline.split_whitespace() {
+        if !arg.starts_with("console=") {
+            continue;
+        }
+        let mut parts = arg["console=".len()..].splitn(2, ',');
+        let name = parts.next().unwrap();
+        let options = parts.next();
+        match name {
+            "ttyS0" => choice = Some(Choice::Mini),
+            "ttyAMA0" => match options {
+                None => choice = Some(Choice::Pl011(pl011::Config::default())),
+                Some(options) => {
+                    if let Some(config) = pl011::Config::parse(options) {
+                        choice = Some(Choice::Pl011(config));
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    choice
+}
+
+impl Serial {
+    /// Initializes the serial port selected on the ATAG command line.
+    pub fn from_cmdline() -> Serial {
+        let choice = Atags::get().find_map(|atag| atag.cmd()).and_then(parse_cmdline);
+        match choice {
+            Some(Choice::Pl011(config)) => Serial::Pl011(Pl011::new(config)),
+            Some(Choice::Mini) | None => Serial::Mini(MiniUart::new()),
+        }
+    }
+
+    /// Returns the interrupt raised by this port.
+    pub fn interrupt(&self) -> Interrupt {
+        match self {
+            Serial::Mini(_) => Interrupt::Aux,
+            Serial::Pl011(_) => Interrupt::Uart,
+        }
+    }
+
+    pub fn enable_interrupts(&mut self) {
+        match self {
+            Serial::Mini(uart) => uart.enable_interrupts(),
+            Serial::Pl011(uart) => uart.enable_interrupts(),
+        }
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        match self {
+            Serial::Mini(uart) => uart.interrupts_enabled(),
+            Serial::Pl011(uart) => uart.interrupts_enabled(),
+        }
+    }
+
+    pub fn handle_interrupt(&mut self) {
+        match self {
+            Serial::Mini(uart) => uart.handle_interrupt(),
+            Serial::Pl011(uart) => uart.handle_interrupt(),
+        }
+    }
+
+    pub fn flush(&mut self) {
+        match self {
+            Serial::Mini(uart) => uart.flush(),
+            Serial::Pl011(uart) => uart.flush(),
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match self {
+            Serial::Mini(uart) => uart.write_byte(byte),
+            Serial::Pl011(uart) => uart.write_byte(byte),
+        }
+    }
+
+    pub fn has_byte(&mut self) -> bool {
+        match self {
+            Serial::Mini(uart) => uart.has_byte(),
+            Serial::Pl011(uart) => uart.has_byte(),
+        }
+    }
+
+    pub fn read_byte(&mut self) -> u8 {
+        match self {
+            Serial::Mini(uart) => uart.read_byte(),
+            Serial::Pl011(uart) => uart.read_byte(),
+        }
+    }
+}
+
+impl io::Read for Serial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Serial::Mini(uart) => uart.read(buf),
+            Serial::Pl011(uart) => uart.read(buf),
+        }
+    }
+}
+
+impl io::Write for Serial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Serial::Mini(uart) => uart.write(buf),
+            Serial::Pl011(uart) => uart.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Serial::flush(self);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_cmdline, Choice};
+    use pi::pl011::{Config, Parity};
+
+    #[test]
+    fn cmdline() {
+        assert_eq!(parse_cmdline(""), None);
+        assert_eq!(parse_cmdline("root=/dev/mmcblk0p2 console=tty1"), None);
+        assert_eq!(parse_cmdline("console=ttyS0"), Some(Choice::Mini));
+        assert_eq!(parse_cmdline("console=ttyAMA0"), Some(Choice::Pl011(Config::default())));
+
+        let config = Config { baud: 9600, parity: Parity::Even, data_bits: 7, ..Config::default() };
+        assert_eq!(parse_cmdline("quiet console=ttyAMA0,9600e7"), Some(Choice::Pl011(config)));
+    }
+
+    #[test]
+    fn last_console_wins() {
+        assert_eq!(parse_cmdline("console=ttyAMA0 console=ttyS0"), Some(Choice::Mini));
+        assert_eq!(parse_cmdline("console=ttyS0 console=tty1"), Some(Choice::Mini));
+        // malformed options are ignored
+        assert_eq!(parse_cmdline("console=ttyS0 console=ttyAMA0,fast"), Some(Choice::Mini));
+    }
+}
@@ -0,0 +1,279 @@
+//! Support for debugging trapped contexts: access to memory through the page
+//! tables of the trapped context, software breakpoints and single-stepping.
+//!
+//! Breakpoints are `brk` instructions patched over the original instruction.
+//! To resume from a breakpoint, the original instruction is put back, stepped
+//! over with `MDSCR_EL1.SS`, and the `brk` is re-inserted from the resulting
+//! software step exception.
+
+pub mod gdb;
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use aarch64::{MDSCR_EL1, OSLAR_EL1, PAR_EL1, SPSR_EL1};
+use kernel_api::{OsError, OsResult};
+
+use crate::mutex::Mutex;
+use crate::param::NCORES;
+use crate::traps::TrapFrame;
+
+/// Comment of the `brk` instructions inserted by `insert_breakpoint`.
+pub const BREAKPOINT_COMMENT: u16 = 0xdb9;
+
+/// Maximum number of breakpoints that can be set at once.
+pub const MAX_BREAKPOINTS: usize = 16;
+
+/// Encoding of `brk #BREAKPOINT_COMMENT`.
+const BRK_INSN: u32 = 0xd420_0000 | (BREAKPOINT_COMMENT as u32) << 5;
+
+/// SPSR mode bits for EL1 using `SP_EL1`.
+const MODE_EL1H: u64 = 0b0101;
+
+#[derive(Debug, Copy, Clone)]
+struct Breakpoint {
+    /// Address the breakpoint was set at, in the context that set it.
+    va: usize,
+    /// Physical address of the patched instruction.
+    pa: usize,
+    /// The instruction replaced by the `brk`.
+    insn: u32,
+}
+
+static BREAKPOINTS: Mutex<[Option<Breakpoint>; MAX_BREAKPOINTS]> =
+    Mutex::new([None; MAX_BREAKPOINTS]);
+
+/// How to leave the debugger.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Resume {
+    /// Run until the next breakpoint.
+    Continue,
+    /// Execute a single instruction and trap again.
+    Step,
+}
+
+/// A single step in progress on one core.
+#[derive(Debug, Copy, Clone)]
+struct Step {
+    /// Physical address of a breakpoint to re-insert after the step.
+    reinsert: Option<usize>,
+    /// Whether to stop in the debugger after the step.
+    stop: bool,
+    /// The `D` and `I` bits of the stepped context's SPSR before the step.
+    mask: u64,
+}
+
+static STEPS: [Mutex<Option<Step>>; NCORES] = [
+    Mutex::new(None),
+    Mutex::new(None),
+    Mutex::new(None),
+    Mutex::new(None),
+];
+
+/// Translates `va` through the page tables of the trapped context, which are
+/// still installed while handling the trap.
+pub fn translate(va: usize) -> Option<usize> {
+    let par = unsafe {
+        aarch64::at_s1e1r(va as u64);
+        PAR_EL1.get()
+    };
+    if PAR_EL1::get_value(par, PAR_EL1::F) != 0 {
+        return None;
+    }
+    Some(PAR_EL1::get_masked(par, PAR_EL1::PA) as usize | (va & 0xfff))
+}
+
+/// Calls `f` with the physical address of every byte of `[va, va + len)` and
+/// its offset in the range. The whole range is translated up front so that a
+/// failing access has no side effect.
+fn for_each_byte<F: FnMut(usize, usize)>(va: usize, len: usize, mut f: F) -> OsResult<()> {
+    let end = va.checked_add(len).ok_or(OsError::BadAddress)?;
+    let mut page = va & !0xfff;
+    while page < end {
+        translate(page).ok_or(OsError::BadAddress)?;
+        page = match page.checked_add(0x1000) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+
+    for offset in 0..len {
+        let pa = translate(va + offset).ok_or(OsError::BadAddress)?;
+        f(pa, offset);
+    }
+    Ok(())
+}
+
+/// Reads `buf.len()` bytes at the virtual address `va` of the trapped context.
+/// Breakpoints are hidden: the instructions they replaced are returned.
+///
+/// # Errors
+///
+/// Returns `OsError::BadAddress` if any byte is not mapped.
+pub fn read_memory(va: usize, buf: &mut [u8]) -> OsResult<()> {
+    for_each_byte(va, buf.len(), |pa, i| unsafe {
+        buf[i] = core::ptr::read_volatile(pa as *const u8);
+    })?;
+
+    for bp in BREAKPOINTS.lock().iter().flatten() {
+        for (i, &byte) in bp.insn.to_le_bytes().iter().enumerate() {
+            let addr = bp.va.wrapping_add(i);
+            if addr >= va && addr - va < buf.len() && translate(addr) == Some(bp.pa + i) {
+                buf[addr - va] = byte;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes `buf` to the virtual address `va` of the trapped context. Memory is
+/// written through its physical address, so read-only pages such as program
+/// text can be patched too.
+///
+/// # Errors
+///
+/// Returns `OsError::BadAddress` if any byte is not mapped.
+pub fn write_memory(va: usize, buf: &[u8]) -> OsResult<()> {
+    for_each_byte(va, buf.len(), |pa, i| unsafe {
+        core::ptr::write_volatile(pa as *mut u8, buf[i]);
+        aarch64::sync_icache(pa as u64);
+    })
+}
+
+/// Reads the `u64` at the virtual address `va` of the trapped context.
+pub fn read_u64(va: usize) -> OsResult<u64> {
+    let mut bytes = [0u8; size_of::<u64>()];
+    read_memory(va, &mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+unsafe fn patch(pa: usize, insn: u32) -> u32 {
+    let old = core::ptr::read_volatile(pa as *const u32);
+    core::ptr::write_volatile(pa as *mut u32, insn);
+    aarch64::sync_icache(pa as u64);
+    old
+}
+
+/// Sets a breakpoint at the virtual address `va` of the trapped context.
+///
+/// # Errors
+///
+/// Returns `OsError::InvalidArgument` if `va` is not word-aligned,
+/// `OsError::BadAddress` if it is not mapped, `OsError::FileExists` if a
+/// breakpoint is already set there and `OsError::NoMemory` if all
+/// `MAX_BREAKPOINTS` slots are in use.
+pub fn insert_breakpoint(va: usize) -> OsResult<()> {
+    if va % 4 != 0 {
+        return Err(OsError::InvalidArgument);
+    }
+    let pa = translate(va).ok_or(OsError::BadAddress)?;
+
+    let mut breakpoints = BREAKPOINTS.lock();
+    if breakpoints.iter().flatten().any(|bp| bp.pa == pa) {
+        return Err(OsError::FileExists);
+    }
+    let slot = breakpoints.iter_mut().find(|bp| bp.is_none()).ok_or(OsError::NoMemory)?;
+    let insn = unsafe { patch(pa, BRK_INSN) };
+    *slot = Some(Breakpoint { va, pa, insn });
+    Ok(())
+}
+
+/// Removes the breakpoint set at the virtual address `va`.
+///
+/// # Errors
+///
+/// Returns `OsError::NoEntry` if there is no breakpoint at `va`.
+pub fn remove_breakpoint(va: usize) -> OsResult<()> {
+    let pa = translate(va);
+    let mut breakpoints = BREAKPOINTS.lock();
+    let slot = breakpoints
+        .iter_mut()
+        .find(|bp| bp.map_or(false, |bp| Some(bp.pa) == pa || bp.va == va))
+        .ok_or(OsError::NoEntry)?;
+    let bp = slot.take().unwrap();
+    unsafe { patch(bp.pa, bp.insn) };
+    Ok(())
+}
+
+/// Returns the addresses of all breakpoints, in the order they were set in.
+pub fn breakpoints() -> Vec<usize> {
+    BREAKPOINTS.lock().iter().flatten().map(|bp| bp.va).collect()
+}
+
+/// Returns the breakpoint that `tf` is stopped at, if any.
+fn breakpoint_at(tf: &TrapFrame) -> Option<Breakpoint> {
+    let pa = translate(tf.get_elr() as usize)?;
+    BREAKPOINTS.lock().iter().flatten().find(|bp| bp.pa == pa).cloned()
+}
+
+/// Returns `true` if `tf` is stopped at a breakpoint set with
+/// `insert_breakpoint`, rather than at a `brk` compiled into the program.
+pub fn is_breakpoint(tf: &TrapFrame) -> bool {
+    breakpoint_at(tf).is_some()
+}
+
+/// Returns `true` if `tf` was trapped from EL1.
+pub fn is_kernel(tf: &TrapFrame) -> bool {
+    SPSR_EL1::get_value(tf.get_spsr(), SPSR_EL1::M) & 0b1100 != 0
+}
+
+/// Returns the stack pointer of the trapped context. Kernel contexts run on
+/// `SP_EL1`, which is not part of the trap frame: the frame was pushed right
+/// below the interrupted stack.
+pub fn stack_pointer(tf: &TrapFrame) -> u64 {
+    if SPSR_EL1::get_value(tf.get_spsr(), SPSR_EL1::M) == MODE_EL1H {
+        tf as *const TrapFrame as u64 + size_of::<TrapFrame>() as u64
+    } else {
+        tf.get_sp()
+    }
+}
+
+/// Prepares `tf` to be resumed. If `tf` is stopped at a breakpoint, the
+/// original instruction is stepped over first, even when continuing.
+pub fn resume(tf: &mut TrapFrame, how: Resume) {
+    let bp = breakpoint_at(tf);
+    if how == Resume::Continue && bp.is_none() {
+        return;
+    }
+
+    if let Some(bp) = bp {
+        unsafe { patch(bp.pa, bp.insn) };
+    }
+
+    // Interrupts are masked for the step so that the step exception is taken
+    // right after the stepped instruction, on this core.
+    let spsr = tf.get_spsr();
+    let mask = spsr & (SPSR_EL1::D | SPSR_EL1::I);
+    *STEPS[aarch64::affinity()].lock() = Some(Step {
+        reinsert: bp.map(|bp| bp.pa),
+        stop: how == Resume::Step,
+        mask,
+    });
+    tf.set_spsr((spsr & !SPSR_EL1::D) | SPSR_EL1::I | SPSR_EL1::SS);
+    unsafe {
+        OSLAR_EL1.set(0);
+        MDSCR_EL1.set(MDSCR_EL1.get() | MDSCR_EL1::SS | MDSCR_EL1::KDE);
+    }
+}
+
+/// Finishes a single step after a software step exception. Returns `true` if
+/// the debugger should be entered.
+pub fn finish_step(tf: &mut TrapFrame) -> bool {
+    unsafe {
+        MDSCR_EL1.set(MDSCR_EL1.get() & !(MDSCR_EL1::SS | MDSCR_EL1::KDE));
+    }
+    let step = match STEPS[aarch64::affinity()].lock().take() {
+        Some(step) => step,
+        None => return true,
+    };
+
+    let spsr = tf.get_spsr() & !(SPSR_EL1::SS | SPSR_EL1::D | SPSR_EL1::I);
+    tf.set_spsr(spsr | step.mask);
+    if let Some(pa) = step.reinsert {
+        let breakpoints = BREAKPOINTS.lock();
+        if breakpoints.iter().flatten().any(|bp| bp.pa == pa) {
+            unsafe { patch(pa, BRK_INSN) };
+        }
+    }
+    step.stop
+}
@@ -0,0 +1,618 @@
+//! A stub for the GDB remote serial protocol, so that `gdb` can debug trapped
+//! contexts over a serial line.
+//!
+//! The stub is entered with the `gdb` command of the debug monitor. From then
+//! on every breakpoint and step is reported to `gdb` until it detaches. The
+//! stub supports register and memory access, `Z0` software breakpoints and
+//! `c`/`s`; the target description only contains the core registers.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kernel_api::OsResult;
+use shim::io;
+
+use crate::console::CONSOLE;
+use crate::debug::{self, Resume};
+use crate::traps::TrapFrame;
+
+/// Largest packet accepted from `gdb`, as advertised in `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+
+/// Number of the `sp`, `pc` and `cpsr` registers; `x0`..`x30` are 0 to 30.
+const SP: usize = 31;
+const PC: usize = 32;
+const CPSR: usize = 33;
+const NUM_REGS: usize = 34;
+
+/// Set while `gdb` is attached.
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+
+/// What the stub is debugging.
+pub trait Target {
+    /// Returns the value of register `n`, numbered as in the target
+    /// description.
+    fn register(&self, n: usize) -> u64;
+
+    /// Sets register `n` to `val`. Returns `false` if it cannot be changed.
+    fn set_register(&mut self, n: usize, val: u64) -> bool;
+
+    /// Reads `buf.len()` bytes at `addr`.
+    fn read_memory(&mut self, addr: usize, buf: &mut [u8]) -> OsResult<()>;
+
+    /// Writes `buf` at `addr`.
+    fn write_memory(&mut self, addr: usize, buf: &[u8]) -> OsResult<()>;
+
+    /// Sets a software breakpoint at `addr`.
+    fn insert_breakpoint(&mut self, addr: usize) -> OsResult<()>;
+
+    /// Removes the software breakpoint at `addr`.
+    fn remove_breakpoint(&mut self, addr: usize) -> OsResult<()>;
+}
+
+/// How a session with `gdb` ended.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Exit {
+    /// `gdb` asked to continue or step and is waiting for the next stop.
+    Resume(Resume),
+    /// `gdb` detached or killed the target.
+    Detach,
+}
+
+/// The protocol side of the stub, talking to `gdb` over `conn`.
+pub struct Stub<'a, T> {
+    conn: &'a mut T,
+}
+
+impl<'a, T: io::Read + io::Write> Stub<'a, T> {
+    pub fn new(conn: &'a mut T) -> Stub<'a, T> {
+        Stub { conn }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8];
+        loop {
+            match self.conn.read(&mut byte) {
+                Ok(1) => return Ok(byte[0]),
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn read_hex_byte(&mut self) -> io::Result<Option<u8>> {
+        let hi = self.read_byte()?;
+        let lo = self.read_byte()?;
+        Ok(match (hex_digit(hi), hex_digit(lo)) {
+            (Some(hi), Some(lo)) => Some(hi << 4 | lo),
+            _ => None,
+        })
+    }
+
+    /// Reads the next packet into `buf`, acknowledging it. Anything outside
+    /// of a packet, including interrupt requests, is ignored.
+    pub fn read_packet(&mut self, buf: &mut Vec<u8>) -> io::Result<()> {
+        loop {
+            while self.read_byte()? != b'$' {}
+
+            buf.clear();
+            let mut sum = 0u8;
+            let mut valid = true;
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    b'$' => {
+                        buf.clear();
+                        sum = 0;
+                    }
+                    byte => {
+                        if buf.len() == PACKET_SIZE {
+                            valid = false;
+                        } else {
+                            buf.push(byte);
+                        }
+                        sum = sum.wrapping_add(byte);
+                    }
+                }
+            }
+
+            if valid && self.read_hex_byte()? == Some(sum) {
+                self.conn.write_all(b"+")?;
+                return Ok(());
+            }
+            self.conn.write_all(b"-")?;
+        }
+    }
+
+    /// Sends `data` as a packet, resending it until `gdb` acknowledges it.
+    pub fn write_packet(&mut self, data: &[u8]) -> io::Result<()> {
+        let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let mut trailer = [b'#', 0, 0];
+        trailer[1] = HEX[(sum >> 4) as usize];
+        trailer[2] = HEX[(sum & 0xf) as usize];
+        loop {
+            self.conn.write_all(b"$")?;
+            self.conn.write_all(data)?;
+            self.conn.write_all(&trailer)?;
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Answers packets from `gdb` until it resumes or detaches from `target`.
+    pub fn serve(&mut self, target: &mut dyn Target) -> io::Result<Exit> {
+        let mut packet = Vec::new();
+        loop {
+            self.read_packet(&mut packet)?;
+            let mut reply = Vec::new();
+            if let Some(exit) = handle(&packet, target, &mut reply) {
+                if exit == Exit::Detach && packet.first() == Some(&b'D') {
+                    self.write_packet(b"OK")?;
+                }
+                return Ok(exit);
+            }
+            self.write_packet(&reply)?;
+        }
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parses a big-endian hex number, as used for addresses and lengths.
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter().try_fold(0usize, |acc, &c| hex_digit(c).map(|d| acc << 4 | d as usize))
+}
+
+/// Decodes pairs of hex digits into bytes.
+fn decode_hex(s: &[u8]) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    s.chunks(2)
+        .map(|pair| Some(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?))
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8], out: &mut Vec<u8>) {
+    for &b in bytes {
+        out.push(HEX[(b >> 4) as usize]);
+        out.push(HEX[(b & 0xf) as usize]);
+    }
+}
+
+/// Size of register `n` in bytes.
+fn register_size(n: usize) -> usize {
+    if n == CPSR { 4 } else { 8 }
+}
+
+fn encode_register(target: &dyn Target, n: usize, out: &mut Vec<u8>) {
+    let bytes = target.register(n).to_le_bytes();
+    encode_hex(&bytes[..register_size(n)], out);
+}
+
+fn decode_register(hex: &[u8], n: usize) -> Option<u64> {
+    let bytes = decode_hex(hex)?;
+    if bytes.len() != register_size(n) {
+        return None;
+    }
+    Some(bytes.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64))
+}
+
+/// Decodes the values of all registers, as sent with `G`.
+fn decode_registers(mut hex: &[u8]) -> Option<[u64; NUM_REGS]> {
+    let mut regs = [0; NUM_REGS];
+    for (n, reg) in regs.iter_mut().enumerate() {
+        let len = 2 * register_size(n);
+        *reg = decode_register(hex.get(..len)?, n)?;
+        hex = &hex[len..];
+    }
+    Some(regs)
+}
+
+/// Splits `s` at the first `sep`.
+fn split(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let i = s.iter().position(|&c| c == sep)?;
+    Some((&s[..i], &s[i + 1..]))
+}
+
+/// Parses `addr,len`.
+fn parse_range(s: &[u8]) -> Option<(usize, usize)> {
+    let (addr, len) = split(s, b',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+/// The target description, listing the core registers only.
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target><architecture>aarch64</architecture>\
+         <feature name=\"org.gnu.gdb.aarch64.core\">",
+    );
+    for n in 0..31 {
+        write!(xml, "<reg name=\"x{}\" bitsize=\"64\"/>", n).unwrap();
+    }
+    xml.push_str(
+        "<reg name=\"sp\" bitsize=\"64\" type=\"data_ptr\"/>\
+         <reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\"/>\
+         <reg name=\"cpsr\" bitsize=\"32\"/>\
+         </feature></target>",
+    );
+    xml
+}
+
+/// Handles `packet`, writing the reply to `reply`. Returns `Some` if the
+/// target should be resumed instead of replying.
+fn handle(packet: &[u8], target: &mut dyn Target, reply: &mut Vec<u8>) -> Option<Exit> {
+    const ERROR: &[u8] = b"E01";
+    const FAULT: &[u8] = b"E0e";
+
+    let (&kind, args) = match packet.split_first() {
+        Some(split) => split,
+        None => return None,
+    };
+    match kind {
+        b'?' => reply.extend_from_slice(b"S05"),
+        b'g' => {
+            for n in 0..NUM_REGS {
+                encode_register(target, n, reply);
+            }
+        }
+        b'G' => match decode_registers(args) {
+            Some(regs) => {
+                for (n, &val) in regs.iter().enumerate() {
+                    // registers that cannot be changed are left alone
+                    if target.register(n) != val {
+                        target.set_register(n, val);
+                    }
+                }
+                reply.extend_from_slice(b"OK");
+            }
+            None => reply.extend_from_slice(ERROR),
+        },
+        b'p' => match parse_hex(args) {
+            Some(n) if n < NUM_REGS => encode_register(target, n, reply),
+            _ => reply.extend_from_slice(ERROR),
+        },
+        b'P' => {
+            let parsed = split(args, b'=').and_then(|(n, val)| {
+                let n = parse_hex(n).filter(|&n| n < NUM_REGS)?;
+                Some((n, decode_register(val, n)?))
+            });
+            match parsed {
+                Some((n, val)) if target.set_register(n, val) => reply.extend_from_slice(b"OK"),
+                _ => reply.extend_from_slice(ERROR),
+            }
+        }
+        b'm' => match parse_range(args) {
+            Some((addr, len)) if len <= PACKET_SIZE / 2 => {
+                let mut buf = vec![0u8; len];
+                match target.read_memory(addr, &mut buf) {
+                    Ok(()) => encode_hex(&buf, reply),
+                    Err(_) => reply.extend_from_slice(FAULT),
+                }
+            }
+            _ => reply.extend_from_slice(ERROR),
+        },
+        b'M' => {
+            let parsed = split(args, b':').and_then(|(range, data)| {
+                let (addr, len) = parse_range(range)?;
+                Some((addr, decode_hex(data).filter(|data| data.len() == len)?))
+            });
+            match parsed {
+                Some((addr, data)) => match target.write_memory(addr, &data) {
+                    Ok(()) => reply.extend_from_slice(b"OK"),
+                    Err(_) => reply.extend_from_slice(FAULT),
+                },
+                None => reply.extend_from_slice(ERROR),
+            }
+        }
+        b'Z' | b'z' => {
+            // only software breakpoints (type 0) are supported
+            let addr = match split(args, b',') {
+                Some((b"0", rest)) => split(rest, b',').and_then(|(addr, _)| parse_hex(addr)),
+                _ => return None,
+            };
+            let result = match addr {
+                Some(addr) if kind == b'Z' => target.insert_breakpoint(addr),
+                Some(addr) => target.remove_breakpoint(addr),
+                None => {
+                    reply.extend_from_slice(ERROR);
+                    return None;
+                }
+            };
+            match result {
+                Ok(()) | Err(kernel_api::OsError::FileExists) => reply.extend_from_slice(b"OK"),
+                Err(_) => reply.extend_from_slice(ERROR),
+            }
+        }
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                target.set_register(PC, addr as u64);
+            }
+            let how = if kind == b'c' { Resume::Continue } else { Resume::Step };
+            return Some(Exit::Resume(how));
+        }
+        b'D' | b'k' => return Some(Exit::Detach),
+        b'H' => reply.extend_from_slice(b"OK"),
+        b'q' if args.starts_with(b"Supported") => {
+            write!(Bytes(reply), "PacketSize={:x};qXfer:features:read+", PACKET_SIZE).unwrap();
+        }
+        b'q' if args == b"Attached" => reply.extend_from_slice(b"1"),
+        b'q' if args.starts_with(b"Xfer:features:read:target.xml:") => {
+            let xml = target_xml();
+            match parse_range(&args[b"Xfer:features:read:target.xml:".len()..]) {
+                Some((offset, len)) => {
+                    let start = core::cmp::min(offset, xml.len());
+                    let end = core::cmp::min(start + core::cmp::min(len, PACKET_SIZE / 2), xml.len());
+                    reply.push(if end == xml.len() { b'l' } else { b'm' });
+                    reply.extend_from_slice(&xml.as_bytes()[start..end]);
+                }
+                None => reply.extend_from_slice(ERROR),
+            }
+        }
+        // an empty reply tells gdb the packet is not supported
+        _ => {}
+    }
+    None
+}
+
+/// Adapts a byte vector to `fmt::Write`.
+struct Bytes<'a>(&'a mut Vec<u8>);
+
+impl<'a> Write for Bytes<'a> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// A trapped context as seen by the stub.
+struct Trapped<'a>(&'a mut TrapFrame);
+
+impl<'a> Target for Trapped<'a> {
+    fn register(&self, n: usize) -> u64 {
+        match n {
+            0..=30 => self.0.get_gpr(n as u8),
+            SP => debug::stack_pointer(&self.0),
+            PC => self.0.get_elr(),
+            _ => self.0.get_spsr() & 0xffff_ffff,
+        }
+    }
+
+    fn set_register(&mut self, n: usize, val: u64) -> bool {
+        match n {
+            0..=30 => self.0.set_gpr(n as u8, val),
+            SP if debug::is_kernel(self.0) => return false,
+            SP => self.0.set_sp(val),
+            PC => self.0.set_elr(val),
+            _ => self.0.set_spsr(val),
+        }
+        true
+    }
+
+    fn read_memory(&mut self, addr: usize, buf: &mut [u8]) -> OsResult<()> {
+        debug::read_memory(addr, buf)
+    }
+
+    fn write_memory(&mut self, addr: usize, buf: &[u8]) -> OsResult<()> {
+        debug::write_memory(addr, buf)
+    }
+
+    fn insert_breakpoint(&mut self, addr: usize) -> OsResult<()> {
+        debug::insert_breakpoint(addr)
+    }
+
+    fn remove_breakpoint(&mut self, addr: usize) -> OsResult<()> {
+        debug::remove_breakpoint(addr)
+    }
+}
+
+/// Returns `true` if `gdb` is attached, in which case traps are reported to
+/// it rather than to the debug monitor.
+pub fn is_attached() -> bool {
+    ATTACHED.load(Ordering::Relaxed)
+}
+
+/// Serves `gdb` over the console until it resumes `tf`. If `stopped` is
+/// `true`, `tf` is first reported to `gdb` as stopped; this is the case for
+/// every trap after the one `gdb` attached from.
+pub fn enter(tf: &mut TrapFrame, stopped: bool) {
+    ATTACHED.store(true, Ordering::Relaxed);
+    let exit = {
+        let mut console = CONSOLE.lock();
+        let mut stub = Stub::new(console.uart());
+        let mut target = Trapped(tf);
+        if stopped {
+            stub.write_packet(b"S05").and_then(|_| stub.serve(&mut target))
+        } else {
+            stub.serve(&mut target)
+        }
+    };
+    match exit {
+        Ok(Exit::Resume(how)) => debug::resume(tf, how),
+        Ok(Exit::Detach) | Err(_) => {
+            ATTACHED.store(false, Ordering::Relaxed);
+            debug::resume(tf, Resume::Continue);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel_api::OsError;
+    use shim::io::Cursor;
+
+    struct Mock {
+        regs: [u64; NUM_REGS],
+        mem: Vec<u8>,
+        breakpoints: Vec<usize>,
+    }
+
+    impl Mock {
+        fn new() -> Mock {
+            Mock { regs: [0; NUM_REGS], mem: (0..64).collect(), breakpoints: Vec::new() }
+        }
+    }
+
+    impl Target for Mock {
+        fn register(&self, n: usize) -> u64 {
+            self.regs[n]
+        }
+
+        fn set_register(&mut self, n: usize, val: u64) -> bool {
+            self.regs[n] = val;
+            true
+        }
+
+        fn read_memory(&mut self, addr: usize, buf: &mut [u8]) -> OsResult<()> {
+            let src = self.mem.get(addr..addr + buf.len()).ok_or(OsError::BadAddress)?;
+            buf.copy_from_slice(src);
+            Ok(())
+        }
+
+        fn write_memory(&mut self, addr: usize, buf: &[u8]) -> OsResult<()> {
+            let dst = self.mem.get_mut(addr..addr + buf.len()).ok_or(OsError::BadAddress)?;
+            dst.copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn insert_breakpoint(&mut self, addr: usize) -> OsResult<()> {
+            self.breakpoints.push(addr);
+            Ok(())
+        }
+
+        fn remove_breakpoint(&mut self, addr: usize) -> OsResult<()> {
+            let i = self.breakpoints.iter().position(|&a| a == addr).ok_or(OsError::NoEntry)?;
+            self.breakpoints.remove(i);
+            Ok(())
+        }
+    }
+
+    fn reply(packet: &str, target: &mut Mock) -> (String, Option<Exit>) {
+        let mut reply = Vec::new();
+        let exit = handle(packet.as_bytes(), target, &mut reply);
+        (String::from_utf8(reply).unwrap(), exit)
+    }
+
+    /// A connection that replays `input` and records what is written.
+    struct Wire {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl io::Read for Wire {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.input.read(buf)? {
+                0 => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of input")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl io::Write for Wire {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn packets() {
+        let mut wire = Wire {
+            input: Cursor::new(b"junk$m0,4#fc$m0,4#fd+".to_vec()),
+            output: Vec::new(),
+        };
+        let mut packet = Vec::new();
+        Stub::new(&mut wire).read_packet(&mut packet).unwrap();
+        assert_eq!(packet, b"m0,4");
+        assert_eq!(wire.output, b"-+");
+
+        Stub::new(&mut wire).write_packet(b"OK").unwrap();
+        assert_eq!(&wire.output[2..], b"$OK#9a");
+    }
+
+    #[test]
+    fn registers() {
+        let mut target = Mock::new();
+        target.regs[0] = 0x1122_3344_5566_7788;
+        target.regs[CPSR] = 0x6000_03c5;
+
+        let (regs, _) = reply("g", &mut target);
+        assert_eq!(regs.len(), 2 * (33 * 8 + 4));
+        assert!(regs.starts_with("8877665544332211"));
+        assert!(regs.ends_with("c5030060"));
+
+        assert_eq!(reply("p20", &mut target).0, "0000000000000000");
+        assert_eq!(reply("P20=efbeadde00000000", &mut target).0, "OK");
+        assert_eq!(target.regs[PC], 0xdead_beef);
+        assert_eq!(reply("P21=0000", &mut target).0, "E01");
+
+        let (_, exit) = reply(&format!("G{}", regs), &mut target);
+        assert_eq!(exit, None);
+        assert_eq!(target.regs[PC], 0);
+        assert_eq!(target.regs[0], 0x1122_3344_5566_7788);
+    }
+
+    #[test]
+    fn memory() {
+        let mut target = Mock::new();
+        assert_eq!(reply("m4,4", &mut target).0, "04050607");
+        assert_eq!(reply("m3e,4", &mut target).0, "E0e");
+        assert_eq!(reply("M8,2:abcd", &mut target).0, "OK");
+        assert_eq!(&target.mem[8..10], &[0xab, 0xcd]);
+        assert_eq!(reply("M8,2:ab", &mut target).0, "E01");
+    }
+
+    #[test]
+    fn breakpoints_and_resume() {
+        let mut target = Mock::new();
+        assert_eq!(reply("Z0,400a0,4", &mut target).0, "OK");
+        assert_eq!(target.breakpoints, vec![0x400a0]);
+        assert_eq!(reply("z0,400a0,4", &mut target).0, "OK");
+        assert_eq!(reply("z0,400a0,4", &mut target).0, "E01");
+        assert_eq!(reply("Z1,400a0,4", &mut target).0, "");
+
+        assert_eq!(reply("c", &mut target).1, Some(Exit::Resume(Resume::Continue)));
+        assert_eq!(reply("s80000", &mut target).1, Some(Exit::Resume(Resume::Step)));
+        assert_eq!(target.regs[PC], 0x80000);
+        assert_eq!(reply("D", &mut target).1, Some(Exit::Detach));
+        assert_eq!(reply("vCont?", &mut target), (String::new(), None));
+    }
+
+    #[test]
+    fn target_description() {
+        let mut target = Mock::new();
+        let (first, _) = reply("qXfer:features:read:target.xml:0,c", &mut target);
+        assert_eq!(first, "m<?xml versio");
+        let xml = target_xml();
+        let (last, _) = reply(&format!("qXfer:features:read:target.xml:{:x},1000", xml.len() - 9), &mut target);
+        assert_eq!(last, "l</target>");
+        assert!(xml.contains("<reg name=\"x30\" bitsize=\"64\"/><reg name=\"sp\""));
+    }
+}
@@ -7,7 +7,7 @@ use shim::ioerr;
 use shim::path::Path;
 
 pub use fat32::traits;
-use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
+use fat32::vfat::{Dir, Entry, File, Usage, VFat, VFatHandle};
 
 use self::sd::Sd;
 use crate::mutex::Mutex;
@@ -62,6 +62,18 @@ impl FileSystem {
         *self.0.lock() = Some(PiVFatHandle::from(vfat)); 
         
     }
+
+    /// Returns the number of bytes per cluster.
+    pub fn cluster_size(&self) -> u64 {
+        let handle = self.0.lock().as_ref().expect("file system uninitialized").clone();
+        handle.lock(|vfat| vfat.cluster_size())
+    }
+
+    /// Returns the size and free space of the file system.
+    pub fn usage(&self) -> io::Result<Usage> {
+        let handle = self.0.lock().as_ref().expect("file system uninitialized").clone();
+        handle.lock(|vfat| vfat.usage())
+    }
 }
 
 // FIXME: Implement `fat32::traits::FileSystem` for `&FileSystem`
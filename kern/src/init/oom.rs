@@ -1,6 +1,18 @@
 use core::alloc::Layout;
 
+use crate::console::kprintln;
+use crate::ALLOCATOR;
+
 #[alloc_error_handler]
-pub fn oom(_layout: Layout) -> ! {
+pub fn oom(layout: Layout) -> ! {
+    kprintln!(
+        "out of memory: failed to allocate {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+    match ALLOCATOR.stats() {
+        Some(stats) => kprintln!("{}", stats),
+        None => kprintln!("{:?}", ALLOCATOR),
+    }
     panic!("OOM");
 }
@@ -1,5 +1,5 @@
 use core::panic::PanicInfo;
-use crate::console::kprintln;
+use crate::console::{kprintln, CONSOLE};
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {    
@@ -10,5 +10,7 @@ fn panic(_info: &PanicInfo) -> ! {
     kprintln!("LINE: {}", location.line());
     kprintln!("COLUMN: {}", location.column());
     kprintln!("\n{}", _info.message().unwrap());
+    // nothing will drain the UART's transmit buffer from here on
+    CONSOLE.lock().uart().flush();
     loop {}
 }
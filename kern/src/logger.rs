@@ -1,6 +1,6 @@
-use log::{LevelFilter, Metadata, Record};
+use log::{Level, LevelFilter, Metadata, Record};
 
-use crate::console::kprintln;
+use crate::console::{Vt, CONSOLE};
 
 struct KernelLogger;
 
@@ -13,7 +13,15 @@ impl log::Log for KernelLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            kprintln!("[{}] {}", record.level(), record.args());
+            let now = pi::timer::current_time();
+            let mut console = CONSOLE.lock();
+            let line = format_args!("[{:>5}.{:06}] [{}] {}\n",
+                now.as_secs(), now.subsec_micros(), record.level(), record.args());
+            console.print(Vt::Log, line);
+            // problems are shown on the shell's terminal as well
+            if record.level() <= Level::Warn {
+                console.print(Vt::Shell, line);
+            }
         }
     }
 
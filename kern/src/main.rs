@@ -19,6 +19,7 @@ extern crate log;
 
 pub mod allocator;
 pub mod console;
+pub mod debug;
 pub mod fs;
 pub mod logger;
 pub mod mutex;
@@ -54,6 +55,7 @@ use fs::FileSystem;
 use net::uspi::Usb;
 use net::GlobalEthernetDriver;
 use process::GlobalScheduler;
+use shell::NetShell;
 use traps::irq::{Fiq, GlobalIrq};
 use vm::VMManager;
 
@@ -71,6 +73,7 @@ pub static USB: Usb = Usb::uninitialized();
 pub static GLOABAL_IRQ: GlobalIrq = GlobalIrq::new();
 pub static FIQ: Fiq = Fiq::new();
 pub static ETHERNET: GlobalEthernetDriver = GlobalEthernetDriver::uninitialized();
+pub static NETSH: NetShell = NetShell::uninitialized();
 
 extern "C" {
     static __text_beg: u64;
@@ -100,9 +103,26 @@ fn kmain() -> ! {
         FILESYSTEM.initialize();
         VMM.initialize();
         SCHEDULER.initialize();
+        console::enable_interrupts();
+        initialize_network();
         init::initialize_app_cores();
         // kprintln!("yo");
         VMM.wait();
         SCHEDULER.start()
     }
-}
\ No newline at end of file
+}
+
+/// Initializes USPi and, if a USB ethernet device is attached, the ethernet
+/// driver. USB transfers complete from the USB FIQ, so FIQs are unmasked
+/// while USPi probes the devices. Without a device, sockets are unavailable.
+fn initialize_network() {
+    aarch64::enable_fiq_interrupt();
+    if USB.initialize() && USB.is_eth_available() {
+        ETHERNET.initialize();
+        info!("ethernet initialized: {}", USB.get_eth_addr());
+        NETSH.initialize();
+    } else {
+        warn!("no USB ethernet device, networking disabled");
+    }
+    aarch64::disable_fiq_interrupt();
+}
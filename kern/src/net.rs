@@ -1,7 +1,12 @@
 ///! Network device that wraps USPi in smoltcp abstraction
+pub mod dhcp;
+pub mod dns;
+pub mod icmp;
+pub mod phy;
+pub mod slaac;
+pub mod socket;
 pub mod uspi;
 
-use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::vec;
 use alloc::vec::Vec;
@@ -9,35 +14,54 @@ use core::convert::TryInto;
 use core::fmt;
 use core::time::Duration;
 
-use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
-use smoltcp::phy::{self, Device, DeviceCapabilities};
-use smoltcp::socket::{SocketHandle, SocketRef, TcpSocketBuffer};
+use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
+use smoltcp::phy::{Device, DeviceCapabilities};
+use smoltcp::socket::{
+    IcmpPacketMetadata, IcmpSocketBuffer, SocketHandle, SocketRef, TcpSocketBuffer,
+    UdpPacketMetadata, UdpSocketBuffer,
+};
 use smoltcp::time::Instant;
-use smoltcp::wire::{IpAddress, IpCidr};
+use smoltcp::wire::{EthernetAddress, IpCidr, Ipv4Address, Ipv6Address, Ipv6Cidr};
 
+use pi::timer;
+
+use crate::allocator::{SlabBox, SlabCache};
 use crate::mutex::Mutex;
-use crate::param::MTU;
+use crate::param::{ETHERNET_POLL_INTERVAL, MTU, SOCKET_BUFFER_SIZE};
 use crate::USB;
 
+use self::dhcp::{Dhcp, IpConfig, NetConfig};
+use self::dns::Resolver;
+use self::phy::{Phy, Statistics};
+use self::slaac::Slaac;
+
+pub use self::socket::Socket;
+
 // We always use owned buffer as internal storage
 pub type SocketSet = smoltcp::socket::SocketSet<'static, 'static, 'static>;
 pub type TcpSocket = smoltcp::socket::TcpSocket<'static>;
+pub type UdpSocket = smoltcp::socket::UdpSocket<'static, 'static>;
+pub type IcmpSocket = smoltcp::socket::IcmpSocket<'static, 'static>;
 pub type EthernetInterface<T> = smoltcp::iface::EthernetInterface<'static, 'static, 'static, T>;
 
 /// 8-byte aligned `u8` slice.
 #[repr(align(8))]
 struct FrameBuf([u8; MTU as usize]);
 
+/// Cache backing the buffers of all `Frame`s. Frames are allocated for every
+/// packet sent or received, so each core keeps a magazine of free buffers.
+static FRAME_BUFS: SlabCache<FrameBuf> = SlabCache::with_magazines("net_frame");
+
 /// A fixed size buffer with length tracking functionality.
 pub struct Frame {
-    buf: Box<FrameBuf>,
+    buf: SlabBox<FrameBuf>,
     len: u32,
 }
 
 impl fmt::Debug for Frame {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Frame")
-            .field("buf", &{ self.buf.as_ref() as *const FrameBuf })
+            .field("buf", &{ &*self.buf as *const FrameBuf })
             .field("len", &self.len)
             .finish()
     }
@@ -46,7 +70,9 @@ impl fmt::Debug for Frame {
 impl Frame {
     pub fn new() -> Self {
         Frame {
-            buf: Box::new(FrameBuf([0; MTU as usize])),
+            buf: FRAME_BUFS
+                .alloc(FrameBuf([0; MTU as usize]))
+                .expect("out of memory for net frames"),
             len: MTU,
         }
     }
@@ -77,12 +103,15 @@ impl Frame {
     }
 }
 
-#[derive(Debug)]
-pub struct UsbEthernet;
+/// The USB ethernet device, counting the frames that go through it.
+#[derive(Debug, Default)]
+pub struct UsbEthernet {
+    statistics: Statistics,
+}
 
 impl<'a> Device<'a> for UsbEthernet {
     type RxToken = RxToken;
-    type TxToken = TxToken;
+    type TxToken = TxToken<'a>;
 
     fn capabilities(&self) -> DeviceCapabilities {
         let mut capability = DeviceCapabilities::default();
@@ -94,8 +123,10 @@ impl<'a> Device<'a> for UsbEthernet {
         let mut frame = Frame::new();
         match USB.recv_frame(&mut frame) {
             Some(_) => {
+                self.statistics.rx_frames += 1;
+                self.statistics.rx_bytes += frame.len() as u64;
                 let rx = RxToken { frame };
-                let tx = TxToken;
+                let tx = TxToken(&mut self.statistics);
                 Some((rx, tx))
             }
             _ => None,
@@ -103,7 +134,17 @@ impl<'a> Device<'a> for UsbEthernet {
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        Some(TxToken)
+        Some(TxToken(&mut self.statistics))
+    }
+}
+
+impl Phy for UsbEthernet {
+    fn ethernet_addr(&self) -> EthernetAddress {
+        USB.get_eth_addr()
+    }
+
+    fn statistics(&self) -> Statistics {
+        self.statistics
     }
 }
 
@@ -111,7 +152,7 @@ pub struct RxToken {
     frame: Frame,
 }
 
-impl phy::RxToken for RxToken {
+impl smoltcp::phy::RxToken for RxToken {
     fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
@@ -120,9 +161,9 @@ impl phy::RxToken for RxToken {
     }
 }
 
-pub struct TxToken;
+pub struct TxToken<'a>(&'a mut Statistics);
 
-impl phy::TxToken for TxToken {
+impl<'a> smoltcp::phy::TxToken for TxToken<'a> {
     fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
     where
         F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
@@ -130,66 +171,260 @@ impl phy::TxToken for TxToken {
         let mut frame = Frame::new();
         frame.set_len(len.try_into().unwrap());
         let result = f(frame.as_mut_slice());
-        USB.send_frame(&frame);
+        if USB.send_frame(&frame).is_some() {
+            self.0.tx_frames += 1;
+            self.0.tx_bytes += len as u64;
+        }
         result
     }
 }
 
-/// Creates and returns a new ethernet interface using `UsbEthernet` struct.
-pub fn create_interface() -> EthernetInterface<UsbEthernet> {
-    // Lab 5 2.B
-    unimplemented!("create_interface")
+/// Creates and returns a new ethernet interface on `phy`. The interface has
+/// the unspecified address 0.0.0.0/0, its IPv6 link-local address and no
+/// routes until it is configured with `EthernetDriver::configure()`.
+pub fn create_interface<P: Phy>(phy: P) -> EthernetInterface<P> {
+    let ethernet_addr = phy.ethernet_addr();
+    let ip_addrs = vec![
+        IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0),
+        IpCidr::Ipv6(slaac::link_local(ethernet_addr)),
+    ];
+    EthernetInterfaceBuilder::new(phy)
+        .ethernet_addr(ethernet_addr)
+        .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+        .ip_addrs(ip_addrs)
+        .routes(Routes::new(BTreeMap::new()))
+        .finalize()
+}
+
+/// Returns the current time as a smoltcp `Instant`.
+pub fn now() -> Instant {
+    Instant::from_millis(timer::current_time().as_millis() as i64)
 }
 
 const PORT_MAP_SIZE: usize = 65536 / 64;
 
-pub struct EthernetDriver {
+/// First port of the ephemeral port range.
+pub const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// The network stack on a device `P`: the interface, its sockets and the
+/// ports they use.
+pub struct EthernetDriver<P: Phy> {
     /// A set of sockets
     socket_set: SocketSet,
     /// Bitmap to track the port usage
     port_map: [u64; PORT_MAP_SIZE],
     /// Internal ethernet interface
-    ethernet: EthernetInterface<UsbEthernet>,
+    ethernet: EthernetInterface<P>,
+    /// DHCP client configuring the interface, if enabled
+    dhcp: Option<Dhcp>,
+    /// Current IPv4 configuration of the interface
+    config: Option<IpConfig>,
+    /// TCP sockets made listeners by `listen()`, with the port they listen on
+    listeners: Vec<(SocketHandle, u16)>,
+    /// DNS resolver using the DNS servers of the configuration
+    dns: Resolver,
+    /// IPv6 configuration from router advertisements
+    slaac: Slaac,
 }
 
-impl EthernetDriver {
-    /// Creates a fresh ethernet driver.
-    fn new() -> EthernetDriver {
-        // Lab 5 2.B
-        unimplemented!("new")
+impl<P: Phy> EthernetDriver<P> {
+    /// Creates a fresh ethernet driver on `phy`, configured by `net_config`.
+    fn new(phy: P, net_config: NetConfig, timestamp: Instant) -> EthernetDriver<P> {
+        let mut socket_set = SocketSet::new(Vec::new());
+        let dhcp = if net_config.dhcp {
+            Some(Dhcp::new(&mut socket_set, net_config.fallback.clone(), timestamp))
+        } else {
+            None
+        };
+        let dns = Resolver::new(&mut socket_set);
+        let slaac = Slaac::new(&mut socket_set, phy.ethernet_addr(), timestamp);
+
+        let mut driver = EthernetDriver {
+            socket_set,
+            port_map: [0; PORT_MAP_SIZE],
+            ethernet: create_interface(phy),
+            dhcp,
+            config: None,
+            listeners: Vec::new(),
+            dns,
+            slaac,
+        };
+        if !net_config.dhcp {
+            driver.configure(net_config.fallback);
+        }
+        driver
     }
 
-    /// Polls the ethernet interface.
+    /// Polls the ethernet interface, the DHCP client, the IPv6 configuration
+    /// and the DNS resolver, then drops the released sockets that have
+    /// finished closing.
     /// See also `smoltcp::iface::EthernetInterface::poll()`.
     fn poll(&mut self, timestamp: Instant) {
-        // Lab 5 2.B
-        unimplemented!("poll")
+        match self.ethernet.poll(&mut self.socket_set, timestamp) {
+            Ok(_) => {}
+            Err(e) => debug!("poll error: {}", e),
+        }
+        if let Some(dhcp) = self.dhcp.as_mut() {
+            if let Some(config) = dhcp.poll(&mut self.ethernet, &mut self.socket_set, timestamp) {
+                self.configure(config);
+            }
+        }
+        if self.slaac.poll(&mut self.socket_set, timestamp) {
+            self.configure_ipv6();
+        }
+        self.poll_dns(timestamp);
+        self.socket_set.prune();
     }
 
-    /// Returns an advisory wait time to call `poll()` the next time.
+    /// Applies an IPv4 configuration to the interface: its address and the
+    /// default route. A configuration without a gateway removes the default
+    /// route.
+    fn configure(&mut self, config: IpConfig) {
+        if self.config.as_ref() == Some(&config) {
+            return;
+        }
+
+        let routes = self.ethernet.routes_mut();
+        match config.gateway {
+            Some(gateway) => {
+                if let Err(e) = routes.add_default_ipv4_route(gateway) {
+                    warn!("failed to add the default route via {}: {}", gateway, e);
+                }
+            }
+            None => {
+                routes.remove_default_ipv4_route();
+            }
+        }
+        info!(
+            "net: address {}, gateway {:?}, dns {:?}",
+            config.address, config.gateway, config.dns_servers
+        );
+        self.config = Some(config);
+        self.update_ip_addrs();
+    }
+
+    /// Applies the IPv6 configuration from router advertisements to the
+    /// interface: its global address and the default route.
+    fn configure_ipv6(&mut self) {
+        self.update_ip_addrs();
+        let routes = self.ethernet.routes_mut();
+        match self.slaac.router() {
+            Some(router) => {
+                if let Err(e) = routes.add_default_ipv6_route(router) {
+                    warn!("failed to add the default route via {}: {}", router, e);
+                }
+            }
+            None => {
+                routes.remove_default_ipv6_route();
+            }
+        }
+        info!("net: ipv6 address {:?}, router {:?}", self.slaac.address(), self.slaac.router());
+    }
+
+    /// Sets the addresses of the interface: the IPv4 address of the
+    /// configuration, or 0.0.0.0/0 until there is one, then the global IPv6
+    /// address if any and the link-local one. smoltcp picks the first address
+    /// of a family as the source of packets, so the global address comes
+    /// before the link-local one.
+    fn update_ip_addrs(&mut self) {
+        let ipv4 = match self.config.as_ref() {
+            Some(config) => IpCidr::Ipv4(config.address),
+            None => IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0),
+        };
+        let mut addrs = vec![ipv4];
+        addrs.extend(self.slaac.address().map(IpCidr::Ipv6));
+        addrs.push(IpCidr::Ipv6(self.slaac.link_local()));
+        self.ethernet.update_ip_addrs(move |ip_addrs| *ip_addrs = addrs.into());
+    }
+
+    /// Returns the current IPv4 configuration of the interface, or `None` if
+    /// it is not configured yet.
+    pub fn config(&self) -> Option<&IpConfig> {
+        self.config.as_ref()
+    }
+
+    /// Returns the IPv6 addresses of the interface: the global address formed
+    /// from a router advertisement, if any, and the link-local address.
+    pub fn ipv6_addrs(&self) -> Vec<Ipv6Cidr> {
+        let mut addrs: Vec<Ipv6Cidr> = self.slaac.address().into_iter().collect();
+        addrs.push(self.slaac.link_local());
+        addrs
+    }
+
+    /// Returns the default IPv6 router, if a router advertisement gave one.
+    pub fn ipv6_router(&self) -> Option<Ipv6Address> {
+        self.slaac.router()
+    }
+
+    /// Returns the MAC address of the interface.
+    pub fn ethernet_addr(&self) -> EthernetAddress {
+        self.ethernet.ethernet_addr()
+    }
+
+    /// Returns the frame counters of the device.
+    pub fn statistics(&self) -> Statistics {
+        self.ethernet.device().statistics()
+    }
+
+    /// Returns whether the interface is configured with DHCP.
+    pub fn uses_dhcp(&self) -> bool {
+        self.dhcp.is_some()
+    }
+
+    /// Returns the DNS servers of the current configuration.
+    pub fn dns_servers(&self) -> &[Ipv4Address] {
+        match self.config.as_ref() {
+            Some(config) => &config.dns_servers,
+            None => &[],
+        }
+    }
+
+    /// Returns an advisory wait time to call `poll()` the next time, at most
+    /// `ETHERNET_POLL_INTERVAL` since received frames are only processed by
+    /// `poll()`.
     /// See also `smoltcp::iface::EthernetInterface::poll_delay()`.
     fn poll_delay(&mut self, timestamp: Instant) -> Duration {
-        // Lab 5 2.B
-        unimplemented!("poll_delay")
+        let mut delay = match self.ethernet.poll_delay(&self.socket_set, timestamp) {
+            Some(delay) => {
+                core::cmp::min(Duration::from_millis(delay.total_millis()), ETHERNET_POLL_INTERVAL)
+            }
+            None => ETHERNET_POLL_INTERVAL,
+        };
+        if let Some(dhcp) = self.dhcp.as_ref() {
+            delay = core::cmp::min(delay, dhcp.next_poll(timestamp));
+        }
+        if let Some(next_poll) = self.slaac.next_poll(timestamp) {
+            delay = core::cmp::min(delay, next_poll);
+        }
+        delay
     }
 
     /// Marks a port as used. Returns `Some(port)` on success, `None` on failure.
     pub fn mark_port(&mut self, port: u16) -> Option<u16> {
-        // Lab 5 2.B
-        unimplemented!("mark_port")
+        let (index, bit) = (port as usize / 64, 1 << (port % 64));
+        if self.port_map[index] & bit != 0 {
+            return None;
+        }
+        self.port_map[index] |= bit;
+        Some(port)
     }
 
     /// Clears used bit of a port. Returns `Some(port)` on success, `None` on failure.
     pub fn erase_port(&mut self, port: u16) -> Option<u16> {
-        // Lab 5 2.B
-        unimplemented!("erase_port")
+        let (index, bit) = (port as usize / 64, 1 << (port % 64));
+        if self.port_map[index] & bit == 0 {
+            return None;
+        }
+        self.port_map[index] &= !bit;
+        Some(port)
     }
 
     /// Returns the first open port between the ephemeral port range 49152 ~ 65535.
     /// Note that this function does not mark the returned port.
     pub fn get_ephemeral_port(&mut self) -> Option<u16> {
-        // Lab 5 2.B
-        unimplemented!("get_ephemeral_port")
+        let port_map = &self.port_map;
+        (EPHEMERAL_PORT_START..=65535)
+            .find(|&port| port_map[port as usize / 64] & (1 << (port % 64)) == 0)
     }
 
     /// Finds a socket with a `SocketHandle`.
@@ -197,15 +432,45 @@ impl EthernetDriver {
         self.socket_set.get::<TcpSocket>(handle)
     }
 
+    /// Finds a UDP socket with a `SocketHandle`.
+    pub fn get_udp_socket(&mut self, handle: SocketHandle) -> SocketRef<'_, UdpSocket> {
+        self.socket_set.get::<UdpSocket>(handle)
+    }
+
+    /// Finds an ICMP socket with a `SocketHandle`.
+    pub fn get_icmp_socket(&mut self, handle: SocketHandle) -> SocketRef<'_, IcmpSocket> {
+        self.socket_set.get::<IcmpSocket>(handle)
+    }
+
     /// This function creates a new TCP socket, adds it to the internal socket
     /// set, and returns the `SocketHandle` of the new socket.
     pub fn add_socket(&mut self) -> SocketHandle {
-        let rx_buffer = TcpSocketBuffer::new(vec![0; 16384]);
-        let tx_buffer = TcpSocketBuffer::new(vec![0; 16384]);
+        let rx_buffer = TcpSocketBuffer::new(vec![0; SOCKET_BUFFER_SIZE]);
+        let tx_buffer = TcpSocketBuffer::new(vec![0; SOCKET_BUFFER_SIZE]);
         let tcp_socket = TcpSocket::new(rx_buffer, tx_buffer);
         self.socket_set.add(tcp_socket)
     }
 
+    /// This function creates a new UDP socket, adds it to the internal socket
+    /// set, and returns the `SocketHandle` of the new socket. Each direction
+    /// buffers up to 16 datagrams.
+    pub fn add_udp_socket(&mut self) -> SocketHandle {
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 16], vec![0; SOCKET_BUFFER_SIZE]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 16], vec![0; SOCKET_BUFFER_SIZE]);
+        let udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+        self.socket_set.add(udp_socket)
+    }
+
+    /// This function creates a new ICMP socket, adds it to the internal
+    /// socket set, and returns the `SocketHandle` of the new socket. Each
+    /// direction buffers up to 8 messages.
+    pub fn add_icmp_socket(&mut self) -> SocketHandle {
+        let rx_buffer = IcmpSocketBuffer::new(vec![IcmpPacketMetadata::EMPTY; 8], vec![0; 4096]);
+        let tx_buffer = IcmpSocketBuffer::new(vec![IcmpPacketMetadata::EMPTY; 8], vec![0; 4096]);
+        let icmp_socket = IcmpSocket::new(rx_buffer, tx_buffer);
+        self.socket_set.add(icmp_socket)
+    }
+
     /// Releases a socket from the internal socket set.
     pub fn release(&mut self, handle: SocketHandle) {
         self.socket_set.release(handle);
@@ -217,8 +482,8 @@ impl EthernetDriver {
     }
 }
 
-/// A thread-safe wrapper for `EthernetDriver`.
-pub struct GlobalEthernetDriver(Mutex<Option<EthernetDriver>>);
+/// A thread-safe wrapper for the `EthernetDriver` on the USB ethernet device.
+pub struct GlobalEthernetDriver(Mutex<Option<EthernetDriver<UsbEthernet>>>);
 
 impl GlobalEthernetDriver {
     pub const fn uninitialized() -> GlobalEthernetDriver {
@@ -227,12 +492,22 @@ impl GlobalEthernetDriver {
 
     pub fn initialize(&self) {
         let mut lock = self.0.lock();
-        *lock = Some(EthernetDriver::new());
+        let mut driver = EthernetDriver::new(UsbEthernet::default(), NetConfig::from_cmdline(), now());
+        driver.dns.set_hosts(&dns::read_hosts());
+        *lock = Some(driver);
+    }
+
+    /// Returns `true` if `initialize()` has been called.
+    pub fn is_initialized(&self) -> bool {
+        self.0.lock().is_some()
     }
 
     pub fn poll(&self, timestamp: Instant) {
-        // Lab 5 2.B
-        unimplemented!("poll")
+        self.0
+            .lock()
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .poll(timestamp)
     }
 
     pub fn poll_delay(&self, timestamp: Instant) -> Duration {
@@ -251,6 +526,14 @@ impl GlobalEthernetDriver {
             .mark_port(port)
     }
 
+    pub fn erase_port(&self, port: u16) -> Option<u16> {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .erase_port(port)
+    }
+
     pub fn get_ephemeral_port(&self) -> Option<u16> {
         self.0
             .lock()
@@ -267,6 +550,14 @@ impl GlobalEthernetDriver {
             .add_socket()
     }
 
+    pub fn add_udp_socket(&self) -> SocketHandle {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .add_udp_socket()
+    }
+
     /// Enters a critical region and execute the provided closure with a mutable
     /// reference to the socket.
     pub fn with_socket<F, R>(&self, handle: SocketHandle, f: F) -> R
@@ -282,11 +573,26 @@ impl GlobalEthernetDriver {
         f(&mut socket)
     }
 
+    /// Enters a critical region and execute the provided closure with a mutable
+    /// reference to the UDP socket.
+    pub fn with_udp_socket<F, R>(&self, handle: SocketHandle, f: F) -> R
+    where
+        F: FnOnce(&mut SocketRef<'_, UdpSocket>) -> R,
+    {
+        let mut guard = self.0.lock();
+        let mut socket = guard
+            .as_mut()
+            .expect("Uninitialized EthernetDriver")
+            .get_udp_socket(handle);
+
+        f(&mut socket)
+    }
+
     /// Enters a critical region and execute the provided closure with a mutable
     /// reference to the inner ethernet driver.
     pub fn critical<F, R>(&self, f: F) -> R
     where
-        F: FnOnce(&mut EthernetDriver) -> R,
+        F: FnOnce(&mut EthernetDriver<UsbEthernet>) -> R,
     {
         let mut guard = self.0.lock();
         let mut ethernet = guard.as_mut().expect("Uninitialized EthernetDriver");
@@ -0,0 +1,266 @@
+//! IPv4 configuration of the ethernet interface with DHCP, falling back to a
+//! static configuration from the ATAG command line.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use pi::atags::Atags;
+use smoltcp::dhcp::Dhcpv4Client;
+use smoltcp::phy::Device;
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer};
+use smoltcp::time::{self, Instant};
+use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+use crate::net::{EthernetInterface, SocketSet};
+use crate::param::DHCP_TIMEOUT;
+
+/// An IPv4 configuration of the interface.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IpConfig {
+    /// The address of the interface and the prefix of its subnet.
+    pub address: Ipv4Cidr,
+    /// The router of the default route.
+    pub gateway: Option<Ipv4Address>,
+    /// The DNS servers, in order of preference.
+    pub dns_servers: Vec<Ipv4Address>,
+}
+
+impl Default for IpConfig {
+    /// Returns the link-local address 169.254.32.10/16, so the interface can
+    /// be reached from a host on the same link.
+    fn default() -> IpConfig {
+        IpConfig {
+            address: Ipv4Cidr::new(Ipv4Address::new(169, 254, 32, 10), 16),
+            gateway: None,
+            dns_servers: Vec::new(),
+        }
+    }
+}
+
+/// How the interface is configured.
+///
+/// The configuration is chosen at boot by an `ip=` option on the ATAG command
+/// line, in the format used by Linux:
+///
+/// - `ip=dhcp` (the default, also `on` or `any`): acquire a lease with DHCP,
+///   falling back to the link-local `IpConfig::default()`.
+/// - `ip=off` (also `none`): use `IpConfig::default()` without DHCP.
+/// - `ip=<client>:<server>:<gateway>:<netmask>:<hostname>:<device>:<autoconf>:<dns0>:<dns1>`:
+///   use the given static configuration. Trailing fields may be omitted and
+///   `server`, `hostname` and `device` are ignored. With an `autoconf` of
+///   `dhcp`, `on` or `any`, a lease is acquired with DHCP and the static
+///   configuration is the fallback. Without a netmask, the prefix is 24.
+///
+/// The last valid `ip=` option wins.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetConfig {
+    /// Whether to acquire a lease with DHCP.
+    pub dhcp: bool,
+    /// The configuration used without DHCP, or when no lease is acquired in
+    /// `DHCP_TIMEOUT`.
+    pub fallback: IpConfig,
+}
+
+impl Default for NetConfig {
+    fn default() -> NetConfig {
+        NetConfig { dhcp: true, fallback: IpConfig::default() }
+    }
+}
+
+impl NetConfig {
+    /// Returns the configuration selected on the ATAG command line.
+    pub fn from_cmdline() -> NetConfig {
+        Atags::get()
+            .find_map(|atag| atag.cmd())
+            .map(parse_cmdline)
+            .unwrap_or_default()
+    }
+}
+
+/// Returns whether `autoconf` asks for DHCP.
+fn is_dhcp(autoconf: &str) -> bool {
+    match autoconf {
+        "dhcp" | "on" | "any" => true,
+        _ => false,
+    }
+}
+
+/// Parses the value of an `ip=` option. Returns `None` if it is malformed.
+fn parse_ip_option(option: &str) -> Option<NetConfig> {
+    match option {
+        "dhcp" | "on" | "any" => return Some(NetConfig::default()),
+        "off" | "none" => return Some(NetConfig { dhcp: false, ..NetConfig::default() }),
+        _ => {}
+    }
+
+    let mut fields = option.split(':');
+    let client: Ipv4Address = fields.next()?.parse().ok()?;
+    let _server = fields.next();
+    let gateway = match fields.next() {
+        None | Some("") => None,
+        Some(gateway) => Some(gateway.parse().ok()?),
+    };
+    let prefix_len = match fields.next() {
+        None | Some("") => 24,
+        Some(netmask) => prefix_len(netmask.parse().ok()?)?,
+    };
+    let _hostname = fields.next();
+    let _device = fields.next();
+    let dhcp = fields.next().map_or(false, is_dhcp);
+    let mut dns_servers = Vec::new();
+    for server in fields.take(2).filter(|server| !server.is_empty()) {
+        dns_servers.push(server.parse().ok()?);
+    }
+
+    Some(NetConfig {
+        dhcp,
+        fallback: IpConfig { address: Ipv4Cidr::new(client, prefix_len), gateway, dns_servers },
+    })
+}
+
+/// Returns the prefix length of `netmask`, or `None` if its ones are not
+/// contiguous.
+fn prefix_len(netmask: Ipv4Address) -> Option<u8> {
+    let mask = u32::from_be_bytes(netmask.0);
+    let len = (!mask).leading_zeros();
+    if mask.count_ones() == len {
+        Some(len as u8)
+    } else {
+        None
+    }
+}
+
+/// Returns the configuration selected by the last valid `ip=` option of
+/// `cmdline`, or the default one.
+fn parse_cmdline(cmdline: &str) -> NetConfig {
+    cmdline
+        .split_whitespace()
+        .filter(|arg| arg.starts_with("ip="))
+        .filter_map(|arg| parse_ip_option(&arg["ip=".len()..]))
+        .last()
+        .unwrap_or_default()
+}
+
+/// A DHCP client that acquires and renews a lease for the interface, and
+/// yields the fallback configuration if no lease is acquired in time.
+pub struct Dhcp {
+    client: Dhcpv4Client,
+    fallback: IpConfig,
+    /// When to yield `fallback` if no lease has been acquired by then.
+    deadline: Option<Instant>,
+}
+
+impl Dhcp {
+    /// Creates a DHCP client whose raw socket is added to `sockets`.
+    pub fn new(sockets: &mut SocketSet, fallback: IpConfig, now: Instant) -> Dhcp {
+        let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 900]);
+        let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 600]);
+        Dhcp {
+            client: Dhcpv4Client::new(sockets, rx_buffer, tx_buffer, now),
+            fallback,
+            deadline: Some(now + time::Duration::from_millis(DHCP_TIMEOUT.as_millis() as u64)),
+        }
+    }
+
+    /// Runs the DHCP client. Returns the configuration to apply to the
+    /// interface when a lease is acquired or renewed, or when the fallback
+    /// configuration is due.
+    pub fn poll<D>(
+        &mut self,
+        iface: &mut EthernetInterface<D>,
+        sockets: &mut SocketSet,
+        now: Instant,
+    ) -> Option<IpConfig>
+    where
+        D: for<'d> Device<'d>,
+    {
+        let config = match self.client.poll(iface, sockets, now) {
+            Ok(config) => config,
+            Err(e) => {
+                debug!("dhcp: {}", e);
+                None
+            }
+        };
+
+        if let Some(config) = config {
+            if let Some(address) = config.address {
+                self.deadline = None;
+                return Some(IpConfig {
+                    address,
+                    gateway: config.router,
+                    dns_servers: config.dns_servers.iter().filter_map(|&server| server).collect(),
+                });
+            }
+        }
+
+        match self.deadline {
+            Some(deadline) if now >= deadline => {
+                self.deadline = None;
+                warn!("dhcp: no lease acquired, using the fallback configuration");
+                Some(self.fallback.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns how long to wait before the next call to `poll()`.
+    pub fn next_poll(&self, now: Instant) -> Duration {
+        Duration::from_millis(self.client.next_poll(now).total_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_cmdline, prefix_len, IpConfig, NetConfig};
+    use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+    #[test]
+    fn netmask() {
+        assert_eq!(prefix_len(Ipv4Address::new(255, 255, 255, 0)), Some(24));
+        assert_eq!(prefix_len(Ipv4Address::new(255, 255, 0, 0)), Some(16));
+        assert_eq!(prefix_len(Ipv4Address::new(255, 255, 255, 255)), Some(32));
+        assert_eq!(prefix_len(Ipv4Address::new(0, 0, 0, 0)), Some(0));
+        assert_eq!(prefix_len(Ipv4Address::new(255, 0, 255, 0)), None);
+    }
+
+    #[test]
+    fn cmdline() {
+        assert_eq!(parse_cmdline(""), NetConfig::default());
+        assert_eq!(parse_cmdline("console=ttyS0 ip=dhcp"), NetConfig::default());
+        assert_eq!(
+            parse_cmdline("ip=off"),
+            NetConfig { dhcp: false, fallback: IpConfig::default() }
+        );
+
+        let fallback = IpConfig {
+            address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 2, 15), 24),
+            gateway: Some(Ipv4Address::new(10, 0, 2, 2)),
+            dns_servers: vec![Ipv4Address::new(10, 0, 2, 3)],
+        };
+        assert_eq!(
+            parse_cmdline("ip=10.0.2.15::10.0.2.2:255.255.255.0:pi:eth0:off:10.0.2.3"),
+            NetConfig { dhcp: false, fallback: fallback.clone() }
+        );
+        assert_eq!(
+            parse_cmdline("ip=10.0.2.15::10.0.2.2::::dhcp:10.0.2.3:"),
+            NetConfig { dhcp: true, fallback }
+        );
+
+        let fallback = IpConfig {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 1, 2), 24),
+            ..IpConfig::default()
+        };
+        assert_eq!(parse_cmdline("ip=192.168.1.2"), NetConfig { dhcp: false, fallback });
+    }
+
+    #[test]
+    fn last_valid_option_wins() {
+        assert_eq!(parse_cmdline("ip=off ip=dhcp"), NetConfig::default());
+        assert_eq!(parse_cmdline("ip=10.0.0.1 ip=off").dhcp, false);
+        // malformed options are ignored
+        assert_eq!(parse_cmdline("ip=dhcp ip=10.0.0.300"), NetConfig::default());
+        assert_eq!(parse_cmdline("ip=dhcp ip=10.0.0.1::10.0.0"), NetConfig::default());
+        assert_eq!(parse_cmdline("ip=dhcp ip=10.0.0.1:::255.0.255.0"), NetConfig::default());
+    }
+}
@@ -0,0 +1,771 @@
+//! A DNS stub resolver. Names are looked up in the static entries of
+//! `/etc/hosts`, then in a cache of earlier answers, and finally with
+//! recursive queries over UDP to the DNS servers of the interface
+//! configuration. A name is queried for its A records first, and for its
+//! AAAA records if it has none.
+//!
+//! A response is only taken from the server the query was sent to, with the
+//! random identifier of the query and the question it asked. Queries are sent
+//! from a random ephemeral port.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use kernel_api::{OsError, OsResult};
+use shim::io::Read;
+use smoltcp::socket::{SocketHandle, UdpPacketMetadata, UdpSocketBuffer};
+use smoltcp::time::{self, Instant};
+use smoltcp::wire::{IpEndpoint, Ipv4Address, Ipv6Address};
+
+use fat32::traits::{Entry, FileSystem};
+
+use crate::net::{EthernetDriver, Phy, SocketSet, UdpSocket, EPHEMERAL_PORT_START};
+use crate::param::{DNS_ATTEMPTS, DNS_CACHE_SIZE, DNS_RETRY};
+use crate::FILESYSTEM;
+
+/// The port DNS servers listen on.
+const DNS_PORT: u16 = 53;
+
+/// The file with the static entries.
+const HOSTS_PATH: &str = "/etc/hosts";
+
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// Response code of a name that does not exist.
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// An address a name resolves to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Address {
+    V4(Ipv4Address),
+    V6([u8; 16]),
+}
+
+/// The data of a resource record.
+#[derive(Clone, Debug, PartialEq)]
+enum RecordData {
+    Address(Address),
+    Cname(String),
+}
+
+/// A resource record of the answer section of a response.
+#[derive(Clone, Debug, PartialEq)]
+struct Record {
+    name: String,
+    ttl: u32,
+    data: RecordData,
+}
+
+/// A parsed DNS response.
+#[derive(Debug)]
+struct Response {
+    id: u16,
+    rcode: u8,
+    /// The name and type of the question, echoed from the query.
+    question: (String, u16),
+    records: Vec<Record>,
+}
+
+/// A xorshift64* generator for the query identifiers and the source port,
+/// seeded from the hardware random number generator.
+struct Random(u64);
+
+impl Random {
+    fn new() -> Random {
+        #[cfg(not(test))]
+        let seed = pi::rng::random_u64();
+        #[cfg(test)]
+        let seed = 0x9e37_79b9_7f4a_7c15;
+        // the state must not be zero
+        Random(seed | 1)
+    }
+
+    fn next_u16(&mut self) -> u16 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 48) as u16
+    }
+}
+
+/// Returns `name` in lower case and without a trailing dot, as names are
+/// compared.
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+/// Returns a recursive query with identifier `id` for the records of type
+/// `qtype` of `name`, or `None` if `name` is not a valid domain name.
+fn encode_query(id: u16, name: &str, qtype: u16) -> Option<Vec<u8>> {
+    let mut msg = Vec::with_capacity(12 + name.len() + 6);
+    msg.extend_from_slice(&id.to_be_bytes());
+    // recursion desired, one question
+    msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    if msg.len() - 12 > 255 {
+        return None;
+    }
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Some(msg)
+}
+
+/// Reads the possibly compressed name at `pos` of `msg`. Returns the name in
+/// lower case and the position after it, or `None` if it is malformed.
+fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    // bounds the number of compression pointers followed
+    for _ in 0..128 {
+        let len = *msg.get(pos)? as usize;
+        match len & 0xc0 {
+            0xc0 => {
+                let target = ((len & 0x3f) << 8) | *msg.get(pos + 1)? as usize;
+                end = end.or(Some(pos + 2));
+                pos = target;
+            }
+            0 if len == 0 => return Some((name, end.unwrap_or(pos + 1))),
+            0 => {
+                let label = core::str::from_utf8(msg.get(pos + 1..pos + 1 + len)?).ok()?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&label.to_ascii_lowercase());
+                pos += 1 + len;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Parses a DNS response. The records of the answer section other than A,
+/// AAAA and CNAME records are skipped. Returns `None` if `msg` is not a
+/// well-formed response to a single question of class IN.
+fn parse_response(msg: &[u8]) -> Option<Response> {
+    let header = msg.get(..12)?;
+    if header[2] & 0x80 == 0 || be16(&header[4..]) != 1 {
+        return None;
+    }
+    let id = be16(&header[0..]);
+    let rcode = header[3] & 0x0f;
+    let answers = be16(&header[6..]);
+
+    let (qname, next) = read_name(msg, 12)?;
+    let fixed = msg.get(next..next + 4)?;
+    if be16(&fixed[2..]) != CLASS_IN {
+        return None;
+    }
+    let question = (qname, be16(&fixed[0..]));
+    let mut pos = next + 4;
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        let (name, next) = read_name(msg, pos)?;
+        let fixed = msg.get(next..next + 10)?;
+        let (rtype, class) = (be16(&fixed[0..]), be16(&fixed[2..]));
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdata_pos = next + 10;
+        let rdata = msg.get(rdata_pos..rdata_pos + be16(&fixed[8..]) as usize)?;
+        pos = rdata_pos + rdata.len();
+
+        let data = match (rtype, class, rdata.len()) {
+            (TYPE_A, CLASS_IN, 4) => RecordData::Address(Address::V4(Ipv4Address::from_bytes(rdata))),
+            (TYPE_AAAA, CLASS_IN, 16) => {
+                let mut addr = [0; 16];
+                addr.copy_from_slice(rdata);
+                RecordData::Address(Address::V6(addr))
+            }
+            (TYPE_CNAME, CLASS_IN, _) => RecordData::Cname(read_name(msg, rdata_pos)?.0),
+            _ => continue,
+        };
+        records.push(Record { name, ttl, data });
+    }
+    Some(Response { id, rcode, question, records })
+}
+
+/// Follows the CNAME records from `name` to the addresses it resolves to.
+/// Returns them with the smallest TTL of the records followed; there are no
+/// addresses if the chain ends without any.
+fn addresses_of(records: &[Record], name: &str) -> (Vec<Address>, u32) {
+    let mut name = name;
+    let mut ttl = u32::max_value();
+    // a chain longer than the records loops
+    for _ in 0..=records.len() {
+        let mut addresses = Vec::new();
+        let mut alias = None;
+        for record in records.iter().filter(|record| record.name == name) {
+            ttl = core::cmp::min(ttl, record.ttl);
+            match record.data {
+                RecordData::Address(addr) => addresses.push(addr),
+                RecordData::Cname(ref target) => alias = Some(target.as_str()),
+            }
+        }
+        if !addresses.is_empty() {
+            return (addresses, ttl);
+        }
+        match alias {
+            Some(target) => name = target,
+            None => break,
+        }
+    }
+    (Vec::new(), 0)
+}
+
+/// Parses an IPv4 address in dotted decimal or an IPv6 address.
+fn parse_address(text: &str) -> Option<Address> {
+    if let Ok(addr) = text.parse::<Ipv4Address>() {
+        return Some(Address::V4(addr));
+    }
+    text.parse::<Ipv6Address>().ok().map(|addr| Address::V6(addr.0))
+}
+
+/// Parses static entries in the format of `/etc/hosts`: an address followed
+/// by its names on each line, with `#` starting a comment.
+fn parse_hosts(text: &str) -> Vec<(String, Address)> {
+    let mut hosts = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+        let addr = match fields.next().and_then(parse_address) {
+            Some(addr) => addr,
+            None => continue,
+        };
+        hosts.extend(fields.map(|name| (normalize(name), addr)));
+    }
+    hosts
+}
+
+/// Returns the contents of `/etc/hosts` on the FAT volume, or an empty string
+/// if it cannot be read.
+pub fn read_hosts() -> String {
+    let mut file = match FILESYSTEM.open(HOSTS_PATH).ok().and_then(|entry| entry.into_file()) {
+        Some(file) => file,
+        None => return String::new(),
+    };
+
+    let mut bytes = Vec::new();
+    let mut buf = [0; 512];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => bytes.extend_from_slice(&buf[..read]),
+            Err(e) => {
+                warn!("dns: failed to read {}: {:?}", HOSTS_PATH, e);
+                return String::new();
+            }
+        }
+    }
+    String::from_utf8(bytes).unwrap_or_default()
+}
+
+/// Addresses of a name in the cache, valid until `expires`.
+struct CacheEntry {
+    addresses: Vec<Address>,
+    expires: Instant,
+}
+
+/// A query for the A records of a name, or for its AAAA records once it is
+/// known to have no A record.
+struct Query {
+    name: String,
+    id: u16,
+    qtype: u16,
+    /// The server the query was last sent to.
+    server: Ipv4Address,
+    /// How many times the query has been sent.
+    attempts: u8,
+    /// When to send the query again or, once it has a result, when to drop
+    /// the result if nobody has taken it.
+    deadline: Instant,
+    result: Option<OsResult<Vec<Address>>>,
+}
+
+impl Query {
+    /// Returns `true` if `response`, received from `from`, answers the query:
+    /// it comes from the server the query was sent to, with its identifier
+    /// and its question.
+    fn is_answered_by(&self, response: &Response, from: IpEndpoint) -> bool {
+        self.result.is_none()
+            && from == IpEndpoint::new(self.server.into(), DNS_PORT)
+            && response.id == self.id
+            && response.question.0 == self.name
+            && response.question.1 == self.qtype
+    }
+}
+
+/// The state of the resolver: its UDP socket, the static entries, the cache
+/// and the queries in flight.
+pub struct Resolver {
+    socket: SocketHandle,
+    hosts: Vec<(String, Address)>,
+    cache: BTreeMap<String, CacheEntry>,
+    queries: Vec<Query>,
+    random: Random,
+}
+
+/// Converts a `core::time::Duration` to a smoltcp duration.
+fn duration(duration: core::time::Duration) -> time::Duration {
+    time::Duration::from_millis(duration.as_millis() as u64)
+}
+
+impl Resolver {
+    /// Creates a resolver whose UDP socket is added to `sockets`. The socket
+    /// is bound by the first query.
+    pub fn new(sockets: &mut SocketSet) -> Resolver {
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 2048]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 2048]);
+        Resolver {
+            socket: sockets.add(UdpSocket::new(rx_buffer, tx_buffer)),
+            hosts: Vec::new(),
+            cache: BTreeMap::new(),
+            queries: Vec::new(),
+            random: Random::new(),
+        }
+    }
+
+    /// Replaces the static entries with those of `text`, in the format of
+    /// `/etc/hosts`.
+    pub fn set_hosts(&mut self, text: &str) {
+        self.hosts = parse_hosts(text);
+    }
+
+    /// Returns the addresses of `name` in the static entries or the cache.
+    fn lookup(&self, name: &str, now: Instant) -> Option<Vec<Address>> {
+        let hosts: Vec<Address> = self
+            .hosts
+            .iter()
+            .filter(|(host, _)| host == name)
+            .map(|&(_, addr)| addr)
+            .collect();
+        if !hosts.is_empty() {
+            return Some(hosts);
+        }
+        match self.cache.get(name) {
+            Some(entry) if entry.expires > now => Some(entry.addresses.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the result of the query for `name`, which is
+    /// `OsError::IoErrorWouldBlock` while it is in flight, or `None` if
+    /// there is no query for `name`. A result is returned only once.
+    fn take_result(&mut self, name: &str) -> Option<OsResult<Vec<Address>>> {
+        let index = self.queries.iter().position(|query| query.name == name)?;
+        if self.queries[index].result.is_none() {
+            return Some(Err(OsError::IoErrorWouldBlock));
+        }
+        self.queries.swap_remove(index).result
+    }
+
+    /// Starts a query for `name`, sent by the next call to `poll()`.
+    fn start(&mut self, name: String, now: Instant) -> OsResult<()> {
+        encode_query(0, &name, TYPE_A).ok_or(OsError::InvalidArgument)?;
+        let id = self.random.next_u16();
+        let server = Ipv4Address::UNSPECIFIED;
+        let query = Query { name, id, qtype: TYPE_A, server, attempts: 0, deadline: now, result: None };
+        self.queries.push(query);
+        Ok(())
+    }
+
+    /// Returns a random port to bind the socket to, in the ephemeral range.
+    fn random_port(&mut self) -> u16 {
+        let range = 65536 - EPHEMERAL_PORT_START as u32;
+        EPHEMERAL_PORT_START + (self.random.next_u16() as u32 % range) as u16
+    }
+
+    /// Adds the addresses of `name` to the cache for `ttl` seconds, evicting
+    /// the entry that expires first when the cache is full.
+    fn cache_addresses(&mut self, name: &str, addresses: &[Address], ttl: u32, now: Instant) {
+        if self.cache.len() >= DNS_CACHE_SIZE && !self.cache.contains_key(name) {
+            let first = self
+                .cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(name, _)| name.clone());
+            if let Some(first) = first {
+                self.cache.remove(&first);
+            }
+        }
+        let expires = now + time::Duration::from_secs(ttl as u64);
+        self.cache.insert(String::from(name), CacheEntry { addresses: addresses.to_vec(), expires });
+    }
+
+    /// Handles the responses received by the socket, then sends the queries
+    /// that are due to the DNS `servers` in turn. A name without A records is
+    /// queried again for its AAAA records. A query is given up after
+    /// `DNS_ATTEMPTS` attempts.
+    fn poll(&mut self, sockets: &mut SocketSet, servers: &[Ipv4Address], now: Instant) {
+        let mut socket = sockets.get::<UdpSocket>(self.socket);
+        while let Ok((payload, endpoint)) = socket.recv() {
+            let response = match parse_response(payload) {
+                Some(response) => response,
+                None => continue,
+            };
+            let index = self.queries.iter().position(|query| query.is_answered_by(&response, endpoint));
+            let index = match index {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let name = self.queries[index].name.clone();
+            let (addresses, ttl) = addresses_of(&response.records, &name);
+            if response.rcode == 0 && addresses.is_empty() && self.queries[index].qtype == TYPE_A {
+                // the AAAA query is sent by the loop below
+                let id = self.random.next_u16();
+                let query = &mut self.queries[index];
+                query.id = id;
+                query.qtype = TYPE_AAAA;
+                query.attempts = 0;
+                query.deadline = now;
+                continue;
+            }
+            let result = match response.rcode {
+                0 if addresses.is_empty() => Err(OsError::NoEntry),
+                0 => {
+                    self.cache_addresses(&name, &addresses, ttl, now);
+                    Ok(addresses)
+                }
+                RCODE_NXDOMAIN => Err(OsError::NoEntry),
+                _ => Err(OsError::IoError),
+            };
+            let query = &mut self.queries[index];
+            query.result = Some(result);
+            query.deadline = now + duration(DNS_RETRY);
+        }
+
+        self.queries.retain(|query| query.result.is_none() || query.deadline > now);
+        for query in self.queries.iter_mut().filter(|query| query.result.is_none()) {
+            if query.deadline > now {
+                continue;
+            }
+            if query.attempts >= DNS_ATTEMPTS || servers.is_empty() {
+                query.result = Some(Err(OsError::IoErrorTimedOut));
+                query.deadline = now + duration(DNS_RETRY);
+                continue;
+            }
+
+            let server = servers[query.attempts as usize % servers.len()];
+            query.server = server;
+            if let Some(msg) = encode_query(query.id, &query.name, query.qtype) {
+                let endpoint = IpEndpoint::new(server.into(), DNS_PORT);
+                if let Err(e) = socket.send_slice(&msg, endpoint) {
+                    debug!("dns: failed to send a query to {}: {}", server, e);
+                }
+            }
+            query.attempts += 1;
+            query.deadline = now + duration(DNS_RETRY);
+        }
+    }
+}
+
+impl<P: Phy> EthernetDriver<P> {
+    /// Resolves `name`, which may also be an IPv4 address, to its addresses.
+    /// A query to the DNS servers is sent by `poll()`, and this fails with
+    /// `OsError::IoErrorWouldBlock` until it is answered.
+    ///
+    /// # Errors
+    ///
+    /// - `OsError::NoEntry`: The name does not exist, or no DNS server is
+    ///   configured.
+    /// - `OsError::InvalidArgument`: The name is not a valid domain name.
+    /// - `OsError::IoErrorTimedOut`: No DNS server answered.
+    /// - `OsError::IoError`: The DNS server failed to answer.
+    pub fn resolve(&mut self, name: &str, now: Instant) -> OsResult<Vec<Address>> {
+        if let Some(addr) = parse_address(name) {
+            return Ok(vec![addr]);
+        }
+
+        let name = normalize(name);
+        if let Some(addresses) = self.dns.lookup(&name, now) {
+            return Ok(addresses);
+        }
+        if let Some(result) = self.dns.take_result(&name) {
+            return result;
+        }
+        if self.dns_servers().is_empty() {
+            return Err(OsError::NoEntry);
+        }
+
+        let handle = self.dns.socket;
+        if !self.get_udp_socket(handle).is_open() {
+            let port = self.dns.random_port();
+            let port = match self.mark_port(port) {
+                Some(port) => port,
+                None => self
+                    .get_ephemeral_port()
+                    .and_then(|port| self.mark_port(port))
+                    .ok_or(OsError::NoEntry)?,
+            };
+            if self.get_udp_socket(handle).bind(port).is_err() {
+                self.erase_port(port);
+                return Err(OsError::Unknown);
+            }
+        }
+        self.dns.start(name, now)?;
+        Err(OsError::IoErrorWouldBlock)
+    }
+
+    /// Polls the resolver with the DNS servers of the current configuration.
+    pub(super) fn poll_dns(&mut self, now: Instant) {
+        let servers = match self.config.as_ref() {
+            Some(config) => config.dns_servers.clone(),
+            None => Vec::new(),
+        };
+        self.dns.poll(&mut self.socket_set, &servers, now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smoltcp::wire::Ipv4Cidr;
+
+    use crate::net::dhcp::{IpConfig, NetConfig};
+    use crate::net::phy::Loopback;
+    use crate::net::socket::Socket;
+    use kernel_api::SocketType;
+
+    const LOCALHOST: Ipv4Address = Ipv4Address([127, 0, 0, 1]);
+
+    /// Returns a driver on the loopback device that sends DNS queries to
+    /// `dns_server`.
+    fn driver(dns_server: Ipv4Address) -> EthernetDriver<Loopback> {
+        let config = NetConfig {
+            dhcp: false,
+            fallback: IpConfig {
+                address: Ipv4Cidr::new(LOCALHOST, 8),
+                gateway: None,
+                dns_servers: vec![dns_server],
+            },
+        };
+        EthernetDriver::new(Loopback::new(), config, Instant::from_millis(0))
+    }
+
+    /// Returns a response to `query` with `answers`, each a name pointer or
+    /// label sequence followed by the rest of a resource record.
+    fn response(query: &[u8], rcode: u8, answers: &[&[u8]]) -> Vec<u8> {
+        let mut msg = query.to_vec();
+        msg[2] = 0x81;
+        msg[3] = 0x80 | rcode;
+        msg[7] = answers.len() as u8;
+        for answer in answers {
+            msg.extend_from_slice(answer);
+        }
+        msg
+    }
+
+    /// Polls `driver` from `*t` on until `server` receives a datagram, and
+    /// returns it with its sender.
+    fn receive(driver: &mut EthernetDriver<Loopback>, server: Socket, t: &mut i64) -> (Vec<u8>, IpEndpoint) {
+        let mut buf = [0; 512];
+        loop {
+            driver.poll(Instant::from_millis(*t));
+            *t += 10;
+            if let Ok((len, sender)) = driver.recv_from(server, &mut buf) {
+                return (buf[..len].to_vec(), sender);
+            }
+        }
+    }
+
+    #[test]
+    fn query() {
+        let query = encode_query(0x1234, "pi.example", TYPE_A).unwrap();
+        assert_eq!(
+            query,
+            b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+              \x02pi\x07example\x00\x00\x01\x00\x01"
+                .to_vec()
+        );
+        assert_eq!(encode_query(1, "a..b", TYPE_A), None);
+        assert_eq!(encode_query(1, &"a".repeat(64), TYPE_A), None);
+    }
+
+    #[test]
+    fn cname_chain() {
+        let query = encode_query(7, "www.example", TYPE_A).unwrap();
+        let msg = response(&query, 0, &[
+            // www.example CNAME pi.example, with a compressed name
+            b"\xc0\x0c\x00\x05\x00\x01\x00\x00\x0e\x10\x00\x05\x02pi\xc0\x10",
+            // pi.example A 10.0.0.2
+            b"\xc0\x29\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\x0a\x00\x00\x02",
+            // pi.example AAAA fd00::2
+            b"\xc0\x29\x00\x1c\x00\x01\x00\x00\x00\x3c\x00\x10\
+              \xfd\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x02",
+        ]);
+
+        let response = parse_response(&msg).unwrap();
+        assert_eq!((response.id, response.rcode), (7, 0));
+        assert_eq!(response.records[0].data, RecordData::Cname("pi.example".into()));
+        let mut v6 = [0; 16];
+        v6[0] = 0xfd;
+        v6[15] = 2;
+        assert_eq!(
+            addresses_of(&response.records, "www.example"),
+            (vec![Address::V4(Ipv4Address::new(10, 0, 0, 2)), Address::V6(v6)], 60)
+        );
+        assert!(addresses_of(&response.records, "example").0.is_empty());
+    }
+
+    #[test]
+    fn malformed_responses() {
+        let query = encode_query(7, "pi.example", TYPE_A).unwrap();
+        // a query is not a response
+        assert!(parse_response(&query).is_none());
+        // a name pointing to itself
+        let msg = response(&query, 0, &[b"\xc0\x1c\x00\x01"]);
+        assert!(parse_response(&msg).is_none());
+        // truncated record data
+        let msg = response(&query, 0, &[b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\x0a"]);
+        assert!(parse_response(&msg).is_none());
+    }
+
+    #[test]
+    fn responses_match_their_query() {
+        let server = Ipv4Address::new(10, 0, 0, 53);
+        let query = Query {
+            name: "pi.example".into(),
+            id: 7,
+            qtype: TYPE_A,
+            server,
+            attempts: 1,
+            deadline: Instant::from_millis(0),
+            result: None,
+        };
+        let from = IpEndpoint::new(server.into(), DNS_PORT);
+        let answer = |id, name, qtype| parse_response(&response(&encode_query(id, name, qtype).unwrap(), 0, &[])).unwrap();
+
+        assert!(query.is_answered_by(&answer(7, "pi.example", TYPE_A), from));
+        assert!(!query.is_answered_by(&answer(8, "pi.example", TYPE_A), from));
+        assert!(!query.is_answered_by(&answer(7, "evil.example", TYPE_A), from));
+        assert!(!query.is_answered_by(&answer(7, "pi.example", TYPE_AAAA), from));
+        let other = IpEndpoint::new(Ipv4Address::new(10, 0, 0, 66).into(), DNS_PORT);
+        assert!(!query.is_answered_by(&answer(7, "pi.example", TYPE_A), other));
+        let port = IpEndpoint::new(server.into(), 5353);
+        assert!(!query.is_answered_by(&answer(7, "pi.example", TYPE_A), port));
+
+        // a single question of class IN only
+        let mut msg = response(&encode_query(7, "pi.example", TYPE_A).unwrap(), 0, &[]);
+        msg[5] = 2;
+        assert!(parse_response(&msg).is_none());
+        msg[5] = 1;
+        let class = msg.len() - 1;
+        msg[class] = 3;
+        assert!(parse_response(&msg).is_none());
+    }
+
+    #[test]
+    fn hosts() {
+        let hosts = parse_hosts("# static entries\n10.0.0.1 gw Router.lan # the router\n::1 localhost\n\n");
+        let gw = Address::V4(Ipv4Address::new(10, 0, 0, 1));
+        let localhost = Address::V6(Ipv6Address::LOOPBACK.0);
+        assert_eq!(
+            hosts,
+            vec![("gw".into(), gw), ("router.lan".into(), gw), ("localhost".into(), localhost)]
+        );
+    }
+
+    #[test]
+    fn resolve_over_loopback() {
+        let mut driver = driver(LOCALHOST);
+        driver.dns.set_hosts("10.0.0.1 gw");
+        let server = driver.create(SocketType::Udp);
+        driver.bind(server, DNS_PORT).unwrap();
+
+        let now = Instant::from_millis(0);
+        assert_eq!(driver.resolve("10.0.0.7", now), Ok(vec![Address::V4(Ipv4Address::new(10, 0, 0, 7))]));
+        assert_eq!(driver.resolve("ff02::1", now), Ok(vec![Address::V6(Ipv6Address::LINK_LOCAL_ALL_NODES.0)]));
+        assert_eq!(driver.resolve("GW.", now), Ok(vec![Address::V4(Ipv4Address::new(10, 0, 0, 1))]));
+        assert_eq!(driver.resolve("pi.example", now), Err(OsError::IoErrorWouldBlock));
+        assert_eq!(driver.resolve("pi.example", now), Err(OsError::IoErrorWouldBlock));
+
+        let mut t = 0;
+        let (query, client) = receive(&mut driver, server, &mut t);
+        let answer: &[u8] = b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\x0a\x00\x00\x02";
+        let msg = response(&query, 0, &[answer]);
+        driver.send_to(server, &msg, client).unwrap();
+
+        let pi = vec![Address::V4(Ipv4Address::new(10, 0, 0, 2))];
+        let result = loop {
+            driver.poll(Instant::from_millis(t));
+            t += 10;
+            match driver.resolve("pi.example", Instant::from_millis(t)) {
+                Err(OsError::IoErrorWouldBlock) => continue,
+                result => break result,
+            }
+        };
+        assert_eq!(result, Ok(pi.clone()));
+        // answered from the cache until the TTL of 60 s expires
+        assert_eq!(driver.resolve("pi.example", Instant::from_millis(t + 59_000)), Ok(pi));
+        let later = Instant::from_millis(t + 61_000);
+        assert_eq!(driver.resolve("pi.example", later), Err(OsError::IoErrorWouldBlock));
+    }
+
+    #[test]
+    fn aaaa_query_without_a_records() {
+        let mut driver = driver(LOCALHOST);
+        let server = driver.create(SocketType::Udp);
+        driver.bind(server, DNS_PORT).unwrap();
+        assert_eq!(driver.resolve("v6.example", Instant::from_millis(0)), Err(OsError::IoErrorWouldBlock));
+
+        let mut t = 0;
+        // no A record
+        let (query, client) = receive(&mut driver, server, &mut t);
+        assert_eq!(query[query.len() - 4..], [0, 1, 0, 1]);
+        driver.send_to(server, &response(&query, 0, &[]), client).unwrap();
+
+        let (query, client) = receive(&mut driver, server, &mut t);
+        assert_eq!(query[query.len() - 4..], [0, 28, 0, 1]);
+        let answer: &[u8] = b"\xc0\x0c\x00\x1c\x00\x01\x00\x00\x00\x3c\x00\x10\
+              \xfd\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x02";
+        driver.send_to(server, &response(&query, 0, &[answer]), client).unwrap();
+
+        let mut v6 = [0; 16];
+        v6[0] = 0xfd;
+        v6[15] = 2;
+        let result = loop {
+            driver.poll(Instant::from_millis(t));
+            t += 10;
+            match driver.resolve("v6.example", Instant::from_millis(t)) {
+                Err(OsError::IoErrorWouldBlock) => continue,
+                result => break result,
+            }
+        };
+        assert_eq!(result, Ok(vec![Address::V6(v6)]));
+    }
+
+    #[test]
+    fn unanswered_query_times_out() {
+        let mut driver = driver(Ipv4Address::new(127, 0, 0, 53));
+        assert_eq!(driver.resolve("pi.example", Instant::from_millis(0)), Err(OsError::IoErrorWouldBlock));
+
+        let mut t = 0;
+        let result = loop {
+            driver.poll(Instant::from_millis(t));
+            t += 100;
+            match driver.resolve("pi.example", Instant::from_millis(t)) {
+                Err(OsError::IoErrorWouldBlock) => continue,
+                result => break result,
+            }
+        };
+        assert_eq!(result, Err(OsError::IoErrorTimedOut));
+        assert!(t >= 3000);
+    }
+}
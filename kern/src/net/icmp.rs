@@ -0,0 +1,154 @@
+//! ICMP echo, for `ping`. Each echo request carries the time it was sent, so
+//! the round-trip time is computed from the reply alone.
+
+use core::convert::TryInto;
+use core::time::Duration;
+
+use kernel_api::{OsError, OsResult};
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::{IcmpEndpoint, SocketHandle};
+use smoltcp::wire::{Icmpv4Packet, Icmpv4Repr, IpAddress, Ipv4Address};
+
+use crate::net::socket::socket_error;
+use crate::net::{EthernetDriver, Phy};
+
+/// Length of the data of an echo request: the time it was sent, then a
+/// pattern.
+pub const ECHO_DATA_LEN: usize = 56;
+
+/// An ICMP socket receiving the echo replies with one identifier.
+#[derive(Clone, Copy, Debug)]
+pub struct Echo {
+    handle: SocketHandle,
+    ident: u16,
+}
+
+/// An echo reply.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EchoReply {
+    pub from: Ipv4Address,
+    pub seq: u16,
+    /// Length of the ICMP message.
+    pub len: usize,
+    /// When the request was sent, as given to `send_echo`.
+    pub sent: Duration,
+}
+
+impl<P: Phy> EthernetDriver<P> {
+    /// Creates an ICMP socket for the echo replies with identifier `ident`.
+    pub fn open_echo(&mut self, ident: u16) -> OsResult<Echo> {
+        let handle = self.add_icmp_socket();
+        match self.get_icmp_socket(handle).bind(IcmpEndpoint::Ident(ident)) {
+            Ok(()) => Ok(Echo { handle, ident }),
+            Err(e) => {
+                self.release(handle);
+                Err(socket_error(e))
+            }
+        }
+    }
+
+    /// Queues an echo request with sequence number `seq` to `dst`, recording
+    /// `sent` as the time it is sent.
+    pub fn send_echo(&mut self, echo: Echo, dst: Ipv4Address, seq: u16, sent: Duration) -> OsResult<()> {
+        let mut data = [0; ECHO_DATA_LEN];
+        data[..8].copy_from_slice(&(sent.as_micros() as u64).to_be_bytes());
+        for (i, byte) in data[8..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let repr = Icmpv4Repr::EchoRequest { ident: echo.ident, seq_no: seq, data: &data };
+
+        let mut socket = self.get_icmp_socket(echo.handle);
+        if !socket.can_send() {
+            return Err(OsError::IoErrorWouldBlock);
+        }
+        let payload = socket.send(repr.buffer_len(), dst.into()).map_err(socket_error)?;
+        repr.emit(&mut Icmpv4Packet::new_unchecked(payload), &ChecksumCapabilities::default());
+        Ok(())
+    }
+
+    /// Returns the next echo reply received, or fails with
+    /// `OsError::IoErrorWouldBlock` if there is none. Other messages are
+    /// dropped.
+    pub fn recv_echo(&mut self, echo: Echo) -> OsResult<EchoReply> {
+        let mut socket = self.get_icmp_socket(echo.handle);
+        while socket.can_recv() {
+            let (payload, from) = socket.recv().map_err(socket_error)?;
+            let from = match from {
+                IpAddress::Ipv4(from) => from,
+                _ => continue,
+            };
+            let packet = match Icmpv4Packet::new_checked(payload) {
+                Ok(packet) => packet,
+                Err(_) => continue,
+            };
+            match Icmpv4Repr::parse(&packet, &ChecksumCapabilities::default()) {
+                Ok(Icmpv4Repr::EchoReply { ident, seq_no, data })
+                    if ident == echo.ident && data.len() >= 8 =>
+                {
+                    let sent = u64::from_be_bytes(data[..8].try_into().unwrap());
+                    return Ok(EchoReply {
+                        from,
+                        seq: seq_no,
+                        len: payload.len(),
+                        sent: Duration::from_micros(sent),
+                    });
+                }
+                _ => continue,
+            }
+        }
+        Err(OsError::IoErrorWouldBlock)
+    }
+
+    /// Closes the socket of `echo`.
+    pub fn close_echo(&mut self, echo: Echo) {
+        self.release(echo.handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use kernel_api::OsError;
+    use smoltcp::time::Instant;
+    use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
+
+    use crate::net::dhcp::{IpConfig, NetConfig};
+    use crate::net::phy::Loopback;
+    use crate::net::EthernetDriver;
+
+    use super::{EchoReply, ECHO_DATA_LEN};
+
+    const LOCALHOST: Ipv4Address = Ipv4Address([127, 0, 0, 1]);
+
+    fn driver() -> EthernetDriver<Loopback> {
+        let config = NetConfig {
+            dhcp: false,
+            fallback: IpConfig { address: Ipv4Cidr::new(LOCALHOST, 8), ..IpConfig::default() },
+        };
+        EthernetDriver::new(Loopback::new(), config, Instant::from_millis(0))
+    }
+
+    #[test]
+    fn echo_over_loopback() {
+        let mut driver = driver();
+        let echo = driver.open_echo(0x1234).unwrap();
+        let other = driver.open_echo(0x4321).unwrap();
+        assert_eq!(driver.recv_echo(echo), Err(OsError::IoErrorWouldBlock));
+
+        let sent = Duration::from_micros(1_500_250);
+        driver.send_echo(echo, LOCALHOST, 7, sent).unwrap();
+        for t in (0..100).step_by(10) {
+            driver.poll(Instant::from_millis(t));
+        }
+
+        let reply = EchoReply { from: LOCALHOST, seq: 7, len: 8 + ECHO_DATA_LEN, sent };
+        assert_eq!(driver.recv_echo(echo), Ok(reply));
+        assert_eq!(driver.recv_echo(echo), Err(OsError::IoErrorWouldBlock));
+        // The reply is only for the socket with its identifier.
+        assert_eq!(driver.recv_echo(other), Err(OsError::IoErrorWouldBlock));
+
+        driver.close_echo(echo);
+        driver.close_echo(other);
+    }
+}
@@ -0,0 +1,57 @@
+//! Network devices the `EthernetDriver` runs on.
+//!
+//! On the board, frames go through `UsbEthernet`. `Loopback` receives the
+//! frames it sends, and on the host `Tap` and `PcapReplay` connect the stack
+//! to a TAP interface or to a recorded capture so it can be tested with
+//! `cargo test`.
+
+mod loopback;
+#[cfg(test)]
+pub mod pcap;
+#[cfg(test)]
+pub mod tap;
+
+use alloc::vec::Vec;
+
+use smoltcp::phy::{self, Device};
+use smoltcp::time::Instant;
+use smoltcp::wire::EthernetAddress;
+
+pub use self::loopback::Loopback;
+#[cfg(test)]
+pub use self::pcap::PcapReplay;
+#[cfg(test)]
+pub use self::tap::Tap;
+
+/// Counters of the frames a device has received and sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Statistics {
+    pub rx_frames: u64,
+    pub rx_bytes: u64,
+    pub tx_frames: u64,
+    pub tx_bytes: u64,
+}
+
+/// An ethernet device: a smoltcp `Device` with a MAC address.
+pub trait Phy: for<'d> Device<'d> {
+    /// Returns the MAC address of the device.
+    fn ethernet_addr(&self) -> EthernetAddress;
+
+    /// Returns the frame counters of the device. Devices that do not count
+    /// frames report zeros.
+    fn statistics(&self) -> Statistics {
+        Statistics::default()
+    }
+}
+
+/// A received frame owned by the token.
+pub struct VecRxToken(pub Vec<u8>);
+
+impl phy::RxToken for VecRxToken {
+    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        f(&mut self.0)
+    }
+}
@@ -0,0 +1,182 @@
+//! Replay of pcap captures, for testing the stack on the host against
+//! recorded traffic.
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io;
+
+use smoltcp::phy::{self, Device, DeviceCapabilities};
+use smoltcp::time::Instant;
+use smoltcp::wire::EthernetAddress;
+
+use crate::net::phy::{Phy, VecRxToken};
+use crate::param::MTU;
+
+/// Magic number of a pcap capture with timestamps in microseconds.
+const MAGIC: u32 = 0xa1b2_c3d4;
+/// Magic number of a pcap capture with timestamps in nanoseconds.
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+/// Link type of captures of ethernet frames.
+const LINKTYPE_ETHERNET: u32 = 1;
+
+const HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Parses a pcap capture of ethernet frames, in either byte order, and
+/// returns its frames.
+pub fn parse(capture: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    if capture.len() < HEADER_LEN {
+        return Err(invalid("truncated pcap header"));
+    }
+    let magic = u32::from_le_bytes(capture[0..4].try_into().unwrap());
+    let read_u32: fn(&[u8]) -> u32 = match magic {
+        MAGIC | MAGIC_NANOS => |bytes| u32::from_le_bytes(bytes.try_into().unwrap()),
+        _ if magic.swap_bytes() == MAGIC || magic.swap_bytes() == MAGIC_NANOS => {
+            |bytes| u32::from_be_bytes(bytes.try_into().unwrap())
+        }
+        _ => return Err(invalid("not a pcap capture")),
+    };
+    if read_u32(&capture[20..24]) != LINKTYPE_ETHERNET {
+        return Err(invalid("not a capture of ethernet frames"));
+    }
+
+    let mut frames = Vec::new();
+    let mut rest = &capture[HEADER_LEN..];
+    while !rest.is_empty() {
+        if rest.len() < RECORD_HEADER_LEN {
+            return Err(invalid("truncated pcap record header"));
+        }
+        let len = read_u32(&rest[8..12]) as usize;
+        rest = &rest[RECORD_HEADER_LEN..];
+        if rest.len() < len {
+            return Err(invalid("truncated pcap record"));
+        }
+        frames.push(rest[..len].to_vec());
+        rest = &rest[len..];
+    }
+    Ok(frames)
+}
+
+/// Returns a pcap capture of `frames`, with zero timestamps.
+pub fn capture(frames: &[&[u8]]) -> Vec<u8> {
+    let mut capture = Vec::new();
+    capture.extend_from_slice(&MAGIC.to_le_bytes());
+    // version 2.4
+    capture.extend_from_slice(&2u16.to_le_bytes());
+    capture.extend_from_slice(&4u16.to_le_bytes());
+    for &field in &[0, 0, 65535, LINKTYPE_ETHERNET] {
+        capture.extend_from_slice(&field.to_le_bytes());
+    }
+    for frame in frames {
+        let len = frame.len() as u32;
+        for &field in &[0, 0, len, len] {
+            capture.extend_from_slice(&field.to_le_bytes());
+        }
+        capture.extend_from_slice(frame);
+    }
+    capture
+}
+
+/// A device that receives the frames of a capture, in order, and records the
+/// frames it sends.
+#[derive(Debug)]
+pub struct PcapReplay {
+    addr: EthernetAddress,
+    frames: VecDeque<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+}
+
+impl PcapReplay {
+    /// Creates a device with the MAC address `addr` replaying `capture`.
+    pub fn new(capture: &[u8], addr: EthernetAddress) -> io::Result<PcapReplay> {
+        Ok(PcapReplay {
+            addr,
+            frames: parse(capture)?.into_iter().collect(),
+            sent: Vec::new(),
+        })
+    }
+
+    /// Returns the frames sent so far.
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+}
+
+impl Phy for PcapReplay {
+    fn ethernet_addr(&self) -> EthernetAddress {
+        self.addr
+    }
+}
+
+impl<'a> Device<'a> for PcapReplay {
+    type RxToken = VecRxToken;
+    type TxToken = TxToken<'a>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capability = DeviceCapabilities::default();
+        capability.max_transmission_unit = MTU as usize;
+        capability
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let frame = self.frames.pop_front()?;
+        Some((VecRxToken(frame), TxToken(&mut self.sent)))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken(&mut self.sent))
+    }
+}
+
+pub struct TxToken<'a>(&'a mut Vec<Vec<u8>>);
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        let mut frame = vec![0; len];
+        let result = f(&mut frame);
+        if result.is_ok() {
+            self.0.push(frame);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capture, parse};
+
+    #[test]
+    fn round_trip() {
+        let frames: &[&[u8]] = &[b"first frame", b"", b"third"];
+        assert_eq!(parse(&capture(frames)).unwrap(), frames);
+    }
+
+    #[test]
+    fn big_endian() {
+        let mut bytes = capture(&[&b"frame"[..]]);
+        // the file header and the record header are made of u32s, but for
+        // the version, which is two u16s
+        for field in bytes[..40].chunks_mut(4) {
+            field.reverse();
+        }
+        bytes[4..8].copy_from_slice(&[0, 2, 0, 4]);
+        assert_eq!(parse(&bytes).unwrap(), vec![b"frame".to_vec()]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(parse(b"short").is_err());
+        let mut bytes = capture(&[&b"frame"[..]]);
+        bytes.pop();
+        assert!(parse(&bytes).is_err());
+        bytes[0] = 0;
+        assert!(parse(&bytes).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! TAP interfaces of the host, for testing the stack against real peers,
+//! e.g. a DHCP server listening on the interface:
+//!
+//! ```sh
+//! sudo ip tuntap add name tap0 mode tap user $USER
+//! sudo ip addr add 10.0.2.2/24 dev tap0 && sudo ip link set tap0 up
+//! sudo dnsmasq -d -i tap0 --dhcp-range=10.0.2.15,10.0.2.100
+//! cargo test -- --ignored tap
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::raw::{c_int, c_short, c_ulong};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+use smoltcp::phy::{self, Device, DeviceCapabilities};
+use smoltcp::time::Instant;
+use smoltcp::wire::EthernetAddress;
+
+use crate::net::phy::{Phy, VecRxToken};
+use crate::param::MTU;
+
+const TUNSETIFF: c_ulong = 0x4004_54ca;
+const IFF_TAP: c_short = 0x0002;
+const IFF_NO_PI: c_short = 0x1000;
+const O_NONBLOCK: c_int = 0o4000;
+
+/// Length of an ethernet header.
+const ETHERNET_HEADER_LEN: usize = 14;
+
+/// `struct ifreq` with the `ifr_flags` member of its union.
+#[repr(C)]
+struct IfReq {
+    name: [u8; 16],
+    flags: c_short,
+    _pad: [u8; 22],
+}
+
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+/// A TAP interface of the host.
+#[derive(Debug)]
+pub struct Tap {
+    file: File,
+    addr: EthernetAddress,
+}
+
+impl Tap {
+    /// Attaches to the existing TAP interface `name`. The device uses the
+    /// MAC address `addr`, which must differ from the interface's own.
+    pub fn open(name: &str, addr: EthernetAddress) -> io::Result<Tap> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(O_NONBLOCK)
+            .open("/dev/net/tun")?;
+
+        let mut ifreq = IfReq { name: [0; 16], flags: IFF_TAP | IFF_NO_PI, _pad: [0; 22] };
+        if name.len() >= ifreq.name.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "interface name too long"));
+        }
+        ifreq.name[..name.len()].copy_from_slice(name.as_bytes());
+        if unsafe { ioctl(file.as_raw_fd(), TUNSETIFF, &mut ifreq as *mut IfReq) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Tap { file, addr })
+    }
+}
+
+impl Phy for Tap {
+    fn ethernet_addr(&self) -> EthernetAddress {
+        self.addr
+    }
+}
+
+impl<'a> Device<'a> for Tap {
+    type RxToken = VecRxToken;
+    type TxToken = TxToken<'a>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capability = DeviceCapabilities::default();
+        capability.max_transmission_unit = MTU as usize;
+        capability
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let mut frame = vec![0; MTU as usize + ETHERNET_HEADER_LEN];
+        match self.file.read(&mut frame) {
+            Ok(len) => {
+                frame.truncate(len);
+                Some((VecRxToken(frame), TxToken(&mut self.file)))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => None,
+            Err(e) => panic!("tap: read failed: {}", e),
+        }
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken(&mut self.file))
+    }
+}
+
+pub struct TxToken<'a>(&'a mut File);
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        let mut frame = vec![0; len];
+        let result = f(&mut frame)?;
+        self.0.write_all(&frame).map_err(|_| smoltcp::Error::Exhausted)?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant as StdInstant};
+
+    use smoltcp::time::Instant;
+    use smoltcp::wire::EthernetAddress;
+
+    use super::Tap;
+    use crate::net::dhcp::NetConfig;
+    use crate::net::EthernetDriver;
+
+    /// Acquires a lease from a DHCP server on `tap0`; see the module
+    /// documentation for a setup.
+    #[test]
+    #[ignore]
+    fn tap_dhcp() {
+        let tap = Tap::open("tap0", EthernetAddress([0x02, 0, 0, 0, 0, 0x10])).unwrap();
+        let mut driver = EthernetDriver::new(tap, NetConfig::default(), Instant::from_millis(0));
+
+        let start = StdInstant::now();
+        while driver.config().is_none() && start.elapsed() < Duration::from_secs(5) {
+            let now = Instant::from_millis(start.elapsed().as_millis() as i64);
+            driver.poll(now);
+            thread::sleep(driver.poll_delay(now));
+        }
+        let config = driver.config().expect("no lease acquired");
+        assert_ne!(config.address, NetConfig::default().fallback.address);
+    }
+}
@@ -0,0 +1,374 @@
+//! IPv6 configuration of the ethernet interface without a server (stateless
+//! address autoconfiguration, RFC 4862). The interface has a link-local
+//! address formed from its MAC address; it solicits router advertisements,
+//! and forms a global address from the prefix they announce and takes their
+//! sender as the default router.
+//!
+//! Neighbor discovery is done by smoltcp with the neighbor cache of the
+//! interface. Duplicate address detection is not: both addresses are formed
+//! from the MAC address, which is assumed to be unique on the link.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::socket::{RawPacketMetadata, RawSocket, RawSocketBuffer, SocketHandle};
+use smoltcp::time::{self, Instant};
+use smoltcp::wire::{
+    EthernetAddress, Icmpv6Packet, Icmpv6Repr, IpProtocol, IpVersion, Ipv6Address, Ipv6Cidr,
+    Ipv6Packet, Ipv6Repr, NdiscPrefixInfoFlags, NdiscRepr,
+};
+
+use crate::net::SocketSet;
+use crate::param::{ROUTER_SOLICITATIONS, ROUTER_SOLICITATION_INTERVAL};
+
+/// The all-routers multicast address, where router solicitations are sent.
+const ALL_ROUTERS: Ipv6Address =
+    Ipv6Address([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+/// The hop limit of neighbor discovery messages, which never leave the link.
+const NDISC_HOP_LIMIT: u8 = 255;
+
+/// Prefix length of the addresses formed from an interface identifier.
+const PREFIX_LEN: u8 = 64;
+
+/// A valid lifetime of this many seconds never expires.
+const INFINITE_LIFETIME: u64 = 0xffff_ffff;
+
+/// Returns the interface identifier of `mac`: its modified EUI-64, with the
+/// universal/local bit flipped and `ff:fe` in the middle.
+fn interface_id(mac: EthernetAddress) -> [u8; 8] {
+    let mac = mac.0;
+    [mac[0] ^ 0x02, mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5]]
+}
+
+/// Returns the address formed from the 64-bit `prefix` and the interface
+/// identifier of `mac`.
+fn address(prefix: Ipv6Address, mac: EthernetAddress) -> Ipv6Address {
+    let mut bytes = prefix.0;
+    bytes[8..].copy_from_slice(&interface_id(mac));
+    Ipv6Address(bytes)
+}
+
+/// Returns the link-local address of the interface with MAC address `mac`.
+pub fn link_local(mac: EthernetAddress) -> Ipv6Cidr {
+    let prefix = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0);
+    Ipv6Cidr::new(address(prefix, mac), PREFIX_LEN)
+}
+
+/// Returns an IPv6 packet carrying the neighbor discovery message `repr`.
+fn ndisc_packet(src: Ipv6Address, dst: Ipv6Address, repr: NdiscRepr) -> Vec<u8> {
+    let icmp = Icmpv6Repr::Ndisc(repr);
+    let ip = Ipv6Repr {
+        src_addr: src,
+        dst_addr: dst,
+        next_header: IpProtocol::Icmpv6,
+        payload_len: icmp.buffer_len(),
+        hop_limit: NDISC_HOP_LIMIT,
+    };
+
+    let mut buf = vec![0; ip.buffer_len() + icmp.buffer_len()];
+    let mut packet = Ipv6Packet::new_unchecked(&mut buf[..]);
+    ip.emit(&mut packet);
+    icmp.emit(
+        &src.into(),
+        &dst.into(),
+        &mut Icmpv6Packet::new_unchecked(packet.payload_mut()),
+        &ChecksumCapabilities::default(),
+    );
+    buf
+}
+
+/// Returns a router solicitation from `src`, giving `mac` as the link-layer
+/// address to answer to.
+fn router_solicitation(src: Ipv6Address, mac: EthernetAddress) -> Vec<u8> {
+    ndisc_packet(src, ALL_ROUTERS, NdiscRepr::RouterSolicit { lladdr: Some(mac) })
+}
+
+/// What a router advertisement configures.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Advert {
+    /// The link-local address of the router.
+    router: Ipv6Address,
+    /// How long the router is a default router; zero if it is not one.
+    router_lifetime: time::Duration,
+    /// The prefix to form an address from, with how long the address is
+    /// valid.
+    prefix: Option<(Ipv6Address, time::Duration)>,
+}
+
+/// Parses `packet` as a router advertisement. Returns `None` for other
+/// packets and for advertisements that did not come from a router on the
+/// link. A prefix is only kept if it is for address configuration and 64
+/// bits long.
+fn parse_advert(packet: &[u8]) -> Option<Advert> {
+    let packet = Ipv6Packet::new_checked(packet).ok()?;
+    let ip = Ipv6Repr::parse(&packet).ok()?;
+    if ip.next_header != IpProtocol::Icmpv6
+        || ip.hop_limit != NDISC_HOP_LIMIT
+        || !ip.src_addr.is_link_local()
+    {
+        return None;
+    }
+
+    let icmp = Icmpv6Packet::new_checked(packet.payload()).ok()?;
+    let repr = Icmpv6Repr::parse(
+        &ip.src_addr.into(),
+        &ip.dst_addr.into(),
+        &icmp,
+        &ChecksumCapabilities::default(),
+    )
+    .ok()?;
+    match repr {
+        Icmpv6Repr::Ndisc(NdiscRepr::RouterAdvert { router_lifetime, prefix_info, .. }) => {
+            let prefix = prefix_info
+                .filter(|info| {
+                    info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF)
+                        && info.prefix_len == PREFIX_LEN
+                        && !info.prefix.is_link_local()
+                })
+                .map(|info| (info.prefix, info.valid_lifetime));
+            Some(Advert { router: ip.src_addr, router_lifetime, prefix })
+        }
+        _ => None,
+    }
+}
+
+/// The IPv6 configuration of the interface, kept up to date from the router
+/// advertisements received.
+pub struct Slaac {
+    /// Raw socket receiving ICMPv6 and sending router solicitations
+    socket: SocketHandle,
+    mac: EthernetAddress,
+    /// How many router solicitations are left to send, and when the next one
+    /// is due
+    solicitations: u8,
+    next_solicitation: Instant,
+    /// The global address, with when it expires unless its lifetime is
+    /// infinite
+    address: Option<(Ipv6Cidr, Option<Instant>)>,
+    /// The default router, with when it stops being one
+    router: Option<(Ipv6Address, Instant)>,
+}
+
+impl Slaac {
+    /// Creates the configuration of the interface with MAC address `mac`,
+    /// whose raw socket is added to `sockets`. The first router solicitation
+    /// is sent by the first `poll()`.
+    pub fn new(sockets: &mut SocketSet, mac: EthernetAddress, now: Instant) -> Slaac {
+        let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 4], vec![0; 2048]);
+        let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 128]);
+        let socket = RawSocket::new(IpVersion::Ipv6, IpProtocol::Icmpv6, rx_buffer, tx_buffer);
+        Slaac {
+            socket: sockets.add(socket),
+            mac,
+            solicitations: ROUTER_SOLICITATIONS,
+            next_solicitation: now,
+            address: None,
+            router: None,
+        }
+    }
+
+    /// Returns the link-local address of the interface.
+    pub fn link_local(&self) -> Ipv6Cidr {
+        link_local(self.mac)
+    }
+
+    /// Returns the global address formed from a router advertisement, if any.
+    pub fn address(&self) -> Option<Ipv6Cidr> {
+        self.address.map(|(address, _)| address)
+    }
+
+    /// Returns the default router, if any.
+    pub fn router(&self) -> Option<Ipv6Address> {
+        self.router.map(|(router, _)| router)
+    }
+
+    /// Sends the router solicitations that are due, applies the router
+    /// advertisements received, and drops the address and the router whose
+    /// lifetime is over. Returns whether the address or the router changed.
+    pub fn poll(&mut self, sockets: &mut SocketSet, now: Instant) -> bool {
+        let mut socket = sockets.get::<RawSocket>(self.socket);
+        let mut changed = false;
+        while socket.can_recv() {
+            let advert = match socket.recv() {
+                Ok(packet) => parse_advert(packet),
+                Err(_) => break,
+            };
+            if let Some(advert) = advert {
+                changed |= self.apply(advert, now);
+            }
+        }
+
+        if self.solicitations > 0 && now >= self.next_solicitation {
+            let packet = router_solicitation(self.link_local().address(), self.mac);
+            match socket.send_slice(&packet) {
+                Ok(()) => {
+                    self.solicitations -= 1;
+                    self.next_solicitation = now
+                        + time::Duration::from_millis(ROUTER_SOLICITATION_INTERVAL.as_millis() as u64);
+                }
+                Err(e) => debug!("slaac: {}", e),
+            }
+        }
+
+        self.expire(now) || changed
+    }
+
+    /// Applies a router advertisement received at `now`. Returns whether the
+    /// address or the router changed.
+    fn apply(&mut self, advert: Advert, now: Instant) -> bool {
+        // No more solicitations are needed once a router has answered.
+        self.solicitations = 0;
+        let old = (self.address(), self.router());
+
+        if advert.router_lifetime.total_millis() > 0 {
+            self.router = Some((advert.router, now + advert.router_lifetime));
+        } else if self.router() == Some(advert.router) {
+            self.router = None;
+        }
+
+        if let Some((prefix, valid_lifetime)) = advert.prefix {
+            let addr = Ipv6Cidr::new(address(prefix, self.mac), PREFIX_LEN);
+            let expires = match valid_lifetime.secs() {
+                INFINITE_LIFETIME => None,
+                _ => Some(now + valid_lifetime),
+            };
+            self.address = Some((addr, expires));
+        }
+
+        old != (self.address(), self.router())
+    }
+
+    /// Drops the address and the router whose lifetime is over at `now`.
+    /// Returns whether any was dropped.
+    fn expire(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+        if let Some((_, Some(expires))) = self.address {
+            if now >= expires {
+                self.address = None;
+                changed = true;
+            }
+        }
+        if let Some((_, expires)) = self.router {
+            if now >= expires {
+                self.router = None;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Returns how long to wait before the next call to `poll()` to send the
+    /// next router solicitation, or `None` if none is left to send.
+    pub fn next_poll(&self, now: Instant) -> Option<Duration> {
+        if self.solicitations == 0 {
+            return None;
+        }
+        match self.next_solicitation > now {
+            true => Some(Duration::from_millis((self.next_solicitation - now).total_millis())),
+            false => Some(Duration::from_millis(0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smoltcp::time::Duration;
+    use smoltcp::wire::{NdiscPrefixInformation, NdiscRouterFlags};
+
+    use super::*;
+
+    const MAC: EthernetAddress = EthernetAddress([0xb8, 0x27, 0xeb, 0x12, 0x34, 0x56]);
+    const ROUTER: Ipv6Address = Ipv6Address([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+    /// Returns a router advertisement from `src`, announcing the prefix
+    /// 2001:db8:1::/`prefix_len` valid for `valid` seconds if given.
+    fn advert(src: Ipv6Address, router_lifetime: u64, prefix: Option<(u8, u32)>) -> Vec<u8> {
+        let prefix_info = prefix.map(|(prefix_len, valid)| NdiscPrefixInformation {
+            prefix_len,
+            flags: NdiscPrefixInfoFlags::ON_LINK | NdiscPrefixInfoFlags::ADDRCONF,
+            valid_lifetime: Duration::from_secs(valid as u64),
+            preferred_lifetime: Duration::from_secs(valid as u64),
+            prefix: Ipv6Address::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0),
+        });
+        let repr = NdiscRepr::RouterAdvert {
+            hop_limit: 64,
+            flags: NdiscRouterFlags::empty(),
+            router_lifetime: Duration::from_secs(router_lifetime),
+            reachable_time: Duration::from_millis(0),
+            retrans_time: Duration::from_millis(0),
+            lladdr: None,
+            mtu: None,
+            prefix_info,
+        };
+        ndisc_packet(src, Ipv6Address::LINK_LOCAL_ALL_NODES, repr)
+    }
+
+    #[test]
+    fn addresses() {
+        let link_local = Ipv6Cidr::new(Ipv6Address::new(0xfe80, 0, 0, 0, 0xba27, 0xebff, 0xfe12, 0x3456), 64);
+        assert_eq!(super::link_local(MAC), link_local);
+
+        let prefix = Ipv6Address::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0);
+        let global = Ipv6Address::new(0x2001, 0xdb8, 1, 0, 0xba27, 0xebff, 0xfe12, 0x3456);
+        assert_eq!(address(prefix, MAC), global);
+    }
+
+    #[test]
+    fn solicitation() {
+        let src = super::link_local(MAC).address();
+        let buf = router_solicitation(src, MAC);
+        let packet = Ipv6Packet::new_checked(&buf[..]).unwrap();
+        let ip = Ipv6Repr::parse(&packet).unwrap();
+        assert_eq!((ip.src_addr, ip.dst_addr), (src, ALL_ROUTERS));
+        assert_eq!((ip.next_header, ip.hop_limit), (IpProtocol::Icmpv6, 255));
+
+        let icmp = Icmpv6Packet::new_checked(packet.payload()).unwrap();
+        let repr = Icmpv6Repr::parse(&src.into(), &ALL_ROUTERS.into(), &icmp, &ChecksumCapabilities::default());
+        assert_eq!(repr, Ok(Icmpv6Repr::Ndisc(NdiscRepr::RouterSolicit { lladdr: Some(MAC) })));
+    }
+
+    #[test]
+    fn router_advertisements() {
+        let prefix = Ipv6Address::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0);
+        assert_eq!(
+            parse_advert(&advert(ROUTER, 1800, Some((64, 600)))),
+            Some(Advert {
+                router: ROUTER,
+                router_lifetime: Duration::from_secs(1800),
+                prefix: Some((prefix, Duration::from_secs(600))),
+            })
+        );
+        // Only 64-bit prefixes form addresses.
+        assert_eq!(parse_advert(&advert(ROUTER, 1800, Some((48, 600)))).unwrap().prefix, None);
+        // Routers send from their link-local address.
+        let global = Ipv6Address::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 1);
+        assert_eq!(parse_advert(&advert(global, 1800, None)), None);
+        assert_eq!(parse_advert(&router_solicitation(ROUTER, MAC)), None);
+    }
+
+    #[test]
+    fn lifetimes() {
+        let mut sockets = SocketSet::new(Vec::new());
+        let start = Instant::from_millis(0);
+        let mut slaac = Slaac::new(&mut sockets, MAC, start);
+        assert_eq!(slaac.next_poll(start), Some(core::time::Duration::from_millis(0)));
+
+        let advert = parse_advert(&advert(ROUTER, 30, Some((64, 60)))).unwrap();
+        assert!(slaac.apply(advert, start));
+        assert!(!slaac.apply(advert, start));
+        assert_eq!(slaac.next_poll(start), None);
+        let global = Ipv6Address::new(0x2001, 0xdb8, 1, 0, 0xba27, 0xebff, 0xfe12, 0x3456);
+        assert_eq!(slaac.address(), Some(Ipv6Cidr::new(global, 64)));
+        assert_eq!(slaac.router(), Some(ROUTER));
+
+        assert!(!slaac.expire(Instant::from_secs(29)));
+        assert!(slaac.expire(Instant::from_secs(30)));
+        assert_eq!(slaac.router(), None);
+        assert!(slaac.address().is_some());
+        assert!(slaac.expire(Instant::from_secs(60)));
+        assert_eq!(slaac.address(), None);
+    }
+}
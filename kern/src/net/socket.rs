@@ -0,0 +1,599 @@
+//! Sockets held by processes and the operations behind the socket system
+//! calls. The operations only touch the `EthernetDriver`, so they can be
+//! tested on the host over a `Loopback` device.
+//!
+//! An operation that would have to wait fails with
+//! `OsError::IoErrorWouldBlock`; the system calls retry it until it completes
+//! unless the socket is non-blocking.
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use kernel_api::{OsError, OsResult, SocketStatus, SocketType, POLL_IN, POLL_OUT};
+use smoltcp::socket::{self as smol, SocketHandle, TcpSocket, TcpState};
+use smoltcp::wire::{IpEndpoint, IpProtocol};
+
+use crate::net::{EthernetDriver, Phy};
+
+/// A socket held by a process: its handle in the socket set of the
+/// `EthernetDriver`, its protocol and how its operations wait.
+#[derive(Clone, Copy, Debug)]
+pub struct Socket {
+    handle: SocketHandle,
+    ty: SocketType,
+    /// Whether operations fail with `OsError::IoErrorWouldBlock` instead of
+    /// waiting.
+    pub nonblocking: bool,
+    /// How long an operation waits before failing with
+    /// `OsError::IoErrorTimedOut`, or `None` to wait as long as needed.
+    pub timeout: Option<Duration>,
+}
+
+impl Socket {
+    fn new(handle: SocketHandle, ty: SocketType) -> Socket {
+        Socket { handle, ty, nonblocking: false, timeout: None }
+    }
+
+    /// Returns the handle of a TCP socket, or fails with
+    /// `OsError::IllegalSocketOperation`.
+    fn tcp(self) -> OsResult<SocketHandle> {
+        match self.ty {
+            SocketType::Tcp => Ok(self.handle),
+            SocketType::Udp => Err(OsError::IllegalSocketOperation),
+        }
+    }
+
+    /// Returns the handle of a UDP socket, or fails with
+    /// `OsError::IllegalSocketOperation`.
+    fn udp(self) -> OsResult<SocketHandle> {
+        match self.ty {
+            SocketType::Udp => Ok(self.handle),
+            SocketType::Tcp => Err(OsError::IllegalSocketOperation),
+        }
+    }
+}
+
+/// The protocol of a socket in the socket set, with the state of a TCP
+/// connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SocketKind {
+    Tcp(TcpState),
+    Udp,
+    Icmp,
+    /// A raw socket of the kernel, e.g. the one of the DHCP client.
+    Raw(IpProtocol),
+}
+
+/// A socket in the socket set, as listed by `netstat`. The endpoints are
+/// unspecified when they do not apply.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SocketEntry {
+    pub kind: SocketKind,
+    pub local: IpEndpoint,
+    pub remote: IpEndpoint,
+}
+
+/// Maps an error returned by a smoltcp socket to an `OsError`.
+pub(super) fn socket_error(e: smoltcp::Error) -> OsError {
+    match e {
+        smoltcp::Error::Illegal => OsError::IllegalSocketOperation,
+        smoltcp::Error::Unaddressable => OsError::BadAddress,
+        _ => OsError::Unknown,
+    }
+}
+
+/// Returns whether the connection of a TCP socket is being established.
+fn is_connecting(socket: &TcpSocket) -> bool {
+    match socket.state() {
+        TcpState::SynSent | TcpState::SynReceived => true,
+        _ => false,
+    }
+}
+
+/// Returns whether `recv` on a TCP socket has to wait: its connection is
+/// being established, or is open and nothing has been received.
+fn recv_would_block(socket: &TcpSocket) -> bool {
+    !socket.can_recv() && (is_connecting(socket) || socket.may_recv())
+}
+
+/// Returns whether `send` on a TCP socket has to wait: its connection is
+/// being established, or is open and the send buffer is full.
+fn send_would_block(socket: &TcpSocket) -> bool {
+    !socket.can_send() && (is_connecting(socket) || socket.may_send())
+}
+
+impl<P: Phy> EthernetDriver<P> {
+    /// Creates a socket of type `ty`.
+    pub fn create(&mut self, ty: SocketType) -> Socket {
+        let handle = match ty {
+            SocketType::Tcp => self.add_socket(),
+            SocketType::Udp => self.add_udp_socket(),
+        };
+        Socket::new(handle, ty)
+    }
+
+    /// Returns every socket of the socket set, including those used by the
+    /// kernel itself and those still closing.
+    pub fn sockets(&self) -> Vec<SocketEntry> {
+        let unspecified = IpEndpoint::default();
+        self.socket_set
+            .iter()
+            .filter_map(|socket| {
+                let (kind, local, remote) = match *socket {
+                    smol::Socket::Tcp(ref socket) => (
+                        SocketKind::Tcp(socket.state()),
+                        socket.local_endpoint(),
+                        socket.remote_endpoint(),
+                    ),
+                    smol::Socket::Udp(ref socket) => (SocketKind::Udp, socket.endpoint(), unspecified),
+                    smol::Socket::Icmp(_) => (SocketKind::Icmp, unspecified, unspecified),
+                    smol::Socket::Raw(ref socket) => {
+                        (SocketKind::Raw(socket.ip_protocol()), unspecified, unspecified)
+                    }
+                    _ => return None,
+                };
+                Some(SocketEntry { kind, local, remote })
+            })
+            .collect()
+    }
+
+    /// Returns the status of a socket. A UDP socket is active once it is
+    /// bound and never listens.
+    pub fn status(&mut self, socket: Socket) -> SocketStatus {
+        match socket.ty {
+            SocketType::Tcp => {
+                let socket = self.get_socket(socket.handle);
+                SocketStatus {
+                    is_active: socket.is_active(),
+                    is_listening: socket.is_listening(),
+                    can_send: socket.can_send(),
+                    can_recv: socket.can_recv(),
+                }
+            }
+            SocketType::Udp => {
+                let socket = self.get_udp_socket(socket.handle);
+                SocketStatus {
+                    is_active: socket.is_open(),
+                    is_listening: false,
+                    can_send: socket.can_send(),
+                    can_recv: socket.can_recv(),
+                }
+            }
+        }
+    }
+
+    /// Returns the events among `POLL_IN` and `POLL_OUT` that have occurred
+    /// on a socket, i.e. which operations would not wait. A listening socket
+    /// is readable once `accept` would not wait.
+    pub fn poll_events(&mut self, socket: Socket) -> u32 {
+        let handle = socket.handle;
+        let listening = self.listeners.iter().any(|&(listener, _)| listener == handle);
+        let (readable, writable) = match socket.ty {
+            SocketType::Tcp if listening => match self.get_socket(handle).state() {
+                TcpState::Listen | TcpState::SynReceived => (false, false),
+                _ => (true, false),
+            },
+            SocketType::Tcp => {
+                let socket = self.get_socket(handle);
+                (!recv_would_block(&socket), !send_would_block(&socket))
+            }
+            SocketType::Udp => {
+                let socket = self.get_udp_socket(handle);
+                (socket.can_recv(), socket.can_send())
+            }
+        };
+
+        let mut events = 0;
+        if readable {
+            events |= POLL_IN;
+        }
+        if writable {
+            events |= POLL_OUT;
+        }
+        events
+    }
+
+    /// Connects a TCP socket from a local ephemeral port to `remote`. The
+    /// connection is established in the background; `send` and `recv` wait
+    /// for it.
+    pub fn connect(&mut self, socket: Socket, remote: IpEndpoint) -> OsResult<()> {
+        let handle = socket.tcp()?;
+        let local_port = self
+            .get_ephemeral_port()
+            .and_then(|port| self.mark_port(port))
+            .ok_or(OsError::NoEntry)?;
+        let result = self.get_socket(handle).connect(remote, local_port);
+        result.map_err(|e| {
+            self.erase_port(local_port);
+            socket_error(e)
+        })
+    }
+
+    /// Listens on `local_port` for an inbound connection with a TCP socket.
+    pub fn listen(&mut self, socket: Socket, local_port: u16) -> OsResult<()> {
+        let handle = socket.tcp()?;
+        self.mark_port(local_port)
+            .ok_or(OsError::IllegalSocketOperation)?;
+        let result = self.get_socket(handle).listen(local_port);
+        match result {
+            Ok(()) => {
+                self.listeners.push((handle, local_port));
+                Ok(())
+            }
+            Err(e) => {
+                self.erase_port(local_port);
+                Err(socket_error(e))
+            }
+        }
+    }
+
+    /// Takes the connection accepted by a listening TCP socket. The socket
+    /// keeps the connection and a fresh socket listens on the same port in
+    /// its place. Returns the new listening socket, the connected socket and
+    /// the remote endpoint. Fails with `OsError::IoErrorWouldBlock` until a
+    /// connection is established.
+    pub fn accept(&mut self, socket: Socket) -> OsResult<(Socket, Socket, IpEndpoint)> {
+        let handle = socket.tcp()?;
+        let index = self
+            .listeners
+            .iter()
+            .position(|&(listener, _)| listener == handle)
+            .ok_or(OsError::IllegalSocketOperation)?;
+
+        let remote = {
+            let socket = self.get_socket(handle);
+            match socket.state() {
+                TcpState::Listen | TcpState::SynReceived => return Err(OsError::IoErrorWouldBlock),
+                _ => socket.remote_endpoint(),
+            }
+        };
+
+        let port = self.listeners[index].1;
+        let listener = self.add_socket();
+        if let Err(e) = self.get_socket(listener).listen(port) {
+            self.release(listener);
+            return Err(socket_error(e));
+        }
+        self.listeners[index].0 = listener;
+        Ok((Socket { handle: listener, ..socket }, socket, remote))
+    }
+
+    /// Queues `buf` to be sent with a connected TCP socket. Returns the number
+    /// of bytes queued.
+    pub fn send(&mut self, socket: Socket, buf: &[u8]) -> OsResult<usize> {
+        let handle = socket.tcp()?;
+        let mut socket = self.get_socket(handle);
+        if send_would_block(&socket) {
+            return Err(OsError::IoErrorWouldBlock);
+        }
+        socket.send_slice(buf).map_err(socket_error)
+    }
+
+    /// Receives data from a connected TCP socket into `buf`. Returns the
+    /// number of bytes received, which is 0 once the remote endpoint has
+    /// closed the connection and everything has been received.
+    pub fn recv(&mut self, socket: Socket, buf: &mut [u8]) -> OsResult<usize> {
+        let handle = socket.tcp()?;
+        let mut socket = self.get_socket(handle);
+        if recv_would_block(&socket) {
+            return Err(OsError::IoErrorWouldBlock);
+        }
+        if socket.is_listening() {
+            return Err(OsError::IllegalSocketOperation);
+        }
+        if !socket.may_recv() {
+            return Ok(0);
+        }
+        socket.recv_slice(buf).map_err(socket_error)
+    }
+
+    /// Binds a UDP socket to `local_port`, or to an ephemeral port if
+    /// `local_port` is 0. TCP and UDP sockets share the same port space.
+    /// Returns the bound port.
+    pub fn bind(&mut self, socket: Socket, local_port: u16) -> OsResult<u16> {
+        let handle = socket.udp()?;
+        let port = if local_port == 0 {
+            self.get_ephemeral_port()
+                .and_then(|port| self.mark_port(port))
+                .ok_or(OsError::NoEntry)?
+        } else {
+            self.mark_port(local_port)
+                .ok_or(OsError::IllegalSocketOperation)?
+        };
+
+        let result = self.get_udp_socket(handle).bind(port);
+        match result {
+            Ok(()) => Ok(port),
+            Err(e) => {
+                self.erase_port(port);
+                Err(socket_error(e))
+            }
+        }
+    }
+
+    /// Queues `buf` to be sent as one datagram to `remote` with a UDP socket.
+    /// An unbound socket is bound to an ephemeral port first. Returns the
+    /// number of bytes queued.
+    pub fn send_to(&mut self, socket: Socket, buf: &[u8], remote: IpEndpoint) -> OsResult<usize> {
+        let handle = socket.udp()?;
+        if !self.get_udp_socket(handle).is_open() {
+            self.bind(socket, 0)?;
+        }
+        match self.get_udp_socket(handle).send_slice(buf, remote) {
+            Ok(()) => Ok(buf.len()),
+            Err(smoltcp::Error::Exhausted) => Err(OsError::IoErrorWouldBlock),
+            Err(e) => Err(socket_error(e)),
+        }
+    }
+
+    /// Receives a datagram from a UDP socket into `buf`; the part that does not
+    /// fit is discarded. Returns the number of bytes received and the sender.
+    pub fn recv_from(&mut self, socket: Socket, buf: &mut [u8]) -> OsResult<(usize, IpEndpoint)> {
+        let handle = socket.udp()?;
+        match self.get_udp_socket(handle).recv_slice(buf) {
+            Ok(received) => Ok(received),
+            Err(smoltcp::Error::Exhausted) => Err(OsError::IoErrorWouldBlock),
+            Err(e) => Err(socket_error(e)),
+        }
+    }
+
+    /// Closes a socket, frees its local port and releases it from the
+    /// internal socket set. A TCP socket is dropped by `poll()` once it has
+    /// finished closing. The port of an accepted connection stays taken while
+    /// a socket listens on it.
+    pub fn close(&mut self, socket: Socket) {
+        let handle = socket.handle;
+        let port = match socket.ty {
+            SocketType::Tcp => {
+                let mut socket = self.get_socket(handle);
+                let port = socket.local_endpoint().port;
+                socket.close();
+                port
+            }
+            SocketType::Udp => {
+                let mut socket = self.get_udp_socket(handle);
+                let port = socket.endpoint().port;
+                socket.close();
+                port
+            }
+        };
+
+        let listener = self.listeners.iter().position(|&(listener, _)| listener == handle);
+        let shared = match listener {
+            Some(index) => {
+                self.listeners.remove(index);
+                false
+            }
+            None => self.listeners.iter().any(|&(_, listen_port)| listen_port == port),
+        };
+        if port != 0 && !shared {
+            self.erase_port(port);
+        }
+        self.release(handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kernel_api::{OsError, SocketType, POLL_IN, POLL_OUT};
+    use smoltcp::time::Instant;
+    use smoltcp::wire::{
+        EthernetAddress, EthernetFrame, EthernetProtocol, IpAddress, IpEndpoint, IpProtocol,
+        Ipv4Address, Ipv4Cidr, Ipv4Packet, Ipv4Repr, UdpPacket, UdpRepr,
+    };
+    use smoltcp::phy::ChecksumCapabilities;
+
+    use crate::net::dhcp::{IpConfig, NetConfig};
+    use crate::net::phy::pcap::{self, PcapReplay};
+    use crate::net::phy::{Loopback, Phy};
+    use crate::net::{EthernetDriver, EPHEMERAL_PORT_START};
+
+    const LOCALHOST: Ipv4Address = Ipv4Address([127, 0, 0, 1]);
+
+    fn driver<P: Phy>(phy: P) -> EthernetDriver<P> {
+        let config = NetConfig {
+            dhcp: false,
+            fallback: IpConfig {
+                address: Ipv4Cidr::new(LOCALHOST, 8),
+                ..IpConfig::default()
+            },
+        };
+        EthernetDriver::new(phy, config, Instant::from_millis(0))
+    }
+
+    /// Polls `driver` every 10 ms for the first `ms` milliseconds after `start`.
+    fn run<P: Phy>(driver: &mut EthernetDriver<P>, start: i64, ms: i64) {
+        for t in (start..start + ms).step_by(10) {
+            driver.poll(Instant::from_millis(t));
+        }
+    }
+
+    #[test]
+    fn tcp_over_loopback() {
+        let mut driver = driver(Loopback::new());
+        let server = driver.create(SocketType::Tcp);
+        let client = driver.create(SocketType::Tcp);
+
+        driver.listen(server, 80).unwrap();
+        assert!(driver.status(server).is_listening);
+        driver.connect(client, IpEndpoint::new(LOCALHOST.into(), 80)).unwrap();
+        run(&mut driver, 0, 5000);
+        assert!(driver.status(server).is_active);
+        assert!(driver.status(client).can_send);
+
+        assert_eq!(driver.send(client, b"hello"), Ok(5));
+        run(&mut driver, 5000, 1000);
+        assert!(driver.status(server).can_recv);
+        let mut buf = [0; 16];
+        assert_eq!(driver.recv(server, &mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(driver.recv(server, &mut buf), Err(OsError::IoErrorWouldBlock));
+    }
+
+    #[test]
+    fn accept_and_end_of_stream() {
+        let mut driver = driver(Loopback::new());
+        let listener = driver.create(SocketType::Tcp);
+        let client = driver.create(SocketType::Tcp);
+
+        driver.listen(listener, 80).unwrap();
+        assert_eq!(driver.accept(listener).unwrap_err(), OsError::IoErrorWouldBlock);
+        assert_eq!(driver.poll_events(listener), 0);
+        assert_eq!(driver.accept(client).unwrap_err(), OsError::IllegalSocketOperation);
+
+        driver.connect(client, IpEndpoint::new(LOCALHOST.into(), 80)).unwrap();
+        assert_eq!(driver.send(client, b"x"), Err(OsError::IoErrorWouldBlock));
+        run(&mut driver, 0, 5000);
+        assert_eq!(driver.poll_events(listener), POLL_IN);
+        let (listener, server, remote) = driver.accept(listener).unwrap();
+        assert_eq!(remote, IpEndpoint::new(LOCALHOST.into(), EPHEMERAL_PORT_START));
+        assert!(driver.status(listener).is_listening);
+        assert_eq!(driver.poll_events(server), POLL_OUT);
+
+        driver.close(client);
+        run(&mut driver, 5000, 1000);
+        assert_eq!(driver.poll_events(server), POLL_IN | POLL_OUT);
+        let mut buf = [0; 16];
+        assert_eq!(driver.recv(server, &mut buf), Ok(0));
+
+        // the listener keeps the port after the connection is closed
+        driver.close(server);
+        assert_eq!(driver.mark_port(80), None);
+        driver.close(listener);
+        assert_eq!(driver.mark_port(80), Some(80));
+    }
+
+    #[test]
+    fn udp_over_loopback() {
+        let mut driver = driver(Loopback::new());
+        let server = driver.create(SocketType::Udp);
+        let client = driver.create(SocketType::Udp);
+        assert!(!driver.status(server).is_active);
+
+        assert_eq!(driver.bind(server, 514), Ok(514));
+        assert!(driver.status(server).is_active);
+        let mut buf = [0; 16];
+        assert_eq!(driver.recv_from(server, &mut buf), Err(OsError::IoErrorWouldBlock));
+
+        let remote = IpEndpoint::new(LOCALHOST.into(), 514);
+        assert_eq!(driver.send_to(client, b"ping", remote), Ok(4));
+        run(&mut driver, 0, 5000);
+        let sender = IpEndpoint::new(LOCALHOST.into(), EPHEMERAL_PORT_START);
+        assert_eq!(driver.recv_from(server, &mut buf), Ok((4, sender)));
+        assert_eq!(&buf[..4], b"ping");
+    }
+
+    #[test]
+    fn ports() {
+        let mut driver = driver(Loopback::new());
+        let tcp = driver.create(SocketType::Tcp);
+        let udp = driver.create(SocketType::Udp);
+
+        driver.listen(tcp, 7).unwrap();
+        assert_eq!(driver.bind(udp, 7), Err(OsError::IllegalSocketOperation));
+        assert_eq!(driver.bind(udp, 0), Ok(EPHEMERAL_PORT_START));
+
+        driver.close(tcp);
+        driver.close(udp);
+        assert_eq!(driver.mark_port(7), Some(7));
+        assert_eq!(driver.get_ephemeral_port(), Some(EPHEMERAL_PORT_START));
+    }
+
+    #[test]
+    fn socket_table() {
+        use smoltcp::socket::TcpState;
+
+        use crate::net::socket::{SocketEntry, SocketKind};
+
+        let mut driver = driver(Loopback::new());
+        let tcp = driver.create(SocketType::Tcp);
+        let udp = driver.create(SocketType::Udp);
+        driver.listen(tcp, 23).unwrap();
+        driver.bind(udp, 514).unwrap();
+
+        let sockets = driver.sockets();
+        let listener = SocketEntry {
+            kind: SocketKind::Tcp(TcpState::Listen),
+            local: IpEndpoint::new(IpAddress::Unspecified, 23),
+            remote: IpEndpoint::default(),
+        };
+        assert!(sockets.contains(&listener));
+        let bound = sockets.iter().find(|entry| entry.kind == SocketKind::Udp && entry.local.port == 514);
+        assert!(bound.is_some());
+
+        driver.close(udp);
+        run(&mut driver, 0, 10);
+        assert!(!driver.sockets().iter().any(|entry| entry.local.port == 514));
+    }
+
+    #[test]
+    fn wrong_socket_type() {
+        let mut driver = driver(Loopback::new());
+        let tcp = driver.create(SocketType::Tcp);
+        let udp = driver.create(SocketType::Udp);
+        let remote = IpEndpoint::new(LOCALHOST.into(), 7);
+
+        assert_eq!(driver.listen(udp, 7), Err(OsError::IllegalSocketOperation));
+        assert_eq!(driver.connect(udp, remote), Err(OsError::IllegalSocketOperation));
+        assert_eq!(driver.send(udp, b"x"), Err(OsError::IllegalSocketOperation));
+        assert_eq!(driver.bind(tcp, 7), Err(OsError::IllegalSocketOperation));
+        assert_eq!(driver.send_to(tcp, b"x", remote), Err(OsError::IllegalSocketOperation));
+        // the failed operations did not take the port
+        assert_eq!(driver.mark_port(7), Some(7));
+    }
+
+    #[test]
+    fn poll_delay_is_bounded() {
+        use crate::param::ETHERNET_POLL_INTERVAL;
+
+        let mut driver = driver(Loopback::new());
+        assert!(driver.poll_delay(Instant::from_millis(0)) <= ETHERNET_POLL_INTERVAL);
+    }
+
+    /// Returns an ethernet frame carrying a UDP datagram.
+    fn udp_frame(dst: EthernetAddress, src: IpEndpoint, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let (src_addr, dst_addr) = match src.addr {
+            IpAddress::Ipv4(addr) => (addr, LOCALHOST),
+            _ => unreachable!(),
+        };
+        let udp = UdpRepr { src_port: src.port, dst_port, payload };
+        let ip = Ipv4Repr {
+            src_addr,
+            dst_addr,
+            protocol: IpProtocol::Udp,
+            payload_len: udp.buffer_len(),
+            hop_limit: 64,
+        };
+
+        let header_len = EthernetFrame::<&[u8]>::header_len();
+        let mut bytes = vec![0; header_len + ip.buffer_len() + udp.buffer_len()];
+        let mut frame = EthernetFrame::new_unchecked(&mut bytes[..]);
+        frame.set_dst_addr(dst);
+        frame.set_src_addr(EthernetAddress([0x02, 0, 0, 0, 0, 0x42]));
+        frame.set_ethertype(EthernetProtocol::Ipv4);
+
+        let checksum = ChecksumCapabilities::default();
+        let mut packet = Ipv4Packet::new_unchecked(frame.payload_mut());
+        ip.emit(&mut packet, &checksum);
+        let mut datagram = UdpPacket::new_unchecked(packet.payload_mut());
+        udp.emit(&mut datagram, &src_addr.into(), &dst_addr.into(), &checksum);
+        bytes
+    }
+
+    #[test]
+    fn replayed_udp_datagram() {
+        let addr = Loopback::new().ethernet_addr();
+        let src = IpEndpoint::new(Ipv4Address::new(127, 0, 0, 2).into(), 5000);
+        let frame = udp_frame(addr, src, 514, b"log line");
+        let capture = pcap::capture(&[&frame[..]]);
+
+        let mut driver = driver(PcapReplay::new(&capture, addr).unwrap());
+        let socket = driver.create(SocketType::Udp);
+        driver.bind(socket, 514).unwrap();
+        run(&mut driver, 0, 100);
+
+        let mut buf = [0; 16];
+        assert_eq!(driver.recv_from(socket, &mut buf), Ok((8, src)));
+        assert_eq!(&buf[..8], b"log line");
+    }
+}
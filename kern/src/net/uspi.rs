@@ -14,7 +14,8 @@ use smoltcp::wire::EthernetAddress;
 use crate::mutex::Mutex;
 use crate::net::Frame;
 use crate::traps::irq::IrqHandlerRegistry;
-use crate::ALLOCATOR;
+use crate::traps::TrapFrame;
+use crate::{ALLOCATOR, FIQ, GLOABAL_IRQ};
 
 const DEBUG_USPI: bool = false;
 pub macro uspi_trace {
@@ -72,10 +73,13 @@ mod inner {
 
     impl USPi {
         /// The caller should assure that this function is called only once
-        /// during the lifetime of the kernel.
-        pub unsafe fn initialize() -> Self {
-            assert!(USPiInitialize() != 0);
-            USPi(())
+        /// during the lifetime of the kernel. Returns `None` if USPi fails to
+        /// initialize, e.g. when there is no USB host controller.
+        pub unsafe fn initialize() -> Option<Self> {
+            if USPiInitialize() == 0 {
+                return None;
+            }
+            Some(USPi(()))
         }
 
         /// Returns whether ethernet is available on RPi
@@ -148,40 +152,62 @@ unsafe fn layout(size: usize) -> Layout {
     Layout::from_size_align_unchecked(size + core::mem::size_of::<usize>(), 16)
 }
 
-#[no_mangle]
+/// Allocates `size` bytes for USPi. The size is stored in front of the
+/// returned block so that `free` can rebuild the layout. Host test builds
+/// leave `malloc` and `free` to the C library.
+#[cfg_attr(not(test), no_mangle)]
+#[cfg_attr(test, allow(dead_code))]
 fn malloc(size: u32) -> *mut c_void {
-    // Lab 5 2.B
-    unimplemented!("malloc")
+    unsafe {
+        let ptr = ALLOCATOR.alloc(layout(size as usize));
+        if ptr.is_null() {
+            return ptr as *mut c_void;
+        }
+        (ptr as *mut usize).write(size as usize);
+        ptr.add(core::mem::size_of::<usize>()) as *mut c_void
+    }
 }
 
-#[no_mangle]
+#[cfg_attr(not(test), no_mangle)]
+#[cfg_attr(test, allow(dead_code))]
 fn free(ptr: *mut c_void) {
-    // Lab 5 2.B
-    unimplemented!("free")
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let ptr = (ptr as *mut u8).sub(core::mem::size_of::<usize>());
+        let size = (ptr as *const usize).read();
+        ALLOCATOR.dealloc(ptr, layout(size));
+    }
 }
 
 #[no_mangle]
 pub fn TimerSimpleMsDelay(nMilliSeconds: u32) {
-    // Lab 5 2.B
-    unimplemented!("TimerSimpleMsDelay")
+    spin_sleep(Duration::from_millis(nMilliSeconds as u64));
 }
 
 #[no_mangle]
 pub fn TimerSimpleusDelay(nMicroSeconds: u32) {
-    // Lab 5 2.B
-    unimplemented!("TimerSimpleusDelay")
+    spin_sleep(Duration::from_micros(nMicroSeconds as u64));
 }
 
 #[no_mangle]
 pub fn MsDelay(nMilliSeconds: u32) {
-    // Lab 5 2.B
-    unimplemented!("MsDelay")
+    spin_sleep(Duration::from_millis(nMilliSeconds as u64));
 }
 
 #[no_mangle]
 pub fn usDelay(nMicroSeconds: u32) {
-    // Lab 5 2.B
-    unimplemented!("usDelay")
+    spin_sleep(Duration::from_micros(nMicroSeconds as u64));
+}
+
+/// Returns the NUL-terminated C string at `ptr`.
+unsafe fn c_str<'a>(ptr: *const u8) -> &'a str {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    core::str::from_utf8(slice::from_raw_parts(ptr, len)).unwrap_or("<invalid utf-8>")
 }
 
 /// Registers `pHandler` to the kernel's IRQ handler registry.
@@ -192,15 +218,26 @@ pub fn usDelay(nMicroSeconds: u32) {
 /// registry. Otherwise, register the handler to the global IRQ interrupt handler.
 #[no_mangle]
 pub unsafe fn ConnectInterrupt(nIRQ: u32, pHandler: TInterruptHandler, pParam: *mut c_void) {
-    // Lab 5 2.B
-    unimplemented!("ConnectInterrupt")
+    let int = Interrupt::from(nIRQ as usize);
+    let handler = pHandler.expect("USPi registered a null interrupt handler");
+    // The parameter is only ever used by the handler itself.
+    let param = pParam as usize;
+    let handler = Box::new(move |_: &mut TrapFrame| handler(param as *mut c_void));
+
+    let mut controller = Controller::new();
+    if int == Interrupt::Usb {
+        FIQ.register((), handler);
+        controller.enable_fiq(int);
+    } else {
+        GLOABAL_IRQ.register(int, handler);
+        controller.enable(int);
+    }
 }
 
 /// Writes a log message from USPi using `uspi_trace!` macro.
 #[no_mangle]
 pub unsafe fn DoLogWrite(_pSource: *const u8, _Severity: u32, pMessage: *const u8) {
-    // Lab 5 2.B
-    unimplemented!("DoLogWrite")
+    uspi_trace!("{}", c_str(pMessage));
 }
 
 #[no_mangle]
@@ -210,8 +247,7 @@ pub fn DebugHexdump(_pBuffer: *const c_void, _nBufLen: u32, _pSource: *const u8)
 
 #[no_mangle]
 pub unsafe fn uspi_assertion_failed(pExpr: *const u8, pFile: *const u8, nLine: u32) {
-    // Lab 5 2.B
-    unimplemented!("uspi_assertion_failed")
+    panic!("USPi assertion failed: {} ({}:{})", c_str(pExpr), c_str(pFile), nLine);
 }
 
 pub struct Usb(pub Mutex<Option<USPi>>);
@@ -221,11 +257,14 @@ impl Usb {
         Usb(Mutex::new(None))
     }
 
-    pub fn initialize(&self) {
+    /// Initializes USPi if it's not already initialized. Returns `false` if
+    /// it failed to initialize.
+    pub fn initialize(&self) -> bool {
         let mut inner = self.0.lock();
         if let None = *inner {
-            *inner = Some(unsafe { USPi::initialize() });
+            *inner = unsafe { USPi::initialize() };
         }
+        inner.is_some()
     }
 
     pub fn is_eth_available(&self) -> bool {
@@ -10,18 +10,41 @@ pub const PAGE_ALIGN: usize = 16;
 pub const PAGE_SIZE: usize = 64 * 1024;
 pub const PAGE_MASK: usize = !(PAGE_SIZE - 1);
 
-pub const USER_MASK_BITS: usize = 34;
+pub const USER_MASK_BITS: usize = 31;
 pub const KERNEL_MASK_BITS: usize = 31;
 
-pub const USER_IMG_BASE: usize = 0xffff_ffff_c000_0000;
+pub const USER_IMG_BASE: usize = 0xffff_fffe_0000_0000;
 const_assert_eq!(
     USER_IMG_BASE,
     ((1 << USER_MASK_BITS) - 1) << (64 - USER_MASK_BITS)
 );
-pub const USER_STACK_BASE: usize = core::usize::MAX & PAGE_MASK; //0xffff_ffff_ffff_0000
-pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
+pub const USER_MAX_VM_SIZE: usize = 0x2_0000_0000;
 const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
 
+// User virtual memory layout. Every region but `text` is preceded by an
+// unmapped guard page so that running off the end of one region faults
+// instead of silently walking into the next one.
+//
+//   text  : [USER_TEXT_BASE,  USER_TEXT_BASE + USER_TEXT_SIZE)   program image
+//   heap  : [USER_HEAP_BASE,  USER_HEAP_BASE + USER_HEAP_SIZE)   grows up
+//   mmap  : [USER_MMAP_BASE,  USER_MMAP_BASE + USER_MMAP_SIZE)
+//   stack : [USER_STACK_BASE, 2^64)                              grows down
+pub const USER_GUARD_SIZE: usize = PAGE_SIZE;
+
+pub const USER_TEXT_BASE: usize = USER_IMG_BASE;
+pub const USER_TEXT_SIZE: usize = 0x4000_0000;
+
+pub const USER_HEAP_BASE: usize = USER_TEXT_BASE + USER_TEXT_SIZE + USER_GUARD_SIZE;
+pub const USER_HEAP_SIZE: usize = 0xC000_0000 - USER_GUARD_SIZE;
+
+pub const USER_MMAP_BASE: usize = USER_HEAP_BASE + USER_HEAP_SIZE + USER_GUARD_SIZE;
+pub const USER_MMAP_SIZE: usize = 0xC000_0000 - USER_GUARD_SIZE;
+
+pub const USER_STACK_SIZE: usize = 16 * PAGE_SIZE;
+pub const USER_STACK_BASE: usize = core::usize::MAX - USER_STACK_SIZE + 1; //0xffff_ffff_fff0_0000
+const_assert_eq!(USER_STACK_BASE & !PAGE_MASK, 0);
+const_assert_eq!(USER_MMAP_BASE + USER_MMAP_SIZE + USER_GUARD_SIZE <= USER_STACK_BASE, true);
+
 pub const KERN_STACK_BASE: usize = 0x80_000;
 pub const KERN_STACK_ALIGN: usize = PAGE_ALIGN;
 pub const KERN_STACK_SIZE: usize = PAGE_SIZE;
@@ -36,3 +59,55 @@ pub const USPI_TIMER_HZ: usize = 10;
 // Match this value with `USPI_FRAME_BUFFER_SIZE` in `uspi.h`
 pub const USPI_FRAME_BUFFER_SIZE: u32 = 1600;
 pub const MTU: u32 = 1500;
+
+/// Bit of the system counter whose transitions wake core 0 up while it waits
+/// for console input: every 2^13 ticks of the 19.2 MHz counter, about 0.4 ms.
+pub const CONSOLE_EVENT_STREAM_BIT: u64 = 12;
+
+/// Size of the send and receive buffers of TCP and UDP sockets, in bytes.
+pub const SOCKET_BUFFER_SIZE: usize = 16384;
+
+/// Longest time between two polls of the ethernet interface.
+pub const ETHERNET_POLL_INTERVAL: Duration = Duration::from_millis(1000 / USPI_TIMER_HZ as u64);
+
+/// How long to wait for a DHCP lease before applying the fallback IPv4
+/// configuration.
+pub const DHCP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many router solicitations are sent to find an IPv6 router, and how
+/// long apart.
+pub const ROUTER_SOLICITATIONS: u8 = 3;
+pub const ROUTER_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+
+/// How long to wait for the answer of a DNS server before asking again.
+pub const DNS_RETRY: Duration = Duration::from_secs(1);
+
+/// How many times a DNS query is sent before the name is given up.
+pub const DNS_ATTEMPTS: u8 = 3;
+
+/// Most names kept in the DNS cache.
+pub const DNS_CACHE_SIZE: usize = 64;
+
+/// Most sessions open at once on the shell service.
+pub const NETSH_SESSIONS: usize = 4;
+
+/// Longest password accepted by the shell service.
+pub const NETSH_PASSWORD_MAX: usize = 64;
+
+/// Most output of a session buffered on top of the send buffer of its
+/// connection. A session writing more waits for the client to take some.
+pub const NETSH_OUTPUT_MAX: usize = 16384;
+
+/// How long a client may leave the output of its session untaken before the
+/// connection is closed.
+pub const NETSH_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often `ping` sends an echo request, and how long it waits for the
+/// reply to the last one.
+pub const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many echo requests `ping` sends unless told otherwise.
+pub const PING_COUNT: u16 = 4;
+
+/// Most echo requests `ping -c` accepts.
+pub const PING_COUNT_MAX: u16 = 100;
@@ -3,8 +3,8 @@ mod scheduler;
 mod stack;
 mod state;
 
-pub use self::process::{Id, Process};
-pub use self::scheduler::GlobalScheduler;
+pub use self::process::{exit, sleep, Id, Process, PROCESSES};
+pub use self::scheduler::{GlobalScheduler, ProcessInfo};
 pub use self::stack::Stack;
 pub use self::state::State;
 pub use crate::param::TICK;
@@ -4,24 +4,55 @@ use shim::io;
 use shim::path::Path;
 
 use aarch64::*;
-use smoltcp::socket::SocketHandle;
 
+use crate::allocator::{SlabBox, SlabCache};
 use crate::param::*;
 use crate::process::{Stack, State};
 use crate::traps::TrapFrame;
 use crate::vm::*;
 use crate::fs::PiVFatHandle;
 use crate::console::kprintln;
+use crate::net::Socket;
 
-use crate::FILESYSTEM;
+use crate::{ETHERNET, FILESYSTEM};
 use fat32::traits::{FileSystem, Entry};
 
-use kernel_api::{OsError, OsResult};
+use kernel_api::{OsError, OsResult, NR_EXIT, NR_SLEEP};
 
 use fat32::vfat::{File};
 use shim::io::Read;
+use core::time::Duration;
 
 
+/// Puts the calling process to sleep for `span` with the `sleep` system
+/// call. For the processes created with `Process::kernel()`, which cannot
+/// use `kernel_api::syscall`.
+pub fn sleep(span: Duration) {
+    let ms = span.as_millis() as u64;
+    unsafe {
+        asm!("mov x0, $0
+              svc $1"
+             :: "r"(ms), "i"(NR_SLEEP)
+             : "x0", "x7"
+             : "volatile");
+    }
+}
+
+/// Ends the calling process with the `exit` system call. For the processes
+/// created with `Process::kernel()`.
+pub fn exit() -> ! {
+    unsafe {
+        asm!("svc $0" :: "i"(NR_EXIT) : "x7" : "volatile");
+    }
+    unreachable!("exit returned");
+}
+
+/// Cache backing the saved trap frames of all processes.
+pub static TRAP_FRAMES: SlabCache<TrapFrame> = SlabCache::new("trap_frame");
+
+/// Cache backing the processes in the scheduler's queue.
+pub static PROCESSES: SlabCache<Process> = SlabCache::new("process");
+
 /// Type alias for the type of a process ID.
 pub type Id = u64;
 
@@ -29,16 +60,15 @@ pub type Id = u64;
 #[derive(Debug)]
 pub struct Process {
     /// The saved trap frame of a process.
-    pub context: Box<TrapFrame>,
+    pub context: SlabBox<TrapFrame>,
     /// The memory allocation used for the process's stack.
     pub stack: Stack,
     /// The page table describing the Virtual Memory of the process
     pub vmap: Box<UserPageTable>,
     /// The scheduling state of the process.
     pub state: State,
-    // Lab 5 2.C
-    // Socket handles held by the current process
-    // pub sockets: Vec<SocketHandle>,
+    /// Sockets held by the current process, indexed by descriptor.
+    pub sockets: Vec<Socket>,
 }
 
 impl Process {
@@ -56,8 +86,8 @@ impl Process {
         } else {
             let stack = stack.unwrap();
             let state = State::Ready;
-            let context = Box::new(trap_frame);
-            let process = Process { context, stack, vmap, state };
+            let context = TRAP_FRAMES.alloc(trap_frame)?;
+            let process = Process { context, stack, vmap, state, sockets: Vec::new() };
     
             Ok(process)
         }
@@ -78,7 +108,7 @@ impl Process {
 
         let mut process = Process::do_load(pn)?;
 
-        process.context.set_elr(USER_IMG_BASE as u64);
+        process.context.set_elr(USER_TEXT_BASE as u64);
         // panic!("{}", Process::get_stack_top().as_u64());
         process.context.set_sp(Process::get_stack_top().as_u64());
         //set the EL to be 0 so that on 'eret' the process is running in EL0
@@ -98,6 +128,23 @@ impl Process {
         Ok(process)
     }
 
+    /// Creates a process that runs `entry` in the kernel, at EL1 on its own
+    /// stack. IRQs stay masked while it runs, so it is never preempted and
+    /// gives the core up by sleeping with `sleep()`. FIQs are unmasked: the
+    /// USB transfers of the ethernet driver complete from the USB FIQ.
+    pub fn kernel(entry: extern "C" fn() -> !) -> OsResult<Process> {
+        use crate::VMM;
+
+        let mut process = Process::new()?;
+        process.context.set_elr(entry as u64);
+        process.context.set_sp(process.stack.top().as_u64());
+        // EL1t with `D`, `A` and `I` set
+        process.context.set_spsr(0x0000_0384);
+        process.context.set_ttbr0(VMM.get_baddr().as_u64());
+        process.context.set_ttbr1(process.vmap.get_baddr().as_u64());
+        Ok(process)
+    }
+
     /// Creates a process and open a file with given path.
     /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
     /// permission to load file's contents.
@@ -115,12 +162,15 @@ impl Process {
     }
 
     fn create_process_from_file(mut file: File<PiVFatHandle>) -> OsResult<Process> {
-    
-        let mut process = Process::new().unwrap();
-        //alloacte stack in USER virtual memory space
-        let stack_page = process.vmap.alloc(Process::get_stack_base(), PagePerm::RW);
+        if file.size as usize > USER_TEXT_SIZE {
+            return Err(OsError::NoVmSpace);
+        }
+
+        let mut process = Process::new()?;
+        // only the top page of the stack; the rest is mapped as it is touched
+        process.vmap.grow_stack(Process::get_stack_top());
 
-        let mut page_start_address = USER_IMG_BASE as u64;
+        let mut page_start_address = USER_TEXT_BASE as u64;
         let mut bytes_copied_to_mem = 0;
         while bytes_copied_to_mem < file.size {
             let mut page = process.vmap.alloc(VirtualAddr::from(page_start_address), PagePerm::RWX);
@@ -152,7 +202,7 @@ impl Process {
     pub fn get_stack_top() -> VirtualAddr {
         let base = Process::get_stack_base().as_usize();
 
-        let top = base + PAGE_SIZE - 1;
+        let top = base + USER_STACK_SIZE - 1;
         //stack pointer must be 16 byte aligned on ARM
         let align = 16;
         let divides = top / align;
@@ -198,4 +248,16 @@ impl Process {
         }
     }
 
+    /// Closes every socket held by the process and frees their local ports.
+    pub fn release_sockets(&mut self) {
+        if self.sockets.is_empty() {
+            return;
+        }
+        let sockets = &mut self.sockets;
+        ETHERNET.critical(|ethernet| {
+            for socket in sockets.drain(..) {
+                ethernet.close(socket);
+            }
+        });
+    }
 }
\ No newline at end of file
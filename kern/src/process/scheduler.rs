@@ -11,7 +11,7 @@ use core::time::Duration;
 use aarch64::*;
 use crate::shell::shell;
 use pi::{timer, interrupt, local_interrupt};
-use crate::console::{kprintln, kprint};
+use crate::console::{kprintln, kprint, CONSOLE};
 
 
 use crate::param::{PAGE_MASK, PAGE_SIZE, TICK, USER_IMG_BASE, PAGE_ALIGN};
@@ -22,7 +22,8 @@ use crate::mutex::Mutex;
 use crate::net::uspi::TKernelTimerHandle;
 use crate::param::*;
 use crate::percore::{get_preemptive_counter, is_mmu_ready, local_irq};
-use crate::process::{Id, Process, State};
+use crate::allocator::SlabBox;
+use crate::process::{Id, Process, State, PROCESSES};
 use crate::traps::irq::IrqHandlerRegistry;
 use crate::traps::TrapFrame;
 use crate::VMM;
@@ -91,6 +92,22 @@ impl GlobalScheduler {
         }
     }
 
+    /// Returns the ID and state of every process in the queue.
+    pub fn processes(&self) -> Vec<ProcessInfo> {
+        self.critical(|scheduler| scheduler.processes())
+    }
+
+    /// Returns `true` if the process `id` is in the queue and not dead.
+    pub fn is_alive(&self, id: Id) -> bool {
+        self.critical(|scheduler| scheduler.is_alive(id))
+    }
+
+    /// Kills the process `id`. For more details, see the documentation on
+    /// `Scheduler::kill_pid()`.
+    pub fn kill_pid(&self, id: Id) -> bool {
+        self.critical(|scheduler| scheduler.kill_pid(id))
+    }
+
     /// Kills currently running process and returns that process's ID.
     /// For more details, see the documentation on `Scheduler::kill()`.
     #[must_use]
@@ -104,7 +121,9 @@ impl GlobalScheduler {
         SCHEDULER.switch(State::Ready, tf);
     }
     pub fn local_timer_handler(tf: &mut TrapFrame) {
-        kprint!("local tick on {}\n", affinity());
+        trace!("local tick on {}", affinity());
+        // partial lines of process output show up within a tick
+        CONSOLE.lock().flush_line();
         local_interrupt::local_tick_in(affinity(), TICK);
         //TODO: should this always be Ready or could it be Waiting? Do I need to add another parameter to know this info
         SCHEDULER.switch(State::Ready, tf);
@@ -144,9 +163,9 @@ impl GlobalScheduler {
     /// Registers a timer handler with `Usb::start_kernel_timer` which will
     /// invoke `poll_ethernet` after 1 second.
     pub fn initialize_global_timer_interrupt(&self) {
-        // let mut interrupt_controller = interrupt::Controller::new();
-        // interrupt_controller.enable(interrupt::Interrupt::Timer1);
-        // GLOABAL_IRQ.register(interrupt::Interrupt::Timer1, Box::new(GlobalScheduler::timer_handler));
+        if ETHERNET.is_initialized() {
+            USB.start_kernel_timer(Duration::from_secs(1), Some(poll_ethernet));
+        }
     }
 
     /// Initializes the per-core local timer interrupt with `pi::local_interrupt`.
@@ -209,20 +228,32 @@ impl GlobalScheduler {
 /// Poll the ethernet driver and re-register a timer handler using
 /// `Usb::start_kernel_timer`.
 extern "C" fn poll_ethernet(_: TKernelTimerHandle, _: *mut c_void, _: *mut c_void) {
-    // Lab 5 2.B
-    unimplemented!("poll_ethernet")
+    let now = Instant::from_millis(timer::current_time().as_millis() as i64);
+    ETHERNET.poll(now);
+    let delay = ETHERNET.poll_delay(now);
+    USB.start_kernel_timer(delay, Some(poll_ethernet));
+}
+
+/// A snapshot of one process in the scheduler's queue.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessInfo {
+    pub id: Id,
+    pub state: &'static str,
 }
 
 /// Internal scheduler struct which is not thread-safe.
 pub struct Scheduler {
-    processes: VecDeque<Process>,
+    processes: VecDeque<SlabBox<Process>>,
     last_id: Option<Id>,
+    /// Running processes that were killed by `kill_pid()`. They are marked
+    /// `Dead` once their core schedules them out.
+    killed: Vec<Id>,
 }
 
 impl Scheduler {
     /// Returns a new `Scheduler` with an empty queue.
     fn new() -> Box<Scheduler> {
-        Box::new(Scheduler { processes: VecDeque::new(), last_id: None })
+        Box::new(Scheduler { processes: VecDeque::new(), last_id: None, killed: Vec::new() })
     }
 
     /// Adds a process to the scheduler's queue and returns that process's ID if
@@ -230,9 +261,13 @@ impl Scheduler {
     /// the process and saved in its `trap_frame`. If no further processes can
     /// be scheduled, returns `None`.
     ///
+    /// The process is moved into the `PROCESSES` cache, so that it is not
+    /// copied around as the queue is reordered.
+    ///
     /// It is the caller's responsibility to ensure that the first time `switch`
     /// is called, that process is executing on the CPU.
-    fn add(&mut self, mut process: Process) -> Option<Id> {
+    fn add(&mut self, process: Process) -> Option<Id> {
+        let mut process = PROCESSES.alloc(process).ok()?;
         if let Some(last_id) = self.last_id {
             if let Some(new_id) = last_id.checked_add(1) {
                 process.context.set_tpidr(new_id);
@@ -279,9 +314,15 @@ impl Scheduler {
         }
         if !found { return false; }
         let mut removed_process = self.processes.remove(i).expect("Index out of bounds when trying to remove");
-        removed_process.state = new_state;
         let pid = removed_process.context.get_tpidr();
-        removed_process.context = Box::new(*tf);
+        removed_process.state = match self.killed.iter().position(|&id| id == pid) {
+            Some(k) => {
+                self.killed.remove(k);
+                State::Dead
+            }
+            None => new_state,
+        };
+        *removed_process.context = *tf;
         self.processes.push_back(removed_process);
 
         sev();
@@ -297,6 +338,7 @@ impl Scheduler {
     /// `Some` of the next process`s process ID.
     fn switch_to(&mut self, tf: &mut TrapFrame) -> Option<Id> {
         // kprintln!("{:?}", self.processes);
+        self.reap();
         let mut i = 0;
         let mut found = false;
         // kprintln!("Before queue: {:?}\n\n\n\n\n", &self.processes);
@@ -333,6 +375,7 @@ impl Scheduler {
     /// instance, and returns the dead process's process ID.
     fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
         if let true = self.schedule_out(State::Dead, tf) {
+            self.release_process_resources(tf);
             let process_to_be_dropped = self.processes.pop_back();
             drop(process_to_be_dropped);
             Some(tf.get_tpidr())
@@ -341,10 +384,69 @@ impl Scheduler {
         }
     }
 
+    /// Kills the process `id`. A process that is not running is removed from
+    /// the queue and dropped right away. A running process keeps running
+    /// until its core schedules it out, at which point it becomes `Dead`.
+    ///
+    /// Returns `false` if there is no live process `id`.
+    pub fn kill_pid(&mut self, id: Id) -> bool {
+        let i = match self.processes.iter().position(|p| p.context.get_tpidr() == id) {
+            Some(i) => i,
+            None => return false,
+        };
+        match self.processes[i].state {
+            State::Dead => false,
+            State::Running => {
+                if !self.killed.contains(&id) {
+                    self.killed.push(id);
+                }
+                true
+            }
+            _ => {
+                let mut process = self.processes.remove(i).unwrap();
+                process.release_sockets();
+                drop(process);
+                true
+            }
+        }
+    }
+
+    /// Returns `true` if the process `id` is in the queue and not dead.
+    pub fn is_alive(&self, id: Id) -> bool {
+        self.processes.iter().any(|p| match p.state {
+            State::Dead => false,
+            _ => p.context.get_tpidr() == id,
+        })
+    }
+
+    /// Returns the ID and state of every process in the queue.
+    pub fn processes(&self) -> Vec<ProcessInfo> {
+        self.processes
+            .iter()
+            .map(|p| ProcessInfo { id: p.context.get_tpidr(), state: p.state.name() })
+            .collect()
+    }
+
+    /// Drops every `Dead` process. A process only becomes `Dead` when it is
+    /// scheduled out, so none of them is using a core. The ids in `killed`
+    /// that no longer name a live process are forgotten with them.
+    fn reap(&mut self) {
+        for process in self.processes.iter_mut() {
+            if let State::Dead = process.state {
+                process.release_sockets();
+            }
+        }
+        self.processes.retain(|p| match p.state {
+            State::Dead => false,
+            _ => true,
+        });
+        let processes = &self.processes;
+        self.killed.retain(|&id| processes.iter().any(|p| p.context.get_tpidr() == id));
+    }
+
     /// Releases all process resources held by the current process such as sockets.
     fn release_process_resources(&mut self, tf: &mut TrapFrame) {
-        // Lab 5 2.C
-        unimplemented!("release_process_resources")
+        self.find_process(tf).release_sockets();
     }
 
     /// Finds a process corresponding with tpidr saved in a trap frame.
@@ -352,7 +454,7 @@ impl Scheduler {
     pub fn find_process(&mut self, tf: &TrapFrame) -> &mut Process {
         for i in 0..self.processes.len() {
             if self.processes[i].context.get_tpidr() == tf.get_tpidr() {
-                return &mut self.processes[i];
+                return &mut *self.processes[i];
             }
         }
         panic!("Invalid TrapFrame");
@@ -1,209 +1,505 @@
+mod debug;
+mod files;
+mod line;
+mod net;
+mod nettools;
+mod parse;
+mod text;
+
+pub use self::debug::debugger;
+pub use self::net::NetShell;
+
 use shim::io;
 use shim::path::{Path, PathBuf, Component};
 
 // use kernel_api::syscall;
-use stack_vec::StackVec;
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
+use alloc::format;
 use alloc::string::String;
 use pi::atags::Atags;
 
 use fat32::traits::FileSystem;
-use fat32::traits::{Dir, Entry as EntryTrait, Metadata, Timestamp};
-use fat32::vfat::Entry;
-
-use crate::console::{kprint, kprintln, CONSOLE};
-use crate::fs::PiVFatHandle;
+use fat32::traits::{Dir, Entry as EntryTrait, Metadata};
+
+use crate::console::{self, Vt, CONSOLE};
+use self::line::{Completer, LineEditor};
+use self::parse::{Command, Env, Error, Pipeline};
+use crate::mutex::Mutex;
+use crate::param::NCORES;
+use crate::process::{Id, Process};
 use crate::ALLOCATOR;
 use crate::FILESYSTEM;
+use crate::SCHEDULER;
 
-use shim::io::Read;
+use shim::io::Write;
+use core::fmt;
 use core::time::Duration;
 use pi::timer::spin_sleep;
-use core::str::from_utf8;
 use core::str::FromStr;
 
 
-/// Error type for `Command` parse failures.
-#[derive(Debug)]
-enum Error {
-    Empty,
-    TooManyArgs,
+/// Files written by output redirection, by absolute path. The FAT32 file
+/// system is read-only, so redirected output is kept in memory; `cat` looks
+/// here before going to the disk.
+type Buffers = BTreeMap<String, Vec<u8>>;
+
+/// A program started in the background with `&`.
+struct Job {
+    /// Job number, as used by `kill %<n>`.
+    num: usize,
+    id: Id,
+    command: String,
+}
+
+/// The state of one shell session.
+struct Shell {
+    cwd: PathBuf,
+    env: Env,
+    buffers: Buffers,
+    jobs: Vec<Job>,
+}
+
+impl Shell {
+    /// Creates a session in `/` with the default environment.
+    fn new() -> Shell {
+        let mut shell = Shell {
+            cwd: PathBuf::new(),
+            env: Env::new(),
+            buffers: Buffers::new(),
+            jobs: Vec::new(),
+        };
+        shell.cwd.push("/");
+        shell.env.insert(String::from("HOME"), String::from("/"));
+        shell.env.insert(String::from("PWD"), String::from("/"));
+        shell.env.insert(String::from("PATH"), String::from("/bin"));
+        shell
+    }
+
+    /// Removes the jobs that have exited and returns them.
+    fn finished_jobs(&mut self) -> Vec<Job> {
+        let (done, running) = core::mem::replace(&mut self.jobs, Vec::new())
+            .into_iter()
+            .partition(|job| !SCHEDULER.is_alive(job.id));
+        self.jobs = running;
+        done
+    }
 }
 
-/// A structure representing a single shell command.
-struct Command<'a> {
-    args: StackVec<'a, &'a str>,
+/// The terminal of a shell session: typed bytes are read from it and the
+/// output and diagnostics of the commands are written to it.
+pub trait Terminal: io::Read + io::Write {
+    /// Returns a byte typed on the terminal without waiting, if there is
+    /// one. Used to catch Ctrl-C while a program runs in the foreground.
+    fn try_read_byte(&mut self) -> Option<u8>;
+
+    /// Called when a program starts running in the foreground of the
+    /// session, and with `false` when it exits.
+    fn set_foreground_job(&mut self, _running: bool) {}
+
+    /// Returns whether programs can be started from the session. Programs
+    /// read and write the console, so a session on another terminal refuses
+    /// to start them.
+    fn runs_programs(&self) -> bool {
+        true
+    }
+}
+
+/// Where a built-in writes: the terminal of the session, or the buffer
+/// feeding the next command of a pipeline or a redirection.
+pub enum Output<'a> {
+    Term(&'a mut dyn Terminal),
+    Pipe(&'a mut Vec<u8>),
+}
+
+impl<'a> Output<'a> {
+    /// Returns `true` if Ctrl-C has been typed on the terminal since the last
+    /// call. A built-in whose output is piped cannot be interrupted.
+    pub fn interrupted(&mut self) -> bool {
+        match self {
+            Output::Term(term) => term.try_read_byte() == Some(0x03),
+            Output::Pipe(_) => false,
+        }
+    }
+}
+
+impl<'a> io::Write for Output<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Term(term) => term.write(buf),
+            Output::Pipe(pipe) => pipe.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Term(term) => term.flush(),
+            Output::Pipe(pipe) => pipe.flush(),
+        }
+    }
 }
 
-impl<'a> Command<'a> {
-    /// Parse a command from a string `s` using `buf` as storage for the
-    /// arguments.
-    ///
-    /// # Errors
-    ///
-    /// If `s` contains no arguments, returns `Error::Empty`. If there are more
-    /// arguments than `buf` can hold, returns `Error::TooManyArgs`.
-    fn parse(s: &'a str, buf: &'a mut [&'a str]) -> Result<Command<'a>, Error> {
-        let mut args = StackVec::new(buf);
-        for arg in s.split(' ').filter(|a| !a.is_empty()) {
-            args.push(arg).map_err(|_| Error::TooManyArgs)?;
+/// The shell's terminal of the console.
+pub struct ConsoleTerminal;
+
+impl io::Read for ConsoleTerminal {
+    /// Reads the bytes typed on the shell's terminal, waiting for at least
+    /// one.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
         }
+        buf[0] = console::read_byte(Vt::Shell);
+        let mut read = 1;
+        let mut console = CONSOLE.lock();
+        while read < buf.len() {
+            match console.try_read_byte(Vt::Shell) {
+                Some(byte) => buf[read] = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+}
+
+impl io::Write for ConsoleTerminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        CONSOLE.lock().write_to(Vt::Shell, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
-        if args.is_empty() {
-            return Err(Error::Empty);
+impl Terminal for ConsoleTerminal {
+    /// Returns Ctrl-C if it was typed on the `Programs` terminal, or the next
+    /// byte typed on the shell's terminal.
+    fn try_read_byte(&mut self) -> Option<u8> {
+        let mut console = CONSOLE.lock();
+        if console.take_interrupt() {
+            return Some(0x03);
         }
+        console.try_read_byte(Vt::Shell)
+    }
+
+    /// Shows the `Programs` terminal, where user processes read and write,
+    /// while a program runs in the foreground.
+    fn set_foreground_job(&mut self, running: bool) {
+        CONSOLE.lock().switch_to(if running { Vt::Programs } else { Vt::Shell });
+    }
+}
+
+/// Diagnostics of the commands run on each core. A session runs one command
+/// at a time on the core it is fed on, and writes them to its terminal once
+/// the command returns.
+static STDERR: [Mutex<Option<Vec<u8>>>; NCORES] =
+    [Mutex::new(None), Mutex::new(None), Mutex::new(None), Mutex::new(None)];
+
+/// Internal function called by the `eprint[ln]!` macros.
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    #[cfg(not(test))]
+    {
+        let mut stderr = STDERR[aarch64::affinity()].lock();
+        stderr.get_or_insert_with(Vec::new).write_fmt(args).ok();
+    }
 
-        Ok(Command { args })
+    #[cfg(test)]
+    {
+        print!("{}", args);
     }
+}
+
+/// Like `kprintln!`, but for the diagnostics of shell commands: they go to
+/// the terminal of the session running the command.
+pub macro eprintln {
+    () => (eprint!("\n")),
+    ($fmt:expr) => (eprint!(concat!($fmt, "\n"))),
+    ($fmt:expr, $($arg:tt)*) => (eprint!(concat!($fmt, "\n"), $($arg)*))
+}
+
+/// Like `kprint!`, but for the diagnostics of shell commands.
+pub macro eprint($($arg:tt)*) {
+    _eprint(format_args!($($arg)*))
+}
 
-    /// Returns this command's path. This is equivalent to the first argument.
-    fn path(&self) -> &str {
-        self.args[0]
+/// Writes the diagnostics of the last command run on this core to `term`.
+fn flush_stderr(term: &mut dyn Terminal) -> io::Result<()> {
+    let stderr = STDERR[aarch64::affinity()].lock().take();
+    match stderr {
+        Some(stderr) => term.write_all(&stderr),
+        None => Ok(()),
     }
 }
 
+/// A shell session: the state of the shell and of its line editor. The
+/// session is fed the bytes typed on its terminal and runs each line once it
+/// is complete, so several sessions can be driven by one loop.
+pub struct Session {
+    shell: Shell,
+    editor: LineEditor,
+    prefix: String,
+}
+
+impl Session {
+    /// Creates a session using `prefix` as the prefix for each line.
+    pub fn new(prefix: &str) -> Session {
+        Session { shell: Shell::new(), editor: LineEditor::new(), prefix: String::from(prefix) }
+    }
 
+    /// Greets the user on `term` and prints the first prompt.
+    pub fn start(&mut self, term: &mut dyn Terminal) -> io::Result<()> {
+        writeln!(term, "a VERY warm welcome to ...")?;
+        writeln!(term, "~~~~~~~~~~~~~~ JOS ~~~~~~~~~~~~~~")?;
+        self.prompt(term)
+    }
+
+    fn prompt_string(&self) -> String {
+        let cwd_string = self.shell.cwd.clone().into_os_string().into_string().unwrap();
+        format!("jakob@cs3210:[{}]{}", cwd_string, self.prefix)
+    }
 
+    /// Reports the background jobs that have exited and prints the prompt.
+    fn prompt(&mut self, term: &mut dyn Terminal) -> io::Result<()> {
+        for job in self.shell.finished_jobs() {
+            writeln!(term, "[{}] Done    {}", job.num, job.command)?;
+        }
+        write!(term, "{}", self.prompt_string())
+    }
 
-/// Starts a shell using `prefix` as the prefix for each line. This function
-/// never returns.
+    /// Feeds a byte typed on `term` to the line editor, completing paths
+    /// relative to the shell's `cwd`. Once the line is complete, runs it and
+    /// prints the next prompt. Returns `Ok(false)` once the user has exited
+    /// the shell.
+    pub fn feed(&mut self, byte: u8, term: &mut dyn Terminal) -> io::Result<bool> {
+        let prompt = self.prompt_string();
+        let completer = ShellCompleter { cwd: &self.shell.cwd };
+        let line = match self.editor.feed(byte, &prompt, &mut &mut *term, &completer)? {
+            Some(line) => line,
+            None => return Ok(true),
+        };
+        writeln!(term)?;
+
+        let exit = match Pipeline::parse(&line, &self.shell.env) {
+            Ok(pipeline) => run_pipeline(pipeline, &mut self.shell, term),
+            Err(Error::Empty) => {
+                writeln!(term)?;
+                false
+            }
+            Err(e) => {
+                writeln!(term, "error: {}", e)?;
+                false
+            }
+        };
+        flush_stderr(term)?;
+        if exit {
+            return Ok(false);
+        }
+        self.prompt(term)?;
+        Ok(true)
+    }
+}
+
+/// Starts a shell on the console using `prefix` as the prefix for each line.
+/// Returns once the user exits it.
 pub fn shell(prefix: &str) {
+    run(prefix, &mut ConsoleTerminal)
+}
+
+/// Runs a shell session on `term` using `prefix` as the prefix for each line,
+/// until the user exits it or the terminal is closed.
+pub fn run(prefix: &str, term: &mut dyn Terminal) {
+    let mut session = Session::new(prefix);
+    if session.start(term).is_err() {
+        return;
+    }
+    let mut byte = [0];
+    while let Ok(1) = term.read(&mut byte) {
+        match session.feed(byte[0], term) {
+            Ok(true) => {}
+            _ => return,
+        }
+    }
+}
+
+/// Prints `prompt` on `term` and reads a line with `editor`, completing paths
+/// relative to the shell's `cwd`. Returns `None` if the terminal is closed.
+fn read_line(
+    editor: &mut LineEditor,
+    prompt: &str,
+    shell: &Shell,
+    term: &mut dyn Terminal,
+) -> Option<String> {
+    write!(term, "{}", prompt).ok()?;
+    let completer = ShellCompleter { cwd: &shell.cwd };
+    let mut byte = [0];
+    let line = loop {
+        if term.read(&mut byte).ok()? == 0 {
+            return None;
+        }
+        if let Some(line) = editor.feed(byte[0], prompt, &mut &mut *term, &completer).ok()? {
+            break line;
+        }
+    };
+    writeln!(term).ok()?;
+    Some(line)
+}
+
+/// Names of the built-in commands, for tab completion.
+const COMMANDS: &[&str] = &[
+    "cat", "cd", "df", "dmesg", "du", "echo", "env", "exit", "export", "find", "head", "hexdump",
+    "jobs", "kill", "ls", "meminfo", "ps", "pwd", "slabinfo", "sleep", "stat", "tail", "unset",
+    "wc", "xxd",
+];
+
+/// Completes command names and paths relative to the shell's `cwd`.
+struct ShellCompleter<'a> {
+    cwd: &'a PathBuf,
+}
+
+impl<'a> Completer for ShellCompleter<'a> {
+    fn complete(&self, word: &str, first: bool, out: &mut Vec<String>) {
+        if first && !word.contains('/') {
+            out.extend(COMMANDS.iter().map(|c| String::from(*c)));
+            return;
+        }
+
+        // complete the last component of `word` inside the directory it names
+        let (dir, prefix) = match word.rfind('/') {
+            Some(i) => (&word[..=i], &word[i + 1..]),
+            None => ("", word),
+        };
+        let mut cwd = self.cwd.clone();
+        let path = construct_path(if dir.is_empty() { "." } else { dir }, &mut cwd);
+        let entries = match FILESYSTEM.open(path).ok().and_then(|e| e.into_dir()) {
+            Some(dir) => match dir.entries() {
+                Ok(entries) => entries,
+                Err(_) => return,
+            },
+            None => return,
+        };
 
-    let mut cwd = PathBuf::new();
-    cwd.push("/");
-
-
-    // spin_sleep(Duration::new(1, 0));
-    
-    kprintln!("a VERY warm welcome to ...");
-    kprintln!("~~~~~~~~~~~~~~ JOS ~~~~~~~~~~~~~~");
-    loop {
-        let cwd_string =  cwd.clone().into_os_string().into_string().unwrap();
-        kprint!("jakob@cs3210:[{}]{}", cwd_string, prefix);
-        
-        let a = &mut [0u8; 512];
-        let mut input_buf = StackVec::new(a);  //should I be handling if the prefix is longer than one character?
-        loop {
-            let mut consoley = CONSOLE.lock();
-            let current_byte = consoley.read_byte();
-            if current_byte == b'\n' || current_byte == b'\r' {
-                match from_utf8(input_buf.as_slice()) { 
-                    Ok(res) => {
-                        kprintln!();
-                        let arg_buf = &mut [""; 64]; //slice that the arguments will be put into
-                        match Command::parse(res, arg_buf) {
-                            Ok(command) => {
-                                let rtn = invoke_appropriate_command(command, &mut cwd);
-                                if rtn { return; }
-                            },
-                            Err(Error::Empty) => {
-                                kprintln!();
-                            },
-                            Err(Error::TooManyArgs) => {
-                                kprintln!("error: too many arguments");
-                            }
-                        };
-                    },
-                    Err(e) => kprintln!("Failed converting from str to [u8]"),
-                }
-                break;
-            } else {
-                if !input_buf.is_full() {
-                    if is_visible_ascii(current_byte) {
-                        if is_erase_ascii(current_byte) {
-                            if input_buf.len() > 0 {
-                                consoley.write_byte(8);
-                                consoley.write_byte(b' ');
-                                consoley.write_byte(8);
-                                input_buf.pop();
-                            }
-                        } else {
-                            input_buf.push(current_byte);
-                            consoley.write_byte(current_byte);
-                        }
-                    }
-                } else {
-                    if is_erase_ascii(current_byte) {
-                        if input_buf.len() > 0 {
-                            consoley.write_byte(8);
-                            consoley.write_byte(b' ');
-                            consoley.write_byte(8);
-                            input_buf.pop();
-                        }
-                    } else {
-                        consoley.write_byte(7u8);
-                    }
-                }
+        for entry in entries {
+            let name = entry.name();
+            if name == "." || name == ".." || !name.starts_with(prefix) {
+                continue;
             }
+            if entry.metadata().hidden() && !prefix.starts_with('.') {
+                continue;
+            }
+            let mut candidate = String::from(dir);
+            candidate.push_str(name);
+            if entry.is_dir() {
+                candidate.push('/');
+            }
+            out.push(candidate);
         }
     }
 }
 
 //helper functions for my shell() function
 
+/// Runs every command of `pipeline`, feeding the output of each command to
+/// the next one. The output of the last command goes to `term` or to the
+/// redirection target. Returns `true` if the shell should exit.
+fn run_pipeline(pipeline: Pipeline, shell: &mut Shell, term: &mut dyn Terminal) -> bool {
+    let last = pipeline.commands.len() - 1;
+    let mut input: Option<Vec<u8>> = None;
+    for (i, command) in pipeline.commands.iter().enumerate() {
+        if command.path() == "exit" {
+            return true;
+        }
 
-fn invoke_appropriate_command(command: Command, cwd: &mut PathBuf) -> bool {
+        let mut output = Vec::new();
+        let input_slice = input.as_ref().map(|v| v.as_slice());
+        let builtin = if i == last && pipeline.redirect.is_none() {
+            invoke_appropriate_command(command, input_slice, &mut Output::Term(&mut *term), shell)
+        } else {
+            invoke_appropriate_command(command, input_slice, &mut Output::Pipe(&mut output), shell)
+        };
+        let result = match builtin {
+            Some(result) => result,
+            None => run_program(command, pipeline.background, shell, term),
+        };
+        if let Err(e) = result {
+            eprintln!("{}: {:?}", command.path(), e);
+        }
+        input = Some(output);
+    }
+
+    if let Some(redirect) = pipeline.redirect {
+        let output = input.unwrap_or_default();
+        if redirect.target == "/dev/console" {
+            term.write_all(&output).ok();
+        } else {
+            let path = construct_path(&redirect.target, &mut shell.cwd);
+            let path = path.into_os_string().into_string().unwrap();
+            let buf = shell.buffers.entry(path).or_insert_with(Vec::new);
+            if !redirect.append {
+                buf.clear();
+            }
+            buf.extend_from_slice(&output);
+        }
+    }
+    false
+}
+
+/// Runs `command` if it is a built-in. Returns `None` for the other
+/// commands, which are looked up in `PATH` and run as user programs.
+fn invoke_appropriate_command(
+    command: &Command,
+    input: Option<&[u8]>,
+    out: &mut Output,
+    shell: &mut Shell,
+) -> Option<io::Result<()>> {
     let command_path = command.path();
-    let command_args = & command.args.as_slice()[1..];
-    let mut exit = false;
-    match command_path {
-        "echo" => echo(command_args),
-        "ls" => ls(command_args, cwd),
-        "cat" => cat(command_args, cwd),
-        "pwd" => pwd(cwd),
-        "cd" => cd(command_args, cwd),
-        "exit" => { exit = true; },
+    let command_args: Vec<&str> = command.args[1..].iter().map(|a| a.as_str()).collect();
+    let command_args = command_args.as_slice();
+    let result = match command_path {
+        "echo" => echo(command_args, out),
+        "ls" => files::ls(command_args, shell, out),
+        "stat" => files::stat(command_args, shell, out),
+        "hexdump" | "xxd" => files::hexdump(command_path, command_args, input, shell, out),
+        "head" | "tail" => files::head_tail(command_path, command_args, input, shell, out),
+        "wc" => files::wc(command_args, input, shell, out),
+        "find" => files::find(command_args, shell, out),
+        "du" => files::du(command_args, shell, out),
+        "df" => files::df(out),
+        "cat" => files::cat(command_args, input, shell, out),
+        "pwd" => pwd(&mut shell.cwd, out),
+        "cd" => cd(command_args, shell),
         "sleep" => sleep(command_args),
-        _ => kprintln!("unknown command: ${}", command_path)
+        "slabinfo" => slabinfo(out),
+        "meminfo" => meminfo(command_args, out),
+        "env" => env(&shell.env, out),
+        "export" => export(command_args, &mut shell.env),
+        "unset" => unset(command_args, &mut shell.env),
+        "jobs" => jobs(shell, out),
+        "kill" => kill(command_args, shell),
+        "ps" => ps(shell, out),
+        "dmesg" => dmesg(command_args, out),
+        "ping" => nettools::ping(command_args, out),
+        "ifconfig" => nettools::ifconfig(command_args, out),
+        "netstat" => nettools::netstat(command_args, out),
+        _ => return None,
     };
-    exit
+    Some(result)
 }
 
 
 //my aresenal of commands lives down here
-fn echo(args: & [&str]) {
-    for s in args {
-        kprint!("{} ", s);
-    }
-    kprintln!();
+fn echo(args: & [&str], out: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(out, "{}", args.join(" "))
 }
-fn pwd(cwd: &mut PathBuf) {
+fn pwd(cwd: &mut PathBuf, out: &mut dyn io::Write) -> io::Result<()> {
     let path_str = cwd.clone().into_os_string().into_string().unwrap();
-    kprintln!("{}", path_str);
-}
-fn cat(args: & [&str], cwd: &mut PathBuf) {
-    let mut count = 0;
-    if args.len() == 0 { kprintln!("Please enter a file name!"); }
-    for file in args {
-        let path = construct_path(file, cwd);
-        match FILESYSTEM.open(path.clone()) {
-            Ok(entry) => {
-                if entry.is_dir() { 
-                    kprintln!("cat: {}: No such file or directory", entry.name());
-                 } else {
-                    let mut file = entry.into_file().unwrap();
-                    let mut buf = Vec::new();
-                    for i in 0..file.size { buf.push(0u8) }; 
-                    file.read(buf.as_mut_slice());
-                    let file_contents = if let Ok(string) = String::from_utf8(buf) {
-                                    string
-                                } else {
-                                    String::from("File contains invalid UTF-8\n")
-                                };
-                    if count < args.len() - 1 {
-                        kprintln!("{}", file_contents);
-                    } else {
-                        kprint!("{}", file_contents);
-                    }
-                    
-                 }
-            },
-            Err(_) => kprintln!("cat: {}: No such file or directory", file)
-        }
-        count += 1;
-    }
+    writeln!(out, "{}", path_str)
 }
-fn cd(args: & [&str], cwd: &mut PathBuf) {
+fn cd(args: & [&str], shell: &mut Shell) -> io::Result<()> {
+    let cwd = &mut shell.cwd;
     let length = args.len();
     let dest;
     if length == 0 {
@@ -211,8 +507,8 @@ fn cd(args: & [&str], cwd: &mut PathBuf) {
     } else if length == 1 {
         dest = args[0];
     } else {
-        kprintln!("too many arguments");
-        return ();
+        eprintln!("too many arguments");
+        return Ok(());
     }
     let path = construct_path(dest, cwd);
     match FILESYSTEM.open(path.clone()) {
@@ -220,134 +516,246 @@ fn cd(args: & [&str], cwd: &mut PathBuf) {
             //resolve path       
             while cwd.pop() == true {;};
             cwd.push(path);
-
+            let pwd = cwd.clone().into_os_string().into_string().unwrap();
+            shell.env.insert(String::from("PWD"), pwd);
         },
-        Err(_) => { kprintln!("no such file or directory"); }
-    }
-
-
-}
-fn ls(args: & [&str], cwd: &mut PathBuf) {
-    let mut path = PathBuf::new();
-    let show_hidden;
-    if args.len() == 0 {
-        //current directory
-        show_hidden = false;
-        path = cwd.clone();
-        let entries = get_entries(&mut path, show_hidden);
-        display_entries(entries);
-    } else if args.len() == 1 {
-        //specified directory
-        if args[0] == "-a" {
-            show_hidden = true;
-            path = cwd.clone();
-        } else {
-            show_hidden = false;
-            path = construct_path(args[0], cwd);
-        }
-        let entries = get_entries(&mut path, show_hidden);
-        display_entries(entries);
-    } else if args.len() == 2 {
-        //flag and specified directory
-        if args[0] == "-a" {
-            //display all 
-            show_hidden = true;
-            path = construct_path(args[1], cwd);
-            let entries = get_entries(&mut path, show_hidden);
-            display_entries(entries);
-
-        } else {
-            kprintln!("Only the '-a' flag is supported currently!");
-        } 
-    } else {
-        kprintln!("Only the '-a' flag is supported currently!");
+        Err(_) => { eprintln!("no such file or directory"); }
     }
+    Ok(())
 }
-
-fn sleep(args: & [&str]) {
+fn sleep(args: & [&str]) -> io::Result<()> {
     // if args.len() > 1 {
-    //     kprintln!("Error - enter command of the form 'sleep <ms>'")
+    //     eprintln!("Error - enter command of the form 'sleep <ms>'")
     // }
     // if let Ok(res) = u32::from_str(args[0]) {
     //     if let Ok(ms) = syscall::sleep(Duration::from_millis(res as u64)) {
-    //         kprintln!("night night for {} milliseconds ..zzzzzz", ms.as_millis());
+    //         eprintln!("night night for {} milliseconds ..zzzzzz", ms.as_millis());
     //     } else {
-    //         kprintln!("Error when invoking 'sleep' syscall!");
+    //         eprintln!("Error when invoking 'sleep' syscall!");
     //     }
     // } else {
-    //     kprintln!("Please enter an integer number of millisecondss to sleep!")
+    //     eprintln!("Please enter an integer number of millisecondss to sleep!")
     // }
-    // kprintln!();
+    // eprintln!();
+    Ok(())
 }
 
-
-//TODO: Add support for ls-ing a file (return vector of length 1)
-fn get_entries(cwd: &mut PathBuf, show_hidden: bool) -> io::Result<Vec<Entry<PiVFatHandle>>> {
-    let entry = FILESYSTEM.open(cwd.clone())?;
-    let mut entry_vec = Vec::new();
-    if entry.is_dir() {
-        let mut entries = entry.into_dir().unwrap().entries().unwrap();
-        let filtered_entries = entries.filter(|entry| show_hidden || !entry.metadata().hidden());
-        for entry in filtered_entries {
-            entry_vec.push(entry);
+fn slabinfo(out: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(out, "{:<16} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+        "name", "objsize", "perchunk", "chunks", "inuse", "cached", "free")?;
+    for stats in crate::allocator::slab_caches() {
+        writeln!(out, "{:<16} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            stats.name, stats.obj_size, stats.per_chunk, stats.chunks,
+            stats.in_use, stats.cached, stats.free)?;
+    }
+    Ok(())
+}
+fn meminfo(args: & [&str], out: &mut dyn io::Write) -> io::Result<()> {
+    use crate::allocator::LiveAlloc;
+
+    match args {
+        [] | ["mark"] | ["leaks"] | ["check"] => {}
+        _ => {
+            eprintln!("usage: meminfo [mark | leaks | check]");
+            return Ok(());
         }
-    } else {
-        entry_vec.push(entry);
-    }
-    
-    Ok(entry_vec)
-}
-fn display_entries(entries: io::Result<Vec<Entry<PiVFatHandle>>>) {
-    match entries {
-        Ok(entries) => {
-            entries.into_iter().for_each(|x| {
-                let mut output_string = String::new();
-                format_output(&mut output_string, &x);
-                kprintln!("{}", output_string);
-            });
-            // entries.into_iter().for_each(|x| kprint!("{}\t", x.name()));
+    }
+    match args {
+        [] => match ALLOCATOR.stats() {
+            Some(stats) => write!(out, "{}", stats)?,
+            None => eprintln!("meminfo: kernel built without the alloc-stats feature"),
         },
-        Err(e) => kprintln!("No such directory,{:?}", e)
+        _ if !ALLOCATOR.is_debug() => {
+            eprintln!("meminfo: {} needs a kernel built with the alloc-debug feature", args[0])
+        }
+        ["mark"] => writeln!(out, "tracking allocations from #{}", ALLOCATOR.set_mark())?,
+        ["leaks"] => {
+            let mut live = [LiveAlloc::default(); 32];
+            let count = ALLOCATOR.live(&mut live);
+            for alloc in live.iter().take(count) {
+                writeln!(out, "#{:<8} {:#x} {} bytes", alloc.seq, alloc.addr, alloc.size)?;
+            }
+            if count > live.len() {
+                writeln!(out, "... and {} more", count - live.len())?;
+            }
+            writeln!(out, "{} allocations since the last mark are still live", count)?;
+        }
+        ["check"] => match ALLOCATOR.check() {
+            Ok(count) => writeln!(out, "{} live allocations ok", count)?,
+            Err(corruption) => writeln!(out, "heap corruption: {}", corruption)?,
+        },
+        _ => {}
+    }
+    Ok(())
+}
+fn env(env: &Env, out: &mut dyn io::Write) -> io::Result<()> {
+    for (name, value) in env {
+        writeln!(out, "{}={}", name, value)?;
+    }
+    Ok(())
+}
+fn export(args: & [&str], env: &mut Env) -> io::Result<()> {
+    for arg in args {
+        match arg.find('=') {
+            Some(i) if i > 0 => {
+                env.insert(String::from(&arg[..i]), String::from(&arg[i + 1..]));
+            }
+            _ => eprintln!("export: usage: export NAME=VALUE"),
+        }
+    }
+    Ok(())
+}
+fn unset(args: & [&str], env: &mut Env) -> io::Result<()> {
+    for arg in args {
+        env.remove(*arg);
+    }
+    Ok(())
+}
+fn jobs(shell: &mut Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    for job in shell.finished_jobs() {
+        writeln!(out, "[{}] Done    {}", job.num, job.command)?;
+    }
+    for job in &shell.jobs {
+        writeln!(out, "[{}] {:<5} Running {}", job.num, job.id, job.command)?;
+    }
+    Ok(())
+}
+fn kill(args: & [&str], shell: &mut Shell) -> io::Result<()> {
+    if args.is_empty() {
+        eprintln!("kill: usage: kill <pid> | %<job>");
     }
+    for arg in args {
+        let id = if arg.starts_with('%') {
+            usize::from_str(&arg[1..]).ok()
+                .and_then(|num| shell.jobs.iter().find(|job| job.num == num))
+                .map(|job| job.id)
+        } else {
+            Id::from_str(arg).ok()
+        };
+        match id {
+            Some(id) if SCHEDULER.kill_pid(id) => {}
+            Some(_) => eprintln!("kill: ({}) - No such process", arg),
+            None => eprintln!("kill: {}: arguments must be process or job IDs", arg),
+        }
+    }
+    Ok(())
+}
+fn ps(shell: &Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(out, "{:>5} {:<8} {}", "PID", "STATE", "COMMAND")?;
+    for process in SCHEDULER.processes() {
+        let command = shell.jobs.iter()
+            .find(|job| job.id == process.id)
+            .map(|job| job.command.as_str())
+            .unwrap_or("");
+        writeln!(out, "{:>5} {:<8} {}", process.id, process.state, command)?;
+    }
+    Ok(())
+}
+fn dmesg(args: & [&str], out: &mut dyn io::Write) -> io::Result<()> {
+    let clear = match args {
+        [] => false,
+        ["-c"] => true,
+        _ => {
+            eprintln!("usage: dmesg [-c]");
+            return Ok(());
+        }
+    };
+    let log = {
+        let mut console = CONSOLE.lock();
+        let log = console.history(Vt::Log);
+        if clear {
+            console.clear_history(Vt::Log);
+        }
+        log
+    };
+    out.write_all(&log)
 }
 
-fn format_output<T: EntryTrait>(formatted_output: &mut String, entry: &T) -> ::core::fmt::Result {
-    use core::fmt::Write;
+/// Looks `name` up as a program file. Names containing a `/` are taken
+/// relative to `cwd`; other names are searched in each directory of the
+/// colon-separated `PATH` variable. `<name>.bin` is tried after `<name>`.
+fn find_program(name: &str, shell: &mut Shell) -> Option<PathBuf> {
+    let is_file = |path: &PathBuf| {
+        FILESYSTEM.open(path.clone()).map(|entry| entry.is_file()).unwrap_or(false)
+    };
+    let candidates = |path: PathBuf| {
+        let mut with_ext = path.clone().into_os_string().into_string().unwrap();
+        with_ext.push_str(".bin");
+        [path, PathBuf::from(with_ext)]
+    };
 
-    fn write_bool(to: &mut String, b: bool, c: char) -> ::core::fmt::Result {
-        if b {
-            write!(to, "{}", c)
-        } else {
-            write!(to, "-")
+    if name.contains('/') {
+        return candidates(construct_path(name, &mut shell.cwd)).iter().find(|p| is_file(*p)).cloned();
+    }
+    let dirs = shell.env.get("PATH").cloned().unwrap_or_default();
+    for dir in dirs.split(':').filter(|dir| !dir.is_empty()) {
+        let mut path = construct_path(dir, &mut shell.cwd);
+        path.push(name);
+        if let Some(found) = candidates(path).iter().find(|p| is_file(*p)) {
+            return Some(found.clone());
+        }
+    }
+    None
+}
+
+/// Loads the program named by `command` and schedules it. Unless `background`
+/// is set, waits for it to exit; typing Ctrl-C on `term` meanwhile kills it.
+///
+/// The shell runs with interrupts masked, so a program started from it is run
+/// by the other cores while the shell polls for its exit.
+fn run_program(
+    command: &Command,
+    background: bool,
+    shell: &mut Shell,
+    term: &mut dyn Terminal,
+) -> io::Result<()> {
+    let name = command.path();
+    let path = match find_program(name, shell) {
+        Some(path) => path,
+        None => {
+            eprintln!("unknown command: {}", name);
+            return Ok(());
+        }
+    };
+    if !term.runs_programs() {
+        writeln!(term, "{}: programs can only be started from the console", name)?;
+        return Ok(());
+    }
+    let process = match Process::load(&path) {
+        Ok(process) => process,
+        Err(e) => {
+            eprintln!("{}: failed to load {}: {:?}", name, path.display(), e);
+            return Ok(());
         }
+    };
+    let id = match SCHEDULER.add(process) {
+        Some(id) => id,
+        None => {
+            eprintln!("{}: out of process IDs", name);
+            return Ok(());
+        }
+    };
+
+    if background {
+        let num = shell.jobs.iter().map(|job| job.num).max().unwrap_or(0) + 1;
+        writeln!(term, "[{}] {}", num, id)?;
+        shell.jobs.push(Job { num, id, command: command.args.join(" ") });
+        return Ok(());
     }
-    
-    fn write_timestamp<T: Timestamp>(to: &mut String, ts: T) -> ::core::fmt::Result {
-        write!(
-            to,
-            "{:02}/{:02}/{} {:02}:{:02}:{:02} ",
-            ts.month(),
-            ts.day(),
-            ts.year(),
-            ts.hour(),
-            ts.minute(),
-            ts.second()
-        )
-    }
-    
-    write_bool(formatted_output, entry.is_dir(), 'd')?;
-    write_bool(formatted_output, entry.is_file(), 'f')?;
-    write_bool(formatted_output, entry.metadata().read_only(), 'r')?;
-    write_bool(formatted_output, entry.metadata().hidden(), 'h')?;
-    write!(formatted_output, "\t")?;
-    
-    write_timestamp(formatted_output, entry.metadata().created())?;
-    write_timestamp(formatted_output, entry.metadata().modified())?;
-    write_timestamp(formatted_output, entry.metadata().accessed())?;
-    write!(formatted_output, "\t")?;
-    
-    write!(formatted_output, "{}", entry.name())?;
 
+    term.set_foreground_job(true);
+    let mut interrupted = false;
+    while SCHEDULER.is_alive(id) {
+        if term.try_read_byte() == Some(0x03) {
+            interrupted = true;
+            SCHEDULER.kill_pid(id);
+        }
+        spin_sleep(Duration::from_millis(10));
+    }
+    term.set_foreground_job(false);
+    if interrupted {
+        writeln!(term, "^C")?;
+    }
     Ok(())
 }
 
@@ -374,24 +782,3 @@ fn construct_path(dest: &str, cwd: &mut PathBuf) -> PathBuf {
 
     resolved_path
 }
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-//helper functions for displaying correct characters
-fn is_visible_ascii(ascii_value: u8) -> bool {
-    ascii_value >= 32 && ascii_value <= 127
-}
-fn is_erase_ascii(ascii_value: u8) -> bool {
-    ascii_value == 8 || ascii_value == 127
-}
@@ -0,0 +1,387 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use shim::io;
+
+use crate::console::kprintln;
+use crate::debug::{self, gdb, Resume};
+use crate::traps::TrapFrame;
+
+use super::files::parse_number;
+use super::line::LineEditor;
+use super::parse::{Error, Pipeline};
+use super::{flush_stderr, read_line, run_pipeline, ConsoleTerminal, Shell};
+
+/// Maximum number of frames printed by `bt`.
+const MAX_FRAMES: usize = 32;
+
+/// A register of the trapped context, as named on the command line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Reg {
+    X(u8),
+    Sp,
+    Pc,
+    Spsr,
+    Tpidr,
+}
+
+impl Reg {
+    /// Parses `x0`..`x30`, `fp`, `lr`, `sp`, `pc`, `spsr` and `tpidr`,
+    /// optionally prefixed with `$`.
+    fn parse(name: &str) -> Option<Reg> {
+        let name = if name.starts_with('$') { &name[1..] } else { name };
+        match name {
+            "fp" => Some(Reg::X(29)),
+            "lr" => Some(Reg::X(30)),
+            "sp" => Some(Reg::Sp),
+            "pc" | "elr" => Some(Reg::Pc),
+            "spsr" | "cpsr" => Some(Reg::Spsr),
+            "tpidr" => Some(Reg::Tpidr),
+            _ if name.starts_with('x') => match name[1..].parse::<u8>() {
+                Ok(n) if n <= 30 && !name[1..].starts_with('+') => Some(Reg::X(n)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn get(self, tf: &TrapFrame) -> u64 {
+        match self {
+            Reg::X(n) => tf.get_gpr(n),
+            Reg::Sp => debug::stack_pointer(tf),
+            Reg::Pc => tf.get_elr(),
+            Reg::Spsr => tf.get_spsr(),
+            Reg::Tpidr => tf.get_tpidr(),
+        }
+    }
+
+    /// Sets the register to `val`. Returns `false` if it cannot be changed.
+    fn set(self, tf: &mut TrapFrame, val: u64) -> bool {
+        match self {
+            Reg::X(n) => tf.set_gpr(n, val),
+            Reg::Sp if debug::is_kernel(tf) => return false,
+            Reg::Sp => tf.set_sp(val),
+            Reg::Pc => tf.set_elr(val),
+            Reg::Spsr => tf.set_spsr(val),
+            Reg::Tpidr => tf.set_tpidr(val),
+        }
+        true
+    }
+}
+
+/// Parses a number or the name of a register holding it.
+fn parse_value(s: &str, tf: &TrapFrame) -> Option<u64> {
+    match Reg::parse(s) {
+        Some(reg) => Some(reg.get(tf)),
+        None => parse_number(s).map(|n| n as u64),
+    }
+}
+
+/// Parses the `x/<n><unit>` command into the number of units to print and the
+/// unit size in bytes. The unit is one of `b`, `h`, `w` (the default) and `g`.
+fn parse_examine(cmd: &str) -> Option<(usize, usize)> {
+    if cmd == "x" {
+        return Some((1, 4));
+    }
+    if !cmd.starts_with("x/") {
+        return None;
+    }
+    let spec = &cmd[2..];
+    let digits = spec.find(|c: char| !c.is_ascii_digit()).unwrap_or(spec.len());
+    let count = match &spec[..digits] {
+        "" => 1,
+        n => n.parse().ok()?,
+    };
+    let size = match &spec[digits..] {
+        "b" => 1,
+        "h" => 2,
+        "" | "w" => 4,
+        "g" => 8,
+        _ => return None,
+    };
+    Some((count, size))
+}
+
+/// The condition flags and mode of an SPSR value, e.g. `nZCv EL1h`.
+struct Pstate(u64);
+
+impl fmt::Display for Pstate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, flag) in "NZCV".chars().enumerate() {
+            let set = self.0 & (1 << (31 - i)) != 0;
+            write!(f, "{}", if set { flag } else { flag.to_ascii_lowercase() })?;
+        }
+        let mode = self.0 & 0b1111;
+        write!(f, " EL{}{}", mode >> 2, if mode & 1 == 1 { 'h' } else { 't' })
+    }
+}
+
+/// What to do after a debugger command.
+enum Action {
+    Stay,
+    Resume(Resume),
+    /// Hand the session over to `gdb`.
+    Gdb,
+}
+
+/// Enters the debug monitor for the trapped context `tf`. Besides the normal
+/// shell commands, the monitor accepts `regs`, `x/<n> <addr>`, `set <reg>
+/// <value>`, `bt`, `break [addr]`, `delete <addr>`, `step`, `continue` and
+/// `gdb`, which turns the console into a GDB remote serial protocol link.
+/// Returns once `tf` is ready to be resumed.
+pub fn debugger(tf: &mut TrapFrame) {
+    let mut shell = Shell::new();
+    let mut editor = LineEditor::new();
+    let mut term = ConsoleTerminal;
+
+    let what = if debug::is_breakpoint(tf) { "breakpoint" } else { "stopped" };
+    kprintln!("{} at {:#x}", what, tf.get_elr());
+    loop {
+        let line = match read_line(&mut editor, "(dbg) ", &shell, &mut term) {
+            Some(line) => line,
+            None => continue,
+        };
+        let pipeline = match Pipeline::parse(&line, &shell.env) {
+            Ok(pipeline) => pipeline,
+            Err(Error::Empty) => continue,
+            Err(e) => {
+                kprintln!("error: {}", e);
+                continue;
+            }
+        };
+
+        if pipeline.commands.len() == 1 && pipeline.redirect.is_none() {
+            let command = &pipeline.commands[0];
+            let args: Vec<&str> = command.args[1..].iter().map(|a| a.as_str()).collect();
+            match run_command(command.path(), &args, tf, &mut term) {
+                Some(Ok(Action::Stay)) => continue,
+                Some(Ok(Action::Resume(how))) => return debug::resume(tf, how),
+                Some(Ok(Action::Gdb)) => {
+                    kprintln!("waiting for gdb on this serial line");
+                    return gdb::enter(tf, false);
+                }
+                Some(Err(e)) => {
+                    kprintln!("{}: {:?}", command.path(), e);
+                    continue;
+                }
+                None => {}
+            }
+        }
+
+        let exit = run_pipeline(pipeline, &mut shell, &mut term);
+        flush_stderr(&mut term).ok();
+        if exit {
+            return debug::resume(tf, Resume::Continue);
+        }
+    }
+}
+
+/// Runs the debugger command `cmd`. Returns `None` if `cmd` is not one.
+fn run_command(
+    cmd: &str,
+    args: &[&str],
+    tf: &mut TrapFrame,
+    out: &mut dyn io::Write,
+) -> Option<io::Result<Action>> {
+    let result = match cmd {
+        "regs" => regs(tf, out),
+        "set" => set(args, tf),
+        "bt" => backtrace(tf, out),
+        "break" => set_breakpoint(args, tf, out),
+        "delete" => delete_breakpoint(args, tf),
+        "step" | "s" => return Some(Ok(Action::Resume(Resume::Step))),
+        "continue" | "c" => return Some(Ok(Action::Resume(Resume::Continue))),
+        "gdb" => return Some(Ok(Action::Gdb)),
+        _ => match parse_examine(cmd) {
+            Some((count, size)) => examine(args, count, size, tf, out),
+            None => return None,
+        },
+    };
+    Some(result.map(|_| Action::Stay))
+}
+
+/// `regs`
+fn regs(tf: &TrapFrame, out: &mut dyn io::Write) -> io::Result<()> {
+    for row in (0..31u8).collect::<Vec<_>>().chunks(3) {
+        for &n in row {
+            let pad = if n < 10 { " " } else { "" };
+            write!(out, "{}x{} {:#018x}   ", pad, n, tf.get_gpr(n))?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "  sp {:#018x}     pc {:#018x}   spsr {:#010x} [{}]",
+        debug::stack_pointer(tf), tf.get_elr(), tf.get_spsr(), Pstate(tf.get_spsr()))?;
+    writeln!(out, "tpidr {:#x}  ttbr0 {:#x}  ttbr1 {:#x}",
+        tf.get_tpidr(), tf.get_ttbr0(), tf.get_ttbr1())
+}
+
+/// `x/<n><unit> <addr>`
+fn examine(
+    args: &[&str],
+    count: usize,
+    size: usize,
+    tf: &TrapFrame,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let addr = match args {
+        [addr] => match parse_value(addr, tf) {
+            Some(addr) => addr as usize,
+            None => {
+                kprintln!("x: invalid address '{}'", addr);
+                return Ok(());
+            }
+        },
+        _ => {
+            kprintln!("usage: x/<n>[bhwg] <addr>");
+            return Ok(());
+        }
+    };
+
+    let per_line = core::cmp::min(8, 16 / size);
+    let mut done = 0;
+    while done < count {
+        let line_addr = addr.wrapping_add(done * size);
+        let units = core::cmp::min(per_line, count - done);
+        let mut bytes = [0u8; 16];
+        if debug::read_memory(line_addr, &mut bytes[..units * size]).is_err() {
+            kprintln!("x: cannot access memory at {:#x}", line_addr);
+            break;
+        }
+        write!(out, "{:#018x}:", line_addr)?;
+        for unit in bytes[..units * size].chunks(size) {
+            let val = unit.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64);
+            write!(out, "  {:#0width$x}", val, width = 2 + 2 * size)?;
+        }
+        writeln!(out)?;
+        done += units;
+    }
+    Ok(())
+}
+
+/// `set <reg> <value>`
+fn set(args: &[&str], tf: &mut TrapFrame) -> io::Result<()> {
+    let (reg, val) = match args {
+        [reg, val] => (Reg::parse(reg), parse_value(val, tf)),
+        _ => {
+            kprintln!("usage: set <reg> <value>");
+            return Ok(());
+        }
+    };
+    match (reg, val) {
+        (None, _) => kprintln!("set: unknown register '{}'", args[0]),
+        (_, None) => kprintln!("set: invalid value '{}'", args[1]),
+        (Some(reg), Some(val)) => {
+            if !reg.set(tf, val) {
+                kprintln!("set: {} of a kernel context cannot be changed", args[0]);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `bt`: follows the chain of frame records starting at `x29`.
+fn backtrace(tf: &TrapFrame, out: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(out, "#0  {:#018x}", tf.get_elr())?;
+    let mut fp = tf.get_gpr(29) as usize;
+    for depth in 1..MAX_FRAMES {
+        if fp == 0 || fp % 8 != 0 {
+            break;
+        }
+        let (next, ret) = match (debug::read_u64(fp), debug::read_u64(fp + 8)) {
+            (Ok(next), Ok(ret)) => (next as usize, ret),
+            _ => {
+                kprintln!("bt: cannot access frame record at {:#x}", fp);
+                break;
+            }
+        };
+        if ret == 0 {
+            break;
+        }
+        writeln!(out, "#{:<2} {:#018x}", depth, ret)?;
+        // frames are pushed downwards, so callers live at higher addresses
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+    Ok(())
+}
+
+/// `break [addr]`: sets a breakpoint, or lists them without an argument.
+fn set_breakpoint(args: &[&str], tf: &TrapFrame, out: &mut dyn io::Write) -> io::Result<()> {
+    let addr = match args {
+        [] => {
+            for (i, addr) in debug::breakpoints().iter().enumerate() {
+                writeln!(out, "{:>2}  {:#018x}", i, addr)?;
+            }
+            return Ok(());
+        }
+        [addr] => parse_value(addr, tf),
+        _ => None,
+    };
+    let addr = match addr {
+        Some(addr) => addr as usize,
+        None => {
+            kprintln!("usage: break [addr]");
+            return Ok(());
+        }
+    };
+    match debug::insert_breakpoint(addr) {
+        Ok(()) => writeln!(out, "breakpoint at {:#x}", addr)?,
+        Err(e) => kprintln!("break: {:#x}: {:?}", addr, e),
+    }
+    Ok(())
+}
+
+/// `delete <addr>`
+fn delete_breakpoint(args: &[&str], tf: &TrapFrame) -> io::Result<()> {
+    if args.is_empty() {
+        kprintln!("usage: delete <addr>...");
+    }
+    for arg in args {
+        match parse_value(arg, tf) {
+            Some(addr) => {
+                if let Err(e) = debug::remove_breakpoint(addr as usize) {
+                    kprintln!("delete: {}: {:?}", arg, e);
+                }
+            }
+            None => kprintln!("delete: invalid address '{}'", arg),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers() {
+        assert_eq!(Reg::parse("x0"), Some(Reg::X(0)));
+        assert_eq!(Reg::parse("$x30"), Some(Reg::X(30)));
+        assert_eq!(Reg::parse("fp"), Some(Reg::X(29)));
+        assert_eq!(Reg::parse("lr"), Some(Reg::X(30)));
+        assert_eq!(Reg::parse("$pc"), Some(Reg::Pc));
+        assert_eq!(Reg::parse("x31"), None);
+        assert_eq!(Reg::parse("x+1"), None);
+        assert_eq!(Reg::parse("x"), None);
+        assert_eq!(Reg::parse("0x10"), None);
+    }
+
+    #[test]
+    fn examine_spec() {
+        assert_eq!(parse_examine("x"), Some((1, 4)));
+        assert_eq!(parse_examine("x/4"), Some((4, 4)));
+        assert_eq!(parse_examine("x/16b"), Some((16, 1)));
+        assert_eq!(parse_examine("x/g"), Some((1, 8)));
+        assert_eq!(parse_examine("x/2h"), Some((2, 2)));
+        assert_eq!(parse_examine("x/2q"), None);
+        assert_eq!(parse_examine("xxd"), None);
+    }
+
+    #[test]
+    fn pstate() {
+        assert_eq!(format!("{}", Pstate(0x6000_0005)), "nZCv EL1h");
+        assert_eq!(format!("{}", Pstate(0x8000_0000)), "Nzcv EL0t");
+    }
+}
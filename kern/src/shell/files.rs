@@ -0,0 +1,583 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use shim::io;
+
+use fat32::traits::{Dir as DirTrait, Entry as EntryTrait, Metadata as MetadataTrait, Timestamp};
+use fat32::vfat::{Dir, Entry, Metadata};
+
+use super::eprintln;
+use crate::fs::PiVFatHandle;
+use crate::FILESYSTEM;
+
+use super::construct_path;
+use super::text::{self, Counts, DumpStyle};
+use super::Shell;
+
+/// Number of lines `head` and `tail` print by default.
+const DEFAULT_LINES: usize = 10;
+
+/// Flags and operands of a command line. Flags may be combined (`-lR`) and
+/// the value of a flag may be attached (`-n5`) or follow it (`-n 5`). `--`
+/// ends the flags.
+struct Opts<'a> {
+    flags: Vec<char>,
+    values: Vec<(char, &'a str)>,
+    operands: Vec<&'a str>,
+}
+
+impl<'a> Opts<'a> {
+    /// Parses `args`, taking a value for every flag in `valued`. Returns the
+    /// offending flag if `args` contains one that is not in `known` or a
+    /// valued flag without a value.
+    fn parse(args: &[&'a str], known: &str, valued: &str) -> Result<Opts<'a>, char> {
+        let mut opts = Opts { flags: Vec::new(), values: Vec::new(), operands: Vec::new() };
+        let mut args = args.iter();
+        while let Some(&arg) = args.next() {
+            if arg == "--" {
+                opts.operands.extend(args);
+                break;
+            }
+            if !arg.starts_with('-') || arg.len() == 1 {
+                opts.operands.push(arg);
+                continue;
+            }
+            for (i, flag) in arg[1..].char_indices() {
+                if valued.contains(flag) {
+                    let rest = &arg[1 + i + flag.len_utf8()..];
+                    let value = if rest.is_empty() { args.next().copied() } else { Some(rest) };
+                    opts.values.push((flag, value.ok_or(flag)?));
+                    break;
+                }
+                if !known.contains(flag) {
+                    return Err(flag);
+                }
+                opts.flags.push(flag);
+            }
+        }
+        Ok(opts)
+    }
+
+    fn has(&self, flag: char) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Returns the last value given for `flag` parsed as a number.
+    fn number(&self, flag: char) -> Result<Option<usize>, &'a str> {
+        match self.values.iter().rev().find(|(f, _)| *f == flag) {
+            Some((_, value)) => parse_number(value).map(Some).ok_or(*value),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+pub(super) fn parse_number(s: &str) -> Option<usize> {
+    if s.starts_with("0x") {
+        usize::from_str_radix(&s[2..], 16).ok()
+    } else {
+        usize::from_str(s).ok()
+    }
+}
+
+/// Parses the options of `cmd`, printing a message if they are invalid.
+fn options<'a>(cmd: &str, args: &[&'a str], known: &str, valued: &str) -> Option<Opts<'a>> {
+    match Opts::parse(args, known, valued) {
+        Ok(opts) => Some(opts),
+        Err(flag) if valued.contains(flag) => {
+            eprintln!("{}: option requires an argument -- '{}'", cmd, flag);
+            None
+        }
+        Err(flag) => {
+            eprintln!("{}: invalid option -- '{}'", cmd, flag);
+            None
+        }
+    }
+}
+
+fn report(cmd: &str, name: &str, e: &io::Error) {
+    match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::InvalidInput => {
+            eprintln!("{}: {}: No such file or directory", cmd, name)
+        }
+        _ => eprintln!("{}: {}: {:?}", cmd, name, e),
+    }
+}
+
+/// Opens `name` for reading. Files written by output redirection are found
+/// before files on the disk.
+fn open<'a>(name: &str, shell: &'a Shell) -> io::Result<Box<dyn io::Read + 'a>> {
+    let path = construct_path(name, &mut shell.cwd.clone());
+    if let Some(buf) = shell.buffers.get(path.to_str().unwrap_or("")) {
+        return Ok(Box::new(buf.as_slice()));
+    }
+    match FILESYSTEM.open(path)?.into_file() {
+        Some(file) => Ok(Box::new(file)),
+        None => Err(io::Error::new(io::ErrorKind::Other, "Is a directory")),
+    }
+}
+
+/// Calls `f` with every file named in `operands` or, if there are none,
+/// with the output of the previous command of the pipeline. Errors are
+/// reported and the remaining files are still processed.
+fn each_input<F>(
+    cmd: &str,
+    operands: &[&str],
+    input: Option<&[u8]>,
+    shell: &Shell,
+    mut f: F,
+) -> io::Result<()>
+where
+    F: FnMut(Option<&str>, &mut dyn io::Read) -> io::Result<()>,
+{
+    if operands.is_empty() {
+        match input {
+            Some(mut input) => {
+                if let Err(e) = f(None, &mut input) {
+                    report(cmd, "-", &e);
+                }
+            }
+            None => eprintln!("{}: missing file operand", cmd),
+        }
+    }
+    for &name in operands {
+        let result = match open(name, shell) {
+            Ok(mut file) => f(Some(name), &mut *file),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            report(cmd, name, &e);
+        }
+    }
+    Ok(())
+}
+
+/// Opens the entry at `name`, relative to the shell's working directory.
+fn lookup(name: &str, shell: &Shell) -> io::Result<Entry<PiVFatHandle>> {
+    FILESYSTEM.open(construct_path(name, &mut shell.cwd.clone()))
+}
+
+fn join(dir: &str, name: &str) -> String {
+    let mut path = String::from(dir);
+    if !path.ends_with('/') {
+        path.push('/');
+    }
+    path.push_str(name);
+    path
+}
+
+fn is_dot(entry: &Entry<PiVFatHandle>) -> bool {
+    entry.name() == "." || entry.name() == ".."
+}
+
+/// Returns `true` for entries `ls` and `find` skip without `-a`.
+fn is_hidden(entry: &Entry<PiVFatHandle>) -> bool {
+    entry.metadata().hidden() || entry.metadata().attributes.is_system()
+}
+
+fn size_of(entry: &Entry<PiVFatHandle>) -> usize {
+    entry.as_file().map(|file| file.size).unwrap_or(0)
+}
+
+/// Returns the entries of `dir` sorted by name.
+fn sorted_entries(dir: &Dir<PiVFatHandle>) -> io::Result<Vec<Entry<PiVFatHandle>>> {
+    let mut entries: Vec<_> = dir.entries()?.collect();
+    entries.sort_by_key(|entry| entry.name().to_ascii_lowercase());
+    Ok(entries)
+}
+
+fn write_timestamp<T: Timestamp>(out: &mut dyn io::Write, ts: T) -> io::Result<()> {
+    write!(
+        out,
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        ts.year(),
+        ts.month(),
+        ts.day(),
+        ts.hour(),
+        ts.minute(),
+        ts.second()
+    )
+}
+
+/// Writes the attribute flags of an entry: directory, read-only, hidden,
+/// system and archive.
+fn write_attributes(out: &mut dyn io::Write, entry: &Entry<PiVFatHandle>) -> io::Result<()> {
+    let metadata: &Metadata = entry.metadata();
+    let flags = [
+        (entry.is_dir(), 'd'),
+        (metadata.read_only(), 'r'),
+        (metadata.hidden(), 'h'),
+        (metadata.attributes.is_system(), 's'),
+        (metadata.attributes.is_archive(), 'a'),
+    ];
+    for &(set, c) in flags.iter() {
+        write!(out, "{}", if set { c } else { '-' })?;
+    }
+    Ok(())
+}
+
+fn write_entry(out: &mut dyn io::Write, entry: &Entry<PiVFatHandle>, long: bool) -> io::Result<()> {
+    if long {
+        write_attributes(out, entry)?;
+        write!(out, " {:>10} ", size_of(entry))?;
+        write_timestamp(out, entry.metadata().modified())?;
+        write!(out, " ")?;
+    }
+    let suffix = if entry.is_dir() && !is_dot(entry) { "/" } else { "" };
+    writeln!(out, "{}{}", entry.name(), suffix)
+}
+
+/// `ls [-alR] [path...]`
+pub fn ls(args: &[&str], shell: &Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    let opts = match options("ls", args, "alR", "") {
+        Some(opts) => opts,
+        None => return Ok(()),
+    };
+    let (all, long, recursive) = (opts.has('a'), opts.has('l'), opts.has('R'));
+    let operands = if opts.operands.is_empty() { vec!["."] } else { opts.operands.clone() };
+
+    fn list(
+        path: &str,
+        dir: &Dir<PiVFatHandle>,
+        (all, long, recursive): (bool, bool, bool),
+        header: bool,
+        out: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        if header {
+            writeln!(out, "{}:", path)?;
+        }
+        let entries = sorted_entries(dir)?;
+        let shown = |entry: &&Entry<PiVFatHandle>| all || !(is_dot(entry) || is_hidden(entry));
+        for entry in entries.iter().filter(shown) {
+            write_entry(out, entry, long)?;
+        }
+        if recursive {
+            for entry in entries.iter().filter(shown).filter(|entry| !is_dot(entry)) {
+                if let Some(subdir) = entry.as_dir() {
+                    writeln!(out)?;
+                    list(&join(path, entry.name()), subdir, (all, long, recursive), true, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let header = operands.len() > 1 || recursive;
+    for (i, name) in operands.iter().enumerate() {
+        match lookup(name, shell) {
+            Ok(Entry::Dir(dir)) => {
+                if i > 0 {
+                    writeln!(out)?;
+                }
+                list(name, &dir, (all, long, recursive), header, out)?
+            }
+            Ok(entry) => write_entry(out, &entry, long)?,
+            Err(e) => report("ls", name, &e),
+        }
+    }
+    Ok(())
+}
+
+/// `cat [-v] [file...]`
+pub fn cat(args: &[&str], input: Option<&[u8]>, shell: &Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    let opts = match options("cat", args, "v", "") {
+        Some(opts) => opts,
+        None => return Ok(()),
+    };
+    let visible = opts.has('v');
+    each_input("cat", &opts.operands, input, shell, |_, file| text::cat(file, visible, out))
+}
+
+/// `stat path...`
+pub fn stat(args: &[&str], shell: &Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    if args.is_empty() {
+        eprintln!("stat: missing operand");
+    }
+    for name in args {
+        let entry = match lookup(name, shell) {
+            Ok(entry) => entry,
+            Err(e) => {
+                report("stat", name, &e);
+                continue;
+            }
+        };
+        let metadata = entry.metadata();
+        writeln!(out, "    File: {}", name)?;
+        writeln!(out, "    Type: {}", if entry.is_dir() { "directory" } else { "regular file" })?;
+        writeln!(out, "    Size: {}", size_of(&entry))?;
+        write!(out, "   Attrs: ")?;
+        write_attributes(out, &entry)?;
+        writeln!(out)?;
+        for &(label, ts) in [
+            ("Created", metadata.created()),
+            ("Modified", metadata.modified()),
+            ("Accessed", metadata.accessed()),
+        ]
+        .iter()
+        {
+            write!(out, "{:>8}: ", label)?;
+            write_timestamp(out, ts)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// `hexdump [-n len] [-s skip] [file...]`, also run as `xxd` (which takes
+/// `-l len`).
+pub fn hexdump(
+    cmd: &str,
+    args: &[&str],
+    input: Option<&[u8]>,
+    shell: &Shell,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let opts = match options(cmd, args, "C", "nls") {
+        Some(opts) => opts,
+        None => return Ok(()),
+    };
+    let (len, skip) = match (opts.number('n'), opts.number('l'), opts.number('s')) {
+        (Ok(n), Ok(l), Ok(s)) => (l.or(n), s.unwrap_or(0)),
+        (Err(bad), _, _) | (_, Err(bad), _) | (_, _, Err(bad)) => {
+            eprintln!("{}: invalid number '{}'", cmd, bad);
+            return Ok(());
+        }
+    };
+    let style = if cmd == "xxd" { DumpStyle::Xxd } else { DumpStyle::Canonical };
+    each_input(cmd, &opts.operands, input, shell, |_, file| {
+        text::hexdump(file, skip, len, style, out)
+    })
+}
+
+fn lines_option<'a>(cmd: &str, args: &[&'a str]) -> Option<(usize, Vec<&'a str>)> {
+    let opts = options(cmd, args, "", "n")?;
+    match opts.number('n') {
+        Ok(n) => Some((n.unwrap_or(DEFAULT_LINES), opts.operands)),
+        Err(bad) => {
+            eprintln!("{}: invalid number of lines: '{}'", cmd, bad);
+            None
+        }
+    }
+}
+
+/// `head [-n lines] [file...]` and `tail [-n lines] [file...]`
+pub fn head_tail(
+    cmd: &str,
+    args: &[&str],
+    input: Option<&[u8]>,
+    shell: &Shell,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let (n, operands) = match lines_option(cmd, args) {
+        Some(parsed) => parsed,
+        None => return Ok(()),
+    };
+    let headers = operands.len() > 1;
+    let mut first = true;
+    each_input(cmd, &operands, input, shell, |name, file| {
+        if headers {
+            if !first {
+                writeln!(out)?;
+            }
+            writeln!(out, "==> {} <==", name.unwrap_or(""))?;
+        }
+        first = false;
+        if cmd == "tail" {
+            text::tail(file, n, out)
+        } else {
+            text::head(file, n, out)
+        }
+    })
+}
+
+/// `wc [-lwc] [file...]`
+pub fn wc(args: &[&str], input: Option<&[u8]>, shell: &Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    let opts = match options("wc", args, "lwc", "") {
+        Some(opts) => opts,
+        None => return Ok(()),
+    };
+    let all = opts.flags.is_empty();
+    let (lines, words, bytes) = (all || opts.has('l'), all || opts.has('w'), all || opts.has('c'));
+    let show = |out: &mut dyn io::Write, counts: &Counts, name: Option<&str>| -> io::Result<()> {
+        if lines {
+            write!(out, "{:>8}", counts.lines)?;
+        }
+        if words {
+            write!(out, "{:>8}", counts.words)?;
+        }
+        if bytes {
+            write!(out, "{:>8}", counts.bytes)?;
+        }
+        match name {
+            Some(name) => writeln!(out, " {}", name),
+            None => writeln!(out),
+        }
+    };
+
+    let mut total = Counts::default();
+    let mut files = 0;
+    each_input("wc", &opts.operands, input, shell, |name, file| {
+        let counts = Counts::of(file)?;
+        total.add(&counts);
+        files += 1;
+        show(out, &counts, name)
+    })?;
+    if files > 1 {
+        show(out, &total, Some("total"))?;
+    }
+    Ok(())
+}
+
+/// Calls `f` with the path and entry of everything below `dir`, parents
+/// before their children.
+fn walk<F>(path: &str, dir: &Dir<PiVFatHandle>, f: &mut F) -> io::Result<()>
+where
+    F: FnMut(&str, &Entry<PiVFatHandle>) -> io::Result<()>,
+{
+    for entry in sorted_entries(dir)? {
+        if is_dot(&entry) {
+            continue;
+        }
+        let child = join(path, entry.name());
+        f(&child, &entry)?;
+        if let Some(subdir) = entry.as_dir() {
+            walk(&child, subdir, f)?;
+        }
+    }
+    Ok(())
+}
+
+/// `find [path...] [-name pattern] [-type f|d]`
+pub fn find(args: &[&str], shell: &Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    let mut paths = Vec::new();
+    let mut pattern = None;
+    let mut kind = None;
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "-name" | "-type" => {
+                let value = match args.next() {
+                    Some(&value) => value,
+                    None => {
+                        eprintln!("find: missing argument to `{}'", arg);
+                        return Ok(());
+                    }
+                };
+                if arg == "-name" {
+                    pattern = Some(value);
+                } else if value == "f" || value == "d" {
+                    kind = Some(value == "d");
+                } else {
+                    eprintln!("find: unknown argument to -type: {}", value);
+                    return Ok(());
+                }
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("find: unknown predicate `{}'", arg);
+                return Ok(());
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(".");
+    }
+
+    let matches = |name: &str, is_dir: bool| {
+        pattern.map_or(true, |pattern| text::glob_match(pattern, name))
+            && kind.map_or(true, |dir| is_dir == dir)
+    };
+    for path in paths {
+        let entry = match lookup(path, shell) {
+            Ok(entry) => entry,
+            Err(e) => {
+                report("find", path, &e);
+                continue;
+            }
+        };
+        // the starting point is matched by the last component of its path
+        let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or(path);
+        if matches(name, entry.is_dir()) {
+            writeln!(out, "{}", path)?;
+        }
+        if let Some(dir) = entry.as_dir() {
+            walk(path, dir, &mut |child: &str, entry: &Entry<PiVFatHandle>| {
+                if matches(entry.name(), entry.is_dir()) {
+                    writeln!(out, "{}", child)?;
+                }
+                Ok(())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the KiB used by `bytes` of file data stored in clusters of
+/// `cluster` bytes.
+fn allocated_kib(bytes: usize, cluster: u64) -> u64 {
+    let clusters = (bytes as u64 + cluster - 1) / cluster;
+    (clusters * cluster + 1023) / 1024
+}
+
+/// `du [-s] [path...]`: the space taken up by the files below each path, in
+/// KiB of whole clusters.
+pub fn du(args: &[&str], shell: &Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    let opts = match options("du", args, "s", "") {
+        Some(opts) => opts,
+        None => return Ok(()),
+    };
+    let summarize = opts.has('s');
+    let cluster = FILESYSTEM.cluster_size();
+
+    fn dir_usage(
+        path: &str,
+        dir: &Dir<PiVFatHandle>,
+        cluster: u64,
+        summarize: bool,
+        out: &mut dyn io::Write,
+    ) -> io::Result<u64> {
+        let mut total = 0;
+        for entry in sorted_entries(dir)? {
+            if is_dot(&entry) {
+                continue;
+            }
+            total += match entry.as_dir() {
+                Some(subdir) => dir_usage(&join(path, entry.name()), subdir, cluster, summarize, out)?,
+                None => allocated_kib(size_of(&entry), cluster),
+            };
+        }
+        if !summarize {
+            writeln!(out, "{}\t{}", total, path)?;
+        }
+        Ok(total)
+    }
+
+    let operands = if opts.operands.is_empty() { vec!["."] } else { opts.operands.clone() };
+    for name in operands {
+        match lookup(name, shell) {
+            Ok(Entry::Dir(dir)) => {
+                let total = dir_usage(name, &dir, cluster, summarize, out)?;
+                if summarize {
+                    writeln!(out, "{}\t{}", total, name)?;
+                }
+            }
+            Ok(entry) => writeln!(out, "{}\t{}", allocated_kib(size_of(&entry), cluster), name)?,
+            Err(e) => report("du", name, &e),
+        }
+    }
+    Ok(())
+}
+
+/// `df`: size and free space of the SD card's file system.
+pub fn df(out: &mut dyn io::Write) -> io::Result<()> {
+    let usage = FILESYSTEM.usage()?;
+    let (size, free) = (usage.total_bytes() / 1024, usage.free_bytes() / 1024);
+    let used = size - free;
+    let percent = if size == 0 { 0 } else { (used * 100 + size - 1) / size };
+    writeln!(out, "{:<12} {:>10} {:>10} {:>10} {:>5} {}", "Filesystem", "1K-blocks", "Used", "Available", "Use%", "Mounted on")?;
+    writeln!(out, "{:<12} {:>10} {:>10} {:>10} {:>4}% {}", "sd0", size, used, free, percent, "/")?;
+    writeln!(out, "cluster size: {} bytes, {} of {} clusters free", usage.cluster_size, usage.free_clusters, usage.total_clusters)
+}
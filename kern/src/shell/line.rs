@@ -0,0 +1,537 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use shim::io;
+
+/// Maximum number of bytes in one line.
+pub const MAX_LINE: usize = 512;
+/// Number of lines kept in the history ring.
+pub const HISTORY_SIZE: usize = 32;
+
+const BELL: u8 = 7;
+
+/// A decoded key press.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Key {
+    Char(u8),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Tab,
+    /// Ctrl-U: kill from the start of the line to the cursor.
+    KillLine,
+    /// Ctrl-W: kill the word before the cursor.
+    KillWord,
+    /// Ctrl-L: clear the screen.
+    Clear,
+}
+
+/// State of the ANSI escape sequence decoder.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// Got `ESC`.
+    Esc,
+    /// Got `ESC [` or `ESC O` and the numeric parameter so far.
+    Csi(u8),
+}
+
+/// Supplies tab-completion candidates to a `LineEditor`.
+pub trait Completer {
+    /// Pushes every possible completion of `word` onto `out`. `first` is
+    /// `true` if `word` is the first word of the line, i.e. a command name.
+    /// A candidate ending in `/` is not followed by a space when completed.
+    fn complete(&self, word: &str, first: bool, out: &mut Vec<String>);
+}
+
+impl Completer for () {
+    fn complete(&self, _word: &str, _first: bool, _out: &mut Vec<String>) {}
+}
+
+/// An interactive line editor for a serial terminal.
+///
+/// Bytes typed by the user are fed one at a time with `feed()`, which echoes
+/// them and redraws the line using ANSI escape sequences. Supported keys are
+/// the left/right arrows, Home/End, Delete, backspace, up/down to browse the
+/// history, Tab to complete, and the Emacs-style Ctrl-A, Ctrl-E, Ctrl-U,
+/// Ctrl-W and Ctrl-L.
+#[derive(Debug)]
+pub struct LineEditor {
+    line: Vec<u8>,
+    cursor: usize,
+    escape: Escape,
+    history: VecDeque<String>,
+    /// Index of the history entry being shown, if browsing the history.
+    browsing: Option<usize>,
+    /// The line being edited before browsing the history started.
+    saved: Vec<u8>,
+}
+
+impl LineEditor {
+    /// Returns a new editor with an empty line and no history.
+    pub fn new() -> LineEditor {
+        LineEditor {
+            line: Vec::new(),
+            cursor: 0,
+            escape: Escape::None,
+            history: VecDeque::new(),
+            browsing: None,
+            saved: Vec::new(),
+        }
+    }
+
+    /// Returns the history, oldest line first.
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(|line| line.as_str())
+    }
+
+    /// Returns the current contents of the line.
+    pub fn line(&self) -> &str {
+        core::str::from_utf8(&self.line).unwrap_or("")
+    }
+
+    /// Returns the cursor position within the line.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Processes one input byte, writing the terminal updates to `out`.
+    /// `prompt` is what precedes the line on screen and is used to redraw it.
+    ///
+    /// Returns `Some(line)` once Enter is pressed; the line is added to the
+    /// history and the editor is reset for the next line. Nothing is written
+    /// for the Enter key itself.
+    pub fn feed<W, C>(
+        &mut self,
+        byte: u8,
+        prompt: &str,
+        out: &mut W,
+        completer: &C,
+    ) -> io::Result<Option<String>>
+    where
+        W: io::Write,
+        C: Completer,
+    {
+        let key = match self.decode(byte) {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+
+        match key {
+            Key::Enter => return Ok(Some(self.finish())),
+            Key::Char(c) => self.insert(out, &[c])?,
+            Key::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                    out.write_all(b"\x08")?;
+                    self.redraw_tail(out)?;
+                }
+            }
+            Key::Delete => {
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                    self.redraw_tail(out)?;
+                }
+            }
+            Key::Left => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    out.write_all(b"\x1b[D")?;
+                }
+            }
+            Key::Right => {
+                if self.cursor < self.line.len() {
+                    self.cursor += 1;
+                    out.write_all(b"\x1b[C")?;
+                }
+            }
+            Key::Home => self.move_to(out, 0)?,
+            Key::End => self.move_to(out, self.line.len())?,
+            Key::KillLine => {
+                let end = self.cursor;
+                self.move_to(out, 0)?;
+                self.line.drain(..end);
+                self.redraw_tail(out)?;
+            }
+            Key::KillWord => {
+                let end = self.cursor;
+                let mut start = end;
+                while start > 0 && self.line[start - 1] == b' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.line[start - 1] != b' ' {
+                    start -= 1;
+                }
+                self.move_to(out, start)?;
+                self.line.drain(start..end);
+                self.redraw_tail(out)?;
+            }
+            Key::Clear => {
+                out.write_all(b"\x1b[2J\x1b[H")?;
+                self.redraw(out, prompt)?;
+            }
+            Key::Up => self.browse_up(out, prompt)?,
+            Key::Down => self.browse_down(out, prompt)?,
+            Key::Tab => self.complete(out, prompt, completer)?,
+        }
+        Ok(None)
+    }
+
+    /// Feeds `byte` to the escape sequence decoder. Returns the key once a
+    /// complete key press has been decoded.
+    fn decode(&mut self, byte: u8) -> Option<Key> {
+        match self.escape {
+            Escape::Esc => {
+                self.escape = match byte {
+                    b'[' | b'O' => Escape::Csi(0),
+                    _ => Escape::None,
+                };
+                None
+            }
+            Escape::Csi(param) => {
+                self.escape = Escape::None;
+                match byte {
+                    b'0'..=b'9' => {
+                        self.escape = Escape::Csi(param.saturating_mul(10).saturating_add(byte - b'0'));
+                        None
+                    }
+                    b'A' => Some(Key::Up),
+                    b'B' => Some(Key::Down),
+                    b'C' => Some(Key::Right),
+                    b'D' => Some(Key::Left),
+                    b'H' => Some(Key::Home),
+                    b'F' => Some(Key::End),
+                    b'~' => match param {
+                        1 | 7 => Some(Key::Home),
+                        3 => Some(Key::Delete),
+                        4 | 8 => Some(Key::End),
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+            Escape::None => match byte {
+                0x1b => {
+                    self.escape = Escape::Esc;
+                    None
+                }
+                b'\r' | b'\n' => Some(Key::Enter),
+                8 | 127 => Some(Key::Backspace),
+                b'\t' => Some(Key::Tab),
+                0x01 => Some(Key::Home),
+                0x05 => Some(Key::End),
+                0x02 => Some(Key::Left),
+                0x06 => Some(Key::Right),
+                0x04 => Some(Key::Delete),
+                0x0c => Some(Key::Clear),
+                0x0e => Some(Key::Down),
+                0x10 => Some(Key::Up),
+                0x15 => Some(Key::KillLine),
+                0x17 => Some(Key::KillWord),
+                32..=126 => Some(Key::Char(byte)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Ends the current line, records it in the history and returns it.
+    fn finish(&mut self) -> String {
+        let line = String::from_utf8(core::mem::replace(&mut self.line, Vec::new()))
+            .unwrap_or_else(|_| String::new());
+        self.cursor = 0;
+        self.escape = Escape::None;
+        self.browsing = None;
+
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && self.history.back().map(|l| l.as_str()) != Some(trimmed) {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(String::from(trimmed));
+        }
+        line
+    }
+
+    /// Inserts `bytes` at the cursor, ringing the bell if the line is full.
+    fn insert<W: io::Write>(&mut self, out: &mut W, bytes: &[u8]) -> io::Result<()> {
+        if self.line.len() + bytes.len() > MAX_LINE {
+            return out.write_all(&[BELL]);
+        }
+        let at = self.cursor;
+        self.line.splice(at..at, bytes.iter().cloned());
+        self.cursor += bytes.len();
+        out.write_all(bytes)?;
+        if self.cursor < self.line.len() {
+            self.redraw_tail(out)?;
+        }
+        Ok(())
+    }
+
+    /// Redraws the line from the cursor to the end and puts the cursor back.
+    fn redraw_tail<W: io::Write>(&mut self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.line[self.cursor..])?;
+        out.write_all(b"\x1b[K")?;
+        let back = self.line.len() - self.cursor;
+        if back > 0 {
+            write!(out, "\x1b[{}D", back)?;
+        }
+        Ok(())
+    }
+
+    /// Redraws the prompt and the whole line, leaving the cursor in place.
+    fn redraw<W: io::Write>(&mut self, out: &mut W, prompt: &str) -> io::Result<()> {
+        out.write_all(b"\r")?;
+        out.write_all(prompt.as_bytes())?;
+        out.write_all(&self.line[..self.cursor])?;
+        self.redraw_tail(out)
+    }
+
+    /// Moves the cursor to position `to` of the line.
+    fn move_to<W: io::Write>(&mut self, out: &mut W, to: usize) -> io::Result<()> {
+        if to < self.cursor {
+            write!(out, "\x1b[{}D", self.cursor - to)?;
+        } else if to > self.cursor {
+            write!(out, "\x1b[{}C", to - self.cursor)?;
+        }
+        self.cursor = to;
+        Ok(())
+    }
+
+    /// Replaces the line with `line` and redraws it with the cursor at the end.
+    fn replace<W: io::Write>(&mut self, out: &mut W, prompt: &str, line: Vec<u8>) -> io::Result<()> {
+        self.line = line;
+        self.cursor = self.line.len();
+        self.redraw(out, prompt)
+    }
+
+    fn browse_up<W: io::Write>(&mut self, out: &mut W, prompt: &str) -> io::Result<()> {
+        let index = match self.browsing {
+            None if self.history.is_empty() => return out.write_all(&[BELL]),
+            None => {
+                self.saved = self.line.clone();
+                self.history.len() - 1
+            }
+            Some(0) => return out.write_all(&[BELL]),
+            Some(i) => i - 1,
+        };
+        self.browsing = Some(index);
+        let line = self.history[index].as_bytes().to_vec();
+        self.replace(out, prompt, line)
+    }
+
+    fn browse_down<W: io::Write>(&mut self, out: &mut W, prompt: &str) -> io::Result<()> {
+        let line = match self.browsing {
+            None => return out.write_all(&[BELL]),
+            Some(i) if i + 1 < self.history.len() => {
+                self.browsing = Some(i + 1);
+                self.history[i + 1].as_bytes().to_vec()
+            }
+            Some(_) => {
+                self.browsing = None;
+                core::mem::replace(&mut self.saved, Vec::new())
+            }
+        };
+        self.replace(out, prompt, line)
+    }
+
+    /// Completes the word before the cursor. A unique candidate is inserted
+    /// in full; otherwise the longest common prefix of the candidates is
+    /// inserted, or the candidates are listed if there is no common prefix to
+    /// add.
+    fn complete<W, C>(&mut self, out: &mut W, prompt: &str, completer: &C) -> io::Result<()>
+    where
+        W: io::Write,
+        C: Completer,
+    {
+        let start = self.line[..self.cursor]
+            .iter()
+            .rposition(|&b| b == b' ')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let first = self.line[..start].iter().all(|&b| b == b' ');
+        let word = match core::str::from_utf8(&self.line[start..self.cursor]) {
+            Ok(word) => String::from(word),
+            Err(_) => return Ok(()),
+        };
+
+        let mut candidates = Vec::new();
+        completer.complete(&word, first, &mut candidates);
+        candidates.retain(|c| c.starts_with(word.as_str()));
+        candidates.sort();
+        candidates.dedup();
+
+        match candidates.len() {
+            0 => out.write_all(&[BELL]),
+            1 => {
+                let mut rest = candidates[0].as_bytes()[word.len()..].to_vec();
+                if !candidates[0].ends_with('/') {
+                    rest.push(b' ');
+                }
+                self.insert(out, &rest)
+            }
+            _ => {
+                let common = common_prefix(&candidates);
+                if common.len() > word.len() {
+                    let rest = common.as_bytes()[word.len()..].to_vec();
+                    return self.insert(out, &rest);
+                }
+                out.write_all(b"\r\n")?;
+                for candidate in &candidates {
+                    out.write_all(candidate.as_bytes())?;
+                    out.write_all(b"  ")?;
+                }
+                out.write_all(b"\r\n")?;
+                self.redraw(out, prompt)
+            }
+        }
+    }
+}
+
+/// Returns the longest prefix shared by all of `strings`.
+fn common_prefix(strings: &[String]) -> &str {
+    let first = match strings.first() {
+        Some(first) => first.as_str(),
+        None => return "",
+    };
+    let mut len = first.len();
+    for s in &strings[1..] {
+        len = first
+            .bytes()
+            .zip(s.bytes())
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count();
+    }
+    &first[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    struct Words(&'static [&'static str]);
+
+    impl Completer for Words {
+        fn complete(&self, _word: &str, _first: bool, out: &mut Vec<String>) {
+            out.extend(self.0.iter().map(|w| String::from(*w)));
+        }
+    }
+
+    fn type_keys<C: Completer>(editor: &mut LineEditor, keys: &[u8], completer: &C) -> (Option<String>, Vec<u8>) {
+        let mut out = vec![];
+        let mut line = None;
+        for &key in keys {
+            if let Some(l) = editor.feed(key, "$ ", &mut out, completer).unwrap() {
+                line = Some(l);
+            }
+        }
+        (line, out)
+    }
+
+    fn enter(editor: &mut LineEditor, keys: &[u8]) -> String {
+        let mut keys = keys.to_vec();
+        keys.push(b'\r');
+        type_keys(editor, &keys, &()).0.unwrap()
+    }
+
+    #[test]
+    fn plain_typing() {
+        let mut editor = LineEditor::new();
+        let (line, out) = type_keys(&mut editor, b"ls -a\r", &());
+        assert_eq!(line.as_ref().map(|l| l.as_str()), Some("ls -a"));
+        assert_eq!(out, b"ls -a");
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn insert_and_delete_in_the_middle() {
+        let mut editor = LineEditor::new();
+        // "cat fle", left x2, insert 'i' -> "cat file"
+        assert_eq!(enter(&mut editor, b"cat fle\x1b[D\x1b[Di"), "cat file");
+        // backspace in the middle
+        assert_eq!(enter(&mut editor, b"echo abcd\x1b[D\x1b[D\x7f"), "echo acd");
+        // delete key under the cursor
+        assert_eq!(enter(&mut editor, b"echo abcd\x1b[D\x1b[D\x1b[3~"), "echo abd");
+    }
+
+    #[test]
+    fn home_end_and_kill() {
+        let mut editor = LineEditor::new();
+        assert_eq!(enter(&mut editor, b"bc\x01a\x05d"), "abcd");
+        assert_eq!(enter(&mut editor, b"echo\x1b[Hx\x1b[F!"), "xecho!");
+        assert_eq!(enter(&mut editor, b"junk here\x15ls"), "ls");
+        assert_eq!(enter(&mut editor, b"cat one two  \x17three"), "cat one three");
+        assert_eq!(enter(&mut editor, b"abc def\x1b[D\x1b[D\x15"), "ef");
+    }
+
+    #[test]
+    fn history_ring() {
+        let mut editor = LineEditor::new();
+        enter(&mut editor, b"first");
+        enter(&mut editor, b"second");
+        enter(&mut editor, b"second");
+        enter(&mut editor, b"   ");
+        assert_eq!(editor.history().collect::<Vec<_>>(), vec!["first", "second"]);
+
+        assert_eq!(enter(&mut editor, b"\x1b[A"), "second");
+        assert_eq!(enter(&mut editor, b"\x1b[A\x1b[A\x1b[A"), "first");
+        // down past the newest entry restores the line being typed
+        assert_eq!(enter(&mut editor, b"new\x1b[A\x1b[B"), "new");
+        assert_eq!(enter(&mut editor, b"\x1b[A\x1b[A\x1b[B!"), "new!");
+
+        for i in 0..HISTORY_SIZE + 5 {
+            enter(&mut editor, alloc::format!("cmd {}", i).as_bytes());
+        }
+        assert_eq!(editor.history().count(), HISTORY_SIZE);
+        assert_eq!(editor.history().next(), Some("cmd 5"));
+    }
+
+    #[test]
+    fn line_length_is_bounded() {
+        let mut editor = LineEditor::new();
+        let keys = vec![b'x'; MAX_LINE + 10];
+        let (_, out) = type_keys(&mut editor, &keys, &());
+        assert_eq!(editor.line().len(), MAX_LINE);
+        assert_eq!(out.iter().filter(|&&b| b == BELL).count(), 10);
+    }
+
+    #[test]
+    fn tab_completion() {
+        let mut editor = LineEditor::new();
+        let commands = Words(&["cat", "cd", "echo"]);
+        let (line, _) = type_keys(&mut editor, b"ec\tfoo\r", &commands);
+        assert_eq!(line.as_ref().map(|l| l.as_str()), Some("echo foo"));
+
+        // ambiguous: nothing is inserted and the candidates are listed
+        let (_, out) = type_keys(&mut editor, b"c\t", &commands);
+        assert_eq!(editor.line(), "c");
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("cat  cd"));
+        type_keys(&mut editor, b"\r", &());
+
+        // common prefix is inserted; directories get no trailing space
+        let paths = Words(&["music/", "musings.txt"]);
+        let (line, _) = type_keys(&mut editor, b"ls m\t\r", &paths);
+        assert_eq!(line.as_ref().map(|l| l.as_str()), Some("ls musi"));
+        let paths = Words(&["music/"]);
+        let (line, _) = type_keys(&mut editor, b"ls m\tx\r", &paths);
+        assert_eq!(line.as_ref().map(|l| l.as_str()), Some("ls music/x"));
+    }
+
+    #[test]
+    fn common_prefix_of_candidates() {
+        let words = vec![String::from("abcd"), String::from("abce"), String::from("abx")];
+        assert_eq!(common_prefix(&words), "ab");
+        assert_eq!(common_prefix(&words[..1]), "abcd");
+        assert_eq!(common_prefix(&[]), "");
+    }
+}
@@ -0,0 +1,557 @@
+//! The shell over TCP, for machines whose serial console is out of reach.
+//! The service is enabled with `netsh=<port>` or `netsh=<port>,<password>`
+//! on the kernel command line; each connection gets its own shell session,
+//! once the password has been typed if one is set.
+//!
+//! The service accepts connections in a kernel process of its own, and each
+//! session runs in a kernel process of its own, so a command that takes a
+//! while in one session does not hold up the others. These processes poll
+//! their sockets and sleep in between while the USB timer polls the ethernet
+//! interface on core 0. They run with interrupts masked like the console's
+//! shell, so a built-in that waits without sleeping (`ping`) keeps a core to
+//! itself until it is done. The output of a session is buffered on its
+//! connection; a client that takes none of it for `NETSH_SEND_TIMEOUT` is
+//! disconnected.
+//!
+//! The service speaks enough telnet for `telnet` clients: the client is told
+//! that the server echoes and that characters are sent as typed, and the
+//! other commands it sends are dropped.
+//!
+//! Programs cannot be started from a session, only built-ins. The `read` and
+//! `write` system calls of a program always go to the programs' terminal of
+//! the console and there is no per-process standard input or output to
+//! point at a connection instead, so a program started remotely would print
+//! on a screen nobody in front of the session sees.
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use kernel_api::{OsError, SocketType};
+use pi::atags::Atags;
+use pi::timer;
+use shim::io::{self, Write};
+use smoltcp::wire::IpEndpoint;
+
+use crate::mutex::Mutex;
+use crate::net::socket::Socket;
+use crate::param::{
+    ETHERNET_POLL_INTERVAL, NETSH_OUTPUT_MAX, NETSH_PASSWORD_MAX, NETSH_SEND_TIMEOUT, NETSH_SESSIONS,
+};
+use crate::process::{exit, sleep, Process};
+use crate::{ETHERNET, NETSH, SCHEDULER};
+
+use super::{Session, Terminal};
+
+/// The prefix of the prompt of the sessions.
+const PREFIX: &str = "> ";
+
+/// Telnet "interpret as command" byte, which starts every telnet command.
+const IAC: u8 = 255;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const OPT_ECHO: u8 = 1;
+const OPT_SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// The configuration of the service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub port: u16,
+    pub password: Option<String>,
+}
+
+/// Returns the configuration of the last valid `netsh=` option of
+/// `cmdline`, if any.
+fn parse_cmdline(cmdline: &str) -> Option<Options> {
+    let mut options = None;
+    for arg in cmdline.split_whitespace() {
+        if !arg.starts_with("netsh=") {
+            continue;
+        }
+        let mut parts = arg["netsh=".len()..].splitn(2, ',');
+        let port = match parts.next().unwrap().parse() {
+            Ok(0) | Err(_) => continue,
+            Ok(port) => port,
+        };
+        let password = parts.next().filter(|password| !password.is_empty());
+        options = Some(Options { port, password: password.map(String::from) });
+    }
+    options
+}
+
+/// Where the receiver of a connection is in the telnet stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Telnet {
+    Data,
+    /// After a carriage return, which a client follows with `\n` or `\0`.
+    Cr,
+    /// After `IAC`.
+    Command,
+    /// After `IAC` and an option negotiation verb.
+    Negotiation,
+    /// In a subnegotiation, which ends with `IAC SE`.
+    Sub,
+    /// After `IAC` in a subnegotiation.
+    SubIac,
+}
+
+impl Telnet {
+    /// Returns the byte typed by the user that `byte` carries, if any.
+    /// Telnet commands are dropped, `IAC IAC` stands for 255 and the byte
+    /// that follows a carriage return is dropped.
+    fn filter(&mut self, byte: u8) -> Option<u8> {
+        let (next, typed) = match (*self, byte) {
+            (Telnet::Data, IAC) => (Telnet::Command, None),
+            (Telnet::Data, b'\r') => (Telnet::Cr, Some(byte)),
+            (Telnet::Data, _) => (Telnet::Data, Some(byte)),
+            (Telnet::Cr, b'\n') | (Telnet::Cr, 0) => (Telnet::Data, None),
+            (Telnet::Cr, _) => {
+                *self = Telnet::Data;
+                return self.filter(byte);
+            }
+            (Telnet::Command, IAC) => (Telnet::Data, Some(IAC)),
+            (Telnet::Command, SB) => (Telnet::Sub, None),
+            (Telnet::Command, 251..=254) => (Telnet::Negotiation, None),
+            (Telnet::Command, _) | (Telnet::Negotiation, _) => (Telnet::Data, None),
+            (Telnet::Sub, IAC) => (Telnet::SubIac, None),
+            (Telnet::Sub, _) => (Telnet::Sub, None),
+            (Telnet::SubIac, SE) => (Telnet::Data, None),
+            (Telnet::SubIac, _) => (Telnet::Sub, None),
+        };
+        *self = next;
+        typed
+    }
+}
+
+/// The terminal of a session: a connected TCP socket of the ethernet driver.
+struct NetTerminal {
+    socket: Socket,
+    telnet: Telnet,
+    /// Bytes typed by the user and not read yet.
+    input: VecDeque<u8>,
+    /// Telnet data written by the session and not taken by the socket yet.
+    output: VecDeque<u8>,
+    /// Since when the socket has taken none of `output`, while it is not
+    /// empty.
+    stalled_since: Option<Duration>,
+    /// Whether the connection is closed, by the client or for stalling.
+    closed: bool,
+}
+
+impl NetTerminal {
+    fn new(socket: Socket) -> NetTerminal {
+        NetTerminal {
+            socket,
+            telnet: Telnet::Data,
+            input: VecDeque::new(),
+            output: VecDeque::new(),
+            stalled_since: None,
+            closed: false,
+        }
+    }
+
+    /// Moves the bytes received on the socket to `input` without waiting.
+    fn receive(&mut self) {
+        let mut buf = [0; 64];
+        while !self.closed {
+            let socket = self.socket;
+            match ETHERNET.critical(|driver| driver.recv(socket, &mut buf)) {
+                Ok(0) => self.closed = true,
+                Ok(received) => {
+                    let telnet = &mut self.telnet;
+                    let typed = buf[..received].iter().filter_map(|&byte| telnet.filter(byte));
+                    self.input.extend(typed);
+                }
+                Err(OsError::IoErrorWouldBlock) => break,
+                Err(_) => self.closed = true,
+            }
+        }
+    }
+
+    /// Returns the next byte typed by the user among the bytes received so
+    /// far, if any.
+    fn next_byte(&mut self) -> Option<u8> {
+        self.receive();
+        self.input.pop_front()
+    }
+
+    /// Moves as much of `output` to the socket as its send buffer takes,
+    /// without waiting. Closes the connection once the socket has taken
+    /// none of it for `NETSH_SEND_TIMEOUT`.
+    fn send(&mut self) {
+        while !self.output.is_empty() && !self.closed {
+            let socket = self.socket;
+            let data = self.output.as_slices().0;
+            match ETHERNET.critical(|driver| driver.send(socket, data)) {
+                Ok(0) | Err(OsError::IoErrorWouldBlock) => break,
+                Ok(sent) => {
+                    self.output.drain(..sent);
+                    self.stalled_since = None;
+                }
+                Err(_) => self.closed = true,
+            }
+        }
+        if self.output.is_empty() || self.closed {
+            self.stalled_since = None;
+            return;
+        }
+
+        let now = timer::current_time();
+        if now - *self.stalled_since.get_or_insert(now) >= NETSH_SEND_TIMEOUT {
+            warn!("netsh: closing a connection whose client stopped reading");
+            self.closed = true;
+            self.output.clear();
+        }
+    }
+
+    /// Queues `buf` as is and sends what the socket takes. Waits while more
+    /// than `NETSH_OUTPUT_MAX` bytes are left queued.
+    fn send_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.output.extend(buf);
+        self.send();
+        while self.output.len() > NETSH_OUTPUT_MAX && !self.closed {
+            sleep(ETHERNET_POLL_INTERVAL);
+            self.send();
+        }
+        if self.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "connection closed"));
+        }
+        Ok(())
+    }
+}
+
+impl io::Read for NetTerminal {
+    /// Reads the bytes typed by the user, waiting for at least one. Returns 0
+    /// once the connection is closed.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.input.is_empty() && !self.closed {
+            self.send();
+            self.receive();
+            if self.input.is_empty() {
+                sleep(ETHERNET_POLL_INTERVAL);
+            }
+        }
+        let mut read = 0;
+        while read < buf.len() {
+            match self.input.pop_front() {
+                Some(byte) => buf[read] = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+}
+
+impl io::Write for NetTerminal {
+    /// Sends `buf` to the client as telnet data: `\n` becomes `\r\n` and 255
+    /// is escaped.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            match byte {
+                b'\n' => data.extend_from_slice(b"\r\n"),
+                IAC => data.extend_from_slice(&[IAC, IAC]),
+                _ => data.push(byte),
+            }
+        }
+        self.send_all(&data)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Terminal for NetTerminal {
+    fn try_read_byte(&mut self) -> Option<u8> {
+        self.send();
+        self.next_byte()
+    }
+
+    fn runs_programs(&self) -> bool {
+        false
+    }
+}
+
+/// What a connection is waiting for.
+enum Stage {
+    /// The password, typed so far.
+    Password(String),
+    Shell(Session),
+}
+
+/// A connection to the service.
+struct Connection {
+    term: NetTerminal,
+    stage: Stage,
+}
+
+impl Connection {
+    /// Sets up the client's terminal and asks for `password`, or starts the
+    /// session if there is none.
+    fn open(socket: Socket, password: Option<&str>) -> io::Result<Connection> {
+        let mut term = NetTerminal::new(socket);
+        term.send_all(&[IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SUPPRESS_GO_AHEAD])?;
+        let stage = match password {
+            Some(_) => {
+                write!(term, "Password: ")?;
+                Stage::Password(String::new())
+            }
+            None => {
+                let mut session = Session::new(PREFIX);
+                session.start(&mut term)?;
+                Stage::Shell(session)
+            }
+        };
+        Ok(Connection { term, stage })
+    }
+
+    /// Feeds the bytes typed by the user so far. Returns `false` once the
+    /// connection is to be closed.
+    fn poll(&mut self, password: Option<&str>) -> bool {
+        self.term.send();
+        while let Some(byte) = self.term.next_byte() {
+            let open = match self.stage {
+                Stage::Shell(ref mut session) => session.feed(byte, &mut self.term).unwrap_or(false),
+                Stage::Password(ref mut typed) => match byte {
+                    b'\r' | b'\n' => {
+                        let correct = password == Some(typed.as_str());
+                        self.login(correct).is_ok() && correct
+                    }
+                    0x08 | 0x7f => {
+                        typed.pop();
+                        true
+                    }
+                    _ if typed.len() >= NETSH_PASSWORD_MAX => false,
+                    _ => {
+                        typed.push(byte as char);
+                        true
+                    }
+                },
+            };
+            if !open {
+                return false;
+            }
+        }
+        !self.term.closed
+    }
+
+    /// Starts the session once the password is `correct`.
+    fn login(&mut self, correct: bool) -> io::Result<()> {
+        writeln!(self.term)?;
+        if !correct {
+            return writeln!(self.term, "Login incorrect");
+        }
+        let mut session = Session::new(PREFIX);
+        session.start(&mut self.term)?;
+        self.stage = Stage::Shell(session);
+        Ok(())
+    }
+}
+
+/// The state of the running service.
+struct Service {
+    listener: Socket,
+    password: Option<String>,
+}
+
+impl Service {
+    /// Accepts the pending connections, each handed to a session process of
+    /// its own.
+    fn poll(&mut self) {
+        loop {
+            let listener = self.listener;
+            match ETHERNET.critical(|driver| driver.accept(listener)) {
+                Ok((listener, socket, remote)) => {
+                    self.listener = listener;
+                    self.accept(socket, remote);
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Starts a session on a connection accepted from `remote`, unless there
+    /// are too many already.
+    fn accept(&mut self, socket: Socket, remote: IpEndpoint) {
+        if NETSH.sessions.load(Ordering::SeqCst) >= NETSH_SESSIONS {
+            warn!("netsh: refusing {}: too many sessions", remote);
+            NetTerminal::new(socket).write_all(b"too many sessions\n").ok();
+            ETHERNET.critical(|driver| driver.close(socket));
+            return;
+        }
+        let password = self.password.as_ref().map(|password| password.as_str());
+        let connection = match Connection::open(socket, password) {
+            Ok(connection) => connection,
+            Err(_) => {
+                ETHERNET.critical(|driver| driver.close(socket));
+                return;
+            }
+        };
+
+        NETSH.sessions.fetch_add(1, Ordering::SeqCst);
+        NETSH.pending.lock().push((connection, self.password.clone()));
+        if Process::kernel(session).ok().and_then(|process| SCHEDULER.add(process)).is_some() {
+            info!("netsh: session from {}", remote);
+            return;
+        }
+        warn!("netsh: cannot start a session process for {}", remote);
+        // whichever connection is on top has no process left to take it
+        if let Some((connection, _)) = NETSH.pending.lock().pop() {
+            NETSH.close(connection);
+        }
+    }
+}
+
+/// The shell service, started by `initialize()` once the ethernet driver is.
+/// It is handed over to its process when that process starts.
+pub struct NetShell {
+    service: Mutex<Option<Service>>,
+    /// Connections accepted by the service and not taken by their session
+    /// process yet.
+    pending: Mutex<Vec<(Connection, Option<String>)>>,
+    /// The number of open sessions, pending ones included.
+    sessions: AtomicUsize,
+}
+
+impl NetShell {
+    pub const fn uninitialized() -> NetShell {
+        NetShell {
+            service: Mutex::new(None),
+            pending: Mutex::new(Vec::new()),
+            sessions: AtomicUsize::new(0),
+        }
+    }
+
+    /// Listens on the port given with `netsh=` on the ATAG command line and
+    /// adds the process running the service to the scheduler. The service
+    /// stays off without the option.
+    pub fn initialize(&self) {
+        let options = match Atags::get().find_map(|atag| atag.cmd()).and_then(parse_cmdline) {
+            Some(options) => options,
+            None => return,
+        };
+        let listener = ETHERNET.critical(|driver| {
+            let socket = driver.create(SocketType::Tcp);
+            match driver.listen(socket, options.port) {
+                Ok(()) => Ok(socket),
+                Err(e) => {
+                    driver.close(socket);
+                    Err(e)
+                }
+            }
+        });
+        match listener {
+            Ok(listener) => {
+                info!("netsh: listening on port {}", options.port);
+                let service = Service { listener, password: options.password };
+                *self.service.lock() = Some(service);
+            }
+            Err(e) => {
+                warn!("netsh: cannot listen on port {}: {:?}", options.port, e);
+                return;
+            }
+        }
+        let started = Process::kernel(serve).ok().and_then(|process| SCHEDULER.add(process));
+        if started.is_none() {
+            warn!("netsh: cannot start the service process");
+        }
+    }
+
+    /// Closes the socket of `connection` and ends its session.
+    fn close(&self, connection: Connection) {
+        ETHERNET.critical(|driver| driver.close(connection.term.socket));
+        self.sessions.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The body of the service process: accepts the pending connections, then
+/// sleeps until the next poll of the interface. The service is owned by the
+/// process so that no lock is held while it sleeps.
+extern "C" fn serve() -> ! {
+    let mut service = NETSH.service.lock().take().expect("netsh: service not initialized");
+    loop {
+        service.poll();
+        sleep(ETHERNET_POLL_INTERVAL);
+    }
+}
+
+/// The body of a session process: takes a connection accepted by the service
+/// and runs the lines typed on it until it is closed, sleeping until the next
+/// poll of the interface in between.
+extern "C" fn session() -> ! {
+    let (mut connection, password) = NETSH.pending.lock().pop().expect("netsh: no pending connection");
+    let password = password.as_ref().map(|password| password.as_str());
+    while connection.poll(password) {
+        sleep(ETHERNET_POLL_INTERVAL);
+    }
+    NETSH.close(connection);
+    exit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cmdline() {
+        assert_eq!(parse_cmdline("console=ttyS0"), None);
+        assert_eq!(
+            parse_cmdline("netsh=23"),
+            Some(Options { port: 23, password: None })
+        );
+        assert_eq!(
+            parse_cmdline("ip=dhcp netsh=2323,rack42 quiet"),
+            Some(Options { port: 2323, password: Some(String::from("rack42")) })
+        );
+        assert_eq!(
+            parse_cmdline("netsh=23,secret netsh=24"),
+            Some(Options { port: 24, password: None })
+        );
+        assert_eq!(
+            parse_cmdline("netsh=24 netsh=0 netsh=telnet netsh=70000"),
+            Some(Options { port: 24, password: None })
+        );
+        assert_eq!(parse_cmdline("netsh=23,").unwrap().password, None);
+    }
+
+    fn filter(telnet: &mut Telnet, bytes: &[u8]) -> Vec<u8> {
+        bytes.iter().filter_map(|&byte| telnet.filter(byte)).collect()
+    }
+
+    #[test]
+    fn telnet_line_endings() {
+        let mut telnet = Telnet::Data;
+        assert_eq!(filter(&mut telnet, b"ls\r\n"), b"ls\r");
+        assert_eq!(filter(&mut telnet, b"pwd\r\0"), b"pwd\r");
+        assert_eq!(filter(&mut telnet, b"a\rb"), b"a\rb");
+        assert_eq!(filter(&mut telnet, b"cd\r"), b"cd\r");
+        assert_eq!(filter(&mut telnet, b"\nx"), b"x");
+        assert_eq!(telnet, Telnet::Data);
+    }
+
+    #[test]
+    fn telnet_commands() {
+        let mut telnet = Telnet::Data;
+        // DO ECHO, WONT LINEMODE, then an escaped 255 and a bare command (NOP).
+        let bytes = [b'a', IAC, 253, 1, IAC, 252, 34, IAC, IAC, IAC, 241, b'b'];
+        assert_eq!(filter(&mut telnet, &bytes), [b'a', IAC, b'b']);
+
+        // A window size subnegotiation holding an escaped 255.
+        let bytes = [IAC, SB, 31, 0, 80, IAC, IAC, 24, IAC, SE, b'c'];
+        assert_eq!(filter(&mut telnet, &bytes), b"c");
+
+        // A command right after a carriage return.
+        assert_eq!(filter(&mut telnet, &[b'\r', IAC, 241, b'\n']), b"\r\n");
+        assert_eq!(telnet, Telnet::Data);
+    }
+}
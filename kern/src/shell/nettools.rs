@@ -0,0 +1,428 @@
+//! Network built-ins: `ping`, `ifconfig` and `netstat`.
+//!
+//! `ping` waits for its replies by polling the ethernet interface itself, as
+//! it may run from the console's shell with interrupts masked on core 0,
+//! where the USB timer that would otherwise poll the interface cannot fire.
+//! Typing Ctrl-C on the terminal stops it.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use core::time::Duration;
+
+use kernel_api::OsError;
+use pi::timer::{self, spin_sleep};
+use shim::io;
+use smoltcp::wire::{EthernetAddress, IpAddress, IpEndpoint, Ipv4Address, Ipv6Address, Ipv6Cidr};
+
+use crate::net::{self, dhcp::IpConfig, dns::Address, phy::Statistics};
+use crate::net::icmp::{Echo, ECHO_DATA_LEN};
+use crate::net::socket::{SocketEntry, SocketKind};
+use crate::param::{ETHERNET_POLL_INTERVAL, PING_COUNT, PING_COUNT_MAX, PING_INTERVAL};
+use crate::{ETHERNET, USB};
+
+use super::{eprintln, Output};
+
+/// A duration in milliseconds, with three decimals.
+struct Millis(Duration);
+
+impl fmt::Display for Millis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let micros = self.0.as_micros();
+        write!(f, "{}.{:03}", micros / 1000, micros % 1000)
+    }
+}
+
+/// Counts of the echo requests sent and replies received by `ping`, with
+/// their round-trip times.
+#[derive(Debug, Default)]
+struct PingStats {
+    sent: u32,
+    received: u32,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+}
+
+impl PingStats {
+    fn record(&mut self, rtt: Duration) {
+        if self.received == 0 || rtt < self.min {
+            self.min = rtt;
+        }
+        if rtt > self.max {
+            self.max = rtt;
+        }
+        self.total += rtt;
+        self.received += 1;
+    }
+
+    /// Writes the summary printed once `ping` is done with `host`.
+    fn summary(&self, host: &str, out: &mut dyn io::Write) -> io::Result<()> {
+        let loss = match self.sent {
+            0 => 0,
+            sent => (sent - self.received.min(sent)) * 100 / sent,
+        };
+        writeln!(out, "--- {} ping statistics ---", host)?;
+        writeln!(
+            out,
+            "{} packets transmitted, {} received, {}% packet loss",
+            self.sent, self.received, loss
+        )?;
+        if self.received > 0 {
+            let avg = self.total / self.received;
+            writeln!(
+                out,
+                "round-trip min/avg/max = {}/{}/{} ms",
+                Millis(self.min),
+                Millis(avg),
+                Millis(self.max)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Resolves `host`, an IPv4 address or a name, to an IPv4 address.
+fn resolve(host: &str) -> Result<Ipv4Address, OsError> {
+    loop {
+        match ETHERNET.critical(|driver| driver.resolve(host, net::now())) {
+            Ok(addresses) => {
+                return addresses
+                    .iter()
+                    .filter_map(|address| match *address {
+                        Address::V4(address) => Some(address),
+                        _ => None,
+                    })
+                    .next()
+                    .ok_or(OsError::NoEntry)
+            }
+            Err(OsError::IoErrorWouldBlock) => {
+                ETHERNET.poll(net::now());
+                spin_sleep(ETHERNET_POLL_INTERVAL);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// `ping [-c count] <host>`: sends `count` echo requests to `host`, one per
+/// `PING_INTERVAL`, and prints the replies and their round-trip times. At
+/// most `PING_COUNT_MAX` requests are sent; Ctrl-C stops early.
+pub fn ping(args: &[&str], out: &mut Output) -> io::Result<()> {
+    let (count, host) = match args {
+        [host] => (Some(PING_COUNT), *host),
+        ["-c", count, host] => {
+            let count = u16::from_str(count).ok();
+            (count.filter(|&count| count > 0 && count <= PING_COUNT_MAX), *host)
+        }
+        _ => {
+            eprintln!("usage: ping [-c count] <host>");
+            return Ok(());
+        }
+    };
+    let count = match count {
+        Some(count) => count,
+        None => {
+            eprintln!("ping: count must be between 1 and {}", PING_COUNT_MAX);
+            return Ok(());
+        }
+    };
+    if !ETHERNET.is_initialized() {
+        eprintln!("ping: no network interface");
+        return Ok(());
+    }
+    let dst = match resolve(host) {
+        Ok(dst) => dst,
+        Err(e) => {
+            eprintln!("ping: {}: {:?}", host, e);
+            return Ok(());
+        }
+    };
+
+    let ident = timer::current_time().as_micros() as u16;
+    let echo = match ETHERNET.critical(|driver| driver.open_echo(ident)) {
+        Ok(echo) => echo,
+        Err(e) => {
+            eprintln!("ping: {:?}", e);
+            return Ok(());
+        }
+    };
+    writeln!(out, "PING {} ({}): {} data bytes", host, dst, ECHO_DATA_LEN)?;
+    let mut stats = PingStats::default();
+    let result = ping_loop(echo, dst, count, &mut stats, out);
+    ETHERNET.critical(|driver| driver.close_echo(echo));
+    result?;
+    stats.summary(host, out)
+}
+
+/// Sends the echo requests of `ping` and prints the replies, until Ctrl-C is
+/// typed.
+fn ping_loop(
+    echo: Echo,
+    dst: Ipv4Address,
+    count: u16,
+    stats: &mut PingStats,
+    out: &mut Output,
+) -> io::Result<()> {
+    for seq in 0..count {
+        let sent = timer::current_time();
+        match ETHERNET.critical(|driver| driver.send_echo(echo, dst, seq, sent)) {
+            Ok(()) => stats.sent += 1,
+            Err(e) => eprintln!("ping: icmp_seq={}: {:?}", seq, e),
+        }
+
+        let deadline = sent + PING_INTERVAL;
+        while timer::current_time() < deadline {
+            ETHERNET.poll(net::now());
+            while let Ok(reply) = ETHERNET.critical(|driver| driver.recv_echo(echo)) {
+                let rtt = timer::current_time().checked_sub(reply.sent).unwrap_or_default();
+                writeln!(
+                    out,
+                    "{} bytes from {}: icmp_seq={} time={} ms",
+                    reply.len,
+                    reply.from,
+                    reply.seq,
+                    Millis(rtt)
+                )?;
+                stats.record(rtt);
+            }
+            if out.interrupted() {
+                return writeln!(out, "^C");
+            }
+            spin_sleep(ETHERNET_POLL_INTERVAL);
+        }
+    }
+    Ok(())
+}
+
+/// What `ifconfig` shows of the interface.
+struct Interface {
+    mac: EthernetAddress,
+    link_up: bool,
+    config: Option<IpConfig>,
+    dhcp: bool,
+    ipv6_addrs: Vec<Ipv6Cidr>,
+    ipv6_router: Option<Ipv6Address>,
+    statistics: Statistics,
+}
+
+/// Writes the description of the interface printed by `ifconfig`.
+fn write_interface(out: &mut dyn io::Write, iface: &Interface) -> io::Result<()> {
+    let (config, dhcp, statistics) = (iface.config.as_ref(), iface.dhcp, iface.statistics);
+    writeln!(out, "eth0      link {}", if iface.link_up { "up" } else { "down" })?;
+    writeln!(out, "          ether {}", iface.mac)?;
+    match config {
+        Some(config) => {
+            write!(out, "          inet {}", config.address)?;
+            if let Some(gateway) = config.gateway {
+                write!(out, "  gateway {}", gateway)?;
+            }
+            writeln!(out, "{}", if dhcp { "  (dhcp)" } else { "" })?;
+            if !config.dns_servers.is_empty() {
+                write!(out, "          dns")?;
+                for server in config.dns_servers.iter() {
+                    write!(out, " {}", server)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        None if dhcp => writeln!(out, "          inet unconfigured (waiting for dhcp)")?,
+        None => writeln!(out, "          inet unconfigured")?,
+    }
+    for addr in iface.ipv6_addrs.iter() {
+        write!(out, "          inet6 {}", addr)?;
+        match iface.ipv6_router {
+            Some(router) if !addr.address().is_link_local() => writeln!(out, "  router {}", router)?,
+            _ => writeln!(out)?,
+        }
+    }
+    writeln!(out, "          RX packets {}  bytes {}", statistics.rx_frames, statistics.rx_bytes)?;
+    writeln!(out, "          TX packets {}  bytes {}", statistics.tx_frames, statistics.tx_bytes)
+}
+
+/// `ifconfig`: prints the MAC address, the IPv4 and IPv6 configuration, the
+/// link state and the frame counters of the ethernet interface.
+pub fn ifconfig(args: &[&str], out: &mut dyn io::Write) -> io::Result<()> {
+    if !args.is_empty() {
+        eprintln!("usage: ifconfig");
+        return Ok(());
+    }
+    if !ETHERNET.is_initialized() {
+        eprintln!("ifconfig: no network interface");
+        return Ok(());
+    }
+    let link_up = USB.is_eth_link_up();
+    let iface = ETHERNET.critical(|driver| Interface {
+        mac: driver.ethernet_addr(),
+        link_up,
+        config: driver.config().cloned(),
+        dhcp: driver.uses_dhcp(),
+        ipv6_addrs: driver.ipv6_addrs(),
+        ipv6_router: driver.ipv6_router(),
+        statistics: driver.statistics(),
+    });
+    write_interface(out, &iface)
+}
+
+/// Formats `endpoint` as `netstat` shows it, with `*` for an unspecified
+/// address or port and IPv6 addresses in brackets.
+fn endpoint(endpoint: IpEndpoint) -> String {
+    let addr = match endpoint.addr {
+        addr if addr.is_unspecified() => String::from("*"),
+        IpAddress::Ipv6(addr) => format!("[{}]", addr),
+        addr => format!("{}", addr),
+    };
+    match endpoint.port {
+        0 => format!("{}:*", addr),
+        port => format!("{}:{}", addr, port),
+    }
+}
+
+/// Writes the socket table printed by `netstat`.
+fn write_sockets(out: &mut dyn io::Write, sockets: &[SocketEntry]) -> io::Result<()> {
+    writeln!(out, "{:<6} {:<22} {:<22} {}", "Proto", "Local Address", "Foreign Address", "State")?;
+    for socket in sockets {
+        let (proto, state) = match socket.kind {
+            SocketKind::Tcp(state) => ("tcp", format!("{}", state)),
+            SocketKind::Udp => ("udp", String::new()),
+            SocketKind::Icmp => ("icmp", String::new()),
+            SocketKind::Raw(protocol) => ("raw", format!("{}", protocol)),
+        };
+        writeln!(
+            out,
+            "{:<6} {:<22} {:<22} {}",
+            proto,
+            endpoint(socket.local),
+            endpoint(socket.remote),
+            state
+        )?;
+    }
+    Ok(())
+}
+
+/// `netstat`: lists the sockets of the ethernet driver, including the ones
+/// of the kernel, with their endpoints and the state of TCP connections.
+pub fn netstat(args: &[&str], out: &mut dyn io::Write) -> io::Result<()> {
+    if !args.is_empty() {
+        eprintln!("usage: netstat");
+        return Ok(());
+    }
+    if !ETHERNET.is_initialized() {
+        eprintln!("netstat: no network interface");
+        return Ok(());
+    }
+    let sockets = ETHERNET.critical(|driver| driver.sockets());
+    write_sockets(out, &sockets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec;
+    use smoltcp::socket::TcpState;
+    use smoltcp::wire::{IpProtocol, Ipv4Cidr};
+
+    fn output<F: FnOnce(&mut dyn io::Write) -> io::Result<()>>(f: F) -> String {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn ping_summary() {
+        let mut stats = PingStats { sent: 4, ..PingStats::default() };
+        stats.record(Duration::from_micros(1_250));
+        stats.record(Duration::from_micros(750));
+        stats.record(Duration::from_micros(2_000));
+        let summary = output(|out| stats.summary("gw", out));
+        assert_eq!(
+            summary,
+            "--- gw ping statistics ---\n\
+             4 packets transmitted, 3 received, 25% packet loss\n\
+             round-trip min/avg/max = 0.750/1.333/2.000 ms\n"
+        );
+
+        let lost = PingStats { sent: 2, ..PingStats::default() };
+        let summary = output(|out| lost.summary("gw", out));
+        assert!(summary.ends_with("2 packets transmitted, 0 received, 100% packet loss\n"));
+    }
+
+    #[test]
+    fn interface() {
+        let mac = EthernetAddress([0xb8, 0x27, 0xeb, 0x12, 0x34, 0x56]);
+        let config = IpConfig {
+            address: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 2), 24),
+            gateway: Some(Ipv4Address::new(10, 0, 0, 1)),
+            dns_servers: vec![Ipv4Address::new(10, 0, 0, 1), Ipv4Address::new(9, 9, 9, 9)],
+        };
+        let mut iface = Interface {
+            mac,
+            link_up: true,
+            config: Some(config),
+            dhcp: true,
+            ipv6_addrs: vec![
+                Ipv6Cidr::new(Ipv6Address::new(0x2001, 0xdb8, 1, 0, 0xba27, 0xebff, 0xfe12, 0x3456), 64),
+                Ipv6Cidr::new(Ipv6Address::new(0xfe80, 0, 0, 0, 0xba27, 0xebff, 0xfe12, 0x3456), 64),
+            ],
+            ipv6_router: Some(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+            statistics: Statistics { rx_frames: 3, rx_bytes: 180, tx_frames: 2, tx_bytes: 120 },
+        };
+        let text = output(|out| write_interface(out, &iface));
+        assert_eq!(
+            text,
+            "eth0      link up\n\
+             \x20         ether b8-27-eb-12-34-56\n\
+             \x20         inet 10.0.0.2/24  gateway 10.0.0.1  (dhcp)\n\
+             \x20         dns 10.0.0.1 9.9.9.9\n\
+             \x20         inet6 2001:db8:1::ba27:ebff:fe12:3456/64  router fe80::1\n\
+             \x20         inet6 fe80::ba27:ebff:fe12:3456/64\n\
+             \x20         RX packets 3  bytes 180\n\
+             \x20         TX packets 2  bytes 120\n"
+        );
+
+        iface.link_up = false;
+        iface.config = None;
+        iface.statistics = Statistics::default();
+        let text = output(|out| write_interface(out, &iface));
+        assert!(text.starts_with("eth0      link down\n"));
+        assert!(text.contains("inet unconfigured (waiting for dhcp)\n"));
+    }
+
+    #[test]
+    fn socket_table() {
+        let local = IpAddress::v4(10, 0, 0, 2);
+        let sockets = [
+            SocketEntry {
+                kind: SocketKind::Tcp(TcpState::Listen),
+                local: IpEndpoint::new(IpAddress::Unspecified, 23),
+                remote: IpEndpoint::default(),
+            },
+            SocketEntry {
+                kind: SocketKind::Tcp(TcpState::Established),
+                local: IpEndpoint::new(local, 23),
+                remote: IpEndpoint::new(IpAddress::v4(10, 0, 0, 9), 50123),
+            },
+            SocketEntry {
+                kind: SocketKind::Raw(IpProtocol::Udp),
+                local: IpEndpoint::default(),
+                remote: IpEndpoint::default(),
+            },
+            SocketEntry {
+                kind: SocketKind::Tcp(TcpState::SynSent),
+                local: IpEndpoint::new(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 2).into(), 49152),
+                remote: IpEndpoint::new(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1).into(), 80),
+            },
+        ];
+        let text = output(|out| write_sockets(out, &sockets));
+        let lines: Vec<&str> = text.lines().map(|line| line.trim_end()).collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("Proto  Local Address"));
+        assert_eq!(lines[1], format!("tcp    {:<22} {:<22} LISTEN", "*:23", "*:*"));
+        assert_eq!(lines[2], format!("tcp    {:<22} {:<22} ESTABLISHED", "10.0.0.2:23", "10.0.0.9:50123"));
+        assert_eq!(lines[3], format!("raw    {:<22} {:<22} UDP", "*:*", "*:*"));
+        assert_eq!(lines[4], format!("tcp    {:<22} {:<22} SYN-SENT", "[fe80::2]:49152", "[fe80::1]:80"));
+    }
+}
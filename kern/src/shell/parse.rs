@@ -0,0 +1,366 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::Peekable;
+use core::str::Chars;
+
+/// Shell variables, expanded by `$NAME` and `${NAME}`.
+pub type Env = BTreeMap<String, String>;
+
+/// Error type for command line parse failures.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The line contains no command.
+    Empty,
+    /// A `'` or `"` is never closed.
+    UnterminatedQuote,
+    /// The line ends with a lone `\`.
+    TrailingBackslash,
+    /// A `${` is not closed by `}` or names no variable.
+    BadSubstitution,
+    /// A `|` is not surrounded by two commands.
+    EmptyCommand,
+    /// A `>` or `>>` is not followed by a file name.
+    MissingTarget,
+    /// Output of a command other than the last of a pipeline is redirected.
+    MisplacedRedirect,
+    /// A `&` is not at the end of the line.
+    MisplacedBackground,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Error::Empty => "empty command",
+            Error::UnterminatedQuote => "unterminated quote",
+            Error::TrailingBackslash => "trailing backslash",
+            Error::BadSubstitution => "bad substitution",
+            Error::EmptyCommand => "syntax error near `|`",
+            Error::MissingTarget => "syntax error: expected a file name after `>`",
+            Error::MisplacedRedirect => "only the last command of a pipeline can be redirected",
+            Error::MisplacedBackground => "syntax error near `&`",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// A lexical token of a command line.
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Pipe,
+    /// `>` or, if `true`, `>>`.
+    Redirect(bool),
+    Background,
+}
+
+/// Expands the variable reference following a `$` onto `word`. A `$` that
+/// is not followed by a name is kept as is; unset variables expand to
+/// nothing.
+fn expand(chars: &mut Peekable<Chars>, word: &mut String, env: &Env) -> Result<(), Error> {
+    let mut name = String::new();
+    if chars.peek() == Some(&'{') {
+        chars.next();
+        loop {
+            match chars.next() {
+                Some('}') => break,
+                Some(c) => name.push(c),
+                None => return Err(Error::BadSubstitution),
+            }
+        }
+        if name.is_empty() {
+            return Err(Error::BadSubstitution);
+        }
+    } else {
+        while let Some(&c) = chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        if name.is_empty() {
+            word.push('$');
+            return Ok(());
+        }
+    }
+
+    if let Some(value) = env.get(&name) {
+        word.push_str(value);
+    }
+    Ok(())
+}
+
+/// Splits `s` into words and operators.
+///
+/// Words are separated by unquoted blanks. Within single quotes every
+/// character is literal. Within double quotes `$` expansions happen and `\`
+/// escapes only `"`, `\` and `$`. Outside quotes `\` escapes any character.
+fn tokenize(s: &str, env: &Env) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    // whether `word` is a word even if empty, e.g. after `''`
+    let mut in_word = false;
+    let mut chars = s.chars().peekable();
+
+    macro_rules! end_word {
+        () => {
+            if in_word || !word.is_empty() {
+                tokens.push(Token::Word(core::mem::replace(&mut word, String::new())));
+                in_word = false;
+            }
+        };
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => end_word!(),
+            '|' => {
+                end_word!();
+                tokens.push(Token::Pipe);
+            }
+            '&' => {
+                end_word!();
+                tokens.push(Token::Background);
+            }
+            '>' => {
+                end_word!();
+                let append = chars.peek() == Some(&'>');
+                if append {
+                    chars.next();
+                }
+                tokens.push(Token::Redirect(append));
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.push(c),
+                None => return Err(Error::TrailingBackslash),
+            },
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(Error::UnterminatedQuote),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.peek() {
+                            Some(&c) if c == '"' || c == '\\' || c == '$' => {
+                                word.push(c);
+                                chars.next();
+                            }
+                            _ => word.push('\\'),
+                        },
+                        Some('$') => expand(&mut chars, &mut word, env)?,
+                        Some(c) => word.push(c),
+                        None => return Err(Error::UnterminatedQuote),
+                    }
+                }
+            }
+            '$' => expand(&mut chars, &mut word, env)?,
+            c => word.push(c),
+        }
+    }
+    end_word!();
+
+    Ok(tokens)
+}
+
+/// A structure representing a single shell command.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Command {
+    pub args: Vec<String>,
+}
+
+impl Command {
+    /// Returns this command's path. This is equivalent to the first argument.
+    pub fn path(&self) -> &str {
+        &self.args[0]
+    }
+}
+
+/// Where the output of a pipeline goes instead of the console.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Redirect {
+    pub target: String,
+    /// `>>` appends to the target instead of replacing it.
+    pub append: bool,
+}
+
+/// One or more commands connected by `|`, each reading the output of the
+/// previous one.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    pub redirect: Option<Redirect>,
+    /// The line ended with `&`: the shell does not wait for the pipeline.
+    pub background: bool,
+}
+
+impl Pipeline {
+    /// Parses the command line `s`, expanding variables from `env`.
+    ///
+    /// # Errors
+    ///
+    /// If `s` contains no command, returns `Error::Empty`. Malformed quoting,
+    /// pipes and redirections return the corresponding `Error`.
+    pub fn parse(s: &str, env: &Env) -> Result<Pipeline, Error> {
+        let mut commands = Vec::new();
+        let mut args = Vec::new();
+        let mut redirect = None;
+        let mut background = false;
+
+        let mut tokens = tokenize(s, env)?.into_iter();
+        while let Some(token) = tokens.next() {
+            if background {
+                return Err(Error::MisplacedBackground);
+            }
+            match token {
+                Token::Word(word) => args.push(word),
+                Token::Pipe => {
+                    if redirect.is_some() {
+                        return Err(Error::MisplacedRedirect);
+                    }
+                    if args.is_empty() {
+                        return Err(Error::EmptyCommand);
+                    }
+                    commands.push(Command { args: core::mem::replace(&mut args, Vec::new()) });
+                }
+                Token::Redirect(append) => match tokens.next() {
+                    Some(Token::Word(target)) => redirect = Some(Redirect { target, append }),
+                    _ => return Err(Error::MissingTarget),
+                },
+                Token::Background => background = true,
+            }
+        }
+
+        if args.is_empty() {
+            if commands.is_empty() && redirect.is_none() && !background {
+                return Err(Error::Empty);
+            }
+            return Err(Error::EmptyCommand);
+        }
+        commands.push(Command { args });
+
+        Ok(Pipeline { commands, redirect, background })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn env() -> Env {
+        let mut env = Env::new();
+        env.insert(String::from("HOME"), String::from("/home/pi"));
+        env.insert(String::from("SPACED"), String::from("a b"));
+        env
+    }
+
+    fn words(s: &str) -> Vec<String> {
+        let pipeline = Pipeline::parse(s, &env()).unwrap();
+        assert_eq!(pipeline.commands.len(), 1);
+        pipeline.commands.into_iter().next().unwrap().args
+    }
+
+    #[test]
+    fn splits_on_blanks() {
+        assert_eq!(words("  ls   -a\t/ "), vec!["ls", "-a", "/"]);
+        assert_eq!(Pipeline::parse("   ", &env()), Err(Error::Empty));
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(words("cat 'my file.txt'"), vec!["cat", "my file.txt"]);
+        assert_eq!(words("cat \"my file.txt\""), vec!["cat", "my file.txt"]);
+        assert_eq!(words("echo 'a\"b' \"c'd\""), vec!["echo", "a\"b", "c'd"]);
+        assert_eq!(words("echo pre'fix'\"ed\""), vec!["echo", "prefixed"]);
+        assert_eq!(words("echo '' \"\""), vec!["echo", "", ""]);
+        assert_eq!(words("echo '|' \">\""), vec!["echo", "|", ">"]);
+        assert_eq!(Pipeline::parse("echo 'oops", &env()), Err(Error::UnterminatedQuote));
+        assert_eq!(Pipeline::parse("echo \"oops", &env()), Err(Error::UnterminatedQuote));
+    }
+
+    #[test]
+    fn backslash_escapes() {
+        assert_eq!(words("cat my\\ file"), vec!["cat", "my file"]);
+        assert_eq!(words("echo \\'x\\' \\$HOME \\\\"), vec!["echo", "'x'", "$HOME", "\\"]);
+        assert_eq!(words("echo '\\n'"), vec!["echo", "\\n"]);
+        assert_eq!(words("echo \"\\\"\\n\\$\""), vec!["echo", "\"\\n$"]);
+        assert_eq!(Pipeline::parse("echo \\", &env()), Err(Error::TrailingBackslash));
+    }
+
+    #[test]
+    fn variable_expansion() {
+        assert_eq!(words("cd $HOME/docs"), vec!["cd", "/home/pi/docs"]);
+        assert_eq!(words("echo ${HOME}x \"$HOME\""), vec!["echo", "/home/pix", "/home/pi"]);
+        assert_eq!(words("echo '$HOME'"), vec!["echo", "$HOME"]);
+        assert_eq!(words("echo $SPACED \"$SPACED\""), vec!["echo", "a b", "a b"]);
+        assert_eq!(words("echo $UNSET x"), vec!["echo", "x"]);
+        assert_eq!(words("echo \"$UNSET\""), vec!["echo", ""]);
+        assert_eq!(words("echo $ 5$"), vec!["echo", "$", "5$"]);
+        assert_eq!(Pipeline::parse("echo ${HOME", &env()), Err(Error::BadSubstitution));
+        assert_eq!(Pipeline::parse("echo ${}", &env()), Err(Error::BadSubstitution));
+    }
+
+    #[test]
+    fn pipes() {
+        let pipeline = Pipeline::parse("cat a|head -n 2 | wc", &env()).unwrap();
+        let commands: Vec<_> = pipeline.commands.iter().map(|c| c.args.clone()).collect();
+        assert_eq!(commands, vec![vec!["cat", "a"], vec!["head", "-n", "2"], vec!["wc"]]);
+        assert_eq!(pipeline.commands[1].path(), "head");
+        assert_eq!(pipeline.redirect, None);
+
+        assert_eq!(Pipeline::parse("| wc", &env()), Err(Error::EmptyCommand));
+        assert_eq!(Pipeline::parse("ls |", &env()), Err(Error::EmptyCommand));
+        assert_eq!(Pipeline::parse("ls || wc", &env()), Err(Error::EmptyCommand));
+    }
+
+    #[test]
+    fn redirection() {
+        let pipeline = Pipeline::parse("echo hi >out.txt", &env()).unwrap();
+        assert_eq!(pipeline.commands[0].args, vec!["echo", "hi"]);
+        assert_eq!(
+            pipeline.redirect,
+            Some(Redirect { target: String::from("out.txt"), append: false })
+        );
+
+        let pipeline = Pipeline::parse("ls | wc >> \"$HOME/log\"", &env()).unwrap();
+        assert_eq!(pipeline.commands.len(), 2);
+        assert_eq!(
+            pipeline.redirect,
+            Some(Redirect { target: String::from("/home/pi/log"), append: true })
+        );
+
+        // the target may come before the arguments
+        let pipeline = Pipeline::parse("echo > f a b", &env()).unwrap();
+        assert_eq!(pipeline.commands[0].args, vec!["echo", "a", "b"]);
+
+        assert_eq!(Pipeline::parse("echo >", &env()), Err(Error::MissingTarget));
+        assert_eq!(Pipeline::parse("echo > | wc", &env()), Err(Error::MissingTarget));
+        assert_eq!(Pipeline::parse("echo > f | wc", &env()), Err(Error::MisplacedRedirect));
+        assert_eq!(Pipeline::parse("> f", &env()), Err(Error::EmptyCommand));
+    }
+
+    #[test]
+    fn background() {
+        let pipeline = Pipeline::parse("fib 30 &", &env()).unwrap();
+        assert_eq!(pipeline.commands[0].args, vec!["fib", "30"]);
+        assert!(pipeline.background);
+        assert!(Pipeline::parse("fib&", &env()).unwrap().background);
+        assert!(!Pipeline::parse("echo '&' \\&", &env()).unwrap().background);
+        assert!(Pipeline::parse("ls | wc > f &", &env()).unwrap().background);
+
+        assert_eq!(Pipeline::parse("fib & ls", &env()), Err(Error::MisplacedBackground));
+        assert_eq!(Pipeline::parse("fib & &", &env()), Err(Error::MisplacedBackground));
+        assert_eq!(Pipeline::parse("&", &env()), Err(Error::EmptyCommand));
+    }
+}
@@ -0,0 +1,372 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use shim::io;
+
+/// Number of bytes read from an input at a time.
+pub const CHUNK_SIZE: usize = 4096;
+
+/// Number of bytes shown per line by `hexdump`.
+const DUMP_WIDTH: usize = 16;
+
+/// Calls `f` with successive chunks of `input` until it is exhausted or `f`
+/// returns `false`.
+fn for_each_chunk<F>(input: &mut dyn io::Read, mut f: F) -> io::Result<()>
+where
+    F: FnMut(&[u8]) -> io::Result<bool>,
+{
+    let mut buf = [0u8; CHUNK_SIZE];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if !f(&buf[..n])? {
+            return Ok(());
+        }
+    }
+}
+
+/// Copies the first `n` lines of `input` to `out`.
+pub fn head(input: &mut dyn io::Read, n: usize, out: &mut dyn io::Write) -> io::Result<()> {
+    let mut left = n;
+    if left == 0 {
+        return Ok(());
+    }
+    for_each_chunk(input, |chunk| {
+        let mut end = chunk.len();
+        for (i, &byte) in chunk.iter().enumerate() {
+            if byte == b'\n' {
+                left -= 1;
+                if left == 0 {
+                    end = i + 1;
+                    break;
+                }
+            }
+        }
+        out.write_all(&chunk[..end])?;
+        Ok(left > 0)
+    })
+}
+
+/// Copies the last `n` lines of `input` to `out`. Only `n` lines are kept in
+/// memory at a time.
+pub fn tail(input: &mut dyn io::Read, n: usize, out: &mut dyn io::Write) -> io::Result<()> {
+    if n == 0 {
+        return Ok(());
+    }
+    let mut lines: VecDeque<Vec<u8>> = VecDeque::new();
+    let mut line = Vec::new();
+    for_each_chunk(input, |chunk| {
+        let mut start = 0;
+        for (i, &byte) in chunk.iter().enumerate() {
+            if byte == b'\n' {
+                line.extend_from_slice(&chunk[start..=i]);
+                start = i + 1;
+                if lines.len() == n {
+                    lines.pop_front();
+                }
+                lines.push_back(core::mem::replace(&mut line, Vec::new()));
+            }
+        }
+        line.extend_from_slice(&chunk[start..]);
+        Ok(true)
+    })?;
+    if !line.is_empty() {
+        if lines.len() == n {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    for line in lines {
+        out.write_all(&line)?;
+    }
+    Ok(())
+}
+
+/// Copies `input` to `out` chunk by chunk. If `visible` is set, control
+/// characters are shown as `^X` and bytes above 127 as `M-` followed by the
+/// character for the low seven bits, as `cat -v` does; tabs and newlines are
+/// kept.
+pub fn cat(input: &mut dyn io::Read, visible: bool, out: &mut dyn io::Write) -> io::Result<()> {
+    let mut escaped = Vec::new();
+    for_each_chunk(input, |chunk| {
+        if !visible {
+            out.write_all(chunk)?;
+            return Ok(true);
+        }
+        escaped.clear();
+        for &byte in chunk {
+            let mut c = byte;
+            if c >= 0x80 {
+                escaped.extend_from_slice(b"M-");
+                c -= 0x80;
+            }
+            match c {
+                b'\n' | b'\t' if byte < 0x80 => escaped.push(c),
+                0x00..=0x1f => escaped.extend_from_slice(&[b'^', c + 0x40]),
+                0x7f => escaped.extend_from_slice(b"^?"),
+                _ => escaped.push(c),
+            }
+        }
+        out.write_all(&escaped)?;
+        Ok(true)
+    })
+}
+
+/// Line, word and byte counts of an input, as reported by `wc`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub lines: usize,
+    pub words: usize,
+    pub bytes: usize,
+}
+
+impl Counts {
+    /// Counts the newlines, words and bytes of `input`. A word is a maximal
+    /// run of bytes that are not ASCII whitespace.
+    pub fn of(input: &mut dyn io::Read) -> io::Result<Counts> {
+        let mut counts = Counts::default();
+        let mut in_word = false;
+        for_each_chunk(input, |chunk| {
+            counts.bytes += chunk.len();
+            for &byte in chunk {
+                if byte == b'\n' {
+                    counts.lines += 1;
+                }
+                if byte.is_ascii_whitespace() {
+                    in_word = false;
+                } else if !in_word {
+                    in_word = true;
+                    counts.words += 1;
+                }
+            }
+            Ok(true)
+        })?;
+        Ok(counts)
+    }
+
+    /// Adds `other` to these counts.
+    pub fn add(&mut self, other: &Counts) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Output format of `hexdump`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpStyle {
+    /// `hexdump -C`: single bytes, split into two groups of eight.
+    Canonical,
+    /// `xxd`: bytes in pairs.
+    Xxd,
+}
+
+fn dump_line(
+    offset: usize,
+    bytes: &[u8],
+    style: DumpStyle,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    match style {
+        DumpStyle::Canonical => {
+            write!(out, "{:08x} ", offset)?;
+            for i in 0..DUMP_WIDTH {
+                if i % 8 == 0 {
+                    write!(out, " ")?;
+                }
+                match bytes.get(i) {
+                    Some(byte) => write!(out, "{:02x} ", byte)?,
+                    None => write!(out, "   ")?,
+                }
+            }
+            write!(out, " |")?;
+        }
+        DumpStyle::Xxd => {
+            write!(out, "{:08x}: ", offset)?;
+            for i in 0..DUMP_WIDTH {
+                match bytes.get(i) {
+                    Some(byte) => write!(out, "{:02x}", byte)?,
+                    None => write!(out, "  ")?,
+                }
+                if i % 2 == 1 {
+                    write!(out, " ")?;
+                }
+            }
+            write!(out, " ")?;
+        }
+    }
+
+    for &byte in bytes {
+        let c = if byte >= 0x20 && byte < 0x7f { byte } else { b'.' };
+        out.write_all(&[c])?;
+    }
+    if style == DumpStyle::Canonical {
+        write!(out, "|")?;
+    }
+    writeln!(out)
+}
+
+/// Writes a hex dump of at most `len` bytes of `input` to `out`, starting
+/// `skip` bytes into it.
+pub fn hexdump(
+    input: &mut dyn io::Read,
+    skip: usize,
+    len: Option<usize>,
+    style: DumpStyle,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let mut to_skip = skip;
+    let mut left = len.unwrap_or(core::usize::MAX);
+    let mut offset = skip;
+    let mut line = [0u8; DUMP_WIDTH];
+    let mut filled = 0;
+
+    for_each_chunk(input, |chunk| {
+        let skipped = core::cmp::min(to_skip, chunk.len());
+        to_skip -= skipped;
+        let chunk = &chunk[skipped..];
+        let chunk = &chunk[..core::cmp::min(left, chunk.len())];
+        left -= chunk.len();
+
+        for &byte in chunk {
+            line[filled] = byte;
+            filled += 1;
+            if filled == DUMP_WIDTH {
+                dump_line(offset, &line, style, out)?;
+                offset += DUMP_WIDTH;
+                filled = 0;
+            }
+        }
+        Ok(left > 0)
+    })?;
+
+    if filled > 0 {
+        dump_line(offset, &line[..filled], style, out)?;
+        offset += filled;
+    }
+    if style == DumpStyle::Canonical {
+        writeln!(out, "{:08x}", offset)?;
+    }
+    Ok(())
+}
+
+/// Returns `true` if `name` matches the shell pattern `pattern`, where `*`
+/// matches any run of characters and `?` matches any one character. Letters
+/// are compared case-insensitively, like FAT file names.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[u8], name: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some((b'*', rest)) => (0..=name.len()).any(|i| matches(rest, &name[i..])),
+            Some((&p, rest)) => match name.split_first() {
+                Some((&c, name)) if p == b'?' || p.eq_ignore_ascii_case(&c) => matches(rest, name),
+                _ => false,
+            },
+        }
+    }
+    matches(pattern.as_bytes(), name.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    fn run<F>(input: &[u8], f: F) -> String
+    where
+        F: FnOnce(&mut dyn io::Read, &mut dyn io::Write) -> io::Result<()>,
+    {
+        let mut input = input;
+        let mut out = Vec::new();
+        f(&mut input, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn head_and_tail() {
+        let text = b"one\ntwo\nthree\nfour";
+        assert_eq!(run(text, |i, o| head(i, 2, o)), "one\ntwo\n");
+        assert_eq!(run(text, |i, o| head(i, 10, o)), "one\ntwo\nthree\nfour");
+        assert_eq!(run(text, |i, o| head(i, 0, o)), "");
+        assert_eq!(run(text, |i, o| tail(i, 2, o)), "three\nfour");
+        assert_eq!(run(text, |i, o| tail(i, 10, o)), "one\ntwo\nthree\nfour");
+        assert_eq!(run(b"a\nb\n", |i, o| tail(i, 1, o)), "b\n");
+        assert_eq!(run(b"", |i, o| tail(i, 3, o)), "");
+    }
+
+    #[test]
+    fn head_and_tail_across_chunks() {
+        let mut text = Vec::new();
+        for i in 0..3000 {
+            text.extend_from_slice(alloc::format!("line {}\n", i).as_bytes());
+        }
+        assert!(text.len() > 2 * CHUNK_SIZE);
+        assert_eq!(run(&text, |i, o| tail(i, 2, o)), "line 2998\nline 2999\n");
+        let first = run(&text, |i, o| head(i, 1000, o));
+        assert!(first.ends_with("line 998\nline 999\n"));
+        assert_eq!(first.lines().count(), 1000);
+    }
+
+    #[test]
+    fn cat_is_binary_safe() {
+        let bytes: Vec<u8> = (0..=255u8).cycle().take(3 * CHUNK_SIZE + 7).collect();
+        let mut input = bytes.as_slice();
+        let mut out = Vec::new();
+        cat(&mut input, false, &mut out).unwrap();
+        assert_eq!(out, bytes);
+
+        assert_eq!(run(b"a\tb\n", |i, o| cat(i, true, o)), "a\tb\n");
+        assert_eq!(run(b"\x00\x1b[\x7f", |i, o| cat(i, true, o)), "^@^[[^?");
+        assert_eq!(run(b"\xe9\x8a\xff", |i, o| cat(i, true, o)), "M-iM-^JM-^?");
+    }
+
+    #[test]
+    fn counts() {
+        let mut input: &[u8] = b"  hello world\n\tfoo\n\nbar";
+        let counts = Counts::of(&mut input).unwrap();
+        assert_eq!(counts, Counts { lines: 3, words: 4, bytes: 23 });
+
+        let mut total = Counts::default();
+        total.add(&counts);
+        total.add(&counts);
+        assert_eq!(total.words, 8);
+    }
+
+    #[test]
+    fn hexdump_styles() {
+        assert_eq!(
+            run(b"Hello world\n", |i, o| hexdump(i, 0, None, DumpStyle::Canonical, o)),
+            "00000000  48 65 6c 6c 6f 20 77 6f  72 6c 64 0a              |Hello world.|\n\
+             0000000c\n"
+        );
+        assert_eq!(
+            run(b"Hello world\n", |i, o| hexdump(i, 0, None, DumpStyle::Xxd, o)),
+            "00000000: 4865 6c6c 6f20 776f 726c 640a            Hello world.\n"
+        );
+
+        let bytes: Vec<u8> = (0u8..40).collect();
+        let dump = run(&bytes, |i, o| hexdump(i, 4, Some(20), DumpStyle::Canonical, o));
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("00000004  04 05 06"));
+        assert!(lines[1].starts_with("00000014  14 15 16 17  "));
+        assert_eq!(lines[2], "00000018");
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*.bin", "fib.bin"));
+        assert!(glob_match("*.BIN", "fib.bin"));
+        assert!(glob_match("f?b*", "fib.bin"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("*.bin", "fib.elf"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("fib", "fib.bin"));
+    }
+}
@@ -9,16 +9,19 @@ use alloc::boxed::Box;
 use pi::interrupt::{Controller, Interrupt};
 use pi::local_interrupt::{LocalController, LocalInterrupt};
 
-use self::syndrome::Syndrome;
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
 use crate::percore;
 use crate::traps::irq::IrqHandlerRegistry;
 
 use aarch64;
 use crate::console::kprintln;
-use crate::shell::shell;
+use crate::debug::{self, gdb};
+use crate::shell::debugger;
 use crate::param::{TICK};
-use crate::GLOABAL_IRQ;
+use crate::process::State;
+use crate::vm::VirtualAddr;
+use crate::{FIQ, GLOABAL_IRQ, SCHEDULER};
 
 use pi::timer;
 
@@ -47,6 +50,39 @@ pub struct Info {
     kind: Kind,
 }
 
+/// Hands a context stopped by a breakpoint or step to `gdb` if it is attached,
+/// or to the debug monitor otherwise.
+fn debug_stop(tf: &mut TrapFrame) {
+    if gdb::is_attached() {
+        gdb::enter(tf, true)
+    } else {
+        debugger(tf)
+    }
+}
+
+/// Handles a data abort taken by a user process: a translation fault in its
+/// stack region maps the stack down to the faulting address, and the access
+/// is retried. Any other abort kills the process.
+fn user_data_abort(kind: Fault, tf: &mut TrapFrame) {
+    let far = unsafe { aarch64::FAR_EL1.get() } as usize;
+    if kind == Fault::Translation {
+        let grown = SCHEDULER.critical(|scheduler| {
+            scheduler.find_process(tf).vmap.grow_stack(VirtualAddr::from(far))
+        });
+        if grown {
+            return;
+        }
+    }
+    kprintln!(
+        "process {} killed: {:?} fault at {:#x}, pc {:#x}",
+        tf.get_tpidr(),
+        kind,
+        far,
+        tf.get_elr()
+    );
+    SCHEDULER.switch(State::Dead, tf);
+}
+
 /// This function is called when an exception occurs. The `info` parameter
 /// specifies the source and kind of exception that has occurred. The `esr` is
 /// the value of the exception syndrome register. Finally, `tf` is a pointer to
@@ -66,27 +102,43 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 // kprintln!("{:?}", syndrome);
                 // kprintln!("{:?}", &syndrome);
                 match syndrome {
-                    Syndrome::Brk(comment) => {
-                        tf.set_elr(tf.get_elr() + 4);
-                        shell("(dbg)$ ")
+                    Syndrome::Brk(_) => {
+                        // breakpoints set by the debugger are resumed by
+                        // stepping over the original instruction instead
+                        if !debug::is_breakpoint(tf) {
+                            tf.set_elr(tf.get_elr() + 4);
+                        }
+                        debug_stop(tf)
+                    },
+                    Syndrome::Step => {
+                        if debug::finish_step(tf) {
+                            debug_stop(tf)
+                        }
                     },
                     Syndrome::Svc(n) => {
                         handle_syscall(n, tf);
                     }
+                    Syndrome::DataAbort { kind, .. } if info.source == Source::LowerAArch64 => {
+                        user_data_abort(kind, tf);
+                    }
                     _ => {return ();}
                 };
             },
             Kind::Irq => {
                 let core_idx = aarch64::affinity();
                 // Core 0 handles global IRQs
-                // if core_idx == 0 {
-                //     let controller = Controller::new();
-                //     for interrupt in Interrupt::iter() {
-                //         if controller.is_pending(interrupt) {
-                //             GLOABAL_IRQ.invoke(interrupt, tf);
-                //         }
-                //     }
-                // } 
+                if core_idx == 0 {
+                    // the USPi timer handler polls the network, which waits
+                    // for USB transfers completing from the USB FIQ
+                    aarch64::enable_fiq_interrupt();
+                    let controller = Controller::new();
+                    for interrupt in Interrupt::iter() {
+                        if controller.is_pending(interrupt) {
+                            GLOABAL_IRQ.invoke(interrupt, tf);
+                        }
+                    }
+                    aarch64::disable_fiq_interrupt();
+                }
                 // Each core handes its own local IRQs
                 let local_controller = LocalController::new(core_idx);
                 for local_int in LocalInterrupt::iter() {
@@ -97,7 +149,10 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 
 
             },
-            _ => { kprintln!("Not synchronous or Irq"); }
+            Kind::Fiq => {
+                FIQ.invoke((), tf);
+            },
+            _ => { kprintln!("Not synchronous, Irq or Fiq"); }
         }
 
 }
@@ -34,6 +34,7 @@ impl GlobalIrq {
             Mutex::new(None),
             Mutex::new(None),
             Mutex::new(None),
+            Mutex::new(None),
         ])
     }
 }
@@ -77,6 +78,7 @@ impl Index<Interrupt> for GlobalIrq {
             Gpio2 => 5,
             Gpio3 => 6,
             Uart => 7,
+            Aux => 8,
         };
         &self.0[index]
     }
@@ -96,8 +98,7 @@ impl Index<()> for Fiq {
     type Output = IrqHandlerMutex;
 
     fn index(&self, _: ()) -> &IrqHandlerMutex {
-        // Lab 5 2.B
-        unimplemented!("FIQ Index")
+        &self.0
     }
 }
 
@@ -119,8 +120,11 @@ where
         *self[int].lock() = Some(handler);
     }
 
-    /// Executes an irq handler for the given interrupt.
+    /// Executes the irq handler for the given interrupt. Interrupts without
+    /// a registered handler are ignored.
     fn invoke(&self, int: I, tf: &mut TrapFrame) {
-        (*self[int].lock()).as_mut().unwrap()(tf);
+        if let Some(handler) = self[int].lock().as_mut() {
+            handler(tf);
+        }
     }
 }
@@ -1,12 +1,17 @@
 use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
 use core::time::Duration;
 
-use crate::console::{CONSOLE, kprintln, kprint};
+use crate::console::{CONSOLE, Vt, kprintln};
 use crate::process::{State, Process};
 use crate::traps::TrapFrame;
-use crate::param::TICK;
-use crate::param::USER_IMG_BASE;
-use smoltcp::wire::{IpAddress, IpEndpoint};
+use crate::param::{SOCKET_BUFFER_SIZE, TICK};
+use crate::vm::{access_ok, copy_from_user, copy_to_user, UserPageTable, UserPtr, UserStr, VirtualAddr};
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address, Ipv6Address};
+use crate::net::dns::Address;
+use crate::net::{self, Socket};
 use crate::{ETHERNET, SCHEDULER};
 
 use kernel_api::*;
@@ -23,7 +28,6 @@ use pi::timer;
 /// //TODO: Fix bug where next process after slept one wont get full quantum
 pub fn sys_sleep(ms: u32, tf: &mut TrapFrame) {
     // kprintln!("goodnight sleep.... {}", tf.get_tpidr());
-    kprint!("we sleepy ... \n");
     //TODO: consider weird wrap around cases
     let wake_up_millis = timer::current_time().as_millis() + ms as u128;
     let wake_up_alarm = Duration::from_millis(wake_up_millis as u64);
@@ -82,10 +86,59 @@ pub fn sys_exit(tf: &mut TrapFrame) {
 ///
 /// It only returns the usual status value.
 pub fn sys_write(b: u8, tf: &mut TrapFrame) {
-    kprint!("{}", b as char);
+    CONSOLE.lock().write_buffered(Vt::Programs, &[b]);
     tf.set_gpr(7, 1);
 }
 
+/// Longest read served by a single `read` system call.
+const READ_MAX: usize = 1024;
+
+/// Most sockets watched by a single `poll` system call.
+const POLL_MAX: usize = 64;
+
+/// Reads from the console.
+///
+/// This system call takes the address of the buffer as the first parameter and
+/// the length of the buffer as the second parameter. Input typed on the
+/// programs' terminal is read; the calling process waits until there is some,
+/// then reads as much as is available (at most `READ_MAX` bytes).
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+pub fn sys_read(va: usize, len: usize, tf: &mut TrapFrame) {
+    if let Err(e) = with_vmap(tf, |vmap| access_ok(vmap, va, len, true)) {
+        tf.set_gpr(7, e as u64);
+        return;
+    }
+    if len == 0 {
+        tf.set_gpr(0, 0);
+        tf.set_gpr(7, OsError::Ok as u64);
+        return;
+    }
+
+    let mut buf = vec![0u8; core::cmp::min(len, READ_MAX)];
+    let boxed_fnmut = Box::new(move |p: &mut Process| {
+        let read = CONSOLE.lock().read(Vt::Programs, &mut buf);
+        if read == 0 {
+            return false;
+        }
+        match copy_to_user(&p.vmap, va, &buf[..read]) {
+            Ok(()) => {
+                p.context.set_gpr(0, read as u64);
+                p.context.set_gpr(7, OsError::Ok as u64);
+            }
+            Err(e) => p.context.set_gpr(7, e as u64),
+        }
+        true
+    });
+    SCHEDULER.switch(State::Waiting(boxed_fnmut), tf);
+}
+
 /// Returns the current process's ID.
 ///
 /// This system call does not take parameter.
@@ -102,11 +155,40 @@ pub fn sys_getpid(tf: &mut TrapFrame) {
 /// Creates a socket and saves the socket handle in the current process's
 /// socket list.
 ///
-/// This function does neither take any parameter nor return anything,
-/// except the usual return code that indicates successful syscall execution.
-pub fn sys_sock_create(tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_create")
+/// This system call takes the type of the socket as the first parameter:
+/// `SocketType::Tcp` or `SocketType::Udp`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the descriptor of the new socket.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: There is no network device.
+/// - `OsError::InvalidArgument`: The socket type is unknown.
+pub fn sys_sock_create(ty: u64, tf: &mut TrapFrame) {
+    if !ETHERNET.is_initialized() {
+        tf.set_gpr(7, OsError::NoEntry as u64);
+        return;
+    }
+
+    let ty = if ty == SocketType::Tcp as u64 {
+        SocketType::Tcp
+    } else if ty == SocketType::Udp as u64 {
+        SocketType::Udp
+    } else {
+        tf.set_gpr(7, OsError::InvalidArgument as u64);
+        return;
+    };
+    let socket = ETHERNET.critical(|ethernet| ethernet.create(ty));
+    let sock_idx = SCHEDULER.critical(|scheduler| {
+        let sockets = &mut scheduler.find_process(tf).sockets;
+        sockets.push(socket);
+        sockets.len() - 1
+    });
+
+    tf.set_gpr(0, sock_idx as u64);
+    tf.set_gpr(7, OsError::Ok as u64);
 }
 
 /// Returns the status of a socket.
@@ -121,22 +203,30 @@ pub fn sys_sock_create(tf: &mut TrapFrame) {
 /// - x2: can_send
 /// - x3: can_recv
 ///
+/// A UDP socket is active once it is bound to a local port and never listens.
+///
 /// # Errors
 /// This function returns `OsError::InvalidSocket` if a socket that corresponds
 /// to the provided descriptor is not found.
 pub fn sys_sock_status(sock_idx: usize, tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_status")
+    let socket = match find_socket(sock_idx, tf) {
+        Ok(socket) => socket,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+
+    let status = ETHERNET.critical(|ethernet| ethernet.status(socket));
+    tf.set_gpr(0, status.is_active as u64);
+    tf.set_gpr(1, status.is_listening as u64);
+    tf.set_gpr(2, status.can_send as u64);
+    tf.set_gpr(3, status.can_recv as u64);
+    tf.set_gpr(7, OsError::Ok as u64);
 }
 
 /// Connects a local ephemeral port to a remote IP endpoint with a socket.
 ///
-/// This system call takes a socket descriptor as the first parameter, the IP
-/// of the remote endpoint as the second paramter in big endian, and the port
-/// number of the remote endpoint as the third parameter.
-///
-/// `handle_syscall` should read the value of registers and create a struct that
-/// implements `Into<IpEndpoint>` when calling this function.
+/// This system call takes a socket descriptor as the first parameter and the
+/// address of a `SockAddr` holding the remote endpoint, IPv4 or IPv6, as the
+/// second parameter.
 ///
 /// It only returns the usual status value.
 ///
@@ -145,16 +235,26 @@ pub fn sys_sock_status(sock_idx: usize, tf: &mut TrapFrame) {
 ///
 /// - `OsError::NoEntry`: Fails to allocate an ephemeral port
 /// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
-/// - `OsError::IllegalSocketOperation`: `connect()` returned `smoltcp::Error::Illegal`.
-/// - `OsError::BadAddress`: `connect()` returned `smoltcp::Error::Unaddressable`.
+/// - `OsError::InvalidArgument`: The address family of the `SockAddr` is unknown.
+/// - `OsError::IllegalSocketOperation`: The socket is not a TCP socket, or `connect()` returned
+///   `smoltcp::Error::Illegal`.
+/// - `OsError::BadAddress`: The `SockAddr` is not readable, or `connect()` returned
+///   `smoltcp::Error::Unaddressable`.
 /// - `OsError::Unknown`: All the other errors from calling `connect()`.
-pub fn sys_sock_connect(
-    sock_idx: usize,
-    remote_endpoint: impl Into<IpEndpoint>,
-    tf: &mut TrapFrame,
-) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_connect")
+pub fn sys_sock_connect(sock_idx: usize, addr_va: usize, tf: &mut TrapFrame) {
+    let socket = match find_socket(sock_idx, tf) {
+        Ok(socket) => socket,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+    let remote_endpoint = match read_endpoint(addr_va, tf) {
+        Ok(endpoint) => endpoint,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+
+    match ETHERNET.critical(|ethernet| ethernet.connect(socket, remote_endpoint)) {
+        Ok(()) => tf.set_gpr(7, OsError::Ok as u64),
+        Err(e) => tf.set_gpr(7, e as u64),
+    }
 }
 
 /// Listens on a local port for an inbound connection.
@@ -168,46 +268,492 @@ pub fn sys_sock_connect(
 /// This function can return following errors:
 ///
 /// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
-/// - `OsError::IllegalSocketOperation`: `listen()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::IllegalSocketOperation`: The socket is not a TCP socket, the port is already in
+///   use, or `listen()` returned `smoltcp::Error::Illegal`.
 /// - `OsError::BadAddress`: `listen()` returned `smoltcp::Error::Unaddressable`.
 /// - `OsError::Unknown`: All the other errors from calling `listen()`.
 pub fn sys_sock_listen(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_listen")
+    let socket = match find_socket(sock_idx, tf) {
+        Ok(socket) => socket,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+
+    match ETHERNET.critical(|ethernet| ethernet.listen(socket, local_port)) {
+        Ok(()) => tf.set_gpr(7, OsError::Ok as u64),
+        Err(e) => tf.set_gpr(7, e as u64),
+    }
+}
+
+/// Returns the socket with descriptor `sock_idx` of the process that issued
+/// the system call saved in `tf`.
+fn find_socket(sock_idx: usize, tf: &TrapFrame) -> OsResult<Socket> {
+    SCHEDULER.critical(|scheduler| {
+        scheduler
+            .find_process(tf)
+            .sockets
+            .get(sock_idx)
+            .cloned()
+            .ok_or(OsError::InvalidSocket)
+    })
+}
+
+/// Converts a `SockAddr` passed by a process to an endpoint.
+fn endpoint(addr: SockAddr) -> OsResult<IpEndpoint> {
+    let addr = addr.to_socket_addr()?;
+    let ip = match addr.ip {
+        IpAddr::V4(bytes) => IpAddress::Ipv4(Ipv4Address(bytes)),
+        IpAddr::V6(bytes) => IpAddress::Ipv6(Ipv6Address(bytes)),
+    };
+    Ok(IpEndpoint::new(ip, addr.port))
+}
+
+/// Converts an endpoint to the `SockAddr` returned to a process. An
+/// unspecified address is returned as the IPv4 address 0.0.0.0.
+fn sockaddr(endpoint: IpEndpoint) -> SockAddr {
+    let ip = match endpoint.addr {
+        IpAddress::Ipv6(addr) => IpAddr::V6(addr.0),
+        IpAddress::Ipv4(addr) => IpAddr::V4(addr.0),
+        _ => IpAddr::V4([0; 4]),
+    };
+    SockAddr::from(SocketAddr::new(ip, endpoint.port))
+}
+
+/// Reads the `SockAddr` at `va` of the process that issued the system call
+/// saved in `tf`.
+fn read_endpoint(va: usize, tf: &TrapFrame) -> OsResult<IpEndpoint> {
+    endpoint(with_vmap(tf, |vmap| UserPtr::<SockAddr>::new(va).read(vmap))?)
+}
+
+/// Checks that a `SockAddr` can be written at `va` by the process that
+/// issued the system call saved in `tf`.
+fn check_sockaddr(va: usize, tf: &TrapFrame) -> OsResult<()> {
+    with_vmap(tf, |vmap| access_ok(vmap, va, size_of::<SockAddr>(), true))
 }
 
-/// Returns a slice from a virtual address and a legnth.
+/// Binds a UDP socket to a local port.
+///
+/// This system call takes a socket descriptor as the first parameter and the
+/// local port to bind to as the second parameter. Port 0 binds to an ephemeral
+/// port. TCP and UDP sockets share the same port space.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the bound port.
 ///
 /// # Errors
-/// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
-unsafe fn to_user_slice<'a>(va: usize, len: usize) -> OsResult<&'a [u8]> {
-    let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
-        Ok(core::slice::from_raw_parts(va as *const u8, len))
-    } else {
-        Err(OsError::BadAddress)
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: Fails to allocate an ephemeral port
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::IllegalSocketOperation`: The socket is not a UDP socket, the port is already in
+///   use, or `bind()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::Unknown`: All the other errors from calling `bind()`.
+pub fn sys_sock_bind(sock_idx: usize, local_port: u16, tf: &mut TrapFrame) {
+    let socket = match find_socket(sock_idx, tf) {
+        Ok(socket) => socket,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+
+    match ETHERNET.critical(|ethernet| ethernet.bind(socket, local_port)) {
+        Ok(port) => {
+            tf.set_gpr(0, port as u64);
+            tf.set_gpr(7, OsError::Ok as u64);
+        }
+        Err(e) => tf.set_gpr(7, e as u64),
+    }
+}
+
+/// Sends a datagram with a UDP socket.
+///
+/// This system call takes a socket descriptor as the first parameter, the
+/// address of the buffer as the second parameter, the length of the buffer
+/// as the third parameter, and the address of a `SockAddr` holding the remote
+/// endpoint as the fourth parameter. An unbound socket is bound to an
+/// ephemeral port first.
+/// While the send buffer is full, the process waits as set by `sock_setopt`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes sent.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: Fails to allocate an ephemeral port
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace
+///   slice, the `SockAddr` is not readable, or `send_slice()` returned
+///   `smoltcp::Error::Unaddressable`.
+/// - `OsError::InvalidArgument`: The address family of the `SockAddr` is unknown, or the
+///   datagram is larger than `SOCKET_BUFFER_SIZE`.
+/// - `OsError::IllegalSocketOperation`: The socket is not a UDP socket.
+/// - `OsError::IoErrorWouldBlock`: The socket is non-blocking and the send buffer is full.
+/// - `OsError::IoErrorTimedOut`: The send buffer stayed full for the timeout of the socket.
+/// - `OsError::Unknown`: All the other errors from smoltcp.
+pub fn sys_sock_sendto(
+    sock_idx: usize,
+    va: usize,
+    len: usize,
+    addr_va: usize,
+    tf: &mut TrapFrame,
+) {
+    let socket = match find_socket(sock_idx, tf) {
+        Ok(socket) => socket,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+    let remote_endpoint = match read_endpoint(addr_va, tf) {
+        Ok(endpoint) => endpoint,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+    if let Err(e) = with_vmap(tf, |vmap| access_ok(vmap, va, len, false)) {
+        return tf.set_gpr(7, e as u64);
+    }
+    if len > SOCKET_BUFFER_SIZE {
+        return tf.set_gpr(7, OsError::InvalidArgument as u64);
+    }
+
+    let mut buf = vec![0u8; len];
+    if let Err(e) = with_vmap(tf, |vmap| copy_from_user(vmap, &mut buf, va)) {
+        return tf.set_gpr(7, e as u64);
+    }
+
+    complete_or_wait(Wait::for_socket(&socket), tf, move |_| {
+        let sent = ETHERNET.critical(|ethernet| ethernet.send_to(socket, &buf, remote_endpoint))?;
+        Ok([sent as u64, 0, 0])
+    });
+}
+
+/// Receives a datagram from a UDP socket.
+///
+/// This system call takes a socket descriptor as the first parameter, the
+/// address of the buffer as the second parameter, the length of the buffer as
+/// the third parameter, and the address of a `SockAddr` that receives the
+/// endpoint of the sender as the fourth parameter. The part of the datagram
+/// that does not fit in the buffer is discarded. Until a datagram is received,
+/// the process waits as set by `sock_setopt`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace
+///   slice, or the `SockAddr` is not writable.
+/// - `OsError::IllegalSocketOperation`: The socket is not a UDP socket.
+/// - `OsError::IoErrorWouldBlock`: The socket is non-blocking and no datagram has been received.
+/// - `OsError::IoErrorTimedOut`: No datagram was received in the timeout of the socket.
+/// - `OsError::Unknown`: All the other errors from smoltcp.
+pub fn sys_sock_recvfrom(sock_idx: usize, va: usize, len: usize, addr_va: usize, tf: &mut TrapFrame) {
+    let socket = match find_socket(sock_idx, tf) {
+        Ok(socket) => socket,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+    if let Err(e) = with_vmap(tf, |vmap| access_ok(vmap, va, len, true)) {
+        return tf.set_gpr(7, e as u64);
+    }
+    if let Err(e) = check_sockaddr(addr_va, tf) {
+        return tf.set_gpr(7, e as u64);
+    }
+
+    // A datagram never exceeds the receive buffer, so no more than that is
+    // ever copied out.
+    let mut buf = vec![0u8; core::cmp::min(len, SOCKET_BUFFER_SIZE)];
+    complete_or_wait(Wait::for_socket(&socket), tf, move |p| {
+        let (received, remote) = ETHERNET.critical(|ethernet| ethernet.recv_from(socket, &mut buf))?;
+        copy_to_user(&p.vmap, va, &buf[..received])?;
+        UserPtr::new(addr_va).write(&p.vmap, sockaddr(remote))?;
+        Ok([received as u64, 0, 0])
+    });
+}
+
+/// Accepts an inbound connection with a listening socket.
+///
+/// This system call takes the descriptor of a socket that listens with
+/// `sock_listen` as the first parameter and the address of a `SockAddr` that
+/// receives the remote endpoint as the second parameter. The connection is
+/// moved to a new socket with the same options, and the listening socket keeps
+/// listening on its port. Until a connection is established, the process waits
+/// as set by `sock_setopt`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the descriptor of the connected socket.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::BadAddress`: The `SockAddr` is not writable.
+/// - `OsError::IllegalSocketOperation`: The socket is not a listening TCP socket.
+/// - `OsError::IoErrorWouldBlock`: The socket is non-blocking and no connection is established.
+/// - `OsError::IoErrorTimedOut`: No connection was established in the timeout of the socket.
+/// - `OsError::Unknown`: All the other errors from calling `listen()`.
+pub fn sys_sock_accept(sock_idx: usize, addr_va: usize, tf: &mut TrapFrame) {
+    let socket = match find_socket(sock_idx, tf) {
+        Ok(socket) => socket,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+    if let Err(e) = check_sockaddr(addr_va, tf) {
+        return tf.set_gpr(7, e as u64);
     }
+
+    complete_or_wait(Wait::for_socket(&socket), tf, move |p| {
+        let (listener, connection, remote) = ETHERNET.critical(|ethernet| ethernet.accept(socket))?;
+        p.sockets[sock_idx] = listener;
+        p.sockets.push(connection);
+        UserPtr::new(addr_va).write(&p.vmap, sockaddr(remote))?;
+        Ok([(p.sockets.len() - 1) as u64, 0, 0])
+    });
 }
-/// Returns a mutable slice from a virtual address and a legnth.
+
+/// Sets an option of a socket.
+///
+/// This system call takes a socket descriptor as the first parameter, the
+/// option as the second parameter, and its value as the third parameter:
+///
+/// - `SOCK_OPT_NONBLOCKING`: 1 to make operations that would wait fail with
+///   `OsError::IoErrorWouldBlock`, 0 to make them wait (the default).
+/// - `SOCK_OPT_TIMEOUT`: how many milliseconds an operation waits before
+///   failing with `OsError::IoErrorTimedOut`, or 0 to wait as long as needed
+///   (the default).
+///
+/// It only returns the usual status value.
 ///
 /// # Errors
-/// This functions returns `Err(OsError::BadAddress)` if the slice is not entirely
-/// in userspace.
-unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]> {
-    let overflow = va.checked_add(len).is_none();
-    if va >= USER_IMG_BASE && !overflow {
-        Ok(core::slice::from_raw_parts_mut(va as *mut u8, len))
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
+/// - `OsError::InvalidArgument`: The option is unknown.
+pub fn sys_sock_setopt(sock_idx: usize, opt: u64, value: u64, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| {
+        let socket = scheduler
+            .find_process(tf)
+            .sockets
+            .get_mut(sock_idx)
+            .ok_or(OsError::InvalidSocket)?;
+        match opt {
+            SOCK_OPT_NONBLOCKING => socket.nonblocking = value != 0,
+            SOCK_OPT_TIMEOUT if value == 0 => socket.timeout = None,
+            SOCK_OPT_TIMEOUT => socket.timeout = Some(Duration::from_millis(value)),
+            _ => return Err(OsError::InvalidArgument),
+        }
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => tf.set_gpr(7, OsError::Ok as u64),
+        Err(e) => tf.set_gpr(7, e as u64),
+    }
+}
+
+/// Waits for events on several sockets.
+///
+/// This system call takes the address of an array of `PollFd` as the first
+/// parameter, the number of its entries (at most `POLL_MAX`) as the second
+/// parameter, and a timeout in milliseconds as the third parameter. It waits
+/// until one of the `events` of an entry occurs, or the timeout elapses. A
+/// timeout of 0 returns at once and `u64::max_value()` waits as long as needed.
+/// The `revents` of every entry is set to the events that have occurred.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of entries with events, which is 0 on timeout.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to a descriptor.
+/// - `OsError::BadAddress`: The array is not a valid userspace slice.
+/// - `OsError::InvalidArgument`: There are more than `POLL_MAX` entries.
+pub fn sys_sock_poll(va: usize, count: usize, timeout_ms: u64, tf: &mut TrapFrame) {
+    if count > POLL_MAX {
+        return tf.set_gpr(7, OsError::InvalidArgument as u64);
+    }
+    let fd = move |i: usize| UserPtr::<PollFd>::new(va + i * size_of::<PollFd>());
+    let fds: OsResult<Vec<PollFd>> = with_vmap(tf, |vmap| {
+        (0..count).map(|i| fd(i).read(vmap)).collect()
+    });
+    let fds = match fds {
+        Ok(fds) => fds,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+
+    let deadline = if timeout_ms == u64::max_value() {
+        None
     } else {
-        Err(OsError::BadAddress)
+        Some(timer::current_time() + Duration::from_millis(timeout_ms))
+    };
+    complete_or_wait(Wait::Forever, tf, move |p| {
+        let mut revents = Vec::with_capacity(fds.len());
+        for entry in fds.iter() {
+            let socket = p
+                .sockets
+                .get(entry.descriptor.raw() as usize)
+                .cloned()
+                .ok_or(OsError::InvalidSocket)?;
+            revents.push(ETHERNET.critical(|ethernet| ethernet.poll_events(socket)) & entry.events);
+        }
+
+        let ready = revents.iter().filter(|&&events| events != 0).count();
+        let expired = deadline.map_or(false, |deadline| timer::current_time() >= deadline);
+        if ready == 0 && !expired {
+            return Err(OsError::IoErrorWouldBlock);
+        }
+        for (i, (entry, &revents)) in fds.iter().zip(revents.iter()).enumerate() {
+            fd(i).write(&p.vmap, PollFd { revents, ..*entry })?;
+        }
+        Ok([ready as u64, 0, 0])
+    });
+}
+
+/// Resolves a host name to an IP address.
+///
+/// This system call takes the address of the name as the first parameter, its
+/// length as the second parameter, and the address of a `SockAddr` that
+/// receives the address of the name, with port 0, as the third parameter. The
+/// name is looked up in `/etc/hosts`, in the cache of earlier answers, and then
+/// with the DNS servers of the interface configuration; the process waits for
+/// their answer. The first IPv4 address of the name is returned, or its first
+/// IPv6 address if it has none. An IPv4 or IPv6 address resolves to itself.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::NoEntry`: There is no network device, no DNS server is configured, or the name
+///   does not exist or has no address.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace
+///   slice, or the `SockAddr` is not writable.
+/// - `OsError::InvalidArgument`: The name is not UTF-8 encoded or not a valid domain name.
+/// - `OsError::IoErrorTimedOut`: No DNS server answered.
+/// - `OsError::IoError`: The DNS server failed to answer.
+pub fn sys_resolve(va: usize, len: usize, addr_va: usize, tf: &mut TrapFrame) {
+    if !ETHERNET.is_initialized() {
+        return tf.set_gpr(7, OsError::NoEntry as u64);
+    }
+    let name = match with_vmap(tf, |vmap| UserStr::new(va, len).read(vmap)) {
+        Ok(name) => name,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+    if let Err(e) = check_sockaddr(addr_va, tf) {
+        return tf.set_gpr(7, e as u64);
+    }
+
+    complete_or_wait(Wait::Forever, tf, move |p| {
+        let addresses = ETHERNET.critical(|ethernet| ethernet.resolve(&name, net::now()))?;
+        let ip = addresses
+            .iter()
+            .find(|addr| match addr {
+                Address::V4(_) => true,
+                Address::V6(_) => false,
+            })
+            .or_else(|| addresses.first())
+            .map(|addr| match *addr {
+                Address::V4(addr) => IpAddress::Ipv4(addr),
+                Address::V6(bytes) => IpAddress::Ipv6(Ipv6Address(bytes)),
+            })
+            .ok_or(OsError::NoEntry)?;
+        UserPtr::new(addr_va).write(&p.vmap, sockaddr(IpEndpoint::new(ip, 0)))?;
+        Ok([0, 0, 0])
+    });
+}
+
+/// Values returned in x0, x1 and x2 by a system call that may wait.
+type Returns = [u64; 3];
+
+/// How long a system call may wait for its operation to complete.
+#[derive(Clone, Copy)]
+enum Wait {
+    /// Fail with `OsError::IoErrorWouldBlock` instead of waiting.
+    Never,
+    /// Wait as long as needed.
+    Forever,
+    /// Wait at most this long, then fail with `OsError::IoErrorTimedOut`.
+    For(Duration),
+}
+
+impl Wait {
+    /// Returns how operations on `socket` wait, as set by `sock_setopt`.
+    fn for_socket(socket: &Socket) -> Wait {
+        match (socket.nonblocking, socket.timeout) {
+            (true, _) => Wait::Never,
+            (false, None) => Wait::Forever,
+            (false, Some(timeout)) => Wait::For(timeout),
+        }
     }
 }
 
+/// Writes the result of a system call to `tf`: the returned values on
+/// success and the status value.
+fn set_returns(tf: &mut TrapFrame, result: OsResult<Returns>) {
+    match result {
+        Ok(values) => {
+            for (i, &value) in values.iter().enumerate() {
+                tf.set_gpr(i as u8, value);
+            }
+            tf.set_gpr(7, OsError::Ok as u64);
+        }
+        Err(e) => tf.set_gpr(7, e as u64),
+    }
+}
+
+/// Runs `op` for the process that issued the system call saved in `tf` and
+/// returns its result. If `op` fails with `OsError::IoErrorWouldBlock` and
+/// `wait` allows it, the process waits instead: `op` is retried each time the
+/// scheduler polls the process, until it completes or the wait times out.
+fn complete_or_wait<F>(wait: Wait, tf: &mut TrapFrame, mut op: F)
+where
+    F: FnMut(&mut Process) -> OsResult<Returns> + Send + 'static,
+{
+    let result = SCHEDULER.critical(|scheduler| op(scheduler.find_process(tf)));
+    let deadline = match (result, wait) {
+        (Err(OsError::IoErrorWouldBlock), Wait::Forever) => None,
+        (Err(OsError::IoErrorWouldBlock), Wait::For(timeout)) => {
+            Some(timer::current_time() + timeout)
+        }
+        (result, _) => return set_returns(tf, result),
+    };
+
+    let boxed_fnmut = Box::new(move |p: &mut Process| match op(p) {
+        Err(OsError::IoErrorWouldBlock) => match deadline {
+            Some(deadline) if timer::current_time() >= deadline => {
+                set_returns(&mut p.context, Err(OsError::IoErrorTimedOut));
+                true
+            }
+            _ => false,
+        },
+        result => {
+            set_returns(&mut p.context, result);
+            true
+        }
+    });
+    SCHEDULER.switch(State::Waiting(boxed_fnmut), tf);
+}
+
+/// Runs `f` with the page table of the process that issued the system call
+/// saved in `tf`. All accesses to user memory go through this page table via
+/// `copy_from_user`, `copy_to_user`, `UserPtr` and `UserStr`.
+///
+/// The stack of the process is mapped down to its stack pointer first, so
+/// that buffers on the stack are accessible even where the process has not
+/// touched them yet.
+fn with_vmap<F, R>(tf: &TrapFrame, f: F) -> R
+where
+    F: FnOnce(&UserPageTable) -> R,
+{
+    SCHEDULER.critical(|scheduler| {
+        let vmap = &mut scheduler.find_process(tf).vmap;
+        vmap.grow_stack(VirtualAddr::from(tf.get_sp() as usize));
+        f(&**vmap)
+    })
+}
+
 /// Sends data with a connected socket.
 ///
 /// This system call takes a socket descriptor as the first parameter, the
 /// address of the buffer as the second parameter, and the length of the buffer
-/// as the third parameter.
+/// as the third parameter. While the connection is being established or the
+/// send buffer is full, the process waits as set by `sock_setopt`. At most
+/// `SOCKET_BUFFER_SIZE` bytes are sent by one call.
 ///
 /// In addition to the usual status value, this system call returns one
 /// parameter: the number of bytes sent.
@@ -217,32 +763,66 @@ unsafe fn to_user_slice_mut<'a>(va: usize, len: usize) -> OsResult<&'a mut [u8]>
 ///
 /// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
 /// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
-/// - `OsError::IllegalSocketOperation`: `send_slice()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::IllegalSocketOperation`: The socket is not a TCP socket, or `send_slice()` returned
+///   `smoltcp::Error::Illegal`.
+/// - `OsError::IoErrorWouldBlock`: The socket is non-blocking and nothing can be sent yet.
+/// - `OsError::IoErrorTimedOut`: Nothing could be sent in the timeout of the socket.
 /// - `OsError::Unknown`: All the other errors from smoltcp.
 pub fn sys_sock_send(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_send")
+    let socket = match find_socket(sock_idx, tf) {
+        Ok(socket) => socket,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+    if let Err(e) = with_vmap(tf, |vmap| access_ok(vmap, va, len, false)) {
+        return tf.set_gpr(7, e as u64);
+    }
+
+    let mut buf = vec![0u8; core::cmp::min(len, SOCKET_BUFFER_SIZE)];
+    if let Err(e) = with_vmap(tf, |vmap| copy_from_user(vmap, &mut buf, va)) {
+        return tf.set_gpr(7, e as u64);
+    }
+
+    complete_or_wait(Wait::for_socket(&socket), tf, move |_| {
+        let sent = ETHERNET.critical(|ethernet| ethernet.send(socket, &buf))?;
+        Ok([sent as u64, 0, 0])
+    });
 }
 
 /// Receives data from a connected socket.
 ///
 /// This system call takes a socket descriptor as the first parameter, the
 /// address of the buffer as the second parameter, and the length of the buffer
-/// as the third parameter.
+/// as the third parameter. Until data is received, the process waits as set by
+/// `sock_setopt`. At most `SOCKET_BUFFER_SIZE` bytes are read by one call.
 ///
 /// In addition to the usual status value, this system call returns one
-/// parameter: the number of bytes read.
+/// parameter: the number of bytes read, which is 0 once the remote endpoint has
+/// closed the connection.
 ///
 /// # Errors
 /// This function can return following errors:
 ///
 /// - `OsError::InvalidSocket`: Cannot find a socket that corresponds to the provided descriptor.
 /// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
-/// - `OsError::IllegalSocketOperation`: `recv_slice()` returned `smoltcp::Error::Illegal`.
+/// - `OsError::IllegalSocketOperation`: The socket is not a TCP socket or is listening.
+/// - `OsError::IoErrorWouldBlock`: The socket is non-blocking and nothing has been received.
+/// - `OsError::IoErrorTimedOut`: Nothing was received in the timeout of the socket.
 /// - `OsError::Unknown`: All the other errors from smoltcp.
 pub fn sys_sock_recv(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame) {
-    // Lab 5 2.D
-    unimplemented!("sys_sock_recv")
+    let socket = match find_socket(sock_idx, tf) {
+        Ok(socket) => socket,
+        Err(e) => return tf.set_gpr(7, e as u64),
+    };
+    if let Err(e) = with_vmap(tf, |vmap| access_ok(vmap, va, len, true)) {
+        return tf.set_gpr(7, e as u64);
+    }
+
+    let mut buf = vec![0u8; core::cmp::min(len, SOCKET_BUFFER_SIZE)];
+    complete_or_wait(Wait::for_socket(&socket), tf, move |p| {
+        let received = ETHERNET.critical(|ethernet| ethernet.recv(socket, &mut buf))?;
+        copy_to_user(&p.vmap, va, &buf[..received])?;
+        Ok([received as u64, 0, 0])
+    });
 }
 
 /// Writes a UTF-8 string to the console.
@@ -259,12 +839,11 @@ pub fn sys_sock_recv(sock_idx: usize, va: usize, len: usize, tf: &mut TrapFrame)
 /// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
 /// - `OsError::InvalidArgument`: The provided buffer is not UTF-8 encoded.
 pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
-    let result = unsafe { to_user_slice(va, len) }
-        .and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument));
+    let result = with_vmap(tf, |vmap| UserStr::new(va, len).read(vmap));
 
     match result {
         Ok(msg) => {
-            kprint!("{}", msg);
+            CONSOLE.lock().write_buffered(Vt::Programs, msg.as_bytes());
 
             tf.set_gpr(0, msg.len() as u64);
             tf.set_gpr(7, OsError::Ok as u64);
@@ -275,6 +854,8 @@ pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
     }
 }
 
+/// Runs the system call `num` for the process whose trap frame is `tf`. An
+/// unknown system call fails with `OsError::Unknown`.
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     match num {
         1 => {
@@ -298,9 +879,99 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
             let slice_ptr_va = tf.get_gpr(0) as usize;
             let slice_len = tf.get_gpr(1) as usize;
             sys_write_str(slice_ptr_va, slice_len, tf);
+        },
+        7 => {
+            let va = tf.get_gpr(0) as usize;
+            let len = tf.get_gpr(1) as usize;
+            sys_read(va, len, tf);
+        }
+        20 => {
+            let ty = tf.get_gpr(0);
+            sys_sock_create(ty, tf);
+        }
+        21 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            sys_sock_status(sock_idx, tf);
+        }
+        22 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            let addr_va = tf.get_gpr(1) as usize;
+            sys_sock_connect(sock_idx, addr_va, tf);
+        }
+        23 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            let local_port = tf.get_gpr(1) as u16;
+            sys_sock_listen(sock_idx, local_port, tf);
+        }
+        24 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            let va = tf.get_gpr(1) as usize;
+            let len = tf.get_gpr(2) as usize;
+            sys_sock_send(sock_idx, va, len, tf);
+        }
+        25 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            let va = tf.get_gpr(1) as usize;
+            let len = tf.get_gpr(2) as usize;
+            sys_sock_recv(sock_idx, va, len, tf);
+        }
+        26 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            let local_port = tf.get_gpr(1) as u16;
+            sys_sock_bind(sock_idx, local_port, tf);
+        }
+        27 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            let va = tf.get_gpr(1) as usize;
+            let len = tf.get_gpr(2) as usize;
+            let addr_va = tf.get_gpr(3) as usize;
+            sys_sock_sendto(sock_idx, va, len, addr_va, tf);
+        }
+        28 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            let va = tf.get_gpr(1) as usize;
+            let len = tf.get_gpr(2) as usize;
+            let addr_va = tf.get_gpr(3) as usize;
+            sys_sock_recvfrom(sock_idx, va, len, addr_va, tf);
+        }
+        29 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            let addr_va = tf.get_gpr(1) as usize;
+            sys_sock_accept(sock_idx, addr_va, tf);
+        }
+        30 => {
+            let sock_idx = tf.get_gpr(0) as usize;
+            let opt = tf.get_gpr(1);
+            let value = tf.get_gpr(2);
+            sys_sock_setopt(sock_idx, opt, value, tf);
+        }
+        31 => {
+            let va = tf.get_gpr(0) as usize;
+            let count = tf.get_gpr(1) as usize;
+            let timeout_ms = tf.get_gpr(2);
+            sys_sock_poll(va, count, timeout_ms, tf);
+        }
+        32 => {
+            let va = tf.get_gpr(0) as usize;
+            let len = tf.get_gpr(1) as usize;
+            let addr_va = tf.get_gpr(2) as usize;
+            sys_resolve(va, len, addr_va, tf);
         }
         _ => {
-            panic!("Other syscalls not yet implemented");
+            tf.set_gpr(7, OsError::Unknown as u64);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_syscall_fails() {
+        let mut tf: TrapFrame = Default::default();
+        tf.set_gpr(7, OsError::Ok as u64);
+        handle_syscall(0xffff, &mut tf);
+        assert_eq!(OsError::from(tf.get_gpr(7)), OsError::Unknown);
+    }
+}
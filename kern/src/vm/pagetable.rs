@@ -1,9 +1,10 @@
 
-use core::iter::Chain;
+use core::iter::FlatMap;
 use core::ops::{Deref, DerefMut};
 use core::slice::Iter;
 
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use alloc::fmt;
 use core::alloc::{GlobalAlloc, Layout};
 
@@ -72,14 +73,18 @@ impl L3Entry {
     /// Extracts `ADDR` field of the L3Entry and returns as a `PhysicalAddr`
     /// if valid. Otherwise, return `None`.
     fn get_page_addr(&self) -> Option<PhysicalAddr> {
-        //what does it mean by "if valid"?
         let raw_entry = self.0;
-        let addr: PhysicalAddr = From::from(raw_entry.get_value(RawL3Entry::ADDR));
+        let addr: PhysicalAddr = From::from(raw_entry.get_masked(RawL3Entry::ADDR));
         match self.is_valid() {
             true => Some(addr),
             false => None
         }
     }
+    /// Returns the `AP` (access permission) field of the L3Entry.
+    /// See `aarch64::vmsa::EntryPerm` for the possible values.
+    fn perm(&self) -> u64 {
+        self.0.get_value(RawL3Entry::AP)
+    }
 }
 
 #[repr(C)]
@@ -107,82 +112,89 @@ impl L3PageTable {
 }
 
 
+/// Number of L2 entries, and so the maximum number of L3 tables, needed to
+/// translate a region whose size is set by `mask_bits` (T0SZ/T1SZ).
+const fn l2_span(mask_bits: usize) -> usize {
+    1 << (64 - mask_bits - 29)
+}
+
 #[repr(C)]
 #[repr(align(65536))]
 pub struct PageTable {
     pub l2: L2PageTable,
-    pub l3: [Box<L3PageTable>; 3],
+    /// L3 tables indexed by L2 index. Tables are allocated on demand the first
+    /// time an entry inside their 512MiB span is set.
+    pub l3: Vec<Option<Box<L3PageTable>>>,
+    perm: u64,
 }
 
 impl PageTable {
-    /// Returns a new `Box` containing `PageTable`.
-    /// Entries in L2PageTable should be initialized properly before return.
-    fn new(perm: u64) -> Box<PageTable> {
-        //how would you do this without a box? keeping 
-        // in mind struct being on the stack and trying to get the address of L3 entries to put in L2
-        // let l2 = L2PageTable::new();
-        // let l3 = [L3PageTable::new(), L3PageTable::new(), L3PageTable::new()];
-        // let pagetable = PageTable {
-        //     l2,
-        //     l3
-        // };
-        let mut boxed_pagetable = Box::new(PageTable {
+    /// Returns a new `Box` containing `PageTable` that can translate a region
+    /// of `2^(64 - mask_bits)` bytes. No L3 table is allocated up front; the
+    /// corresponding L2 entries stay invalid until `set_entry()` needs them.
+    fn new(perm: u64, mask_bits: usize) -> Box<PageTable> {
+        let l3 = (0..l2_span(mask_bits)).map(|_| None).collect();
+        Box::new(PageTable {
             l2: L2PageTable::new(),
-            l3: [Box::new(L3PageTable::new()),Box::new(L3PageTable::new()), Box::new(L3PageTable::new())]
-        });
-        
-        PageTable::initialize_l2_entries(&mut boxed_pagetable, perm, 3);
-        
-        boxed_pagetable
-
+            l3,
+            perm,
+        })
     }
-    fn initialize_l2_entries(pagetable: &mut Box<PageTable>, perm: u64, num_entries: u16) {
-        for i in (0..num_entries as usize) {   
-            let current_entry = &mut pagetable.l2.entries[i];
+
+    /// Allocates the L3 table for `l2_index` if it doesn't exist yet and
+    /// points the matching L2 entry at it.
+    fn ensure_l3(&mut self, l2_index: usize) -> &mut L3PageTable {
+        if self.l3[l2_index].is_none() {
+            let table = Box::new(L3PageTable::new());
+            let table_addr = table.as_ptr().as_u64();
+
+            let entry = &mut self.l2.entries[l2_index];
             //set VALID to 1
-            current_entry.set_value(0b1, RawL2Entry::VALID);
-            //set TYPE to 1
-            current_entry.set_value(0b1, RawL2Entry::TYPE);
+            entry.set_value(0b1, RawL2Entry::VALID);
+            //set TYPE to 1 (table descriptor)
+            entry.set_value(0b1, RawL2Entry::TYPE);
             //set ATTR to 0b000 (normal memory)
-            current_entry.set_value(0b000, RawL2Entry::ATTR);
-            // set AP to 0b01 (User R/W)
-            current_entry.set_value(perm, RawL2Entry::AP);
+            entry.set_value(0b000, RawL2Entry::ATTR);
+            entry.set_value(self.perm, RawL2Entry::AP);
             // set SH to 0b11 (Inner shareable)
-            current_entry.set_value(0b11, RawL2Entry::SH);
-            //set AF to 1? (not sure what value and why do we set this when we create it instead of the first time we use it)
-            current_entry.set_value(0b1, RawL2Entry::AF);
-
+            entry.set_value(0b11, RawL2Entry::SH);
+            entry.set_value(0b1, RawL2Entry::AF);
             //set ADDR to address of corresponding L3PageTable
-            let entry_value_47_16 = &*pagetable.l3[i] as *const L3PageTable as *const u64 as u64;
-            current_entry.set_masked(entry_value_47_16, RawL2Entry::ADDR);
+            entry.set_masked(table_addr, RawL2Entry::ADDR);
 
+            self.l3[l2_index] = Some(table);
         }
+        self.l3[l2_index].as_mut().unwrap()
     }
-    
+
     /// Returns the (L2index, L3index) extracted from the given virtual address.
-    /// L2index should be smaller than the number of L3PageTable.
+    /// L2index should be smaller than the number of L2 entries this table spans.
     ///
     /// # Panics
     ///
     /// Panics if the virtual address is not properly aligned to page size.
-    /// Panics if extracted L2index exceeds the number of L3PageTable.
-    fn locate(va: VirtualAddr) -> (usize, usize) {
+    /// Panics if extracted L2index exceeds the number of L2 entries in use.
+    fn locate(&self, va: VirtualAddr) -> (usize, usize) {
         let va_u64 = va.as_u64();
-        if va_u64 % PAGE_SIZE as u64 != 0 { panic!("Virtual address not aligned to PAGE_SZIE"); }
+        if va_u64 % PAGE_SIZE as u64 != 0 { panic!("Virtual address not aligned to PAGE_SIZE"); }
         //13 bits to mask different parts of the VA
         let mask = 0b1_1111_1111_1111;
-        // let l2_index = va_u64 & (mask << 29);
-        let l2_index = (va_u64 & (0b11 << 29)) >> 29;
-        let l3_index = (va_u64 & (mask << 16)) >> 16;
-        if l2_index > 2 { panic!("L2 index can't be greater than 2"); }
+        let l2_index = (va_u64 >> 29) & mask;
+        let l3_index = (va_u64 >> 16) & mask;
+        if l2_index as usize >= self.l3.len() {
+            panic!("L2 index {} out of range (max {})", l2_index, self.l3.len() - 1);
+        }
         (l2_index as usize, l3_index as usize)
     }
 
     /// Returns `true` if the L3entry indicated by the given virtual address is valid.
     /// Otherwise, `false` is returned.
     pub fn is_valid(&self, va: VirtualAddr) -> bool {
-        let (l2_index, l3_index) = PageTable::locate(va);
-        self.l3[l2_index].entries[l3_index].is_valid()
+        let (l2_index, l3_index) = self.locate(va);
+        match self.l3[l2_index] {
+            Some(ref table) => table.entries[l3_index].is_valid(),
+            None => false,
+        }
     }
 
     /// Returns `true` if the L3entry indicated by the given virtual address is invalid.
@@ -191,14 +203,27 @@ impl PageTable {
         !self.is_valid(va)
     }
 
+    /// Returns the L3Entry indicated by the given virtual address if it is valid.
+    /// Otherwise, `None` is returned.
+    pub fn get_entry(&self, va: VirtualAddr) -> Option<L3Entry> {
+        let (l2_index, l3_index) = self.locate(va);
+        match self.l3[l2_index] {
+            Some(ref table) if table.entries[l3_index].is_valid() => Some(table.entries[l3_index]),
+            _ => None,
+        }
+    }
+
     /// Set the given RawL3Entry `entry` to the L3Entry indicated by the given virtual
-    /// address.
+    /// address, allocating the covering L3 table if needed.
     pub fn set_entry(&mut self, va: VirtualAddr, entry: RawL3Entry) -> &mut Self {
-        // are the directions for this method wrong?
-        let (l2_index, l3_index) = PageTable::locate(va);
-        self.l3[l2_index].entries[l3_index].0 = entry;
+        let (l2_index, l3_index) = self.locate(va);
+        self.ensure_l3(l2_index).entries[l3_index].0 = entry;
         self
+    }
 
+    /// Returns the number of L3 tables currently allocated.
+    pub fn l3_count(&self) -> usize {
+        self.l3.iter().filter(|table| table.is_some()).count()
     }
 
     /// Returns a base address of the pagetable. The returned `PhysicalAddr` value
@@ -209,15 +234,22 @@ impl PageTable {
     }
 }
 
-// FIXME: Implement `IntoIterator` for `&PageTable`.
+type L3Slot = Option<Box<L3PageTable>>;
+
+fn l3_entries<'a>(slot: &'a L3Slot) -> Iter<'a, L3Entry> {
+    match slot {
+        Some(table) => table.entries.iter(),
+        None => [].iter(),
+    }
+}
+
+// Iterates over every entry of every allocated L3 table, in L2 index order.
 impl<'a> IntoIterator for &'a PageTable {
     type Item = &'a L3Entry;
-    type IntoIter = Chain<Iter<'a, L3Entry>, Iter<'a, L3Entry>>;
+    type IntoIter = FlatMap<Iter<'a, L3Slot>, Iter<'a, L3Entry>, fn(&'a L3Slot) -> Iter<'a, L3Entry>>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.l3[0].entries.iter()
-            .chain(self.l3[1].entries.iter())
-                // .chain(self.l3[2].entries.iter()))
+        self.l3.iter().flat_map(l3_entries as fn(&'a L3Slot) -> Iter<'a, L3Entry>)
     }
 }
 
@@ -233,7 +265,7 @@ impl KernPageTable {
     /// as address[47:16]. Refer to the definition of `RawL3Entry` in `vmsa.rs` for
     /// more details.
     pub fn new() -> KernPageTable {
-        let mut kernel_pagetable = PageTable::new(0b00); 
+        let mut kernel_pagetable = PageTable::new(EntryPerm::KERN_RW, KERNEL_MASK_BITS);
         let mut current_page_start = 0;
         let last_page_start = IO_BASE_END - PAGE_SIZE;
         //handle cases where end_address is greater than IO_BASE? is that possible
@@ -327,13 +359,56 @@ pub enum PagePerm {
     RWX,
 }
 
+/// The regions of a user process's virtual address space. See `param.rs` for
+/// the layout.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UserRegion {
+    Text,
+    Heap,
+    Mmap,
+    Stack,
+}
+
+impl UserRegion {
+    /// Returns the region containing `va`, or `None` if `va` is outside of the
+    /// user address space or falls on a guard page between regions.
+    pub fn of(va: VirtualAddr) -> Option<UserRegion> {
+        use UserRegion::*;
+        let va = va.as_usize();
+        [Text, Heap, Mmap, Stack]
+            .iter()
+            .find(|region| va >= region.base() && va - region.base() < region.size())
+            .map(|region| *region)
+    }
+
+    /// Returns the lowest virtual address of the region.
+    pub fn base(&self) -> usize {
+        match self {
+            UserRegion::Text => USER_TEXT_BASE,
+            UserRegion::Heap => USER_HEAP_BASE,
+            UserRegion::Mmap => USER_MMAP_BASE,
+            UserRegion::Stack => USER_STACK_BASE,
+        }
+    }
+
+    /// Returns the size of the region in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            UserRegion::Text => USER_TEXT_SIZE,
+            UserRegion::Heap => USER_HEAP_SIZE,
+            UserRegion::Mmap => USER_MMAP_SIZE,
+            UserRegion::Stack => USER_STACK_SIZE,
+        }
+    }
+}
+
 pub struct UserPageTable(Box<PageTable>);
 
 impl UserPageTable {
     /// Returns a new `UserPageTable` containing a `PageTable` created with
     /// `USER_RW` permission.
     pub fn new() -> UserPageTable {
-        let pagetable = PageTable::new(0b01);
+        let pagetable = PageTable::new(EntryPerm::USER_RW, USER_MASK_BITS);
         UserPageTable(pagetable)
     }
 
@@ -341,21 +416,35 @@ impl UserPageTable {
     /// physical address of the allocated page. Returns the allocated page.
     ///
     /// # Panics
-    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
+    /// Panics if the virtual address is not inside one of the `UserRegion`s,
+    /// i.e. it is lower than `USER_IMG_BASE` or sits on a guard page.
     /// Panics if the virtual address has already been allocated.
     /// Panics if allocator fails to allocate a page.
     ///
     /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> &mut [u8] {
+        if UserRegion::of(va).is_none() {
+            panic!("{:?} is not inside a user region", va);
+        }
+        let relative_va = va.as_usize() - USER_IMG_BASE;
+        if self.is_valid(VirtualAddr::from(relative_va)) {
+            panic!("{:?} has already been allocated", va);
+        }
+
         let layout = Page::layout();
         let mut allocated_page_ptr;
         unsafe {
              allocated_page_ptr = ALLOCATOR.alloc(layout);
         }
+        if allocated_page_ptr.is_null() {
+            panic!("failed to allocate a page for {:?}", va);
+        }
         let allocated_page_addr = allocated_page_ptr as *const u64 as u64;
-        let relative_va = va.as_usize() - USER_IMG_BASE;
-        self.initialize_and_set_l3_entry(relative_va, allocated_page_addr);
+        let ap = match perm {
+            PagePerm::RO => EntryPerm::USER_RO,
+            PagePerm::RW | PagePerm::RWX => EntryPerm::USER_RW,
+        };
+        self.initialize_and_set_l3_entry(relative_va, allocated_page_addr, ap);
         unsafe {
             //is this right? why are we returning an array of bytes? where is the return value ever used?
             let mut arr = core::slice::from_raw_parts_mut(allocated_page_ptr, PAGE_SIZE);
@@ -366,7 +455,30 @@ impl UserPageTable {
         }
     }
 
-    fn initialize_and_set_l3_entry(&mut self, va: usize, addr: u64) {
+    /// Maps zeroed pages at the pages of the stack region from the one holding
+    /// `va` up to the top of the stack that are not mapped yet. A process
+    /// starts with the top page of its stack only, and its stack grows down
+    /// as it touches the pages below.
+    ///
+    /// Returns `false` if `va` is not in the stack region.
+    pub fn grow_stack(&mut self, va: VirtualAddr) -> bool {
+        if UserRegion::of(va) != Some(UserRegion::Stack) {
+            return false;
+        }
+        let mut page = va.as_usize() & PAGE_MASK;
+        loop {
+            if !self.is_valid(VirtualAddr::from(page - USER_IMG_BASE)) {
+                let memory = self.alloc(VirtualAddr::from(page), PagePerm::RW);
+                unsafe { core::ptr::write_bytes(memory.as_mut_ptr(), 0, memory.len()) };
+            }
+            page = match page.checked_add(PAGE_SIZE) {
+                Some(next) => next,
+                None => return true,
+            };
+        }
+    }
+
+    fn initialize_and_set_l3_entry(&mut self, va: usize, addr: u64, ap: u64) {
             let mut entry = RawL3Entry::new(0);
             //set VALID to 1
             entry.set_value(0b1, RawL2Entry::VALID);
@@ -374,8 +486,8 @@ impl UserPageTable {
             entry.set_value(0b1, RawL2Entry::TYPE);
             //set ATTR to 0b000 (normal memory)
             entry.set_value(0b000, RawL2Entry::ATTR);
-            // set AP to 0b01 (User R/W)
-            entry.set_value(0b01, RawL2Entry::AP);
+            // set AP to the requested user permission
+            entry.set_value(ap, RawL2Entry::AP);
             // set SH to 0b11 (Inner shareable)
             entry.set_value(0b11, RawL2Entry::SH);
             //set AF to 1? (not sure what value and why do we set this when we create it instead of the first time we use it)
@@ -385,6 +497,30 @@ impl UserPageTable {
             //set the RawL3Entry in the L3 table
             self.set_entry(VirtualAddr::from(va), entry);
     }
+
+    /// Translates the user virtual address `va` into the physical address it
+    /// is mapped to. Returns `None` if `va` is not in the user address space,
+    /// its page is not mapped, or the page is not accessible from EL0 (for
+    /// writing, when `write` is `true`).
+    pub fn translate(&self, va: VirtualAddr, write: bool) -> Option<PhysicalAddr> {
+        let va = va.as_usize();
+        if va < USER_IMG_BASE {
+            return None;
+        }
+        let relative_va = va - USER_IMG_BASE;
+        let page_va = relative_va & PAGE_MASK;
+        let entry = self.get_entry(VirtualAddr::from(page_va))?;
+        let allowed = match entry.perm() {
+            EntryPerm::USER_RW => true,
+            EntryPerm::USER_RO => !write,
+            _ => false,
+        };
+        if !allowed {
+            return None;
+        }
+        let page = entry.get_page_addr()?;
+        Some(page + PhysicalAddr::from(relative_va - page_va))
+    }
 }
 
 impl Deref for KernPageTable {
@@ -441,3 +577,113 @@ impl fmt::Debug for UserPageTable {
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const L2_SPAN: usize = 1 << 29;
+
+    fn page_entry(addr: u64) -> RawL3Entry {
+        let mut entry = RawL3Entry::new(0);
+        entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
+        entry.set_value(PageType::Page, RawL3Entry::TYPE);
+        entry.set_value(0b1, RawL3Entry::AF);
+        entry.set_masked(addr, RawL3Entry::ADDR);
+        entry
+    }
+
+    fn user_table() -> Box<PageTable> {
+        PageTable::new(EntryPerm::USER_RW, USER_MASK_BITS)
+    }
+
+    #[test]
+    fn locate_splits_indices() {
+        let pt = user_table();
+        assert_eq!(pt.locate(VirtualAddr::from(0usize)), (0, 0));
+        assert_eq!(pt.locate(VirtualAddr::from(PAGE_SIZE)), (0, 1));
+        assert_eq!(pt.locate(VirtualAddr::from(L2_SPAN - PAGE_SIZE)), (0, 8191));
+        assert_eq!(pt.locate(VirtualAddr::from(L2_SPAN)), (1, 0));
+        assert_eq!(pt.locate(VirtualAddr::from(3 * L2_SPAN + 7 * PAGE_SIZE)), (3, 7));
+        assert_eq!(
+            pt.locate(VirtualAddr::from(USER_MAX_VM_SIZE - PAGE_SIZE)),
+            (l2_span(USER_MASK_BITS) - 1, 8191)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn locate_panics_on_unaligned() {
+        user_table().locate(VirtualAddr::from(PAGE_SIZE + 8));
+    }
+
+    #[test]
+    #[should_panic]
+    fn locate_panics_out_of_range() {
+        user_table().locate(VirtualAddr::from(USER_MAX_VM_SIZE));
+    }
+
+    #[test]
+    fn set_entry_allocates_l3_on_demand() {
+        let mut pt = user_table();
+        assert_eq!(pt.l3_count(), 0);
+        assert!(pt.is_invalid(VirtualAddr::from(5 * L2_SPAN)));
+
+        pt.set_entry(VirtualAddr::from(5 * L2_SPAN + PAGE_SIZE), page_entry(0x1_0000));
+        assert_eq!(pt.l3_count(), 1);
+        assert!(pt.is_valid(VirtualAddr::from(5 * L2_SPAN + PAGE_SIZE)));
+        assert!(pt.is_invalid(VirtualAddr::from(5 * L2_SPAN)));
+        assert!(pt.is_invalid(VirtualAddr::from(4 * L2_SPAN + PAGE_SIZE)));
+
+        let l2_entry = pt.l2.entries[5];
+        assert_eq!(l2_entry.get_value(RawL2Entry::VALID), EntryValid::Valid);
+        assert_eq!(l2_entry.get_value(RawL2Entry::TYPE), EntryType::Table);
+        assert_eq!(
+            l2_entry.get_masked(RawL2Entry::ADDR),
+            pt.l3[5].as_ref().unwrap().as_ptr().as_u64()
+        );
+        assert_eq!(pt.l2.entries[4].get_value(RawL2Entry::VALID), EntryValid::Invalid);
+
+        // A second entry in the same span reuses the table.
+        pt.set_entry(VirtualAddr::from(5 * L2_SPAN + 2 * PAGE_SIZE), page_entry(0x2_0000));
+        assert_eq!(pt.l3_count(), 1);
+
+        pt.set_entry(VirtualAddr::from(USER_MAX_VM_SIZE - PAGE_SIZE), page_entry(0x3_0000));
+        assert_eq!(pt.l3_count(), 2);
+    }
+
+    #[test]
+    fn iterator_walks_allocated_tables() {
+        let mut pt = user_table();
+        assert_eq!(pt.into_iter().count(), 0);
+
+        pt.set_entry(VirtualAddr::from(9 * L2_SPAN + 3 * PAGE_SIZE), page_entry(0x9_0000));
+        pt.set_entry(VirtualAddr::from(2 * L2_SPAN), page_entry(0x2_0000));
+        pt.set_entry(VirtualAddr::from(2 * L2_SPAN + PAGE_SIZE), page_entry(0x2_1000_0000));
+        assert_eq!(pt.into_iter().count(), 2 * 8192);
+
+        let pages: Vec<u64> = pt
+            .into_iter()
+            .filter(|entry| entry.is_valid())
+            .map(|entry| entry.get_page_addr().unwrap().as_u64())
+            .collect();
+        assert_eq!(pages, vec![0x2_0000, 0x2_1000_0000, 0x9_0000]);
+    }
+
+    #[test]
+    fn user_regions_and_guard_pages() {
+        let region = |va: usize| UserRegion::of(VirtualAddr::from(va));
+
+        assert_eq!(region(USER_IMG_BASE - PAGE_SIZE), None);
+        assert_eq!(region(USER_TEXT_BASE), Some(UserRegion::Text));
+        assert_eq!(region(USER_TEXT_BASE + USER_TEXT_SIZE - PAGE_SIZE), Some(UserRegion::Text));
+        assert_eq!(region(USER_TEXT_BASE + USER_TEXT_SIZE), None);
+        assert_eq!(region(USER_HEAP_BASE), Some(UserRegion::Heap));
+        assert_eq!(region(USER_HEAP_BASE + USER_HEAP_SIZE), None);
+        assert_eq!(region(USER_MMAP_BASE), Some(UserRegion::Mmap));
+        assert_eq!(region(USER_MMAP_BASE + USER_MMAP_SIZE), None);
+        assert_eq!(region(USER_STACK_BASE - PAGE_SIZE), None);
+        assert_eq!(region(USER_STACK_BASE), Some(UserRegion::Stack));
+        assert_eq!(region(core::usize::MAX & PAGE_MASK), Some(UserRegion::Stack));
+    }
+}
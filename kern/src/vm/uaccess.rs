@@ -0,0 +1,339 @@
+use alloc::string::String;
+use alloc::vec;
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of, MaybeUninit};
+
+use kernel_api::{OsError, OsResult, PollFd, SockAddr};
+
+use crate::param::PAGE_SIZE;
+use crate::vm::{UserPageTable, VirtualAddr};
+
+/// Checks that every page of the user range `[va, va + len)` is mapped in
+/// `vmap` and accessible from EL0 (for writing, when `write` is `true`).
+///
+/// # Errors
+///
+/// Returns `OsError::BadAddress` if the range overflows or any page of it is
+/// not mapped with the requested permission. An empty range is always
+/// accessible.
+pub fn access_ok(vmap: &UserPageTable, va: usize, len: usize, write: bool) -> OsResult<()> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = va.checked_add(len).ok_or(OsError::BadAddress)?;
+    let mut page = va & !(PAGE_SIZE - 1);
+    while page < end {
+        vmap.translate(VirtualAddr::from(page), write).ok_or(OsError::BadAddress)?;
+        page = match page.checked_add(PAGE_SIZE) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    Ok(())
+}
+
+/// Walks the user range `[va, va + len)` page by page, calling `f` with the
+/// kernel-accessible pointer of each chunk and the chunk's offset in the range.
+/// The whole range is validated up front so a failing copy has no side effect.
+fn for_each_chunk<F>(vmap: &UserPageTable, va: usize, len: usize, write: bool, mut f: F) -> OsResult<()>
+where
+    F: FnMut(*mut u8, usize, usize),
+{
+    access_ok(vmap, va, len, write)?;
+
+    let mut done = 0;
+    while done < len {
+        let cur = va + done;
+        let chunk = core::cmp::min(len - done, PAGE_SIZE - cur % PAGE_SIZE);
+        let pa = vmap.translate(VirtualAddr::from(cur), write).ok_or(OsError::BadAddress)?;
+        f(pa.as_usize() as *mut u8, done, chunk);
+        done += chunk;
+    }
+    Ok(())
+}
+
+/// Copies `dst.len()` bytes from the user address `va` into `dst`.
+///
+/// # Errors
+///
+/// Returns `OsError::BadAddress` if any byte of the source is not readable by
+/// the process owning `vmap`.
+pub fn copy_from_user(vmap: &UserPageTable, dst: &mut [u8], va: usize) -> OsResult<()> {
+    for_each_chunk(vmap, va, dst.len(), false, |src, offset, len| unsafe {
+        core::ptr::copy_nonoverlapping(src as *const u8, dst[offset..].as_mut_ptr(), len);
+    })
+}
+
+/// Copies `src` to the user address `va`.
+///
+/// # Errors
+///
+/// Returns `OsError::BadAddress` if any byte of the destination is not
+/// writable by the process owning `vmap`.
+pub fn copy_to_user(vmap: &UserPageTable, va: usize, src: &[u8]) -> OsResult<()> {
+    for_each_chunk(vmap, va, src.len(), true, |dst, offset, len| unsafe {
+        core::ptr::copy_nonoverlapping(src[offset..].as_ptr(), dst, len);
+    })
+}
+
+/// Plain data that can be copied to and from user memory as bytes.
+///
+/// # Safety
+///
+/// Every bit pattern must be a valid value of the type, since a process can
+/// put any bytes at a `UserPtr`, and the type must have no padding, whose
+/// bytes would leak kernel memory to the process. Integers, arrays of them
+/// and `#[repr(C)]` structs of them without padding qualify; `bool`, `char`,
+/// enums, references and pointers do not.
+pub unsafe trait UserData: Copy {}
+
+macro impl_user_data($($t:ty),*) {
+    $(unsafe impl UserData for $t {})*
+}
+
+impl_user_data!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+macro impl_user_data_arrays($($n:expr),*) {
+    $(unsafe impl<T: UserData> UserData for [T; $n] {})*
+}
+
+impl_user_data_arrays!(1, 2, 3, 4, 5, 6, 7, 8, 16, 32);
+
+// `#[repr(C)]` structs of integers without padding: `PollFd` holds a `u64`
+// descriptor and two `u32`s, `SockAddr` two `u16`s and 16 bytes.
+unsafe impl UserData for PollFd {}
+unsafe impl UserData for SockAddr {}
+
+/// A typed pointer into user memory. The pointer is never dereferenced
+/// directly; values are copied in and out through the process's page table.
+#[derive(Debug)]
+pub struct UserPtr<T> {
+    va: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        UserPtr { va: self.va, _marker: PhantomData }
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: UserData> UserPtr<T> {
+    /// Returns a `UserPtr` for the user virtual address `va`.
+    pub fn new(va: usize) -> UserPtr<T> {
+        UserPtr { va, _marker: PhantomData }
+    }
+
+    /// Returns the user virtual address of this pointer.
+    pub fn addr(&self) -> usize {
+        self.va
+    }
+
+    fn check_align(&self) -> OsResult<()> {
+        if self.va % align_of::<T>() == 0 {
+            Ok(())
+        } else {
+            Err(OsError::BadAddress)
+        }
+    }
+
+    /// Reads a `T` from user memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BadAddress` if the pointer is misaligned or the value
+    /// is not readable by the process owning `vmap`.
+    pub fn read(&self, vmap: &UserPageTable) -> OsResult<T> {
+        self.check_align()?;
+        let mut val = MaybeUninit::<T>::uninit();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        copy_from_user(vmap, bytes, self.va)?;
+        Ok(unsafe { val.assume_init() })
+    }
+
+    /// Writes `val` to user memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BadAddress` if the pointer is misaligned or the value
+    /// is not writable by the process owning `vmap`.
+    pub fn write(&self, vmap: &UserPageTable, val: T) -> OsResult<()> {
+        self.check_align()?;
+        let bytes = unsafe {
+            core::slice::from_raw_parts(&val as *const T as *const u8, size_of::<T>())
+        };
+        copy_to_user(vmap, self.va, bytes)
+    }
+}
+
+/// A UTF-8 string of known length in user memory.
+#[derive(Debug, Copy, Clone)]
+pub struct UserStr {
+    va: usize,
+    len: usize,
+}
+
+impl UserStr {
+    /// Returns a `UserStr` for the `len` bytes at user virtual address `va`.
+    pub fn new(va: usize, len: usize) -> UserStr {
+        UserStr { va, len }
+    }
+
+    /// Copies the string into kernel memory.
+    ///
+    /// # Errors
+    ///
+    /// - `OsError::BadAddress`: The string is not readable by the process owning `vmap`.
+    /// - `OsError::InvalidArgument`: The string is not UTF-8 encoded.
+    pub fn read(&self, vmap: &UserPageTable) -> OsResult<String> {
+        access_ok(vmap, self.va, self.len, false)?;
+        let mut buf = vec![0u8; self.len];
+        copy_from_user(vmap, &mut buf, self.va)?;
+        String::from_utf8(buf).map_err(|_| OsError::InvalidArgument)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    use aarch64::vmsa::{EntryPerm, EntryValid, PageType, RawL3Entry};
+    use kernel_api::OsError;
+
+    use crate::param::{PAGE_SIZE, USER_HEAP_BASE, USER_IMG_BASE};
+    use crate::vm::{UserPageTable, VirtualAddr};
+
+    use super::*;
+
+    #[repr(align(65536))]
+    struct TestPage([u8; PAGE_SIZE]);
+
+    /// A user page table mapping host pages: a read-write page at
+    /// `USER_HEAP_BASE`, a read-only page after it, and nothing after that.
+    struct Mapping {
+        vmap: UserPageTable,
+        pages: Vec<(usize, Box<TestPage>)>,
+    }
+
+    const RW: usize = USER_HEAP_BASE;
+    const RO: usize = USER_HEAP_BASE + PAGE_SIZE;
+    const UNMAPPED: usize = USER_HEAP_BASE + 2 * PAGE_SIZE;
+
+    impl Mapping {
+        fn new() -> Mapping {
+            let mut mapping = Mapping { vmap: UserPageTable::new(), pages: Vec::new() };
+            mapping.map(RW, EntryPerm::USER_RW);
+            mapping.map(RO, EntryPerm::USER_RO);
+            mapping
+        }
+
+        fn map(&mut self, va: usize, ap: u64) {
+            let mut page = Box::new(TestPage([0; PAGE_SIZE]));
+            let mut entry = RawL3Entry::new(0);
+            entry.set_value(EntryValid::Valid, RawL3Entry::VALID);
+            entry.set_value(PageType::Page, RawL3Entry::TYPE);
+            entry.set_value(ap, RawL3Entry::AP);
+            entry.set_value(0b1, RawL3Entry::AF);
+            entry.set_masked(page.0.as_mut_ptr() as u64, RawL3Entry::ADDR);
+            self.vmap.set_entry(VirtualAddr::from(va - USER_IMG_BASE), entry);
+            self.pages.push((va, page));
+        }
+
+        fn page(&self, va: usize) -> &[u8] {
+            &self.pages.iter().find(|(base, _)| *base == va).unwrap().1 .0
+        }
+    }
+
+    impl Drop for Mapping {
+        /// Unmaps the host pages so the page table does not give them back
+        /// to the kernel allocator.
+        fn drop(&mut self) {
+            for &(va, _) in self.pages.iter() {
+                self.vmap.set_entry(VirtualAddr::from(va - USER_IMG_BASE), RawL3Entry::new(0));
+            }
+        }
+    }
+
+    #[test]
+    fn unmapped_pages() {
+        let mapping = Mapping::new();
+        let vmap = &mapping.vmap;
+        assert_eq!(access_ok(vmap, RW, PAGE_SIZE, true), Ok(()));
+        assert_eq!(access_ok(vmap, UNMAPPED, 1, false), Err(OsError::BadAddress));
+        // Kernel addresses are never user memory.
+        assert_eq!(access_ok(vmap, USER_IMG_BASE - PAGE_SIZE, 1, false), Err(OsError::BadAddress));
+
+        let mut buf = [0; 4];
+        assert_eq!(copy_from_user(vmap, &mut buf, UNMAPPED + 8), Err(OsError::BadAddress));
+        assert_eq!(copy_to_user(vmap, UNMAPPED, &buf), Err(OsError::BadAddress));
+    }
+
+    #[test]
+    fn ranges_crossing_the_mapping() {
+        let mapping = Mapping::new();
+        let data = [0xaa; 8];
+        // The range ends in the unmapped page.
+        assert_eq!(access_ok(&mapping.vmap, UNMAPPED - 4, 8, false), Err(OsError::BadAddress));
+        // A failed copy leaves the mapped part untouched.
+        assert_eq!(copy_to_user(&mapping.vmap, RO - 4, &data), Err(OsError::BadAddress));
+        assert_eq!(&mapping.page(RW)[PAGE_SIZE - 4..], &[0; 4]);
+
+        // Copies spanning two pages read from both.
+        assert_eq!(copy_to_user(&mapping.vmap, RO - 4, &data[..4]), Ok(()));
+        let mut buf = [0; 8];
+        assert_eq!(copy_from_user(&mapping.vmap, &mut buf, RO - 4), Ok(()));
+        assert_eq!(buf, [0xaa, 0xaa, 0xaa, 0xaa, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn overflowing_ranges() {
+        let mapping = Mapping::new();
+        let vmap = &mapping.vmap;
+        assert_eq!(access_ok(vmap, RW, usize::max_value(), false), Err(OsError::BadAddress));
+        assert_eq!(access_ok(vmap, usize::max_value(), 2, false), Err(OsError::BadAddress));
+        assert_eq!(UserStr::new(RW, usize::max_value() - RW + 1).read(vmap), Err(OsError::BadAddress));
+    }
+
+    #[test]
+    fn empty_ranges() {
+        let mapping = Mapping::new();
+        let vmap = &mapping.vmap;
+        assert_eq!(access_ok(vmap, UNMAPPED + 3, 0, true), Ok(()));
+        assert_eq!(access_ok(vmap, 0, 0, false), Ok(()));
+        assert_eq!(copy_from_user(vmap, &mut [], UNMAPPED), Ok(()));
+        assert_eq!(copy_to_user(vmap, 0, &[]), Ok(()));
+        assert_eq!(UserStr::new(UNMAPPED, 0).read(vmap), Ok(String::new()));
+    }
+
+    #[test]
+    fn read_only_pages() {
+        let mapping = Mapping::new();
+        let vmap = &mapping.vmap;
+        assert_eq!(access_ok(vmap, RO, 16, false), Ok(()));
+        assert_eq!(access_ok(vmap, RO, 16, true), Err(OsError::BadAddress));
+        assert_eq!(copy_to_user(vmap, RO + 8, &[1]), Err(OsError::BadAddress));
+        assert_eq!(UserPtr::<u64>::new(RO).write(vmap, 7), Err(OsError::BadAddress));
+        assert_eq!(UserPtr::<u64>::new(RO).read(vmap), Ok(0));
+    }
+
+    #[test]
+    fn typed_pointers_and_strings() {
+        let mapping = Mapping::new();
+        let vmap = &mapping.vmap;
+        let ptr = UserPtr::<u32>::new(RW + 16);
+        assert_eq!(ptr.write(vmap, 0xdead_beef), Ok(()));
+        assert_eq!(ptr.read(vmap), Ok(0xdead_beef));
+        assert_eq!(UserPtr::<u32>::new(RW + 2).read(vmap), Err(OsError::BadAddress));
+        // A value straddling the end of the mapping.
+        assert_eq!(UserPtr::<[u32; 2]>::new(UNMAPPED - 4).read(vmap), Err(OsError::BadAddress));
+
+        assert_eq!(copy_to_user(vmap, RW + 32, b"hello\xff"), Ok(()));
+        assert_eq!(UserStr::new(RW + 32, 5).read(vmap), Ok(String::from("hello")));
+        assert_eq!(UserStr::new(RW + 32, 6).read(vmap), Err(OsError::InvalidArgument));
+    }
+}
@@ -103,3 +103,31 @@ pub fn set_interrupt_mask(mask: u64) {
          : "volatile");
     }
 }
+
+/// Translates `va` through the current EL1 stage 1 page tables as if for a
+/// read. The result, or the reason the translation failed, is left in
+/// `PAR_EL1`.
+#[inline(always)]
+pub unsafe fn at_s1e1r(va: u64) {
+    asm!("at s1e1r, $0
+          isb"
+         :
+         : "r"(va)
+         :
+         : "volatile");
+}
+
+/// Makes instructions written through the data cache at `addr` visible to
+/// instruction fetches on every core.
+#[inline(always)]
+pub unsafe fn sync_icache(addr: u64) {
+    asm!("dc cvau, $0
+          dsb ish
+          ic ialluis
+          dsb ish
+          isb"
+         :
+         : "r"(addr)
+         : "memory"
+         : "volatile");
+}
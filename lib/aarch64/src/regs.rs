@@ -230,6 +230,8 @@ defreg!(CurrentEL, [EL[3 - 2],]);
 
 defreg!(VBAR_EL1, [RES0[10 - 0],]);
 
+defreg!(VBAR_EL2, [RES0[10 - 0],]);
+
 // (ref: D7.5.2 Counter-timer Hypervisor Control Register)
 defreg!(CNTHCTL_EL2, [EL0VCTEN[1 - 1], EL0PCTEN[0 - 0],]);
 
@@ -268,3 +270,25 @@ defreg!(CNTP_CVAL_EL0);
 
 // (ref: D7.5.12 Counter-timer Physical Timer TimerValue Register)
 defreg!(CNTP_TVAL_EL0, [TVAL[31 - 00],]);
+
+// (ref: D7.3.17 Monitor Debug System Control Register)
+defreg!(
+    MDSCR_EL1,
+    [
+        MDE[15 - 15], // Monitor debug events
+        KDE[13 - 13], // Local (kernel) debug enable
+        SS[00 - 00],  // Software step control
+    ]
+);
+
+// (ref: D7.3.26 OS Lock Access Register)
+defreg!(OSLAR_EL1, [OSLK[00 - 00],]);
+
+// (ref: D7.2.73 Physical Address Register)
+defreg!(
+    PAR_EL1,
+    [
+        PA[47 - 12], // Output address of a successful translation
+        F[00 - 00],  // Set if the translation aborted
+    ]
+);
@@ -165,6 +165,33 @@ fn test_ebpb() {
     BiosParameterBlock::from(Cursor::new(&mut data[..]), 1).expect("valid EBPB");
 }
 
+#[test]
+fn check_bpb_layout() {
+    // an MBR with a FAT32 partition at sector 1, holding its BPB
+    let mut data = vec![0u8; 1024];
+    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+    data[446 + 4] = 0xC;
+    data[446 + 8] = 1;
+    let bpb = &mut data[512..];
+    bpb[11..13].copy_from_slice(&512u16.to_le_bytes());
+    bpb[13] = 1;
+    bpb[14..16].copy_from_slice(&32u16.to_le_bytes());
+    bpb[16] = 2;
+    bpb[36..40].copy_from_slice(&8u32.to_le_bytes());
+    bpb[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    // 16 sectors, fewer than the reserved sectors and the FATs take
+    data[512 + 32..512 + 36].copy_from_slice(&16u32.to_le_bytes());
+    let e = VFat::<StdVFatHandle>::from(Cursor::new(data.clone())).unwrap_err();
+    expect_variant!(e, vfat::Error::BadLayout);
+
+    // no sectors per cluster
+    data[512 + 32..512 + 36].copy_from_slice(&1024u32.to_le_bytes());
+    data[512 + 13] = 0;
+    let e = VFat::<StdVFatHandle>::from(Cursor::new(data)).unwrap_err();
+    expect_variant!(e, vfat::Error::BadLayout);
+}
+
 #[test]
 fn check_entry_sizes() {
     check_size!(vfat::dir::VFatRegularDirEntry, 32);
@@ -181,6 +208,22 @@ fn test_vfat_init() {
     vfat_from_resource!("mock4.fat32.img");
 }
 
+#[test]
+fn test_vfat_usage() {
+    fn check_usage(vfat: StdVFatHandle) {
+        let usage = vfat.lock(|fat| fat.usage()).expect("usage");
+        assert!(usage.cluster_size.is_power_of_two());
+        assert!(usage.total_clusters > 0);
+        assert!(usage.free_clusters < usage.total_clusters, "root directory must use a cluster");
+        assert_eq!(usage.free_bytes(), usage.free_clusters as u64 * usage.cluster_size);
+    }
+
+    check_usage(vfat_from_resource!("mock1.fat32.img"));
+    check_usage(vfat_from_resource!("mock2.fat32.img"));
+    check_usage(vfat_from_resource!("mock3.fat32.img"));
+    check_usage(vfat_from_resource!("mock4.fat32.img"));
+}
+
 fn hash_entry<T: Entry>(hash: &mut String, entry: &T) -> ::std::fmt::Result {
     use std::fmt::Write;
 
@@ -473,3 +516,86 @@ fn test_root_entries() {
         let hash = hash_files_recursive_from(vfat, "/");
         assert_hash_eq!("mock 1 file hashes", hash, hash_for!("files-1"));
     }
+
+    /// A device whose reads start failing once `fail` is set.
+    struct Failing<T: BlockDevice> {
+        device: T,
+        fail: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl<T: BlockDevice> BlockDevice for Failing<T> {
+        fn sector_size(&self) -> u64 {
+            self.device.sector_size()
+        }
+
+        fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(io::Error::new(io::ErrorKind::Other, "injected read failure"));
+            }
+            self.device.read_sector(n, buf)
+        }
+
+        fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+            self.device.write_sector(n, buf)
+        }
+    }
+
+    #[test]
+    fn read_errors_are_reported() {
+        let fail = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let device = Failing { device: resource!("mock1.fat32.img"), fail: fail.clone() };
+        let vfat = VFat::<StdVFatHandle>::from(device).expect("failed to initialize VFAT from image");
+
+        let mut entries = vfat.open_dir("/").expect("root").entries().expect("entries");
+        let mut file = entries
+            .find_map(|entry| entry.into_file().filter(|file| file.size() > 0))
+            .expect("a non-empty file in the root directory");
+
+        // file data is not cached, so reading it has to go to the device
+        fail.store(true, std::sync::atomic::Ordering::SeqCst);
+        let mut buf = [0u8; 16];
+        let err = file.read(&mut buf).expect_err("read should fail");
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+
+        fail.store(false, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(file.read(&mut buf).expect("read after recovery"), core::cmp::min(16, file.size() as usize));
+    }
+
+#[test]
+fn read_after_seeking_back() {
+    let vfat = vfat_from_resource!("mock1.fat32.img");
+    let largest = || {
+        vfat.open_dir("/")
+            .expect("root")
+            .entries()
+            .expect("entries")
+            .filter_map(|entry| entry.into_file())
+            .max_by_key(|file| file.size())
+            .expect("a file in the root directory")
+    };
+
+    // small sequential reads resume the walk of the chain
+    let mut file = largest();
+    let size = file.size() as usize;
+    assert!(size >= 2);
+    let mut forward = Vec::new();
+    let mut buf = [0u8; 100];
+    loop {
+        match file.read(&mut buf).expect("read") {
+            0 => break,
+            n => forward.extend_from_slice(&buf[..n]),
+        }
+    }
+    assert_eq!(forward.len(), size);
+
+    // a read before the last position walks the chain again from its start
+    let back = (size / 2) as i64;
+    file.seek(io::SeekFrom::Current(-back)).expect("seek back");
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).expect("read to end");
+    assert_eq!(tail, &forward[size - size / 2..]);
+
+    let mut whole = Vec::new();
+    largest().read_to_end(&mut whole).expect("read at once");
+    assert_eq!(whole, forward);
+}
@@ -7,6 +7,10 @@ use shim::io;
 
 use crate::traits::BlockDevice;
 
+/// A cached sector. Entries live inline in the map of their partition, and
+/// their buffers are as large as the partition's logical sectors, so there is
+/// no fixed-size object for a slab cache to hold: the map's table and the
+/// buffers come from the global allocator.
 #[derive(Debug)]
 struct CacheEntry {
     data: Vec<u8>,
@@ -140,7 +144,7 @@ impl CachedPartition {
             for physical_sector in starting_physical_sector..ending_physical_sector {
                 // let next_slice = virtual_sector_buf.as_mut_slice()[bytes_read..bytes_read + physical_sector_size];
                 // bytes_read += self.device.read_sector(physical_sector, next_slice)? as u64;
-                self.device.read_all_sector(physical_sector, &mut virtual_sector_buf);
+                self.device.read_all_sector(physical_sector, &mut virtual_sector_buf)?;
                 // self.device.read_sector(physical_sector, physical_sector_buf.as_mut_slice());
                 // virtual_sector_buf.extend_from_slice(physical_sector_buf.as_slice());
             } 
@@ -150,6 +154,33 @@ impl CachedPartition {
         }
     }
 
+    /// Reads the sector `sector` into `buf` without caching it, so that
+    /// streaming through large files does not grow the cache. If the sector is
+    /// already cached, the cached copy is returned as it may be newer than
+    /// the disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `sector` is out of range or if there is an error
+    /// reading the sector from the disk.
+    pub fn read_uncached(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.partition.sector_size as usize;
+        if let Some(entry) = self.cache.get(&sector) {
+            buf[..size].copy_from_slice(&entry.data[..size]);
+            return Ok(size);
+        }
+
+        let physical = self.virtual_to_physical(sector).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "sector out of range")
+        })?;
+        let device_size = self.device.sector_size() as usize;
+        for i in 0..self.factor() {
+            let start = i as usize * device_size;
+            self.device.read_sector(physical + i, &mut buf[start..start + device_size])?;
+        }
+        Ok(size)
+    }
+
         // if let Some(found_cache_entry) = self.cache.get(&physical_sector) {
         //     Ok(found_cache_entry.data.as_slice())
         // } else {
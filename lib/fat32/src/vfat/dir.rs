@@ -10,7 +10,7 @@ use shim::ioerr;
 use crate::traits;
 use crate::util::VecExt;
 use crate::vfat::{Attributes, Date, Metadata, Time, Timestamp};
-use crate::vfat::{Cluster, Entry, File, VFatHandle};
+use crate::vfat::{ChainPos, Cluster, Entry, File, VFatHandle};
 
 #[derive(Debug)]
 pub struct Dir<HANDLE: VFatHandle> {
@@ -188,6 +188,7 @@ impl<HANDLE: VFatHandle> Iterator for EntryIter<HANDLE> {
                 start_cluster,
                 name,
                 cursor: 0,
+                chain_pos: ChainPos::new(start_cluster),
                 size,
                 metadata
             };
@@ -8,6 +8,8 @@ pub enum Error {
     Io(io::Error),
     BadSignature,
     NotFound,
+    /// The BPB describes a data region that does not fit the partition.
+    BadLayout,
 }
 
 impl From<mbr::Error> for Error {
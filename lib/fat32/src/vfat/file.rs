@@ -1,11 +1,10 @@
 use alloc::string::String;
-use alloc::vec::Vec;
 
 use shim::io::{self, SeekFrom};
 use shim::ioerr;
 
 use crate::traits;
-use crate::vfat::{Cluster, Metadata, VFatHandle};
+use crate::vfat::{ChainPos, Cluster, Metadata, VFatHandle};
 
 #[derive(Debug)]
 pub struct File<HANDLE: VFatHandle> {
@@ -14,6 +13,8 @@ pub struct File<HANDLE: VFatHandle> {
     pub start_cluster: Cluster,
     pub name: String,
     pub cursor: usize,
+    /// Where the last read left off in the cluster chain.
+    pub chain_pos: ChainPos,
     pub size: usize,
     pub metadata: Metadata
 }
@@ -29,15 +30,20 @@ impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
     }
 }
 impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
+    /// Reads from the current position, touching only the clusters that
+    /// hold the requested bytes.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut read_buf = Vec::new();
-        // let _:() = read_buf;
-        let read_bytes = self.vfat.lock(|fat| fat.read_chain(self.start_cluster, &mut read_buf)).expect("Error getting file size");
-        let buf_bytes = core::cmp::min(buf.len(), (self.size  - self.cursor) as usize);
-        buf[..buf_bytes].copy_from_slice(&read_buf.as_slice()[self.cursor..self.cursor + buf_bytes]);
-        self.cursor += buf_bytes;
-
-        Ok(buf_bytes)
+        let len = core::cmp::min(buf.len(), self.size - self.cursor);
+        if len == 0 {
+            return Ok(0);
+        }
+        let (start, cursor, pos) = (self.start_cluster, self.cursor, &mut self.chain_pos);
+        let read = self.vfat.lock(|fat| fat.read_chain_at(start, pos, cursor, &mut buf[..len]))?;
+        if read == 0 {
+            return ioerr!(UnexpectedEof, "cluster chain ends before the end of the file");
+        }
+        self.cursor += read;
+        Ok(read)
     }
 }
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE> {
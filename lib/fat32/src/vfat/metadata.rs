@@ -36,6 +36,12 @@ impl Attributes {
     pub fn is_dir(&self) -> bool {
         (self.0 & 0x10) == 0x10
     }
+    pub fn is_system(&self) -> bool {
+        (self.0 & 0x04) == 0x04
+    }
+    pub fn is_archive(&self) -> bool {
+        (self.0 & 0x20) == 0x20
+    }
 }
 /// A structure containing a date and time.
 #[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
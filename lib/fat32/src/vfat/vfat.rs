@@ -2,6 +2,7 @@ use core::fmt::Debug;
 use core::marker::PhantomData;
 use core::mem::size_of;
 
+use alloc::vec;
 use alloc::vec::Vec;
 use alloc::string::String;
 
@@ -25,6 +26,44 @@ pub trait VFatHandle: Clone + Debug + Send + Sync {
     fn lock<R>(&self, f: impl FnOnce(&mut VFat<Self>) -> R) -> R;
 }
 
+/// Space usage of a FAT32 file system, as reported by `VFat::usage()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    /// Bytes per cluster.
+    pub cluster_size: u64,
+    /// Number of data clusters.
+    pub total_clusters: u32,
+    /// Number of data clusters not allocated to any file or directory.
+    pub free_clusters: u32,
+}
+
+impl Usage {
+    /// Size of the data region in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_clusters as u64 * self.cluster_size
+    }
+
+    /// Unallocated bytes of the data region.
+    pub fn free_bytes(&self) -> u64 {
+        self.free_clusters as u64 * self.cluster_size
+    }
+}
+
+/// A cluster of a chain and the offset of its first byte in the chain, kept
+/// by a file handle for `VFat::read_chain_at()` to resume from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainPos {
+    pub(crate) cluster: Cluster,
+    pub(crate) offset: usize,
+}
+
+impl ChainPos {
+    /// Returns the position of the first cluster of the chain `start`.
+    pub(crate) fn new(start: Cluster) -> ChainPos {
+        ChainPos { cluster: start, offset: 0 }
+    }
+}
+
 #[derive(Debug)]
 pub struct VFat<HANDLE: VFatHandle> {
     phantom: PhantomData<HANDLE>,
@@ -35,6 +74,7 @@ pub struct VFat<HANDLE: VFatHandle> {
     fat_start_sector: u64, //mbr.partition_1.relative_sector
     data_start_sector: u64, // 
     rootdir_cluster: Cluster,
+    num_clusters: u32,
 }
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
@@ -71,7 +111,7 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         //should these be relative to the start of the partition or the start of the disk itself, in other words
         // should it be: let fat_start_sector = start_of_partition + num_reserved_sectors;
         let fat_start_sector = num_reserved_sectors;
-        let data_start_sector = fat_start_sector as u64 + (num_FATs as u32 * sectors_per_fat) as u64;
+        let data_start_sector = fat_start_sector as u64 + num_FATs as u64 * sectors_per_fat as u64;
         let num_logical_sectors = {
             if num_sectors == 0 {
                 num_sectors_alt as u64
@@ -85,6 +125,11 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
                                     sector_size: bytes_per_sector as u64
                                   };
 
+        // a corrupt BPB may put the data region past the end of the partition
+        let num_clusters = num_logical_sectors
+            .checked_sub(data_start_sector)
+            .and_then(|data_sectors| data_sectors.checked_div(sectors_per_cluster as u64))
+            .ok_or(Error::BadLayout)? as u32;
         let cached_partition = CachedPartition::new(device, partition);
 
         let vfat = VFat {
@@ -95,7 +140,8 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
                         sectors_per_fat,
                         fat_start_sector: fat_start_sector as u64,
                         data_start_sector: data_start_sector,
-                        rootdir_cluster: Cluster::from(root_cluster_num)
+                        rootdir_cluster: Cluster::from(root_cluster_num),
+                        num_clusters,
                         };
         Ok(VFatHandle::new(vfat))
 
@@ -171,6 +217,80 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
             }
         }
        }
+    /// Reads into `buf` from `offset` bytes into the cluster chain starting
+    /// at `start`. Returns the number of bytes read, which is less than
+    /// `buf.len()` only if the chain ends first. Data sectors are read
+    /// around the sector cache.
+    ///
+    /// The walk of the chain resumes from `pos` when `offset` is not before
+    /// it, and `pos` is left at the last cluster reached, so that reading a
+    /// chain front to back follows each link once.
+    pub fn read_chain_at(
+        &mut self,
+        start: Cluster,
+        pos: &mut ChainPos,
+        offset: usize,
+        buf: &mut [u8]
+    ) -> io::Result<usize> {
+        let sector_size = self.bytes_per_sector as usize;
+        let cluster_size = self.cluster_size() as usize;
+
+        let target = offset - offset % cluster_size;
+        if pos.offset > target {
+            *pos = ChainPos::new(start);
+        }
+        let mut cluster = Some(pos.cluster);
+        let mut cluster_offset = pos.offset;
+        while cluster_offset < target {
+            cluster = match cluster {
+                Some(current) => self.next_cluster(current)?,
+                None => return Ok(0),
+            };
+            cluster_offset += cluster_size;
+            if let Some(current) = cluster {
+                *pos = ChainPos { cluster: current, offset: cluster_offset };
+            }
+        }
+
+        let mut offset = offset % cluster_size;
+        let mut read = 0;
+        let mut sector = vec![0u8; sector_size];
+        while read < buf.len() {
+            let current = match cluster {
+                Some(current) => current,
+                None => break,
+            };
+            let first_sector = current.sector_from_cluster(self.data_start_sector, self.sectors_per_cluster as u64);
+            while offset < cluster_size && read < buf.len() {
+                let in_sector = offset % sector_size;
+                let n = core::cmp::min(sector_size - in_sector, buf.len() - read);
+                self.device.read_uncached(first_sector + (offset / sector_size) as u64, &mut sector)?;
+                buf[read..read + n].copy_from_slice(&sector[in_sector..in_sector + n]);
+                read += n;
+                offset += n;
+            }
+            if offset == cluster_size {
+                offset = 0;
+                cluster = self.next_cluster(current)?;
+                cluster_offset += cluster_size;
+                if let Some(next) = cluster {
+                    *pos = ChainPos { cluster: next, offset: cluster_offset };
+                }
+            }
+        }
+        Ok(read)
+    }
+
+    /// Returns the cluster following `cluster` in its chain, or `None` if
+    /// `cluster` is the last one.
+    fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => Ok(Some(next)),
+            Status::Eoc(_) => Ok(None),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "Reserved or bad cluster encountered in chain")),
+        }
+    }
+
     //    fn read_into_chain_buffer(&mut self, curr: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
     //         let bytes_in_cluster = self.sectors_per_cluster as usize * self.bytes_per_sector as usize; 
     //         let mut read_buf = vec![0u8; bytes_in_cluster];
@@ -183,6 +303,28 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
     //         Ok(bytes_read)
     //    }
 
+    /// Returns the number of bytes per cluster.
+    pub fn cluster_size(&self) -> u64 {
+        self.bytes_per_sector as u64 * self.sectors_per_cluster as u64
+    }
+
+    /// Returns the size and free space of the data region by scanning the
+    /// FAT for unused clusters.
+    pub fn usage(&mut self) -> io::Result<Usage> {
+        let mut free_clusters = 0;
+        // data clusters are numbered from 2
+        for cluster in 2..self.num_clusters + 2 {
+            if self.fat_entry(Cluster::from(cluster))?.status() == Status::Free {
+                free_clusters += 1;
+            }
+        }
+        Ok(Usage {
+            cluster_size: self.cluster_size(),
+            total_clusters: self.num_clusters,
+            free_clusters,
+        })
+    }
+
     //
     //  * A method to return a reference to a `FatEntry` for a cluster where the
     //    reference points directly into a cached sector.
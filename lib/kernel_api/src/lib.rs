@@ -28,6 +28,7 @@ pub enum OsError {
     IoErrorInvalidData = 103,
     IoErrorInvalidInput = 104,
     IoErrorTimedOut = 105,
+    IoErrorWouldBlock = 106,
 
     InvalidSocket = 200,
     IllegalSocketOperation = 201,
@@ -50,6 +51,8 @@ impl core::convert::From<u64> for OsError {
             102 => OsError::IoErrorEof,
             103 => OsError::IoErrorInvalidData,
             104 => OsError::IoErrorInvalidInput,
+            105 => OsError::IoErrorTimedOut,
+            106 => OsError::IoErrorWouldBlock,
 
             200 => OsError::InvalidSocket,
             201 => OsError::IllegalSocketOperation,
@@ -66,6 +69,7 @@ impl core::convert::From<io::Error> for OsError {
             io::ErrorKind::InvalidData => OsError::IoErrorInvalidData,
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
+            io::ErrorKind::WouldBlock => OsError::IoErrorWouldBlock,
             io::ErrorKind::NotFound => OsError::NoEntry,
             _ => OsError::IoError,
         }
@@ -78,7 +82,9 @@ pub const NR_EXIT: usize = 3;
 pub const NR_WRITE: usize = 4;
 pub const NR_GETPID: usize = 5;
 pub const NR_WRITE_STR: usize = 6;
+pub const NR_READ: usize = 7;
 
+#[repr(transparent)]
 #[derive(Clone, Copy, Debug)]
 pub struct SocketDescriptor(u64);
 
@@ -88,6 +94,15 @@ impl SocketDescriptor {
     }
 }
 
+/// The protocol of a socket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SocketType {
+    Tcp = 0,
+    Udp = 1,
+}
+
+/// The status of a socket. A UDP socket is active once it is bound to a local
+/// port and never listens.
 #[derive(Debug)]
 pub struct SocketStatus {
     pub is_active: bool,
@@ -96,28 +111,178 @@ pub struct SocketStatus {
     pub can_recv: bool,
 }
 
-pub struct IpAddr {
-    pub ip: u32,
-    pub port: u16,
+/// The option set by `sock_setopt`: whether operations on the socket fail
+/// with `OsError::IoErrorWouldBlock` instead of waiting (0 or 1).
+pub const SOCK_OPT_NONBLOCKING: u64 = 1;
+/// The option set by `sock_setopt`: how many milliseconds an operation on the
+/// socket waits before failing with `OsError::IoErrorTimedOut`, or 0 to wait
+/// as long as needed.
+pub const SOCK_OPT_TIMEOUT: u64 = 2;
+
+/// Event of `PollFd`: `recv`, `recvfrom` or `accept` would not wait.
+pub const POLL_IN: u32 = 1;
+/// Event of `PollFd`: `send` or `sendto` would not wait.
+pub const POLL_OUT: u32 = 2;
+
+/// A socket watched by `sock_poll`: the `events` to wait for, and the
+/// `revents` among them that have occurred.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PollFd {
+    pub descriptor: SocketDescriptor,
+    pub events: u32,
+    pub revents: u32,
+}
+
+impl PollFd {
+    pub fn new(descriptor: SocketDescriptor, events: u32) -> Self {
+        PollFd { descriptor, events, revents: 0 }
+    }
+}
+
+/// An IPv4 or IPv6 address.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum IpAddr {
+    V4([u8; 4]),
+    V6([u8; 16]),
 }
 
 impl IpAddr {
-    pub fn new((ip1, ip2, ip3, ip4): (u8, u8, u8, u8), port: u16) -> Self {
-        IpAddr {
-            ip: u32::from_be_bytes([ip1, ip2, ip3, ip4]),
-            port,
+    pub fn v4(a: u8, b: u8, c: u8, d: u8) -> Self {
+        IpAddr::V4([a, b, c, d])
+    }
+
+    /// Returns the address family of the address: `AF_INET` or `AF_INET6`.
+    pub fn family(&self) -> u16 {
+        match self {
+            IpAddr::V4(_) => AF_INET,
+            IpAddr::V6(_) => AF_INET6,
         }
     }
 }
 
+impl fmt::Display for IpAddr {
+    /// Formats an IPv6 address in the canonical form of RFC 5952: the longest
+    /// run of two or more zero groups is replaced by `::`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = match self {
+            IpAddr::V4([a, b, c, d]) => return write!(f, "{}.{}.{}.{}", a, b, c, d),
+            IpAddr::V6(bytes) => bytes,
+        };
+        let mut groups = [0u16; 8];
+        for (i, group) in groups.iter_mut().enumerate() {
+            *group = u16::from_be_bytes([bytes[2 * i], bytes[2 * i + 1]]);
+        }
+
+        let (mut zeros, mut run) = ((8, 0), (0, 0));
+        for (i, &group) in groups.iter().enumerate() {
+            if group != 0 {
+                run = (i + 1, 0);
+                continue;
+            }
+            run.1 += 1;
+            if run.1 > 1 && run.1 > zeros.1 {
+                zeros = run;
+            }
+        }
+
+        let (start, len) = zeros;
+        for (i, group) in groups.iter().enumerate() {
+            if i == start {
+                f.write_str("::")?;
+            } else if i > start && i < start + len {
+                continue;
+            } else {
+                if i != 0 && i != start + len {
+                    f.write_str(":")?;
+                }
+                write!(f, "{:x}", group)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for IpAddr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let bytes = self.ip.to_be_bytes();
-        write!(
-            f,
-            "IpAddr({}.{}.{}.{}:{})",
-            bytes[0], bytes[1], bytes[2], bytes[3], self.port
-        )
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// An IP address and a port number: an endpoint of a socket.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SocketAddr {
+    pub ip: IpAddr,
+    pub port: u16,
+}
+
+impl SocketAddr {
+    pub fn new(ip: IpAddr, port: u16) -> Self {
+        SocketAddr { ip, port }
+    }
+
+    pub fn v4((ip1, ip2, ip3, ip4): (u8, u8, u8, u8), port: u16) -> Self {
+        SocketAddr { ip: IpAddr::v4(ip1, ip2, ip3, ip4), port }
+    }
+}
+
+impl fmt::Display for SocketAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ip {
+            IpAddr::V4(_) => write!(f, "{}:{}", self.ip, self.port),
+            IpAddr::V6(_) => write!(f, "[{}]:{}", self.ip, self.port),
+        }
+    }
+}
+
+impl fmt::Debug for SocketAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SocketAddr({})", self)
+    }
+}
+
+/// Address family of an IPv4 `SockAddr`.
+pub const AF_INET: u16 = 2;
+/// Address family of an IPv6 `SockAddr`.
+pub const AF_INET6: u16 = 10;
+
+/// A socket address as the socket system calls take and return it, through
+/// a pointer: the address family, the port number, and the address, whose
+/// first 4 bytes hold an IPv4 address.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SockAddr {
+    pub family: u16,
+    pub port: u16,
+    pub addr: [u8; 16],
+}
+
+impl SockAddr {
+    /// Returns a `SockAddr` of no family, to be filled in by a system call.
+    pub const fn unspecified() -> Self {
+        SockAddr { family: 0, port: 0, addr: [0; 16] }
+    }
+
+    /// Returns the socket address, or `OsError::InvalidArgument` if the
+    /// family is unknown.
+    pub fn to_socket_addr(&self) -> OsResult<SocketAddr> {
+        let ip = match self.family {
+            AF_INET => IpAddr::V4([self.addr[0], self.addr[1], self.addr[2], self.addr[3]]),
+            AF_INET6 => IpAddr::V6(self.addr),
+            _ => return Err(OsError::InvalidArgument),
+        };
+        Ok(SocketAddr { ip, port: self.port })
+    }
+}
+
+impl From<SocketAddr> for SockAddr {
+    fn from(addr: SocketAddr) -> Self {
+        let mut bytes = [0; 16];
+        match addr.ip {
+            IpAddr::V4(ip) => bytes[..4].copy_from_slice(&ip),
+            IpAddr::V6(ip) => bytes = ip,
+        }
+        SockAddr { family: addr.ip.family(), port: addr.port, addr: bytes }
     }
 }
 
@@ -127,3 +292,10 @@ pub const NR_SOCK_CONNECT: usize = 22;
 pub const NR_SOCK_LISTEN: usize = 23;
 pub const NR_SOCK_SEND: usize = 24;
 pub const NR_SOCK_RECV: usize = 25;
+pub const NR_SOCK_BIND: usize = 26;
+pub const NR_SOCK_SENDTO: usize = 27;
+pub const NR_SOCK_RECVFROM: usize = 28;
+pub const NR_SOCK_ACCEPT: usize = 29;
+pub const NR_SOCK_SETOPT: usize = 30;
+pub const NR_SOCK_POLL: usize = 31;
+pub const NR_RESOLVE: usize = 32;
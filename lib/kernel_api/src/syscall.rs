@@ -106,6 +106,27 @@ pub fn write_str(msg: &str) {
 
 }
 
+/// Reads input typed on the programs' terminal into `buf`, waiting until
+/// there is some. Returns the number of bytes read.
+pub fn read(buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut bytes_read: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(bytes_read), "=r"(ecode)
+             : "r"(buf.as_mut_ptr() as u64), "r"(buf.len() as u64), "i"(NR_READ)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, bytes_read as usize)
+}
+
 pub fn getpid() -> u64 {
     let mut ecode: u64;
     let mut pid: u64;
@@ -124,35 +145,321 @@ pub fn getpid() -> u64 {
     return pid;
 }
 
+/// Creates a TCP socket.
 pub fn sock_create() -> SocketDescriptor {
-    // Lab 5 2.D
-    unimplemented!("sock_create")
+    sock_create_type(SocketType::Tcp)
 }
 
+/// Creates a UDP socket.
+pub fn sock_create_udp() -> SocketDescriptor {
+    sock_create_type(SocketType::Udp)
+}
+
+fn sock_create_type(ty: SocketType) -> SocketDescriptor {
+    let mut ecode: u64;
+    let mut sock_idx: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(sock_idx), "=r"(ecode)
+             : "r"(ty as u64), "i"(NR_SOCK_CREATE)
+             : "x0", "x7"
+             : "volatile");
+    }
+    if ecode != 1 { panic!("An error occurred while creating a socket!"); }
+
+    SocketDescriptor(sock_idx)
+}
 
 pub fn sock_status(descriptor: SocketDescriptor) -> OsResult<SocketStatus> {
-    // Lab 5 2.D
-    unimplemented!("sock_status")
+    let mut ecode: u64;
+    let mut is_active: u64;
+    let mut is_listening: u64;
+    let mut can_send: u64;
+    let mut can_recv: u64;
+
+    unsafe {
+        asm!("mov x0, $5
+              svc $6
+              mov $0, x0
+              mov $1, x1
+              mov $2, x2
+              mov $3, x3
+              mov $4, x7"
+             : "=r"(is_active), "=r"(is_listening), "=r"(can_send), "=r"(can_recv), "=r"(ecode)
+             : "r"(descriptor.raw()), "i"(NR_SOCK_STATUS)
+             : "x0", "x1", "x2", "x3", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, SocketStatus {
+        is_active: is_active != 0,
+        is_listening: is_listening != 0,
+        can_send: can_send != 0,
+        can_recv: can_recv != 0,
+    })
 }
 
-pub fn sock_connect(descriptor: SocketDescriptor, addr: IpAddr) -> OsResult<()> {
-    // Lab 5 2.D
-    unimplemented!("sock_connect")
+/// Connects a TCP socket to `addr`, an IPv4 or IPv6 endpoint.
+pub fn sock_connect(descriptor: SocketDescriptor, addr: SocketAddr) -> OsResult<()> {
+    let mut ecode: u64;
+    let addr = SockAddr::from(addr);
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(descriptor.raw()), "r"(&addr as *const SockAddr as u64), "i"(NR_SOCK_CONNECT)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
 }
 
 pub fn sock_listen(descriptor: SocketDescriptor, local_port: u16) -> OsResult<()> {
-    // Lab 5 2.D
-    unimplemented!("sock_listen")
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(descriptor.raw()), "r"(local_port as u64), "i"(NR_SOCK_LISTEN)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
 }
 
+/// Sends `buf` with a connected TCP socket. Returns the number of bytes sent.
+/// Waits while the connection is being established or the send buffer is
+/// full, unless the socket is non-blocking.
 pub fn sock_send(descriptor: SocketDescriptor, buf: &[u8]) -> OsResult<usize> {
-    // Lab 5 2.D
-    unimplemented!("sock_send")
+    let mut ecode: u64;
+    let mut bytes_sent: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(bytes_sent), "=r"(ecode)
+             : "r"(descriptor.raw()), "r"(buf.as_ptr() as u64), "r"(buf.len() as u64), "i"(NR_SOCK_SEND)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, bytes_sent as usize)
 }
 
+/// Receives data into `buf` with a connected TCP socket. Returns the number of
+/// bytes read, which is 0 once the remote endpoint has closed the connection.
+/// Waits until data is received, unless the socket is non-blocking.
 pub fn sock_recv(descriptor: SocketDescriptor, buf: &mut [u8]) -> OsResult<usize> {
-    // Lab 5 2.D
-    unimplemented!("sock_recv")
+    let mut ecode: u64;
+    let mut bytes_read: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(bytes_read), "=r"(ecode)
+             : "r"(descriptor.raw()), "r"(buf.as_mut_ptr() as u64), "r"(buf.len() as u64), "i"(NR_SOCK_RECV)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, bytes_read as usize)
+}
+
+/// Binds a UDP socket to `local_port`, or to an ephemeral port if
+/// `local_port` is 0. Returns the bound port.
+pub fn sock_bind(descriptor: SocketDescriptor, local_port: u16) -> OsResult<u16> {
+    let mut ecode: u64;
+    let mut port: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(port), "=r"(ecode)
+             : "r"(descriptor.raw()), "r"(local_port as u64), "i"(NR_SOCK_BIND)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, port as u16)
+}
+
+/// Sends `buf` as one datagram to `addr` with a UDP socket. Returns the number
+/// of bytes sent. Waits while the send buffer is full, unless the socket is
+/// non-blocking.
+pub fn sock_sendto(descriptor: SocketDescriptor, buf: &[u8], addr: SocketAddr) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut bytes_sent: u64;
+    let addr = SockAddr::from(addr);
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              mov x3, $5
+              svc $6
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(bytes_sent), "=r"(ecode)
+             : "r"(descriptor.raw()), "r"(buf.as_ptr() as u64), "r"(buf.len() as u64),
+               "r"(&addr as *const SockAddr as u64), "i"(NR_SOCK_SENDTO)
+             : "x0", "x1", "x2", "x3", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, bytes_sent as usize)
+}
+
+/// Receives a datagram into `buf` with a UDP socket. Returns the number of
+/// bytes read and the sender. Waits until a datagram is received, unless the
+/// socket is non-blocking.
+pub fn sock_recvfrom(descriptor: SocketDescriptor, buf: &mut [u8]) -> OsResult<(usize, SocketAddr)> {
+    let mut ecode: u64;
+    let mut bytes_read: u64;
+    let mut addr = SockAddr::unspecified();
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              mov x3, $5
+              svc $6
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(bytes_read), "=r"(ecode)
+             : "r"(descriptor.raw()), "r"(buf.as_mut_ptr() as u64), "r"(buf.len() as u64),
+               "r"(&mut addr as *mut SockAddr as u64), "i"(NR_SOCK_RECVFROM)
+             : "x0", "x1", "x2", "x3", "x7", "memory"
+             : "volatile");
+    }
+
+    let bytes_read = err_or!(ecode, bytes_read as usize)?;
+    Ok((bytes_read, addr.to_socket_addr()?))
+}
+
+/// Accepts an inbound connection with a listening TCP socket, which keeps
+/// listening. Returns the connected socket and the remote endpoint. Waits
+/// until a connection is established, unless the socket is non-blocking.
+pub fn sock_accept(descriptor: SocketDescriptor) -> OsResult<(SocketDescriptor, SocketAddr)> {
+    let mut ecode: u64;
+    let mut sock_idx: u64;
+    let mut addr = SockAddr::unspecified();
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(sock_idx), "=r"(ecode)
+             : "r"(descriptor.raw()), "r"(&mut addr as *mut SockAddr as u64), "i"(NR_SOCK_ACCEPT)
+             : "x0", "x1", "x7", "memory"
+             : "volatile");
+    }
+
+    let socket = err_or!(ecode, SocketDescriptor(sock_idx))?;
+    Ok((socket, addr.to_socket_addr()?))
+}
+
+fn sock_setopt(descriptor: SocketDescriptor, opt: u64, value: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              svc $4
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(descriptor.raw()), "r"(opt), "r"(value), "i"(NR_SOCK_SETOPT)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Makes the operations on a socket fail with `OsError::IoErrorWouldBlock`
+/// instead of waiting, or makes them wait again.
+pub fn sock_set_nonblocking(descriptor: SocketDescriptor, nonblocking: bool) -> OsResult<()> {
+    sock_setopt(descriptor, SOCK_OPT_NONBLOCKING, nonblocking as u64)
+}
+
+/// Makes the operations on a socket fail with `OsError::IoErrorTimedOut` after
+/// waiting for `timeout`, or wait as long as needed if `timeout` is `None`.
+pub fn sock_set_timeout(descriptor: SocketDescriptor, timeout: Option<Duration>) -> OsResult<()> {
+    let ms = timeout.map_or(0, |timeout| core::cmp::max(timeout.as_millis() as u64, 1));
+    sock_setopt(descriptor, SOCK_OPT_TIMEOUT, ms)
+}
+
+/// Waits until one of the `events` of `fds` occurs, or `timeout` elapses if it
+/// is not `None`. Sets the `revents` of every entry and returns the number of
+/// entries with events, which is 0 on timeout.
+pub fn sock_poll(fds: &mut [PollFd], timeout: Option<Duration>) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut ready: u64;
+    let ms = timeout.map_or(u64::max_value(), |timeout| timeout.as_millis() as u64);
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(ready), "=r"(ecode)
+             : "r"(fds.as_mut_ptr() as u64), "r"(fds.len() as u64), "r"(ms), "i"(NR_SOCK_POLL)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ready as usize)
+}
+
+/// Resolves the host `name` with `/etc/hosts` and the configured DNS servers,
+/// and returns its first address with `port`, preferring IPv4. An IPv4 or
+/// IPv6 address resolves to itself. Waits for the answer of the DNS servers.
+pub fn resolve(name: &str, port: u16) -> OsResult<SocketAddr> {
+    let mut ecode: u64;
+    let mut addr = SockAddr::unspecified();
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              svc $4
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(name.as_ptr() as u64), "r"(name.len() as u64),
+               "r"(&mut addr as *mut SockAddr as u64), "i"(NR_RESOLVE)
+             : "x0", "x1", "x2", "x7", "memory"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())?;
+    Ok(SocketAddr { port, ..addr.to_socket_addr()? })
 }
 
 struct Console;
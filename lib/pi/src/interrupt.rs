@@ -10,6 +10,7 @@ pub enum Interrupt {
     Timer1 = 1,
     Timer3 = 3,
     Usb = 9,
+    Aux = 29,
     Gpio0 = 49,
     Gpio1 = 50,
     Gpio2 = 51,
@@ -18,11 +19,11 @@ pub enum Interrupt {
 }
 
 impl Interrupt {
-    pub const MAX: usize = 8;
+    pub const MAX: usize = 9;
 
     pub fn iter() -> impl Iterator<Item = Interrupt> {
         use Interrupt::*;
-        [Timer1, Timer3, Usb, Gpio0, Gpio1, Gpio2, Gpio3, Uart]
+        [Timer1, Timer3, Usb, Aux, Gpio0, Gpio1, Gpio2, Gpio3, Uart]
             .iter()
             .map(|int| *int)
     }
@@ -35,6 +36,7 @@ impl From<usize> for Interrupt {
             1 => Timer1,
             3 => Timer3,
             9 => Usb,
+            29 => Aux,
             49 => Gpio0,
             50 => Gpio1,
             51 => Gpio2,
@@ -88,20 +90,26 @@ impl Controller {
         }
     }
 
-    // Enables the interrupt `int`.
+    /// Enables the interrupt `int`.
     pub fn enable(&mut self, int: Interrupt) {
         let index = int as u32;
+        // Writing a 0 bit leaves the corresponding interrupt unchanged.
         if index < 32 {
-            self.registers.IRQ_enable_1.or_mask(0b10);
+            self.registers.IRQ_enable_1.write(1 << index);
         } else {
-            self.registers.IRQ_enable_2.or_mask(1 << index % 32);
+            self.registers.IRQ_enable_2.write(1 << (index % 32));
         }
     }
 
     /// Disables the interrupt `int`.
     pub fn disable(&mut self, int: Interrupt) {
         let index = int as u32;
-        // self.registers.IRQ_disable.or_mask(1 << index);
+        // Writing a 0 bit leaves the corresponding interrupt unchanged.
+        if index < 32 {
+            self.registers.IRQ_disable_1.write(1 << index);
+        } else {
+            self.registers.IRQ_disable_2.write(1 << (index % 32));
+        }
     }
 
     /// Returns `true` if `int` is pending. Otherwise, returns `false`.
@@ -115,9 +123,15 @@ impl Controller {
         
     }
 
-    /// Enables the interrupt as FIQ interrupt
+    /// Enables the interrupt as FIQ interrupt. Only one interrupt can be
+    /// routed to FIQ at a time.
     pub fn enable_fiq(&mut self, int: Interrupt) {
-        // Lab 5 2.B
-        unimplemented!("enable_fiq")
+        // bit 7 enables FIQ, bits 6:0 select its source
+        self.registers.FIQ_control.write(0b1000_0000 | int as u32);
+    }
+
+    /// Stops routing an interrupt to FIQ.
+    pub fn disable_fiq(&mut self) {
+        self.registers.FIQ_control.write(0);
     }
 }
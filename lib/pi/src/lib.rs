@@ -11,5 +11,7 @@ pub mod common;
 pub mod gpio;
 pub mod interrupt;
 pub mod local_interrupt;
+pub mod pl011;
+pub mod rng;
 pub mod timer;
 pub mod uart;
@@ -0,0 +1,503 @@
+use core::fmt;
+use core::time::Duration;
+
+use shim::const_assert_size;
+use shim::io;
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile, WriteVolatile};
+
+use crate::common::IO_BASE;
+use crate::gpio::{Function, Gpio};
+use crate::timer;
+use crate::uart::Buffer;
+
+/// The base address of the `UART0` registers.
+const UART0_REG_BASE: usize = IO_BASE + 0x201000;
+
+/// Frequency of the UART reference clock set up by the firmware.
+pub const UART_CLOCK: u32 = 48_000_000;
+
+/// Bits of the `FR` (flag) register.
+#[repr(u32)]
+enum Flag {
+    Busy = 1 << 3,
+    RxEmpty = 1 << 4,
+    TxFull = 1 << 5,
+}
+
+/// Bits of the `LCRH` (line control) register.
+#[repr(u32)]
+enum LineControl {
+    ParityEnable = 1 << 1,
+    EvenParity = 1 << 2,
+    TwoStopBits = 1 << 3,
+    FifoEnable = 1 << 4,
+}
+
+/// Bits of the `CR` (control) register.
+#[repr(u32)]
+enum Control {
+    Enable = 1,
+    TxEnable = 1 << 8,
+    RxEnable = 1 << 9,
+    RtsEnable = 1 << 14,
+    CtsEnable = 1 << 15,
+}
+
+/// Bits of the interrupt mask, status and clear registers.
+#[repr(u32)]
+enum InterruptFlag {
+    Rx = 1 << 4,
+    Tx = 1 << 5,
+    RxTimeout = 1 << 6,
+}
+
+/// Error bits of a byte read from `DR`.
+const DR_ERRORS: u32 = 0xf << 8;
+
+/// Clears every interrupt when written to `ICR`.
+const ICR_ALL: u32 = 0x7ff;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    /// Data register: the received or transmitted byte, and the error flags
+    /// of a received byte.
+    DR: Volatile<u32>,
+    /// Receive status / error clear register.
+    RSRECR: Volatile<u32>,
+    _r0: [Reserved<u32>; 4],
+    /// Flag register.
+    FR: ReadVolatile<u32>,
+    _r1: Reserved<u32>,
+    /// IrDA low-power counter register (unused).
+    ILPR: Volatile<u32>,
+    /// Integer part of the baud rate divisor.
+    IBRD: Volatile<u32>,
+    /// Fractional part of the baud rate divisor, in 1/64ths.
+    FBRD: Volatile<u32>,
+    /// Line control register.
+    LCRH: Volatile<u32>,
+    /// Control register.
+    CR: Volatile<u32>,
+    /// Interrupt FIFO level select register.
+    IFLS: Volatile<u32>,
+    /// Interrupt mask set/clear register.
+    IMSC: Volatile<u32>,
+    /// Raw interrupt status register.
+    RIS: ReadVolatile<u32>,
+    /// Masked interrupt status register.
+    MIS: ReadVolatile<u32>,
+    /// Interrupt clear register.
+    ICR: WriteVolatile<u32>,
+}
+
+const_assert_size!(Registers, 0x48);
+
+/// Parity bit of each character.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// FIFO fill level at which the receive or transmit interrupt is raised.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FifoLevel {
+    Eighth = 0b000,
+    Quarter = 0b001,
+    Half = 0b010,
+    ThreeQuarters = 0b011,
+    SevenEighths = 0b100,
+}
+
+/// Line settings of a `Pl011`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub baud: u32,
+    pub parity: Parity,
+    /// Number of data bits, from 5 to 8.
+    pub data_bits: u8,
+    /// Number of stop bits, 1 or 2.
+    pub stop_bits: u8,
+    /// Whether to use RTS/CTS hardware flow control, on GPIO pins 16 and 17.
+    pub flow_control: bool,
+    /// The receive interrupt is raised when the receive FIFO is filled up to
+    /// this level, or when received bytes have been waiting for a while.
+    pub rx_level: FifoLevel,
+    /// The transmit interrupt is raised when the transmit FIFO drains down
+    /// to this level.
+    pub tx_level: FifoLevel,
+}
+
+impl Default for Config {
+    /// 115200 baud, 8 data bits, no parity, 1 stop bit and no flow control.
+    fn default() -> Config {
+        Config {
+            baud: 115200,
+            parity: Parity::None,
+            data_bits: 8,
+            stop_bits: 1,
+            flow_control: false,
+            rx_level: FifoLevel::Half,
+            tx_level: FifoLevel::Quarter,
+        }
+    }
+}
+
+impl Config {
+    /// Parses line settings written as in a Linux `console=` option:
+    /// `<baud>[<parity>[<bits>[r]]]`, e.g. `115200n8` or `921600e7r`. The
+    /// parity is one of `n`, `o` and `e`, and `r` enables flow control. The
+    /// other settings are left to their defaults.
+    ///
+    /// Returns `None` if `options` is malformed or out of range.
+    pub fn parse(options: &str) -> Option<Config> {
+        let mut config = Config::default();
+        let digits = options.bytes().take_while(|b| b.is_ascii_digit()).count();
+        config.baud = options[..digits].parse().ok()?;
+        if config.baud == 0 || config.baud > UART_CLOCK / 16 {
+            return None;
+        }
+
+        let mut rest = options[digits..].bytes();
+        match rest.next() {
+            None => return Some(config),
+            Some(b'n') => config.parity = Parity::None,
+            Some(b'o') => config.parity = Parity::Odd,
+            Some(b'e') => config.parity = Parity::Even,
+            Some(_) => return None,
+        }
+        match rest.next() {
+            None => return Some(config),
+            Some(bits @ b'5'..=b'8') => config.data_bits = bits - b'0',
+            Some(_) => return None,
+        }
+        match rest.next() {
+            None => return Some(config),
+            Some(b'r') => config.flow_control = true,
+            Some(_) => return None,
+        }
+        match rest.next() {
+            None => Some(config),
+            Some(_) => None,
+        }
+    }
+
+    /// Returns the integer and fractional (in 1/64ths) parts of the baud rate
+    /// divisor, `UART_CLOCK / (16 * baud)`, rounded to the nearest 1/64th.
+    fn divisor(&self) -> (u32, u32) {
+        let div = (4 * UART_CLOCK as u64 + self.baud as u64 / 2) / self.baud as u64;
+        ((div >> 6) as u32, (div & 0x3f) as u32)
+    }
+
+    fn line_control(&self) -> u32 {
+        let mut lcrh = ((self.data_bits as u32 - 5) << 5) | LineControl::FifoEnable as u32;
+        match self.parity {
+            Parity::None => {}
+            Parity::Odd => lcrh |= LineControl::ParityEnable as u32,
+            Parity::Even => lcrh |= LineControl::ParityEnable as u32 | LineControl::EvenParity as u32,
+        }
+        if self.stop_bits == 2 {
+            lcrh |= LineControl::TwoStopBits as u32;
+        }
+        lcrh
+    }
+}
+
+/// The Raspberry Pi's PL011 "full" UART (`UART0`). Unlike the mini UART, its
+/// baud rate does not depend on the core clock and it has 16-byte FIFOs.
+///
+/// Like `MiniUart`, the UART is polled until `enable_interrupts()` is called.
+/// From then on, `handle_interrupt()` must be called from the
+/// `Interrupt::Uart` handler.
+pub struct Pl011 {
+    registers: &'static mut Registers,
+    timeout: Option<Duration>,
+    interrupts: bool,
+    rx: Buffer,
+    tx: Buffer,
+    /// Number of received bytes dropped because the receive buffer was full.
+    overruns: usize,
+    /// Number of received bytes with a framing, parity or break error.
+    errors: usize,
+}
+
+impl Pl011 {
+    /// Initializes `UART0` with the line settings `config` and sets GPIO pins
+    /// 14 and 15 (and 16 and 17 with flow control) to their UART0 function.
+    ///
+    /// By default, reads will never time out. To set a read timeout, use
+    /// `set_read_timeout()`.
+    pub fn new(config: Config) -> Pl011 {
+        assert!(config.data_bits >= 5 && config.data_bits <= 8, "invalid data bits");
+        assert!(config.stop_bits == 1 || config.stop_bits == 2, "invalid stop bits");
+        assert!(config.baud > 0 && config.baud <= UART_CLOCK / 16, "invalid baud rate");
+
+        let registers = unsafe { &mut *(UART0_REG_BASE as *mut Registers) };
+
+        // The UART must be disabled, idle and with its FIFOs flushed before
+        // it is reprogrammed.
+        registers.CR.write(0);
+        while registers.FR.has_mask(Flag::Busy as u32) {}
+        registers.LCRH.and_mask(!(LineControl::FifoEnable as u32));
+
+        Gpio::new(14).into_alt(Function::Alt0);
+        Gpio::new(15).into_alt(Function::Alt0);
+        if config.flow_control {
+            Gpio::new(16).into_alt(Function::Alt3);
+            Gpio::new(17).into_alt(Function::Alt3);
+        }
+
+        registers.ICR.write(ICR_ALL);
+        registers.IMSC.write(0);
+        let (ibrd, fbrd) = config.divisor();
+        registers.IBRD.write(ibrd);
+        registers.FBRD.write(fbrd);
+        // The divisors are only latched by a write to LCRH.
+        registers.LCRH.write(config.line_control());
+        registers.IFLS.write((config.rx_level as u32) << 3 | config.tx_level as u32);
+
+        let mut cr = Control::Enable as u32 | Control::TxEnable as u32 | Control::RxEnable as u32;
+        if config.flow_control {
+            cr |= Control::RtsEnable as u32 | Control::CtsEnable as u32;
+        }
+        registers.CR.write(cr);
+
+        Pl011 {
+            registers,
+            timeout: None,
+            interrupts: false,
+            rx: Buffer::new(),
+            tx: Buffer::new(),
+            overruns: 0,
+            errors: 0,
+        }
+    }
+
+    /// Set the read timeout to `t` duration.
+    pub fn set_read_timeout(&mut self, t: Duration) {
+        self.timeout = Some(t);
+    }
+
+    /// Switches the UART to interrupt-driven operation by enabling its receive
+    /// interrupts. The `Interrupt::Uart` interrupt must be enabled in the
+    /// interrupt controller and routed to `handle_interrupt()` by the caller.
+    pub fn enable_interrupts(&mut self) {
+        self.interrupts = true;
+        self.registers.IMSC.write(InterruptFlag::Rx as u32 | InterruptFlag::RxTimeout as u32);
+    }
+
+    /// Returns `true` if the UART is driven by interrupts.
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts
+    }
+
+    /// Returns the number of received bytes dropped because the receive
+    /// buffer was full.
+    pub fn overruns(&self) -> usize {
+        self.overruns
+    }
+
+    /// Returns the number of received bytes with a framing, parity or break
+    /// error. Such bytes are dropped.
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Services an `Interrupt::Uart` interrupt: drains the receive FIFO into
+    /// the receive buffer and refills the transmit FIFO from the transmit
+    /// buffer.
+    pub fn handle_interrupt(&mut self) {
+        self.drain_rx_fifo();
+        self.fill_tx_fifo();
+        self.registers.ICR.write(InterruptFlag::RxTimeout as u32);
+    }
+
+    /// Reads a byte from the receive FIFO, which must not be empty. Returns
+    /// `None` for bytes received with an error.
+    fn read_fifo(&mut self) -> Option<u8> {
+        let data = self.registers.DR.read();
+        if data & DR_ERRORS != 0 {
+            self.errors += 1;
+            return None;
+        }
+        Some(data as u8)
+    }
+
+    fn drain_rx_fifo(&mut self) {
+        while !self.registers.FR.has_mask(Flag::RxEmpty as u32) {
+            if let Some(byte) = self.read_fifo() {
+                if !self.rx.push(byte) {
+                    self.overruns += 1;
+                }
+            }
+        }
+    }
+
+    /// Moves buffered bytes to the transmit FIFO while it has room. The
+    /// transmit interrupt is only left enabled while bytes are pending.
+    fn fill_tx_fifo(&mut self) {
+        while !self.tx.is_empty() && !self.registers.FR.has_mask(Flag::TxFull as u32) {
+            let byte = self.tx.pop().unwrap();
+            self.registers.DR.write(byte as u32);
+        }
+        if self.tx.is_empty() {
+            self.registers.IMSC.and_mask(!(InterruptFlag::Tx as u32));
+        }
+    }
+
+    /// Blocks until every buffered byte has been handed to the transmit FIFO.
+    /// Interrupts are not needed for this to make progress.
+    pub fn flush(&mut self) {
+        while !self.tx.is_empty() {
+            self.fill_tx_fifo();
+        }
+    }
+
+    /// Write the byte `byte`. This method blocks until there is space available
+    /// in the output FIFO. When the UART is driven by interrupts, it only
+    /// blocks while the transmit buffer is full.
+    pub fn write_byte(&mut self, byte: u8) {
+        if self.interrupts {
+            self.fill_tx_fifo();
+            while self.tx.is_full() {
+                self.fill_tx_fifo();
+            }
+            if self.tx.is_empty() && !self.registers.FR.has_mask(Flag::TxFull as u32) {
+                self.registers.DR.write(byte as u32);
+            } else {
+                self.tx.push(byte);
+                self.registers.IMSC.or_mask(InterruptFlag::Tx as u32);
+            }
+            return;
+        }
+
+        while self.registers.FR.has_mask(Flag::TxFull as u32) {}
+        self.registers.DR.write(byte as u32);
+    }
+
+    /// Returns `true` if there is at least one byte ready to be read. If this
+    /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
+    /// return immediately. This method does not block.
+    pub fn has_byte(&mut self) -> bool {
+        // Bytes received with an error are dropped here so that they cannot
+        // make `read_byte` block.
+        while self.rx.is_empty() && !self.registers.FR.has_mask(Flag::RxEmpty as u32) {
+            if let Some(byte) = self.read_fifo() {
+                self.rx.push(byte);
+            }
+        }
+        !self.rx.is_empty()
+    }
+
+    /// Blocks until there is a byte ready to read. If a read timeout is set,
+    /// this method blocks for at most that amount of time. Otherwise, this
+    /// method blocks indefinitely until there is a byte to read.
+    ///
+    /// Returns `Ok(())` if a byte is ready to read. Returns `Err(())` if the
+    /// timeout expired while waiting for a byte to be ready.
+    pub fn wait_for_byte(&mut self) -> Result<(), ()> {
+        let deadline = self.timeout.map(|timeout| timer::current_time() + timeout);
+        while !self.has_byte() {
+            if deadline.map_or(false, |deadline| timer::current_time() >= deadline) {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
+    pub fn read_byte(&mut self) -> u8 {
+        while !self.has_byte() {}
+        self.rx.pop().unwrap()
+    }
+}
+
+impl fmt::Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+impl io::Read for Pl011 {
+    /// Waits at most the read timeout for the first byte, then reads as many
+    /// bytes as are available without waiting.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.wait_for_byte().is_err() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out when waiting for the first byte"));
+        }
+        let mut num_bytes = 0;
+        while num_bytes < buf.len() && self.has_byte() {
+            buf[num_bytes] = self.read_byte();
+            num_bytes += 1;
+        }
+        Ok(num_bytes)
+    }
+}
+
+impl io::Write for Pl011 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.write_byte(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Pl011::flush(self);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, FifoLevel, Parity};
+
+    #[test]
+    fn parse_options() {
+        assert_eq!(Config::parse("115200"), Some(Config::default()));
+        assert_eq!(Config::parse("115200n8"), Some(Config::default()));
+
+        let config = Config::parse("921600e7r").unwrap();
+        assert_eq!(config.baud, 921600);
+        assert_eq!(config.parity, Parity::Even);
+        assert_eq!(config.data_bits, 7);
+        assert!(config.flow_control);
+        assert_eq!(config.stop_bits, 1);
+        assert_eq!(config.rx_level, FifoLevel::Half);
+
+        assert_eq!(Config::parse("9600o").unwrap().parity, Parity::Odd);
+        for bad in &["", "n8", "0", "115200x", "115200n9", "115200n8x", "115200n8rr", "4000000"] {
+            assert_eq!(Config::parse(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn divisor() {
+        // 48 MHz / (16 * 115200) = 26.04, and 0.04 * 64 = 2.6.
+        let config = |baud| Config { baud, ..Config::default() };
+        assert_eq!(config(115200).divisor(), (26, 3));
+        assert_eq!(config(9600).divisor(), (312, 32));
+        assert_eq!(config(3_000_000).divisor(), (1, 0));
+    }
+
+    #[test]
+    fn line_control() {
+        assert_eq!(Config::default().line_control(), 0b111_0000);
+        let config = Config { parity: Parity::Even, data_bits: 7, stop_bits: 2, ..Config::default() };
+        assert_eq!(config.line_control(), 0b101_1110);
+    }
+}
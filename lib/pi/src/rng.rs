@@ -0,0 +1,54 @@
+use crate::common::IO_BASE;
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Volatile};
+
+/// The base address of the hardware random number generator registers.
+const RNG_REG_BASE: usize = IO_BASE + 0x104000;
+
+/// Number of initial numbers the generator discards while warming up.
+const WARMUP_COUNT: u32 = 0x40000;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CTRL: Volatile<u32>,
+    STATUS: Volatile<u32>,
+    DATA: ReadVolatile<u32>,
+    FF_THRESHOLD: Volatile<u32>,
+    INT_MASK: Volatile<u32>,
+}
+
+/// The Raspberry Pi hardware random number generator.
+pub struct Rng {
+    registers: &'static mut Registers,
+}
+
+impl Rng {
+    /// Returns a new instance of `Rng`, enabling the generator if it is not
+    /// running yet.
+    pub fn new() -> Rng {
+        let registers = unsafe { &mut *(RNG_REG_BASE as *mut Registers) };
+        if registers.CTRL.read() & 1 == 0 {
+            registers.STATUS.write(WARMUP_COUNT);
+            // interrupts off, the generator is polled
+            registers.INT_MASK.write(registers.INT_MASK.read() | 1);
+            registers.CTRL.write(registers.CTRL.read() | 1);
+        }
+        Rng { registers }
+    }
+
+    /// Returns a random 32-bit number, waiting for the generator to produce
+    /// one.
+    pub fn next_u32(&mut self) -> u32 {
+        // the upper byte of `STATUS` counts the words available
+        while self.registers.STATUS.read() >> 24 == 0 {}
+        self.registers.DATA.read()
+    }
+}
+
+/// Returns a random 64-bit number from the hardware generator.
+pub fn random_u64() -> u64 {
+    let mut rng = Rng::new();
+    (rng.next_u32() as u64) << 32 | rng.next_u32() as u64
+}
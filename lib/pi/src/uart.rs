@@ -25,6 +25,60 @@ enum LsrStatus {
     TxAvailable = 1 << 5,
 }
 
+/// Bits of the `AUX_MU_IER_REG` register. The documentation has the receive
+/// and transmit bits swapped, and bits 3:2 must be set as well for receive
+/// interrupts to be raised (BCM2835 errata).
+#[repr(u8)]
+enum IerFlag {
+    RxInterrupt = 0b1101,
+    TxInterrupt = 1 << 1,
+}
+
+/// Size of the receive and transmit buffers used when the UART is driven by
+/// interrupts.
+pub const BUFFER_SIZE: usize = 1024;
+
+/// A fixed-size FIFO of bytes.
+pub(crate) struct Buffer {
+    bytes: [u8; BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl Buffer {
+    pub(crate) const fn new() -> Buffer {
+        Buffer { bytes: [0; BUFFER_SIZE], head: 0, len: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.len == BUFFER_SIZE
+    }
+
+    /// Appends `byte`. Returns `false` if the buffer is full.
+    pub(crate) fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.bytes[(self.head + self.len) % BUFFER_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.bytes[self.head];
+        self.head = (self.head + 1) % BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
 #[repr(C)]
 #[allow(non_snake_case)]
 
@@ -84,9 +138,19 @@ struct Registers {
 const_assert_size!(Registers, 0x7E21506C - 0x7E215040);
 
 /// The Raspberry Pi's "mini UART".
+///
+/// The UART is polled until `enable_interrupts()` is called. From then on,
+/// `handle_interrupt()` must be called from the `Interrupt::Aux` handler: it
+/// moves received bytes to a receive buffer and feeds the transmit FIFO from a
+/// transmit buffer, so that neither reading nor writing has to spin.
 pub struct MiniUart {
     registers: &'static mut Registers,
     timeout: Option<Duration>,
+    interrupts: bool,
+    rx: Buffer,
+    tx: Buffer,
+    /// Number of received bytes dropped because the receive buffer was full.
+    overruns: usize,
 }
 
 impl MiniUart {
@@ -143,7 +207,11 @@ impl MiniUart {
             
             MiniUart {
                 registers,
-                timeout: None
+                timeout: None,
+                interrupts: false,
+                rx: Buffer::new(),
+                tx: Buffer::new(),
+                overruns: 0,
             }
     }
 
@@ -152,9 +220,80 @@ impl MiniUart {
         self.timeout = Some(t);
     }
 
+    /// Switches the UART to interrupt-driven operation by enabling its receive
+    /// interrupt. The `Interrupt::Aux` interrupt must be enabled in the
+    /// interrupt controller and routed to `handle_interrupt()` by the caller.
+    pub fn enable_interrupts(&mut self) {
+        self.interrupts = true;
+        self.registers.IER.write(IerFlag::RxInterrupt as u8);
+    }
+
+    /// Returns `true` if the UART is driven by interrupts.
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts
+    }
+
+    /// Returns the number of received bytes dropped because the receive
+    /// buffer was full.
+    pub fn overruns(&self) -> usize {
+        self.overruns
+    }
+
+    /// Services an `Interrupt::Aux` interrupt: drains the receive FIFO into
+    /// the receive buffer and refills the transmit FIFO from the transmit
+    /// buffer.
+    pub fn handle_interrupt(&mut self) {
+        self.drain_rx_fifo();
+        self.fill_tx_fifo();
+    }
+
+    fn drain_rx_fifo(&mut self) {
+        while self.registers.LSR.has_mask(LsrStatus::DataReady as u8) {
+            let byte = self.registers.IO.read();
+            if !self.rx.push(byte) {
+                self.overruns += 1;
+            }
+        }
+    }
+
+    /// Moves buffered bytes to the transmit FIFO while it has room. The
+    /// transmit interrupt is only left enabled while bytes are pending.
+    fn fill_tx_fifo(&mut self) {
+        while !self.tx.is_empty() && self.registers.LSR.has_mask(LsrStatus::TxAvailable as u8) {
+            let byte = self.tx.pop().unwrap();
+            self.registers.IO.write(byte);
+        }
+        if self.tx.is_empty() {
+            self.registers.IER.and_mask(!(IerFlag::TxInterrupt as u8));
+        }
+    }
+
+    /// Blocks until every buffered byte has been handed to the transmit FIFO.
+    /// Interrupts are not needed for this to make progress.
+    pub fn flush(&mut self) {
+        while !self.tx.is_empty() {
+            self.fill_tx_fifo();
+        }
+    }
+
     /// Write the byte `byte`. This method blocks until there is space available
-    /// in the output FIFO.
+    /// in the output FIFO. When the UART is driven by interrupts, it only
+    /// blocks while the transmit buffer is full.
     pub fn write_byte(&mut self, byte: u8) {
+        if self.interrupts {
+            self.fill_tx_fifo();
+            while self.tx.is_full() {
+                self.fill_tx_fifo();
+            }
+            if self.tx.is_empty() && self.registers.LSR.has_mask(LsrStatus::TxAvailable as u8) {
+                self.registers.IO.write(byte);
+            } else {
+                self.tx.push(byte);
+                self.registers.IER.or_mask(IerFlag::TxInterrupt as u8);
+            }
+            return;
+        }
+
         // while the transmit FIFO is full (can't accept one byte) 
         while !self.registers.LSR.has_mask(LsrStatus::TxAvailable as u8) {
             ;
@@ -173,7 +312,7 @@ impl MiniUart {
     /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
     /// return immediately. This method does not block.
     pub fn has_byte(&self) -> bool {
-        self.registers.LSR.has_mask(LsrStatus::DataReady as u8)
+        !self.rx.is_empty() || self.registers.LSR.has_mask(LsrStatus::DataReady as u8)
     }
 
     /// Blocks until there is a byte ready to read. If a read timeout is set,
@@ -205,7 +344,11 @@ impl MiniUart {
     /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
     pub fn read_byte(&mut self) -> u8 {
         self.wait_for_byte(); //what happens if I dont handle the error here?  
-        self.registers.IO.read() & ! 0u8 
+        // Buffered bytes were received before the ones still in the FIFO.
+        match self.rx.pop() {
+            Some(byte) => byte,
+            None => self.registers.IO.read() & ! 0u8,
+        }
     }
 }
 
@@ -264,9 +407,39 @@ mod uart_io {
             Ok(buf.len())
         }
         fn flush(&mut self) -> io::Result<()> {
+            MiniUart::flush(self);
             Ok(())
         }
 
 
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Buffer, BUFFER_SIZE};
+
+    #[test]
+    fn buffer_is_fifo() {
+        let mut buffer = Buffer::new();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pop(), None);
+
+        for round in 0..3 {
+            for i in 0..BUFFER_SIZE {
+                assert!(buffer.push((i + round) as u8));
+            }
+            assert!(buffer.is_full());
+            assert!(!buffer.push(0));
+            for i in 0..BUFFER_SIZE / 2 {
+                assert_eq!(buffer.pop(), Some((i + round) as u8));
+            }
+            for i in BUFFER_SIZE / 2..BUFFER_SIZE {
+                assert_eq!(buffer.pop(), Some((i + round) as u8));
+            }
+            assert!(buffer.is_empty());
+            assert!(buffer.push(1));
+            assert_eq!(buffer.pop(), Some(1));
+        }
+    }
+}
@@ -5,8 +5,6 @@
 
 mod cr0;
 
-use core::time::Duration;
-
 use kernel_api::syscall::*;
 use kernel_api::{print, println, OsResult};
 
@@ -17,7 +15,28 @@ fn main() {
     }
 }
 
+/// Port the echo server listens on.
+const PORT: u16 = 80;
+
 fn main_inner() -> OsResult<!> {
-    // Lab 5 3
-    unimplemented!("main_inner()")
+    let listener = sock_create();
+    sock_listen(listener, PORT)?;
+    println!("Listening on port {}", PORT);
+
+    let (socket, remote) = sock_accept(listener)?;
+    println!("Connected to {:?}", remote);
+    sock_send(socket, b"Welcome to the echo server!\r\n")?;
+
+    let mut buf = [0u8; 512];
+    loop {
+        let len = sock_recv(socket, &mut buf)?;
+        if len == 0 {
+            println!("Disconnected");
+            exit();
+        }
+        let mut sent = 0;
+        while sent < len {
+            sent += sock_send(socket, &buf[sent..len])?;
+        }
+    }
 }